@@ -0,0 +1,187 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Time a host gets to accept the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Time a connected host gets to answer `*IDN?`.
+const IDN_TIMEOUT: Duration = Duration::from_millis(700);
+
+/// Hosts probed in parallel.
+const WORKERS: usize = 64;
+
+/// Widest prefix accepted, keeping a sweep to 1022 hosts.
+const MIN_PREFIX: u8 = 22;
+
+/// A host that accepted a connection on the SCPI port.
+#[derive(Debug, Clone, Serialize)]
+pub struct FoundInstrument {
+    pub ip: String,
+    /// `*IDN?` response; `None` for an unidentified device that accepted the
+    /// connection but did not answer.
+    pub idn: Option<String>,
+    /// Time to accept the connection.
+    pub latency_ms: f64,
+}
+
+/// The /24 of the interface that routes to the outside, found without
+/// sending anything.
+pub fn local_subnet() -> Result<(Ipv4Addr, u8), String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("Failed to open socket: {}", e))?;
+    socket
+        .connect("192.0.2.1:9")
+        .map_err(|e| format!("No network route to find the local subnet: {}", e))?;
+    match socket.local_addr().map_err(|e| format!("Failed to read local address: {}", e))?.ip() {
+        IpAddr::V4(ip) if !ip.is_loopback() && !ip.is_unspecified() => Ok((ip, 24)),
+        ip => Err(format!("No IPv4 LAN address found (got {})", ip)),
+    }
+}
+
+/// Parse `a.b.c.d/nn`, or a bare address meaning its /24.
+pub fn parse_subnet(s: &str) -> Result<(Ipv4Addr, u8), String> {
+    let (ip, prefix) = match s.trim().split_once('/') {
+        Some((ip, prefix)) => (
+            ip,
+            prefix
+                .parse::<u8>()
+                .map_err(|_| format!("Invalid prefix length in '{}'", s))?,
+        ),
+        None => (s.trim(), 24),
+    };
+    let ip: Ipv4Addr = ip.parse().map_err(|_| format!("Invalid IPv4 address in '{}'", s))?;
+    if !(MIN_PREFIX..=30).contains(&prefix) {
+        return Err(format!(
+            "Subnet /{} not supported; use a prefix from /{} to /30",
+            prefix, MIN_PREFIX
+        ));
+    }
+    Ok((ip, prefix))
+}
+
+/// Host addresses of the subnet, without network and broadcast address.
+pub fn subnet_hosts(ip: Ipv4Addr, prefix: u8) -> Vec<Ipv4Addr> {
+    let mask = u32::MAX << (32 - prefix);
+    let network = u32::from(ip) & mask;
+    let broadcast = network | !mask;
+    (network + 1..broadcast).map(Ipv4Addr::from).collect()
+}
+
+/// Connect to `ip:port` and ask for `*IDN?`, giving up at `end`. A host
+/// that accepts but has no time left to answer is listed unidentified.
+fn probe(ip: Ipv4Addr, port: u16, end: Instant) -> Option<FoundInstrument> {
+    let remaining = || end.saturating_duration_since(Instant::now());
+    let start = Instant::now();
+    let connect_timeout = CONNECT_TIMEOUT.min(remaining());
+    if connect_timeout.is_zero() {
+        return None;
+    }
+    let mut stream = TcpStream::connect_timeout(&SocketAddr::new(IpAddr::V4(ip), port), connect_timeout).ok()?;
+    let latency_ms = start.elapsed().as_secs_f64() * 1e3;
+    let idn = (|| {
+        // A zero timeout is rejected, so no time left means no answer
+        let timeout = IDN_TIMEOUT.min(remaining());
+        stream.set_read_timeout(Some(timeout)).ok()?;
+        stream.set_write_timeout(Some(timeout)).ok()?;
+        stream.write_all(b"*IDN?\n").ok()?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line).ok()?;
+        let line = line.trim();
+        (!line.is_empty()).then(|| line.to_string())
+    })();
+    Some(FoundInstrument {
+        ip: ip.to_string(),
+        idn,
+        latency_ms,
+    })
+}
+
+/// Probe `hosts` on `port` in parallel, calling `found` as instruments
+/// answer. No new host is probed after `deadline`, and probes still in
+/// flight then get only the time left, so the sweep ends at `deadline`.
+pub fn discover(
+    hosts: &[Ipv4Addr],
+    port: u16,
+    deadline: Duration,
+    found: &(dyn Fn(&FoundInstrument) + Sync),
+) -> Vec<FoundInstrument> {
+    let end = Instant::now() + deadline;
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..WORKERS.min(hosts.len()) {
+            scope.spawn(|| {
+                while Instant::now() < end {
+                    let Some(&ip) = hosts.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    if let Some(instrument) = probe(ip, port, end) {
+                        found(&instrument);
+                        if let Ok(mut results) = results.lock() {
+                            results.push(instrument);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap_or_default();
+    results.sort_by_key(|r| r.ip.parse::<Ipv4Addr>().ok());
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn subnet_parsing() {
+        assert_eq!(parse_subnet("192.168.1.77").unwrap(), (Ipv4Addr::new(192, 168, 1, 77), 24));
+        assert_eq!(parse_subnet("10.0.0.0/22").unwrap().1, 22);
+        assert!(parse_subnet("10.0.0.0/16").is_err());
+        assert!(parse_subnet("10.0.0/24").is_err());
+
+        let hosts = subnet_hosts(Ipv4Addr::new(192, 168, 1, 77), 24);
+        assert_eq!(hosts.len(), 254);
+        assert_eq!(hosts[0], Ipv4Addr::new(192, 168, 1, 1));
+        assert_eq!(hosts[253], Ipv4Addr::new(192, 168, 1, 254));
+        assert_eq!(subnet_hosts(Ipv4Addr::new(10, 0, 0, 9), 30).len(), 2);
+    }
+
+    #[test]
+    fn lists_silent_devices_as_unidentified() {
+        let instrument = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = instrument.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = instrument.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream.try_clone().unwrap()).read_line(&mut line).unwrap();
+            stream.write_all(b"Keysight Technologies,N5182B,MY1234,B.01\n").unwrap();
+            // Hold the connection until the probe has read the answer
+            std::thread::sleep(Duration::from_millis(500));
+        });
+        let found = discover(&[Ipv4Addr::LOCALHOST], port, Duration::from_secs(2), &|_| {});
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].idn.as_deref(), Some("Keysight Technologies,N5182B,MY1234,B.01"));
+
+        // Accepts the connection but never answers
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = silent.local_addr().unwrap().port();
+        let found = discover(&[Ipv4Addr::LOCALHOST], port, Duration::from_secs(2), &|_| {});
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].idn, None);
+
+        // The deadline cuts the silent host's IDN wait short
+        let start = Instant::now();
+        let found = discover(&[Ipv4Addr::LOCALHOST], port, Duration::from_millis(100), &|_| {});
+        assert!(start.elapsed() < IDN_TIMEOUT / 2, "{:?}", start.elapsed());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].idn, None);
+        drop(silent);
+    }
+}
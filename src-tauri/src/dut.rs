@@ -1,9 +1,28 @@
-use std::io::{BufRead, BufReader, Read, Write};
-use std::net::TcpStream;
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 
+use crate::iolog::{self, IoKind};
+use crate::scpi;
+
+/// Source name in the I/O log.
+const LOG_SOURCE: &str = "dut";
+
+/// ATE daemon port when the address has none.
+pub const DUT_PORT: u16 = 9600;
+
+/// Receive chains of the board, numbered from 0.
+pub const RX_CHAINS: u8 = 2;
+
+/// Bytes copied per read when fetching a file.
+const FILE_CHUNK_BYTES: usize = 64 * 1024;
+
 /// DUT (Device Under Test) client.
 ///
 /// Communicates with the board's ATE daemon over TCP using JSON commands,
@@ -11,38 +30,107 @@ use serde::{Deserialize, Serialize};
 pub struct DutClient {
     stream: TcpStream,
     reader: BufReader<TcpStream>,
+    /// Address the connection was made to.
+    peer: SocketAddr,
+    /// Read/write timeout set at connect.
+    timeout: Duration,
+    /// Read/write timeout of `read_mib`, which can take longer to answer
+    /// than a command.
+    mib_timeout: Duration,
+    /// Timeout currently set on the socket.
+    applied_timeout: Duration,
+    /// Operation in progress, named in timeout errors.
+    op: &'static str,
+    /// The connection was lost, or a reply timed out and may still arrive
+    /// to be mistaken for the next one. Cleared by `reconnect`.
+    disconnected: bool,
+    /// Radio interface the RX and MIB commands address.
+    interface: WlanInterface,
+    /// The board receives 320 MHz channels (Wi-Fi 7).
+    supports_320: bool,
+    /// ATEInit was accepted, so a reconnect sends it again.
+    initialized: bool,
+    /// How `clear_mib` zeroes the receive counters.
+    mib_reset: MibReset,
+    /// Counters read by `clear_mib`, subtracted from the next `query_mib`.
+    mib_baseline: Option<MibResult>,
+    /// When the last command was sent.
+    last_sent: Instant,
+    /// Time `retry_if` spent waiting and trying again, since the last
+    /// `take_retry_time`.
+    retry_time: Duration,
+}
+
+/// Radio interface of the board to receive on.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WlanInterface {
+    /// Chosen from the carrier frequency: wlan0 for 5 and 6 GHz, wlan1
+    /// for 2.4 GHz.
+    #[default]
+    Auto,
+    Wlan0,
+    Wlan1,
+}
+
+impl WlanInterface {
+    /// Interface name for a carrier at `cf_mhz`.
+    pub fn name(self, cf_mhz: u32) -> &'static str {
+        match self {
+            Self::Wlan0 => "wlan0",
+            Self::Wlan1 => "wlan1",
+            // 6 GHz channels (from 5955 MHz) are received by the 5 GHz radio
+            Self::Auto if cf_mhz >= 5000 => "wlan0",
+            Self::Auto => "wlan1",
+        }
+    }
 }
 
 #[derive(Serialize)]
 enum DutCommand {
+    /// Sent as the bare string `"ATEInit"`.
+    ATEInit,
     ATECmd { cmd: String, args: Vec<String> },
     ReadMib(String),
+    /// Send the contents of a file on the board.
+    GetFile(String),
+    /// Stream the kernel log, one line at a time, until the connection
+    /// closes.
+    FollowLog,
+}
+
+/// Keep-alive request: an `echo` through the daemon's `ate_cmd`, which
+/// leaves the radio alone.
+fn ping_command() -> DutCommand {
+    DutCommand::ATECmd {
+        cmd: "ate_cmd".into(),
+        args: vec!["echo".into(), "ping".into()],
+    }
 }
 
 #[derive(Deserialize)]
 struct ResponseHeader {
     is_error: bool,
-    #[allow(dead_code)]
     file_size: u64,
 }
 
 impl DutClient {
-    /// Connect to the DUT board at `ip` on port 9600.
-    /// Sends ATEInit after connection.
-    pub fn connect(ip: &str, timeout_secs: u64) -> Result<Self, String> {
-        let addr = format!("{}:9600", ip);
-        let socket_addr: std::net::SocketAddr = addr
-            .parse()
-            .map_err(|e| format!("Invalid DUT address '{}': {}", addr, e))?;
+    /// Connect to the DUT board at `host[:port]`, on `DUT_PORT` when no
+    /// port is given. `timeout_secs` limits the connect and each command.
+    pub fn connect(address: &str, timeout_secs: u64) -> Result<Self, String> {
+        let (host, port) = scpi::split_host_port(address.trim()).map_err(|e| format!("DUT: {}", e))?;
+        let (stream, peer) = scpi::connect_tcp(&host, port.unwrap_or(DUT_PORT), Duration::from_secs(timeout_secs))
+            .map_err(|e| format!("DUT: {}", e))?;
 
-        let stream = TcpStream::connect_timeout(&socket_addr, Duration::from_secs(timeout_secs))
-            .map_err(|e| format!("DUT connection to {} failed: {}", addr, e))?;
+        Self::with_stream(stream, peer, Duration::from_secs(timeout_secs))
+    }
 
+    fn with_stream(stream: TcpStream, peer: SocketAddr, timeout: Duration) -> Result<Self, String> {
         stream
-            .set_read_timeout(Some(Duration::from_secs(timeout_secs)))
+            .set_read_timeout(Some(timeout))
             .map_err(|e| format!("DUT set read timeout failed: {}", e))?;
         stream
-            .set_write_timeout(Some(Duration::from_secs(timeout_secs)))
+            .set_write_timeout(Some(timeout))
             .map_err(|e| format!("DUT set write timeout failed: {}", e))?;
 
         let reader = BufReader::new(
@@ -51,104 +139,477 @@ impl DutClient {
                 .map_err(|e| format!("DUT clone stream failed: {}", e))?,
         );
 
-        let client = Self { stream, reader };
-        // client.ate_init()?;
-        Ok(client)
+        Ok(Self {
+            stream,
+            reader,
+            peer,
+            timeout,
+            mib_timeout: timeout,
+            applied_timeout: timeout,
+            op: "command",
+            disconnected: false,
+            interface: WlanInterface::Auto,
+            supports_320: false,
+            initialized: false,
+            mib_reset: MibReset::Command,
+            mib_baseline: None,
+            last_sent: Instant::now(),
+            retry_time: Duration::ZERO,
+        })
+    }
+
+    /// Open a new connection to the same board, keeping the timeouts, and
+    /// send ATEInit again if it was sent before.
+    pub fn reconnect(&mut self) -> Result<(), String> {
+        let _ = self.stream.shutdown(Shutdown::Both);
+        let stream = TcpStream::connect_timeout(&self.peer, self.timeout).map_err(|e| {
+            iolog::error(LOG_SOURCE, format!("DUT reconnect to {} failed: {}", self.peer, e))
+        })?;
+        let fresh = Self::with_stream(stream, self.peer, self.timeout)?;
+        let old = std::mem::replace(self, fresh);
+        self.mib_timeout = old.mib_timeout;
+        self.interface = old.interface;
+        self.supports_320 = old.supports_320;
+        self.mib_reset = old.mib_reset;
+        self.mib_baseline = old.mib_baseline;
+        if old.initialized {
+            self.ate_init()?;
+        }
+        Ok(())
+    }
+
+    /// Run `f`, trying again up to `retries` times after `delay` when it
+    /// fails and reconnecting first when the connection is unusable. Each
+    /// retry is counted in `retried`.
+    pub fn retry<T>(
+        &mut self,
+        retries: u32,
+        delay: Duration,
+        retried: &mut u32,
+        f: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        self.retry_if(retries, delay, retried, |_| true, f)
+    }
+
+    /// `retry`, giving up at once on an error `retryable` refuses.
+    pub fn retry_if<T>(
+        &mut self,
+        retries: u32,
+        delay: Duration,
+        retried: &mut u32,
+        retryable: impl Fn(&str) -> bool,
+        mut f: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut attempt = 0;
+        let mut retry_start = None;
+        let outcome = loop {
+            let result = if self.disconnected {
+                self.reconnect().and_then(|()| f(self))
+            } else {
+                f(self)
+            };
+            match result {
+                Ok(value) => break Ok(value),
+                Err(e) if attempt == retries || !retryable(&e) => {
+                    break Err(match attempt {
+                        0 => e,
+                        _ => format!("{} (gave up after {} retries)", e, attempt),
+                    })
+                }
+                Err(_) => {
+                    attempt += 1;
+                    *retried += 1;
+                    retry_start.get_or_insert_with(Instant::now);
+                    std::thread::sleep(delay);
+                }
+            }
+        };
+        if let Some(start) = retry_start {
+            self.retry_time += start.elapsed();
+        }
+        outcome
+    }
+
+    /// Time spent retrying commands since the last call.
+    pub fn take_retry_time(&mut self) -> Duration {
+        std::mem::take(&mut self.retry_time)
+    }
+
+    /// Initialize the ATE daemon, which some boards need before the first
+    /// `open_rx`. Initializing again is harmless; a board that refuses it
+    /// answers with an error the caller may treat as a warning.
+    pub fn ate_init(&mut self) -> Result<(), String> {
+        self.operation("ate_init", self.timeout, |dut| {
+            dut.send_cmd(DutCommand::ATEInit)?;
+            dut.read_resp()
+        })?;
+        self.initialized = true;
+        Ok(())
+    }
+
+    /// Resolved address of the board.
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Address `interface` in the RX and MIB commands instead of choosing it
+    /// from the frequency.
+    pub fn set_interface(&mut self, interface: WlanInterface) {
+        self.interface = interface;
+    }
+
+    /// Allow 320 MHz in `open_rx`, for boards that support it.
+    pub fn set_supports_320(&mut self, supports_320: bool) {
+        self.supports_320 = supports_320;
+    }
+
+    /// Fail unless the board can receive `bw_mhz`.
+    pub fn check_bandwidth(&self, bw_mhz: u32) -> Result<(), String> {
+        bw_code(bw_mhz, self.supports_320).map(|_| ())
+    }
+
+    /// Time since the last command was sent.
+    pub fn idle_for(&self) -> Duration {
+        self.last_sent.elapsed()
+    }
+
+    /// Whether a keep-alive ping is due: the connection is up and has been
+    /// idle for at least `idle`. A dropped connection waits for
+    /// `ensure_connected`.
+    pub fn keepalive_due(&self, idle: Duration) -> bool {
+        !self.disconnected && self.idle_for() >= idle
+    }
+
+    /// Reconnect if an earlier failure dropped the connection.
+    pub fn ensure_connected(&mut self) -> Result<(), String> {
+        if self.disconnected {
+            self.reconnect()?;
+        }
+        Ok(())
+    }
+
+    /// Give `read_mib` its own read/write timeout.
+    pub fn set_mib_timeout(&mut self, timeout: Duration) {
+        self.mib_timeout = timeout;
+    }
+
+    /// Start following the board's kernel log on a second connection, so
+    /// its lines never mix with command responses on this one.
+    pub fn follow_log(&self) -> Result<(DutLog, DutLogStop), String> {
+        let mut stream = TcpStream::connect_timeout(&self.peer, self.timeout).map_err(|e| {
+            iolog::error(LOG_SOURCE, format!("DUT log connection to {} failed: {}", self.peer, e))
+        })?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .and_then(|()| stream.set_write_timeout(Some(self.timeout)))
+            .map_err(|e| format!("DUT set log timeout failed: {}", e))?;
+        let json = serde_json::to_string(&DutCommand::FollowLog).map_err(|e| format!("DUT serialize failed: {}", e))?;
+        iolog::record(LOG_SOURCE, IoKind::Write, || json.clone());
+        stream
+            .write_all(format!("{}\n", json).as_bytes())
+            .map_err(|e| iolog::error(LOG_SOURCE, format!("DUT log request failed: {}", e)))?;
+
+        let (mut log, stop) = DutLog::new(stream)?;
+        let line = log.next_line()?.ok_or("DUT closed the log connection")?;
+        let header: ResponseHeader = serde_json::from_str(&line)
+            .map_err(|e| iolog::error(LOG_SOURCE, format!("DUT response parse failed: {}", e)))?;
+        if header.is_error {
+            return Err(iolog::error(LOG_SOURCE, "DUT refused to stream its log".into()));
+        }
+        // Lines arrive whenever the kernel logs something
+        log.reader
+            .get_ref()
+            .set_read_timeout(None)
+            .map_err(|e| format!("DUT set log timeout failed: {}", e))?;
+        Ok((log, stop))
+    }
+
+    /// Check that the ATE daemon still answers, using a command with no
+    /// effect on the radio and `scpi::PING_TIMEOUT`.
+    pub fn ping(&mut self) -> Result<(), String> {
+        self.operation("ping", scpi::PING_TIMEOUT, |dut| {
+            dut.send_cmd(ping_command())?;
+            dut.read_resp()
+        })
+    }
+
+    /// Run operation `op` with `timeout` for its reads and writes.
+    fn operation<T>(
+        &mut self,
+        op: &'static str,
+        timeout: Duration,
+        f: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        self.op = op;
+        self.set_timeout(timeout)?;
+        f(self)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), String> {
+        if timeout == self.applied_timeout {
+            return Ok(());
+        }
+        self.stream
+            .set_read_timeout(Some(timeout))
+            .map_err(|e| format!("DUT set read timeout failed: {}", e))?;
+        self.stream
+            .set_write_timeout(Some(timeout))
+            .map_err(|e| format!("DUT set write timeout failed: {}", e))?;
+        self.applied_timeout = timeout;
+        Ok(())
+    }
+
+    /// Describe a failed `what` ("write", "read", ...), naming the
+    /// operation and its limit when it timed out. Either way the
+    /// connection is left unusable.
+    fn io_error(&mut self, what: &str, e: io::Error) -> String {
+        self.disconnected = true;
+        let msg = match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => format!(
+                "DUT {} timed out after {:.1} s ({})",
+                self.op,
+                self.applied_timeout.as_secs_f64(),
+                what
+            ),
+            _ => format!("DUT {} failed: {}", what, e),
+        };
+        iolog::error(LOG_SOURCE, msg)
     }
 
     fn send_cmd(&mut self, cmd: DutCommand) -> Result<(), String> {
+        self.last_sent = Instant::now();
         let json = serde_json::to_string(&cmd).map_err(|e| format!("DUT serialize failed: {}", e))?;
+        iolog::record(LOG_SOURCE, IoKind::Write, || json.clone());
         self.stream
             .write_all(json.as_bytes())
-            .map_err(|e| format!("DUT write failed: {}", e))?;
+            .map_err(|e| self.io_error("write", e))?;
         self.stream
             .write_all(b"\n")
-            .map_err(|e| format!("DUT write newline failed: {}", e))?;
-        self.stream
-            .flush()
-            .map_err(|e| format!("DUT flush failed: {}", e))
+            .map_err(|e| self.io_error("write newline", e))?;
+        self.stream.flush().map_err(|e| self.io_error("flush", e))
     }
 
-    fn read_resp(&mut self) -> Result<(), String> {
+    /// Read and parse the JSON header line of a response.
+    fn read_header(&mut self) -> Result<ResponseHeader, String> {
         let mut line = String::new();
-        self.reader
+        let n = self
+            .reader
             .read_line(&mut line)
-            .map_err(|e| format!("DUT read failed: {}", e))?;
-        let resp: ResponseHeader =
-            serde_json::from_str(&line).map_err(|e| format!("DUT response parse failed: {}", e))?;
+            .map_err(|e| self.io_error("read", e))?;
+        if n == 0 {
+            self.disconnected = true;
+            return Err(iolog::error(LOG_SOURCE, "DUT read failed, connection closed by the board".into()));
+        }
+        iolog::record(LOG_SOURCE, IoKind::Read, || line.trim_end().to_string());
+        let resp: ResponseHeader = serde_json::from_str(&line)
+            .map_err(|e| iolog::error(LOG_SOURCE, format!("DUT response parse failed: {}", e)))?;
         if resp.is_error {
-            Err("DUT returned error".into())
+            // The daemon may explain the error in the payload
+            let detail = self.read_payload(resp.file_size, "error")?;
+            Err(iolog::error(LOG_SOURCE, match detail.trim() {
+                "" => "DUT returned error".into(),
+                detail => format!("DUT returned error: {}", detail),
+            }))
         } else {
-            Ok(())
+            Ok(resp)
         }
     }
 
-    /// Read response and return the raw header line (for MIB parsing).
-    fn read_resp_raw(&mut self) -> Result<String, String> {
-        let mut line = String::new();
+    /// Read a `size` byte payload as text, labelled `what` in the I/O log.
+    fn read_payload(&mut self, size: u64, what: &str) -> Result<String, String> {
+        if size == 0 {
+            return Ok(String::new());
+        }
+        let mut text = vec![0u8; size as usize];
         self.reader
-            .read_line(&mut line)
-            .map_err(|e| format!("DUT read failed: {}", e))?;
-        let resp: ResponseHeader =
-            serde_json::from_str(&line).map_err(|e| format!("DUT response parse failed: {}", e))?;
-        if resp.is_error {
-            Err("DUT returned error".into())
-        } else {
-            let size = resp.file_size as usize;
-            let mut text = vec![0u8;size];
-            self.reader.read_exact(&mut text)
-                .map_err(|e| format!("Can not extract string from dut mib:{e}"))?;
-            String::from_utf8_lossy(&text)
-                .parse()
-                .map_err(|e| format!("Can not parse mib text to string:{e}"))
+            .read_exact(&mut text)
+            .map_err(|e| self.io_error("read payload", e))?;
+        iolog::record_block(LOG_SOURCE, what, &text);
+        Ok(String::from_utf8_lossy(&text).into_owned())
+    }
+
+    fn read_resp(&mut self) -> Result<(), String> {
+        self.read_header().map(|_| ())
+    }
+
+    /// Read a response and return its `file_size` byte payload, which is
+    /// empty for responses without one.
+    fn read_resp_raw(&mut self) -> Result<String, String> {
+        let resp = self.read_header()?;
+        self.read_payload(resp.file_size, self.op)
+    }
+
+    /// Run any ATE command, e.g. a register peek, and return its output.
+    pub fn raw_command(&mut self, args: Vec<String>) -> Result<String, String> {
+        if args.iter().all(|a| a.is_empty()) {
+            return Err("Empty DUT command".into());
         }
+        let cmd = DutCommand::ATECmd {
+            cmd: "ate_cmd".into(),
+            args,
+        };
+        self.operation("command", self.timeout, |dut| {
+            dut.send_cmd(cmd)?;
+            dut.read_resp_raw()
+        })
+    }
+
+    /// Copy `remote_path` from the board to `local_path`, calling `progress`
+    /// with (bytes received, file size). The file is streamed to disk; if
+    /// it arrives incomplete the partial copy is deleted. Returns the size.
+    pub fn fetch_file(
+        &mut self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: &dyn Fn(u64, u64),
+    ) -> Result<u64, String> {
+        self.operation("fetch_file", self.timeout, |dut| {
+            dut.send_cmd(DutCommand::GetFile(remote_path.into()))?;
+            let size = dut.read_header()?.file_size;
+            save_payload(&mut dut.reader, size, local_path, progress).map_err(|(received, e)| {
+                let _ = std::fs::remove_file(local_path);
+                // Unless it was drained, the rest of the file may still be
+                // on its way
+                if !matches!(e, TransferError::Write { drained: true, .. }) {
+                    dut.disconnected = true;
+                }
+                let cause = match e {
+                    TransferError::Read(Some(e)) => dut.io_error("read file", e),
+                    TransferError::Read(None) => "connection closed".into(),
+                    TransferError::Write { error, .. } => format!("writing {} failed: {}", local_path.display(), error),
+                };
+                iolog::error(
+                    LOG_SOURCE,
+                    format!("DUT file {} incomplete, {} of {} bytes received: {}", remote_path, received, size, cause),
+                )
+            })
+        })
     }
 
     /// Open RX on the DUT.
     ///
     /// - `cf_mhz`: carrier frequency in MHz (e.g. 2412, 5180)
-    /// - `bw_mhz`: bandwidth in MHz (e.g. 20, 40, 80)
-    pub fn open_rx(&mut self, cf_mhz: u32, bw_mhz: u32) -> Result<(), String> {
-        let iface = if cf_mhz >= 5000 { "wlan0" } else { "wlan1" };
-        let bw_code = match bw_mhz {
-            40 => 2,
-            80 => 3,
-            160 => 4,
-            _ => 1, // 20 MHz or default
-        };
-        let arg_str = format!(
-            "{} fastconfig -f {} -c {} -w {} -u {} -r",
-            iface, cf_mhz, cf_mhz, bw_code, bw_code
-        );
-        let args: Vec<String> = arg_str.split(' ').map(|s| s.to_string()).collect();
+    /// - `bw_mhz`: bandwidth in MHz: 20, 40, 80, 160, or 320 on boards
+    ///   that support it
+    /// - `chain`: receive chain (antenna) to use, or the firmware default
+    pub fn open_rx(&mut self, cf_mhz: u32, bw_mhz: u32, chain: Option<u8>) -> Result<(), String> {
+        let iface = self.interface.name(cf_mhz);
         let cmd = DutCommand::ATECmd {
             cmd: "ate_cmd".into(),
-            args,
+            args: open_rx_args(iface, cf_mhz, bw_code(bw_mhz, self.supports_320)?, chain)?,
         };
-        self.send_cmd(cmd)?;
-        self.read_resp()
+        self.operation("open_rx", self.timeout, |dut| {
+            dut.send_cmd(cmd)?;
+            dut.read_resp()
+        })
     }
 
     /// Close RX on the DUT.
     ///
     /// - `cf_mhz`: carrier frequency in MHz, used to determine the interface
     pub fn close_rx(&mut self, cf_mhz: u32) -> Result<(), String> {
-        let iface = if cf_mhz >= 5000 { "wlan0" } else { "wlan1" };
+        let iface = self.interface.name(cf_mhz);
         let arg_str = format!("{} fastconfig -k", iface);
         let args: Vec<String> = arg_str.split(' ').map(|s| s.to_string()).collect();
         let cmd = DutCommand::ATECmd {
             cmd: "ate_cmd".into(),
             args,
         };
-        self.send_cmd(cmd)?;
-        self.read_resp()
+        self.operation("close_rx", self.timeout, |dut| {
+            dut.send_cmd(cmd)?;
+            dut.read_resp()
+        })
+    }
+
+    /// Reset the receive counters, for firmware that does not clear them
+    /// on `fastconfig -k`. Returns false when the firmware rejects the
+    /// reset option: it is not sent again, and the counters are read
+    /// instead, either to clear them or as a baseline the next
+    /// `query_mib` subtracts.
+    pub fn clear_mib(&mut self, cf_mhz: u32, bw_mhz: u32) -> Result<bool, String> {
+        self.mib_baseline = None;
+        if self.mib_reset == MibReset::Command {
+            if self.send_clear_mib(cf_mhz)? {
+                return Ok(true);
+            }
+            self.mib_reset = MibReset::Unknown;
+        }
+        let counters = self.read_counters(cf_mhz, bw_mhz)?;
+        match self.mib_reset {
+            MibReset::Unknown => {
+                // A second read tells counters cleared by reading from
+                // ones that only grow
+                let again = self.read_counters(cf_mhz, bw_mhz)?;
+                if let Some(mode) = MibReset::detect(&counters, &again) {
+                    self.mib_reset = mode;
+                }
+                if self.mib_reset == MibReset::Baseline {
+                    self.mib_baseline = Some(again);
+                }
+            }
+            MibReset::Baseline => self.mib_baseline = Some(counters),
+            MibReset::Command | MibReset::Read => {}
+        }
+        Ok(false)
+    }
+
+    /// Send `fastconfig -C`; false when the firmware rejects the option.
+    fn send_clear_mib(&mut self, cf_mhz: u32) -> Result<bool, String> {
+        let iface = self.interface.name(cf_mhz);
+        let arg_str = format!("{} fastconfig -C", iface);
+        let args: Vec<String> = arg_str.split(' ').map(|s| s.to_string()).collect();
+        let cmd = DutCommand::ATECmd {
+            cmd: "ate_cmd".into(),
+            args,
+        };
+        let result = self.operation("clear_mib", self.timeout, |dut| {
+            dut.send_cmd(cmd)?;
+            dut.read_resp()
+        });
+        match result {
+            Ok(()) => Ok(true),
+            Err(e) if is_unsupported_option(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
     }
 
     pub fn read_mib(&mut self, cf_mhz: u32) -> Result<String, String> {
-        let iface = if cf_mhz >= 5000 { "wlan0" } else { "wlan1" };
+        let iface = self.interface.name(cf_mhz);
         let cmd = DutCommand::ReadMib (iface.into());
-        self.send_cmd(cmd)?;
-        self.read_resp_raw()
+        self.operation("read_mib", self.mib_timeout, |dut| {
+            dut.send_cmd(cmd)?;
+            dut.read_resp_raw()
+        })
+    }
+
+    /// Read and parse the MIB counters for `bw_mhz`, keeping the raw
+    /// output in the result. Fails if the DUT reports an error or its
+    /// output holds neither counter, so an `Ok` with zero counts means
+    /// nothing was received.
+    ///
+    /// Counts are relative to the baseline `clear_mib` took, if any.
+    pub fn query_mib(&mut self, cf_mhz: u32, bw_mhz: u32) -> Result<MibResult, String> {
+        let mib = self.read_counters(cf_mhz, bw_mhz)?;
+        Ok(match self.mib_baseline.take() {
+            Some(baseline) => mib.since(&baseline),
+            None => mib,
+        })
+    }
+
+    /// Read and parse the MIB counters as the board reports them.
+    fn read_counters(&mut self, cf_mhz: u32, bw_mhz: u32) -> Result<MibResult, String> {
+        let raw = self.read_mib(cf_mhz)?;
+        Self::mib_from_output(raw, bw_mhz).map_err(|e| iolog::error(LOG_SOURCE, e))
+    }
+
+    /// Parse `raw`, failing when it holds neither counter.
+    fn mib_from_output(raw: String, bw_mhz: u32) -> Result<MibResult, String> {
+        let mut mib = Self::parse_mib_resp(&raw, bw_mhz);
+        if mib.rec_rx_count.is_none() && mib.rx_ok_count.is_none() {
+            return Err(format!("DUT MIB output has no RX counters: {:?}", raw.trim()));
+        }
+        mib.raw = Some(raw);
+        Ok(mib)
     }
 
     /// MIB result extracted from `fastconfig -R` output.
@@ -158,6 +619,7 @@ impl DutClient {
     /// [ 5360.257334] [***debug***] user->rec_rx_count = 1000
     /// ...
     /// receive 20M OK = 0, receive 40M OK = 1000, receive 80M OK = 0, receive 160M OK = 0
+    /// MCS0 OK = 0, MCS1 OK = 0, ..., MCS11 OK = 1000
     /// ```
     pub fn parse_mib_resp(output: &str, bw_mhz: u32) -> MibResult {
         // Extract rec_rx_count: match "user->rec_rx_count = <number>"
@@ -169,40 +631,303 @@ impl DutClient {
                 after_eq.trim().parse::<u32>().ok()
             });
 
-        // Extract per-BW OK count from "receive <BW>M OK = <number>"
-        // Build the key for the target bandwidth, e.g. "receive 20M OK"
-        let bw_key = format!("receive {}M OK", bw_mhz);
-        let rx_ok_count = output
-            .lines()
-            .find_map(|line| {
-                let idx = line.find(&bw_key)?;
-                // From the key position, find the '=' and parse the number after it
-                let after_key = &line[idx + bw_key.len()..];
-                let after_eq = after_key.split('=').nth(1)?;
-                // Take only digits (stop at ',' or end of string)
-                let num_str = after_eq.trim().split(',').next()?.trim();
-                num_str.parse::<u32>().ok()
-            });
+        // OK counts from "receive <BW>M OK = <number>, ..." and, on newer
+        // firmware, "MCS<n> OK = <number>, ..."
+        let ok_by_bw = mib_counts(output, "receive ", "M OK");
+        let ok_by_mcs = mib_counts(output, "MCS", " OK");
 
         MibResult {
             rec_rx_count,
-            rx_ok_count,
+            rx_ok_count: ok_by_bw.get(&bw_mhz).copied(),
+            ok_by_bw,
+            ok_by_mcs: (!ok_by_mcs.is_empty()).then_some(ok_by_mcs),
+            rssi1: mib_field(output, &["rssi1", "rssi_1"]),
+            rssi2: mib_field(output, &["rssi2", "rssi_2"]),
+            fcs_err: mib_field(output, &["user->fcs_err"]),
+            phy_err: mib_field(output, &["user->phy_err"]),
+            raw: None,
         }
     }
 }
 
+/// `fastconfig` code of a receive bandwidth, failing for one the board
+/// cannot receive.
+pub fn bw_code(bw_mhz: u32, supports_320: bool) -> Result<u32, String> {
+    match bw_mhz {
+        20 => Ok(1),
+        40 => Ok(2),
+        80 => Ok(3),
+        160 => Ok(4),
+        320 if supports_320 => Ok(5),
+        320 => Err("DUT does not support 320 MHz".into()),
+        _ => Err(format!(
+            "Unsupported DUT bandwidth {} MHz (20, 40, 80, 160{})",
+            bw_mhz,
+            if supports_320 { " or 320" } else { "" }
+        )),
+    }
+}
+
+/// Fail unless `chain` is a receive chain of the board.
+pub fn check_chain(chain: u8) -> Result<(), String> {
+    if chain < RX_CHAINS {
+        Ok(())
+    } else {
+        Err(format!("Invalid DUT RX chain {} (0 to {})", chain, RX_CHAINS - 1))
+    }
+}
+
+/// Arguments of the `fastconfig` call opening RX on `iface` with
+/// bandwidth code `bw_code`, on `chain` (`-a`) when given.
+fn open_rx_args(iface: &str, cf_mhz: u32, bw_code: u32, chain: Option<u8>) -> Result<Vec<String>, String> {
+    let mut arg_str = format!(
+        "{} fastconfig -f {} -c {} -w {} -u {} -r",
+        iface, cf_mhz, cf_mhz, bw_code, bw_code
+    );
+    if let Some(chain) = chain {
+        check_chain(chain)?;
+        arg_str += &format!(" -a {}", chain);
+    }
+    Ok(arg_str.split(' ').map(|s| s.to_string()).collect())
+}
+
+/// Whether `error` is a command that timed out, which the board may still
+/// have carried out.
+pub fn is_timeout(error: &str) -> bool {
+    error.starts_with("DUT ") && !error.starts_with("DUT returned error") && error.contains(" timed out after ")
+}
+
+/// Whether `error` is the daemon rejecting an option the firmware's
+/// `fastconfig` does not know.
+fn is_unsupported_option(error: &str) -> bool {
+    let error = error.to_ascii_lowercase();
+    error.starts_with("dut returned error:")
+        && ["invalid option", "unknown option", "unrecognized option", "illegal option"]
+            .iter()
+            .any(|rejection| error.contains(rejection))
+}
+
+/// Why a file transfer stopped short.
+enum TransferError {
+    /// Reading failed, or the connection closed (`None`).
+    Read(Option<io::Error>),
+    /// The local file could not be written. `drained` tells whether the
+    /// rest of the payload was then read and discarded, leaving the
+    /// connection in step.
+    Write { error: io::Error, drained: bool },
+}
+
+/// Copy `size` bytes from `reader` to a new file at `path` in chunks. On
+/// failure also returns the bytes received. When the file cannot be
+/// written, the rest of the payload is still read and discarded.
+fn save_payload(
+    reader: &mut impl Read,
+    size: u64,
+    path: &Path,
+    progress: &dyn Fn(u64, u64),
+) -> Result<u64, (u64, TransferError)> {
+    let write_failed = |reader: &mut dyn Read, received: u64, error: io::Error| {
+        let rest = size - received;
+        let drained = io::copy(&mut reader.take(rest), &mut io::sink()).is_ok_and(|n| n == rest);
+        (received, TransferError::Write { error, drained })
+    };
+    let mut file = match File::create(path) {
+        Ok(file) => file,
+        Err(e) => return Err(write_failed(reader, 0, e)),
+    };
+    let mut buf = vec![0u8; FILE_CHUNK_BYTES];
+    let mut received = 0;
+    while received < size {
+        let want = (size - received).min(buf.len() as u64) as usize;
+        match reader.read(&mut buf[..want]) {
+            Ok(0) => return Err((received, TransferError::Read(None))),
+            Ok(n) => {
+                if let Err(e) = file.write_all(&buf[..n]) {
+                    return Err(write_failed(reader, received + n as u64, e));
+                }
+                received += n as u64;
+                progress(received, size);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err((received, TransferError::Read(Some(e)))),
+        }
+    }
+    file.flush().map_err(|error| (received, TransferError::Write { error, drained: true }))?;
+    Ok(received)
+}
+
+/// Counts of `<prefix><n><suffix> = <count>` entries in `output` by `n`,
+/// e.g. `receive 40M OK = 1000` with prefix "receive " and suffix "M OK".
+/// The first entry for each `n` wins.
+fn mib_counts(output: &str, prefix: &str, suffix: &str) -> BTreeMap<u32, u32> {
+    let mut counts = BTreeMap::new();
+    for line in output.lines() {
+        let mut rest = line;
+        while let Some(idx) = rest.find(prefix) {
+            rest = &rest[idx + prefix.len()..];
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let Ok(n) = rest[..digits].parse() else { continue };
+            let Some(value) = rest[digits..]
+                .strip_prefix(suffix)
+                .and_then(|after| after.trim_start().strip_prefix('='))
+            else {
+                continue;
+            };
+            let value = value.trim_start();
+            let end = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+            if let Ok(count) = value[..end].parse() {
+                counts.entry(n).or_insert(count);
+            }
+        }
+    }
+    counts
+}
+
+/// First `<key> = <value>` in `output` for any of `keys`. The value ends
+/// at whitespace or a comma, which the driver sometimes prints full-width
+/// ("rssi_1 = -76， rssi_2 = -77").
+fn mib_field<T: FromStr>(output: &str, keys: &[&str]) -> Option<T> {
+    output.lines().find_map(|line| {
+        keys.iter().find_map(|key| {
+            let idx = line.find(key)?;
+            let after_eq = line[idx + key.len()..].trim_start().strip_prefix('=')?;
+            after_eq
+                .split(|c: char| c == ',' || c == '，' || c.is_whitespace())
+                .find(|v| !v.is_empty())?
+                .parse()
+                .ok()
+        })
+    })
+}
+
+/// The board's kernel log, as streamed by `FollowLog`.
+pub struct DutLog {
+    reader: BufReader<TcpStream>,
+}
+
+/// Ends a `DutLog` from another thread.
+pub struct DutLogStop(TcpStream);
+
+impl DutLog {
+    fn new(stream: TcpStream) -> Result<(Self, DutLogStop), String> {
+        let stop = stream
+            .try_clone()
+            .map_err(|e| format!("DUT clone stream failed: {}", e))?;
+        Ok((Self { reader: BufReader::new(stream) }, DutLogStop(stop)))
+    }
+
+    /// Next log line without its line end; `None` once the board closes
+    /// the connection or `DutLogStop::stop` is called.
+    pub fn next_line(&mut self) -> Result<Option<String>, String> {
+        let mut line = Vec::new();
+        match self.reader.read_until(b'\n', &mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string();
+                Ok(Some(line))
+            }
+            Err(e) => Err(iolog::error(LOG_SOURCE, format!("DUT log read failed: {}", e))),
+        }
+    }
+}
+
+impl DutLogStop {
+    /// Close the log connection, ending a blocked `next_line`.
+    pub fn stop(&self) {
+        let _ = self.0.shutdown(Shutdown::Both);
+    }
+}
+
 /// Parsed MIB statistics from DUT `fastconfig -R` output.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct MibResult {
     /// Total received packet count (`user->rec_rx_count`).
     pub rec_rx_count: Option<u32>,
     /// Decoded OK count for the matching bandwidth (`receive <BW>M OK`).
     pub rx_ok_count: Option<u32>,
+    /// Decoded OK count of every bandwidth in the output, by MHz.
+    pub ok_by_bw: BTreeMap<u32, u32>,
+    /// Decoded OK count by MCS index (`MCS<n> OK`), printed by newer
+    /// firmware only.
+    pub ok_by_mcs: Option<BTreeMap<u32, u32>>,
+    /// RSSI of each receive chain in dBm (`rssi1`/`rssi_1`, `rssi2`/`rssi_2`).
+    pub rssi1: Option<i32>,
+    pub rssi2: Option<i32>,
+    /// Frames dropped for a bad FCS (`user->fcs_err`).
+    pub fcs_err: Option<u32>,
+    /// PHY receive errors (`user->phy_err`).
+    pub phy_err: Option<u32>,
+    /// Output the counters were parsed from, for debugging.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+/// How the MIB counters are zeroed before a sweep step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MibReset {
+    /// `fastconfig -C`, until the firmware rejects it.
+    Command,
+    /// The option was rejected and it is not yet known how the counters
+    /// behave when read.
+    Unknown,
+    /// Reading the counters clears them.
+    Read,
+    /// The counters only grow; a reading is subtracted from the next.
+    Baseline,
+}
+
+impl MibReset {
+    /// Tell from two back-to-back reads whether reading cleared the
+    /// counters; `None` while both were zero.
+    fn detect(first: &MibResult, second: &MibResult) -> Option<Self> {
+        let count = |mib: &MibResult| mib.rec_rx_count.or(mib.rx_ok_count).unwrap_or(0);
+        match (count(first), count(second)) {
+            (0, _) => None,
+            (a, b) if b < a => Some(Self::Read),
+            _ => Some(Self::Baseline),
+        }
+    }
+}
+
+impl MibResult {
+    /// Counts accumulated since `baseline`; RSSI and raw output are this
+    /// reading's.
+    fn since(mut self, baseline: &MibResult) -> MibResult {
+        fn sub(now: &mut Option<u32>, base: Option<u32>) {
+            if let (Some(n), Some(b)) = (now.as_mut(), base) {
+                *n = n.saturating_sub(b);
+            }
+        }
+        fn sub_map(now: &mut BTreeMap<u32, u32>, base: &BTreeMap<u32, u32>) {
+            for (key, count) in now.iter_mut() {
+                *count = count.saturating_sub(base.get(key).copied().unwrap_or(0));
+            }
+        }
+        sub(&mut self.rec_rx_count, baseline.rec_rx_count);
+        sub(&mut self.rx_ok_count, baseline.rx_ok_count);
+        sub(&mut self.fcs_err, baseline.fcs_err);
+        sub(&mut self.phy_err, baseline.phy_err);
+        sub_map(&mut self.ok_by_bw, &baseline.ok_by_bw);
+        if let (Some(now), Some(base)) = (self.ok_by_mcs.as_mut(), baseline.ok_by_mcs.as_ref()) {
+            sub_map(now, base);
+        }
+        self
+    }
+
+    /// Frames decoded OK at any bandwidth other than `bw_mhz`.
+    pub fn ok_at_other_bw(&self, bw_mhz: u32) -> u32 {
+        self.ok_by_bw
+            .iter()
+            .filter(|(&bw, _)| bw != bw_mhz)
+            .map(|(_, &count)| count)
+            .sum()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::net::TcpListener;
 
     const SAMPLE_MIB: &str = r#"
 [ 5360.255098] [***debug***] v_mib_state = 0x0  user->mib = 0
@@ -242,9 +967,313 @@ rssi_1 = -76， rssi_2 = -77
         assert_eq!(result.rx_ok_count, Some(0));
     }
 
+    #[test]
+    fn parse_rssi_and_errors() {
+        let result = DutClient::parse_mib_resp(SAMPLE_MIB, 40);
+        assert_eq!((result.rssi1, result.rssi2), (Some(-76), Some(-77)));
+        assert_eq!((result.fcs_err, result.phy_err), (Some(0), Some(0)));
+    }
+
+    #[test]
+    fn parse_rssi_full_width_comma() {
+        let result = DutClient::parse_mib_resp("rssi_1 = -61， rssi_2 = -64\nuser->fcs_err = 12\n", 20);
+        assert_eq!((result.rssi1, result.rssi2), (Some(-61), Some(-64)));
+        assert_eq!((result.fcs_err, result.phy_err), (Some(12), None));
+    }
+
+    const SAMPLE_MIB_MCS: &str = r#"
+[ 7120.004211] [***debug***] user->rec_rx_count = 1000
+[ 7120.004388] [***debug***] user->fcs_err = 3
+receive 20M OK = 0, receive 40M OK = 0, receive 80M OK = 997, receive 160M OK = 0, receive 320M OK = 0
+MCS0 OK = 0, MCS1 OK = 0, MCS2 OK = 0, MCS3 OK = 0, MCS4 OK = 0, MCS5 OK = 0
+MCS6 OK = 0, MCS7 OK = 12, MCS8 OK = 0, MCS9 OK = 985, MCS10 OK = 0, MCS11 OK = 0
+"#;
+
+    #[test]
+    fn parse_ok_by_bw() {
+        let result = DutClient::parse_mib_resp(SAMPLE_MIB, 40);
+        let expected = BTreeMap::from([(20, 0), (40, 1000), (80, 0), (160, 0)]);
+        assert_eq!(result.ok_by_bw, expected);
+        assert_eq!(result.ok_by_mcs, None);
+        assert_eq!(result.ok_at_other_bw(40), 0);
+        assert_eq!(result.ok_at_other_bw(20), 1000);
+    }
+
+    #[test]
+    fn parse_ok_by_mcs() {
+        let result = DutClient::parse_mib_resp(SAMPLE_MIB_MCS, 80);
+        assert_eq!(result.rx_ok_count, Some(997));
+        assert_eq!(result.ok_by_bw.len(), 5);
+        assert_eq!(result.ok_by_bw[&320], 0);
+        let mcs = result.ok_by_mcs.unwrap();
+        assert_eq!(mcs.len(), 12);
+        assert_eq!((mcs[&7], mcs[&9], mcs[&11]), (12, 985, 0));
+    }
+
     #[test]
     fn parse_rx_ok_missing_bw() {
         let result = DutClient::parse_mib_resp(SAMPLE_MIB, 10);
         assert_eq!(result.rx_ok_count, None);
     }
+
+    #[test]
+    fn connects_to_address_with_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let dut = DutClient::connect(&format!("127.0.0.1:{}", port), 2).unwrap();
+        assert_eq!(dut.peer(), listener.local_addr().unwrap());
+        assert!(matches!(DutClient::connect("127.0.0.1:ate", 2), Err(e) if e.contains("Invalid port")));
+    }
+
+    #[test]
+    fn mib_timeout_named_in_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut dut = DutClient::connect(&format!("127.0.0.1:{}", port), 2).unwrap();
+        dut.set_mib_timeout(Duration::from_millis(100));
+        assert_eq!(dut.read_mib(2412).unwrap_err(), "DUT read_mib timed out after 0.1 s (read)");
+        drop(listener);
+    }
+
+    #[test]
+    fn keepalive_waits_for_idle_and_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut dut = DutClient::connect(&format!("127.0.0.1:{}", port), 2).unwrap();
+        assert!(!dut.keepalive_due(Duration::from_secs(60)));
+        assert!(dut.keepalive_due(Duration::ZERO));
+
+        // The ping goes out as an ate_cmd; the board then hangs up
+        let (board, _) = listener.accept().unwrap();
+        let sent = std::thread::spawn(move || {
+            let mut line = String::new();
+            BufReader::new(&board).read_line(&mut line).unwrap();
+            line
+        });
+        assert!(dut.ping().is_err());
+        assert_eq!(sent.join().unwrap(), serde_json::to_string(&ping_command()).unwrap() + "\n");
+        assert!(!dut.keepalive_due(Duration::ZERO));
+
+        dut.ensure_connected().unwrap();
+        assert!(listener.accept().is_ok());
+        assert!(dut.keepalive_due(Duration::ZERO));
+        assert!(!dut.keepalive_due(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn retry_reconnects_a_closed_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut dut = DutClient::connect(&format!("127.0.0.1:{}", port), 2).unwrap();
+        drop(listener.accept().unwrap());
+        assert_eq!(dut.ping().unwrap_err(), "DUT read failed, connection closed by the board");
+        assert!(dut.disconnected);
+
+        let mut retried = 0;
+        let mut attempts = 0;
+        let result = dut.retry(2, Duration::ZERO, &mut retried, |dut| {
+            attempts += 1;
+            match attempts {
+                1 => Err("DUT returned error".to_string()),
+                _ => Ok(dut.peer()),
+            }
+        });
+        assert_eq!(result.unwrap(), listener.local_addr().unwrap());
+        assert_eq!(retried, 1);
+        assert!(!dut.disconnected);
+        // The retry ran on a new connection
+        assert!(listener.accept().is_ok());
+
+        let result: Result<(), String> = dut.retry(2, Duration::ZERO, &mut retried, |_| Err("DUT returned error".into()));
+        assert_eq!(result.unwrap_err(), "DUT returned error (gave up after 2 retries)");
+        assert_eq!(retried, 3);
+    }
+
+    #[test]
+    fn retry_if_stops_on_refused_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut dut = DutClient::connect(&format!("127.0.0.1:{}", port), 2).unwrap();
+        let mut retried = 0;
+        let mut attempts = 0;
+        let result: Result<(), String> = dut.retry_if(2, Duration::ZERO, &mut retried, |e| !is_timeout(e), |_| {
+            attempts += 1;
+            Err("DUT read_mib timed out after 5.0 s (read)".into())
+        });
+        assert_eq!(result.unwrap_err(), "DUT read_mib timed out after 5.0 s (read)");
+        assert_eq!((attempts, retried), (1, 0));
+        assert!(!is_timeout("DUT returned error: timed out after boot"));
+    }
+
+    #[test]
+    fn retry_time_covers_only_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut dut = DutClient::connect(&format!("127.0.0.1:{}", port), 2).unwrap();
+        let mut retried = 0;
+        dut.retry(2, Duration::from_millis(30), &mut retried, |_| Ok(())).unwrap();
+        assert_eq!(dut.take_retry_time(), Duration::ZERO);
+
+        let mut attempts = 0;
+        dut.retry(2, Duration::from_millis(30), &mut retried, |_| {
+            attempts += 1;
+            match attempts {
+                1 => Err("DUT returned error".to_string()),
+                _ => Ok(()),
+            }
+        })
+        .unwrap();
+        assert!(dut.take_retry_time() >= Duration::from_millis(30));
+        assert_eq!(dut.take_retry_time(), Duration::ZERO);
+    }
+
+    #[test]
+    fn bandwidth_codes() {
+        for (bw_mhz, code) in [(20, 1), (40, 2), (80, 3), (160, 4)] {
+            assert_eq!(bw_code(bw_mhz, false), Ok(code));
+            assert_eq!(bw_code(bw_mhz, true), Ok(code));
+        }
+        assert_eq!(bw_code(320, true), Ok(5));
+        assert_eq!(bw_code(320, false).unwrap_err(), "DUT does not support 320 MHz");
+        assert_eq!(bw_code(161, false).unwrap_err(), "Unsupported DUT bandwidth 161 MHz (20, 40, 80, 160)");
+        assert_eq!(bw_code(0, true).unwrap_err(), "Unsupported DUT bandwidth 0 MHz (20, 40, 80, 160 or 320)");
+        assert_eq!(
+            open_rx_args("wlan0", 6105, bw_code(320, true).unwrap(), None).unwrap().join(" "),
+            "wlan0 fastconfig -f 6105 -c 6105 -w 5 -u 5 -r"
+        );
+    }
+
+    #[test]
+    fn chain_selection() {
+        let args = |chain| open_rx_args("wlan1", 2437, 1, chain).map(|args| args.join(" "));
+        assert_eq!(args(None).unwrap(), "wlan1 fastconfig -f 2437 -c 2437 -w 1 -u 1 -r");
+        assert_eq!(args(Some(0)).unwrap(), "wlan1 fastconfig -f 2437 -c 2437 -w 1 -u 1 -r -a 0");
+        assert_eq!(args(Some(1)).unwrap(), "wlan1 fastconfig -f 2437 -c 2437 -w 1 -u 1 -r -a 1");
+        assert_eq!(args(Some(2)).unwrap_err(), "Invalid DUT RX chain 2 (0 to 1)");
+    }
+
+    #[test]
+    fn interface_overrides() {
+        let args = |interface: WlanInterface, cf_mhz| {
+            open_rx_args(interface.name(cf_mhz), cf_mhz, 2, None).unwrap().join(" ")
+        };
+        assert_eq!(args(WlanInterface::Auto, 2412), "wlan1 fastconfig -f 2412 -c 2412 -w 2 -u 2 -r");
+        assert_eq!(args(WlanInterface::Auto, 5180), "wlan0 fastconfig -f 5180 -c 5180 -w 2 -u 2 -r");
+        assert_eq!(args(WlanInterface::Auto, 5955), "wlan0 fastconfig -f 5955 -c 5955 -w 2 -u 2 -r");
+        assert_eq!(args(WlanInterface::Auto, 7115), "wlan0 fastconfig -f 7115 -c 7115 -w 2 -u 2 -r");
+        for cf_mhz in [2412, 5180, 6115] {
+            assert!(args(WlanInterface::Wlan0, cf_mhz).starts_with("wlan0 "));
+            assert!(args(WlanInterface::Wlan1, cf_mhz).starts_with("wlan1 "));
+        }
+    }
+
+    #[test]
+    fn baseline_is_subtracted() {
+        let base = DutClient::parse_mib_resp(SAMPLE_MIB, 40);
+        let mut now = base.clone();
+        now.rec_rx_count = now.rec_rx_count.map(|n| n + 500);
+        now.rx_ok_count = now.rx_ok_count.map(|n| n + 480);
+        *now.ok_by_bw.get_mut(&40).unwrap() += 480;
+        let diff = now.since(&base);
+        assert_eq!((diff.rec_rx_count, diff.rx_ok_count), (Some(500), Some(480)));
+        assert_eq!(diff.ok_by_bw[&40], 480);
+        assert_eq!(diff.ok_at_other_bw(40), 0);
+        assert_eq!(diff.rssi1, base.rssi1);
+    }
+
+    #[test]
+    fn mib_reset_detection() {
+        let counts = |n| MibResult {
+            rec_rx_count: Some(n),
+            ..Default::default()
+        };
+        assert_eq!(MibReset::detect(&counts(0), &counts(0)), None);
+        assert_eq!(MibReset::detect(&counts(1000), &counts(0)), Some(MibReset::Read));
+        assert_eq!(MibReset::detect(&counts(1000), &counts(1000)), Some(MibReset::Baseline));
+        assert_eq!(MibReset::detect(&counts(1000), &counts(1003)), Some(MibReset::Baseline));
+    }
+
+    #[test]
+    fn unsupported_clear_is_recognized() {
+        assert!(is_unsupported_option("DUT returned error: fastconfig: invalid option -- 'C'"));
+        assert!(is_unsupported_option("DUT returned error: Unknown option -C"));
+        assert!(!is_unsupported_option("DUT returned error"));
+        assert!(!is_unsupported_option("DUT returned error: interface wlan0 is down"));
+        assert!(!is_unsupported_option("DUT clear_mib timed out after 5.0 s (read)"));
+    }
+
+    #[test]
+    fn log_lines_until_stopped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut board, _) = listener.accept().unwrap();
+        board.write_all(b"[ 812.004] wlan0: rx start\r\n[ 812.118] phy: agc lock\n").unwrap();
+
+        let (mut log, stop) = DutLog::new(stream).unwrap();
+        assert_eq!(log.next_line().unwrap().as_deref(), Some("[ 812.004] wlan0: rx start"));
+        assert_eq!(log.next_line().unwrap().as_deref(), Some("[ 812.118] phy: agc lock"));
+
+        // The board keeps the connection open; stop ends the blocked read
+        let reader = std::thread::spawn(move || log.next_line());
+        std::thread::sleep(Duration::from_millis(50));
+        stop.stop();
+        assert_eq!(reader.join().unwrap(), Ok(None));
+    }
+
+    #[test]
+    fn command_json() {
+        let json = |cmd: &DutCommand| serde_json::to_string(cmd).unwrap();
+        assert_eq!(json(&DutCommand::ATEInit), r#""ATEInit""#);
+        assert_eq!(
+            json(&DutCommand::ATECmd {
+                cmd: "ate_cmd".into(),
+                args: vec!["wlan0".into(), "fastconfig".into(), "-k".into()],
+            }),
+            r#"{"ATECmd":{"cmd":"ate_cmd","args":["wlan0","fastconfig","-k"]}}"#
+        );
+        assert_eq!(json(&DutCommand::ReadMib("wlan1".into())), r#"{"ReadMib":"wlan1"}"#);
+        assert_eq!(json(&ping_command()), r#"{"ATECmd":{"cmd":"ate_cmd","args":["echo","ping"]}}"#);
+    }
+
+    #[test]
+    fn saves_payload_in_chunks() {
+        let dir = std::env::temp_dir().join(format!("dut-fetch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("driver.log");
+        let data: Vec<u8> = (0..150_000u32).map(|i| i as u8).collect();
+        let calls = std::cell::Cell::new(0);
+        let progress = |received, total| {
+            assert_eq!(total, data.len() as u64);
+            assert!(received <= total);
+            calls.set(calls.get() + 1);
+        };
+        let saved = save_payload(&mut &data[..], data.len() as u64, &path, &progress).ok().unwrap();
+        assert_eq!(saved, data.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        assert_eq!(calls.get(), 3);
+
+        // The payload ends early
+        let (received, e) = save_payload(&mut &data[..1000], 5000, &path, &|_, _| {}).err().unwrap();
+        assert_eq!(received, 1000);
+        assert!(matches!(e, TransferError::Read(None)));
+
+        // The file cannot be created: the payload is drained all the same
+        let mut reader = &data[..];
+        let (received, e) = save_payload(&mut reader, 5000, &dir.join("no/such/dir"), &|_, _| {}).err().unwrap();
+        assert_eq!(received, 0);
+        assert!(matches!(e, TransferError::Write { drained: true, .. }));
+        assert_eq!(reader.len(), data.len() - 5000);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mib_errors_differ_from_zero_counts() {
+        let zero = "user->rec_rx_count = 0\nreceive 20M OK = 0\n";
+        let mib = DutClient::mib_from_output(zero.into(), 20).unwrap();
+        assert_eq!((mib.rec_rx_count, mib.rx_ok_count), (Some(0), Some(0)));
+        assert_eq!(mib.raw.as_deref(), Some(zero));
+
+        let err = DutClient::mib_from_output("fastconfig: unknown option -R\n".into(), 20).unwrap_err();
+        assert!(err.contains("no RX counters"), "{}", err);
+    }
 }
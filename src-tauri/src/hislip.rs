@@ -0,0 +1,513 @@
+use std::io::{self, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::iolog::{self, IoKind};
+use crate::scpi::{self, ScpiTransport, CANCELLED, DEFAULT_MAX_BLOCK_BYTES, LOG_SOURCE};
+
+/// Registered HiSLIP port.
+pub const DEFAULT_PORT: u16 = 4880;
+
+/// Sub-address of the instrument's first HiSLIP server.
+pub const DEFAULT_SUB_ADDRESS: &str = "hislip0";
+
+const HEADER_LEN: usize = 16;
+
+/// HiSLIP 1.0, in the upper half of the Initialize parameter.
+const PROTOCOL_VERSION: u32 = 0x0100;
+
+/// Two-character vendor ID sent in Initialize.
+const VENDOR_ID: [u8; 2] = *b"WP";
+
+/// Message ID of the first message after Initialize or a device clear.
+const FIRST_MESSAGE_ID: u32 = 0xffff_ff00;
+
+/// Largest message we accept, offered to the server at connect.
+const MAX_MESSAGE_SIZE: u64 = 1 << 20;
+
+// Message types (IVI-6.1, table 4)
+const INITIALIZE: u8 = 0;
+const INITIALIZE_RESPONSE: u8 = 1;
+const FATAL_ERROR: u8 = 2;
+const ERROR: u8 = 3;
+const DATA: u8 = 6;
+const DATA_END: u8 = 7;
+const DEVICE_CLEAR_COMPLETE: u8 = 8;
+const DEVICE_CLEAR_ACKNOWLEDGE: u8 = 9;
+const INTERRUPTED: u8 = 13;
+const ASYNC_MAXIMUM_MESSAGE_SIZE: u8 = 15;
+const ASYNC_MAXIMUM_MESSAGE_SIZE_RESPONSE: u8 = 16;
+const ASYNC_INITIALIZE: u8 = 17;
+const ASYNC_INITIALIZE_RESPONSE: u8 = 18;
+const ASYNC_DEVICE_CLEAR: u8 = 19;
+const ASYNC_DEVICE_CLEAR_ACKNOWLEDGE: u8 = 23;
+
+struct Message {
+    kind: u8,
+    control: u8,
+    param: u32,
+    payload: Vec<u8>,
+}
+
+impl Message {
+    fn new(kind: u8, control: u8, param: u32, payload: &[u8]) -> Self {
+        Self {
+            kind,
+            control,
+            param,
+            payload: payload.to_vec(),
+        }
+    }
+
+    fn header(kind: u8, control: u8, param: u32, len: u64) -> [u8; HEADER_LEN] {
+        let mut header = [0u8; HEADER_LEN];
+        header[..2].copy_from_slice(b"HS");
+        header[2] = kind;
+        header[3] = control;
+        header[4..8].copy_from_slice(&param.to_be_bytes());
+        header[8..].copy_from_slice(&len.to_be_bytes());
+        header
+    }
+
+    fn write_to(&self, stream: &mut impl Write) -> io::Result<()> {
+        let header = Self::header(self.kind, self.control, self.param, self.payload.len() as u64);
+        stream.write_all(&header)?;
+        stream.write_all(&self.payload)?;
+        stream.flush()
+    }
+
+    /// Read one message, refusing payloads over `max_payload` bytes.
+    fn read_from(stream: &mut impl Read, max_payload: u64) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN];
+        stream.read_exact(&mut header)?;
+        if &header[..2] != b"HS" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a HiSLIP message"));
+        }
+        let len = u64::from_be_bytes(header[8..].try_into().unwrap());
+        if len > max_payload {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("HiSLIP message of {} bytes exceeds the {} byte limit", len, max_payload),
+            ));
+        }
+        let mut payload = vec![0u8; len as usize];
+        stream.read_exact(&mut payload)?;
+        Ok(Self {
+            kind: header[2],
+            control: header[3],
+            param: u32::from_be_bytes(header[4..8].try_into().unwrap()),
+            payload,
+        })
+    }
+
+    /// Error text of an Error or FatalError message.
+    fn error_text(&self) -> String {
+        format!(
+            "HiSLIP {}error {}: {}",
+            if self.kind == FATAL_ERROR { "fatal " } else { "" },
+            self.control,
+            String::from_utf8_lossy(&self.payload)
+        )
+    }
+}
+
+/// HiSLIP (IVI-6.1) client. SCPI traffic uses the synchronous channel in
+/// synchronized mode; the asynchronous channel carries device clear.
+pub struct HislipClient {
+    sync: TcpStream,
+    reader: BufReader<TcpStream>,
+    asynchronous: TcpStream,
+    /// ID of the next Data/DataEnd message sent.
+    message_id: u32,
+    /// A complete response was read since the last message sent.
+    rmt_delivered: bool,
+    /// Largest payload the server accepts per message.
+    max_payload: usize,
+    timeout: Duration,
+    last_cmd: String,
+    disconnected: bool,
+    max_block_bytes: usize,
+    peer: SocketAddr,
+}
+
+impl HislipClient {
+    pub fn connect(host: &str, port: u16, sub_address: &str, timeout_secs: u64) -> Result<Self, String> {
+        let timeout = Duration::from_secs(timeout_secs);
+        let (mut sync, peer) = scpi::connect_tcp(host, port, timeout)?;
+        set_stream_timeouts(&sync, timeout)?;
+        let _ = sync.set_nodelay(true);
+
+        let vendor = u16::from_be_bytes(VENDOR_ID) as u32;
+        let init = Message::new(INITIALIZE, 0, (PROTOCOL_VERSION << 16) | vendor, sub_address.as_bytes());
+        let resp = exchange(&mut sync, &init, INITIALIZE_RESPONSE, "Initialize")?;
+        let session_id = resp.param & 0xffff;
+
+        let mut asynchronous = TcpStream::connect_timeout(&peer, timeout)
+            .map_err(|e| format!("HiSLIP async channel to {} failed: {}", peer, e))?;
+        set_stream_timeouts(&asynchronous, timeout)?;
+        let _ = asynchronous.set_nodelay(true);
+        let init = Message::new(ASYNC_INITIALIZE, 0, session_id, &[]);
+        exchange(&mut asynchronous, &init, ASYNC_INITIALIZE_RESPONSE, "AsyncInitialize")?;
+
+        let size = Message::new(ASYNC_MAXIMUM_MESSAGE_SIZE, 0, 0, &MAX_MESSAGE_SIZE.to_be_bytes());
+        let resp = exchange(
+            &mut asynchronous,
+            &size,
+            ASYNC_MAXIMUM_MESSAGE_SIZE_RESPONSE,
+            "AsyncMaximumMessageSize",
+        )?;
+        let server_max = resp
+            .payload
+            .get(..8)
+            .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+            .unwrap_or(MAX_MESSAGE_SIZE);
+        // The server's limit counts the header as well
+        let max_payload = server_max.saturating_sub(HEADER_LEN as u64).clamp(1, MAX_MESSAGE_SIZE) as usize;
+
+        let reader = BufReader::new(
+            sync.try_clone()
+                .map_err(|e| format!("Failed to clone stream: {}", e))?,
+        );
+        Ok(Self {
+            sync,
+            reader,
+            asynchronous,
+            message_id: FIRST_MESSAGE_ID,
+            rmt_delivered: false,
+            max_payload,
+            timeout,
+            last_cmd: String::new(),
+            disconnected: false,
+            max_block_bytes: DEFAULT_MAX_BLOCK_BYTES,
+            peer,
+        })
+    }
+
+    fn io_error(&mut self, what: &str, e: io::Error) -> String {
+        let (msg, lost) = scpi::describe_io_error(what, &e, self.timeout, &self.last_cmd);
+        self.disconnected |= lost;
+        iolog::error(LOG_SOURCE, msg)
+    }
+
+    /// Send `data` as one Data or DataEnd message on the sync channel.
+    fn send_data(&mut self, data: &[u8], end: bool) -> Result<(), String> {
+        let kind = if end { DATA_END } else { DATA };
+        let header = Message::header(kind, self.rmt_delivered as u8, self.message_id, data.len() as u64);
+        self.rmt_delivered = false;
+        self.message_id = self.message_id.wrapping_add(2);
+        self.sync
+            .write_all(&header)
+            .and_then(|()| self.sync.write_all(data))
+            .map_err(|e| self.io_error("Write", e))
+    }
+
+    /// Send `data` as a sequence of Data messages of at most `max_payload`
+    /// bytes, ending with DataEnd when `end` is set.
+    fn send_message(&mut self, data: &[u8], end: bool) -> Result<(), String> {
+        let mut chunks = data.chunks(self.max_payload).peekable();
+        if chunks.peek().is_none() {
+            return self.send_data(&[], end);
+        }
+        while let Some(chunk) = chunks.next() {
+            self.send_data(chunk, end && chunks.peek().is_none())?;
+        }
+        Ok(())
+    }
+
+    /// Read Data messages up to the DataEnd that completes a response.
+    fn read_message(&mut self) -> Result<Vec<u8>, String> {
+        let mut data = Vec::new();
+        let limit = self.max_block_bytes as u64 + HEADER_LEN as u64;
+        loop {
+            let msg = Message::read_from(&mut self.reader, limit).map_err(|e| self.io_error("Read", e))?;
+            match msg.kind {
+                DATA | DATA_END => {
+                    if data.len() + msg.payload.len() > self.max_block_bytes {
+                        return Err(iolog::error(
+                            LOG_SOURCE,
+                            format!(
+                                "Response to '{}' exceeds the {} byte limit",
+                                self.last_cmd, self.max_block_bytes
+                            ),
+                        ));
+                    }
+                    data.extend_from_slice(&msg.payload);
+                    if msg.kind == DATA_END {
+                        self.rmt_delivered = true;
+                        return Ok(data);
+                    }
+                }
+                // A response superseded by a newer command; wait for the next one
+                INTERRUPTED => data.clear(),
+                FATAL_ERROR => {
+                    self.disconnected = true;
+                    return Err(iolog::error(LOG_SOURCE, msg.error_text()));
+                }
+                ERROR => return Err(iolog::error(LOG_SOURCE, msg.error_text())),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl ScpiTransport for HislipClient {
+    fn write_cmd(&mut self, cmd: &str) -> Result<(), String> {
+        self.last_cmd = cmd.to_string();
+        iolog::record(LOG_SOURCE, IoKind::Write, || cmd.to_string());
+        self.send_message(format!("{}\n", cmd).as_bytes(), true)?;
+        self.sync.flush().map_err(|e| self.io_error("Flush", e))
+    }
+
+    fn read_response(&mut self) -> Result<String, String> {
+        let data = self.read_message()?;
+        let response = String::from_utf8_lossy(&data).trim().to_string();
+        iolog::record(LOG_SOURCE, IoKind::Read, || response.clone());
+        Ok(response)
+    }
+
+    fn query_binary_block(&mut self, cmd: &str) -> Result<Vec<u8>, String> {
+        self.write_cmd(cmd)?;
+        let message = self.read_message()?;
+        let data = parse_block(&message).map_err(|e| {
+            iolog::error(LOG_SOURCE, format!("{} from '{}'", e, self.last_cmd))
+        })?;
+        iolog::record_block(LOG_SOURCE, "", &data);
+        Ok(data)
+    }
+
+    fn write_binary_block_progress(
+        &mut self,
+        cmd: &str,
+        data: &[u8],
+        chunk: usize,
+        progress: &dyn Fn(u64),
+        cancel: Option<&AtomicBool>,
+    ) -> Result<(), String> {
+        self.last_cmd = cmd.to_string();
+        iolog::record_block(LOG_SOURCE, cmd, data);
+        let header = format!("{}#{}{}", cmd, data.len().to_string().len(), data.len());
+        self.send_message(header.as_bytes(), false)?;
+        let mut sent = 0;
+        for block in data.chunks(chunk) {
+            if cancel.is_some_and(|c| c.load(Ordering::SeqCst)) {
+                return Err(iolog::error(
+                    LOG_SOURCE,
+                    match self.device_clear() {
+                        Ok(()) => CANCELLED.to_string(),
+                        Err(e) => format!("{}; {}", CANCELLED, e),
+                    },
+                ));
+            }
+            self.send_message(block, false)?;
+            sent += block.len() as u64;
+            progress(sent);
+        }
+        self.send_message(b"\n", true)?;
+        self.sync.flush().map_err(|e| self.io_error("Flush", e))
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), String> {
+        set_stream_timeouts(&self.sync, timeout)?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    fn set_max_block_size(&mut self, bytes: usize) {
+        self.max_block_bytes = bytes;
+    }
+
+    /// AsyncDeviceClear on the async channel, then DeviceClearComplete on the
+    /// sync channel, discarding whatever is pending until it is acknowledged.
+    fn device_clear(&mut self) -> Result<(), String> {
+        iolog::record(LOG_SOURCE, IoKind::Write, || "<device clear>".into());
+        let clear = Message::new(ASYNC_DEVICE_CLEAR, 0, 0, &[]);
+        let ack = exchange(
+            &mut self.asynchronous,
+            &clear,
+            ASYNC_DEVICE_CLEAR_ACKNOWLEDGE,
+            "AsyncDeviceClear",
+        )
+        .map_err(|e| iolog::error(LOG_SOURCE, e))?;
+
+        // Echo the server's feature preference back
+        Message::new(DEVICE_CLEAR_COMPLETE, ack.control, 0, &[])
+            .write_to(&mut self.sync)
+            .map_err(|e| self.io_error("Write DeviceClearComplete", e))?;
+        let limit = self.max_block_bytes as u64 + HEADER_LEN as u64;
+        loop {
+            let msg = Message::read_from(&mut self.reader, limit)
+                .map_err(|e| self.io_error("Read DeviceClearAcknowledge", e))?;
+            if msg.kind == DEVICE_CLEAR_ACKNOWLEDGE {
+                break;
+            }
+        }
+        self.message_id = FIRST_MESSAGE_ID;
+        self.rmt_delivered = false;
+        Ok(())
+    }
+}
+
+fn set_stream_timeouts(stream: &TcpStream, timeout: Duration) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(timeout))
+        .map_err(|e| format!("Failed to set read timeout: {}", e))?;
+    stream
+        .set_write_timeout(Some(timeout))
+        .map_err(|e| format!("Failed to set write timeout: {}", e))
+}
+
+/// Send `msg` and read the reply, which must be of type `expect`.
+fn exchange(stream: &mut TcpStream, msg: &Message, expect: u8, what: &str) -> Result<Message, String> {
+    msg.write_to(stream)
+        .map_err(|e| format!("HiSLIP {} failed: {}", what, e))?;
+    let resp = Message::read_from(stream, MAX_MESSAGE_SIZE)
+        .map_err(|e| format!("HiSLIP {} failed: {}", what, e))?;
+    match resp.kind {
+        k if k == expect => Ok(resp),
+        ERROR | FATAL_ERROR => Err(format!("HiSLIP {} refused: {}", what, resp.error_text())),
+        k => Err(format!("HiSLIP {} answered with message type {}", what, k)),
+    }
+}
+
+/// Data of the IEEE 488.2 arbitrary block that makes up `message`.
+fn parse_block(message: &[u8]) -> Result<Vec<u8>, String> {
+    let (digits, rest) = match message {
+        [b'#', d, rest @ ..] if d.is_ascii_digit() => ((d - b'0') as usize, rest),
+        _ => {
+            let start = String::from_utf8_lossy(&message[..message.len().min(2)]).into_owned();
+            return Err(format!("Expected a binary block, got {:?}", start));
+        }
+    };
+    if digits == 0 {
+        // Indefinite length: runs to the terminating newline
+        let data = rest.strip_suffix(b"\n").unwrap_or(rest);
+        return Ok(data.to_vec());
+    }
+    let length: usize = rest
+        .get(..digits)
+        .and_then(|l| std::str::from_utf8(l).ok())
+        .and_then(|l| l.parse().ok())
+        .ok_or("Invalid binary block length")?;
+    rest.get(digits..digits + length)
+        .map(|data| data.to_vec())
+        .ok_or_else(|| format!("Binary block cut short: {} of {} bytes", rest.len() - digits, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn header_layout() {
+        let header = Message::header(DATA_END, 1, FIRST_MESSAGE_ID, 5);
+        assert_eq!(
+            header,
+            [b'H', b'S', 7, 1, 0xff, 0xff, 0xff, 0x00, 0, 0, 0, 0, 0, 0, 0, 5]
+        );
+    }
+
+    #[test]
+    fn parses_blocks() {
+        assert_eq!(parse_block(b"#15hello\n").unwrap(), b"hello");
+        assert_eq!(parse_block(b"#0abc\n").unwrap(), b"abc");
+        assert!(parse_block(b"#15hel").unwrap_err().contains("cut short"));
+        assert!(parse_block(b"+0,\"No error\"").is_err());
+    }
+
+    /// Minimal HiSLIP server: answers the handshake, then each DataEnd
+    /// with `reply`, and a device clear with the acknowledgements.
+    fn serve(reply: &'static [u8]) -> (HislipClient, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut sync, _) = listener.accept().unwrap();
+            let init = Message::read_from(&mut sync, 1024).unwrap();
+            assert_eq!(init.kind, INITIALIZE);
+            assert_eq!(init.payload, b"hislip0");
+            Message::new(INITIALIZE_RESPONSE, 0, 0x0100_0042, &[]).write_to(&mut sync).unwrap();
+
+            let (mut asynchronous, _) = listener.accept().unwrap();
+            let init = Message::read_from(&mut asynchronous, 1024).unwrap();
+            assert_eq!((init.kind, init.param), (ASYNC_INITIALIZE, 0x42));
+            Message::new(ASYNC_INITIALIZE_RESPONSE, 0, 0, &[]).write_to(&mut asynchronous).unwrap();
+            let size = Message::read_from(&mut asynchronous, 1024).unwrap();
+            assert_eq!(size.kind, ASYNC_MAXIMUM_MESSAGE_SIZE);
+            // Small limit so commands are split into several messages
+            Message::new(ASYNC_MAXIMUM_MESSAGE_SIZE_RESPONSE, 0, 0, &24u64.to_be_bytes())
+                .write_to(&mut asynchronous)
+                .unwrap();
+
+            thread::spawn(move || {
+                while let Ok(clear) = Message::read_from(&mut asynchronous, 1024) {
+                    assert_eq!(clear.kind, ASYNC_DEVICE_CLEAR);
+                    Message::new(ASYNC_DEVICE_CLEAR_ACKNOWLEDGE, 0, 0, &[])
+                        .write_to(&mut asynchronous)
+                        .unwrap();
+                }
+            });
+
+            let mut received = Vec::new();
+            while let Ok(msg) = Message::read_from(&mut sync, 1 << 20) {
+                match msg.kind {
+                    DATA => received.extend_from_slice(&msg.payload),
+                    DATA_END => {
+                        received.extend_from_slice(&msg.payload);
+                        // Writes also end here; the client may be gone already
+                        let _ = Message::new(DATA_END, 0, msg.param, reply).write_to(&mut sync);
+                    }
+                    DEVICE_CLEAR_COMPLETE => {
+                        Message::new(DEVICE_CLEAR_ACKNOWLEDGE, 0, 0, &[]).write_to(&mut sync).unwrap();
+                    }
+                    _ => {}
+                }
+            }
+            received
+        });
+        (HislipClient::connect("127.0.0.1", port, "hislip0", 2).unwrap(), server)
+    }
+
+    #[test]
+    fn query_over_split_messages() {
+        let (mut client, server) = serve(b"Keysight Technologies,N5182B,MY1234,B.01\n");
+        assert_eq!(client.max_payload, 8);
+        assert_eq!(
+            client.query("*IDN?").unwrap(),
+            "Keysight Technologies,N5182B,MY1234,B.01"
+        );
+        client.write_binary_block(":MMEM:DATA \"x\",", b"abcd").unwrap();
+        assert!(client.read_until("END").is_err());
+        drop(client);
+        assert_eq!(server.join().unwrap(), b"*IDN?\n:MMEM:DATA \"x\",#14abcd\n");
+    }
+
+    #[test]
+    fn binary_block_query() {
+        let (mut client, _server) = serve(b"#15hello\n");
+        assert_eq!(client.query_binary_block(":MMEM:DATA? \"x\"").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn device_clear_resets_message_ids() {
+        let (mut client, _server) = serve(b"1\n");
+        client.query("*OPC?").unwrap();
+        assert_ne!(client.message_id, FIRST_MESSAGE_ID);
+        client.device_clear().unwrap();
+        assert_eq!(client.message_id, FIRST_MESSAGE_ID);
+        assert_eq!(client.query("*OPC?").unwrap(), "1");
+    }
+}
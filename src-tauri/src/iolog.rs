@@ -0,0 +1,243 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::sweep::waveform_hash;
+
+/// Size at which the log file is rotated.
+const MAX_LOG_BYTES: u64 = 5 << 20;
+
+/// Rotated files kept next to the active one (`io.log.1` ... `io.log.N`).
+const ROTATED_FILES: u32 = 3;
+
+const LOG_FILE: &str = "io.log";
+
+/// How much instrument and DUT traffic is recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Off = 0,
+    /// Only failed transactions.
+    Errors = 1,
+    /// Every command, response and binary block.
+    Commands = 2,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IoKind {
+    Write,
+    Read,
+    Block,
+    Error,
+}
+
+impl IoKind {
+    fn level(self) -> LogLevel {
+        match self {
+            IoKind::Error => LogLevel::Errors,
+            _ => LogLevel::Commands,
+        }
+    }
+}
+
+/// One logged transaction, also the payload of the "io-log" event.
+#[derive(Debug, Clone, Serialize)]
+pub struct IoLogEntry {
+    /// Seconds since the first logged entry, from a monotonic clock.
+    pub t: f64,
+    /// "vsg" or "dut".
+    pub source: &'static str,
+    pub kind: IoKind,
+    pub text: String,
+}
+
+pub type LiveHook = Box<dyn Fn(&IoLogEntry) + Send>;
+
+struct Sink {
+    dir: PathBuf,
+    file: Option<File>,
+    written: u64,
+    live: Option<LiveHook>,
+}
+
+/// Checked before anything is formatted, so disabled logging costs one
+/// atomic load per transaction.
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Off as u8);
+static SINK: Mutex<Option<Sink>> = Mutex::new(None);
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Start logging at `level` to a rotating file in `dir`, passing every entry
+/// to `live` as well when given. `LogLevel::Off` closes the file.
+pub fn configure(level: LogLevel, dir: &Path, live: Option<LiveHook>) -> Result<(), String> {
+    let mut sink = SINK.lock().map_err(|_| "I/O log lock poisoned".to_string())?;
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    if level == LogLevel::Off {
+        *sink = None;
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create log dir: {}", e))?;
+    let (file, written) = open(&dir.join(LOG_FILE))?;
+    *sink = Some(Sink {
+        dir: dir.to_path_buf(),
+        file: Some(file),
+        written,
+        live,
+    });
+    Ok(())
+}
+
+/// Whether entries of `kind` are currently recorded.
+#[inline]
+pub fn enabled(kind: IoKind) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= kind.level() as u8
+}
+
+/// Record a transaction. `text` is only evaluated when `kind` is enabled.
+#[inline]
+pub fn record(source: &'static str, kind: IoKind, text: impl FnOnce() -> String) {
+    if enabled(kind) {
+        write_entry(source, kind, text());
+    }
+}
+
+/// Record `msg` as a failed transaction and hand it back as the error.
+pub fn error(source: &'static str, msg: String) -> String {
+    record(source, IoKind::Error, || msg.clone());
+    msg
+}
+
+/// Record a binary payload as its length and hash rather than its bytes.
+#[inline]
+pub fn record_block(source: &'static str, label: &str, data: &[u8]) {
+    record(source, IoKind::Block, || describe_block(label, data));
+}
+
+fn describe_block(label: &str, data: &[u8]) -> String {
+    format!("{} <{} bytes, fnv1a {:016x}>", label, data.len(), waveform_hash(data))
+}
+
+fn write_entry(source: &'static str, kind: IoKind, text: String) {
+    let start = *START.get_or_init(Instant::now);
+    let entry = IoLogEntry {
+        t: start.elapsed().as_secs_f64(),
+        source,
+        kind,
+        text,
+    };
+    // Logging must never fail the transaction it describes
+    let Ok(mut guard) = SINK.lock() else { return };
+    let Some(sink) = guard.as_mut() else { return };
+    let line = format_line(&entry);
+    if sink.written + line.len() as u64 > MAX_LOG_BYTES {
+        sink.rotate();
+    }
+    if let Some(file) = sink.file.as_mut() {
+        if file.write_all(line.as_bytes()).is_ok() {
+            sink.written += line.len() as u64;
+        }
+    }
+    if let Some(live) = &sink.live {
+        live(&entry);
+    }
+}
+
+fn format_line(entry: &IoLogEntry) -> String {
+    let kind = match entry.kind {
+        IoKind::Write => ">",
+        IoKind::Read => "<",
+        IoKind::Block => "#",
+        IoKind::Error => "!",
+    };
+    format!("{:12.6} {} {} {}\n", entry.t, entry.source, kind, entry.text)
+}
+
+fn open(path: &Path) -> Result<(File, u64), String> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open I/O log {}: {}", path.display(), e))?;
+    let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+    Ok((file, written))
+}
+
+impl Sink {
+    /// Shift `io.log.N-1` to `io.log.N`, ..., `io.log` to `io.log.1` and start
+    /// a new file. On failure logging carries on without a file.
+    fn rotate(&mut self) {
+        self.file = None;
+        let rotated = |n: u32| self.dir.join(format!("{}.{}", LOG_FILE, n));
+        for n in (1..ROTATED_FILES).rev() {
+            let _ = std::fs::rename(rotated(n), rotated(n + 1));
+        }
+        let active = self.dir.join(LOG_FILE);
+        let _ = std::fs::rename(&active, rotated(1));
+        if let Ok((file, written)) = open(&active) {
+            self.file = Some(file);
+            self.written = written;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_level_skips_traffic() {
+        LEVEL.store(LogLevel::Errors as u8, Ordering::Relaxed);
+        assert!(enabled(IoKind::Error));
+        assert!(!enabled(IoKind::Write));
+        assert!(!enabled(IoKind::Block));
+        LEVEL.store(LogLevel::Off as u8, Ordering::Relaxed);
+        assert!(!enabled(IoKind::Error));
+    }
+
+    #[test]
+    fn block_logged_as_length_and_hash() {
+        assert_eq!(
+            describe_block("MMEM:DATA", b"abc"),
+            "MMEM:DATA <3 bytes, fnv1a e71fa2190541574b>"
+        );
+    }
+
+    #[test]
+    fn line_format() {
+        let entry = IoLogEntry {
+            t: 1.5,
+            source: "vsg",
+            kind: IoKind::Write,
+            text: "*IDN?".into(),
+        };
+        assert_eq!(format_line(&entry), "    1.500000 vsg > *IDN?\n");
+    }
+
+    #[test]
+    fn rotation_shifts_files() {
+        let dir = std::env::temp_dir().join(format!("iolog-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (file, written) = open(&dir.join(LOG_FILE)).unwrap();
+        let mut sink = Sink {
+            dir: dir.clone(),
+            file: Some(file),
+            written,
+            live: None,
+        };
+        for text in ["first", "second"] {
+            sink.file.as_mut().unwrap().write_all(text.as_bytes()).unwrap();
+            sink.rotate();
+        }
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("io.log.1"), "second");
+        assert_eq!(read("io.log.2"), "first");
+        assert_eq!(read(LOG_FILE), "");
+        assert_eq!(sink.written, 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,140 +1,1581 @@
+mod discovery;
 mod dut;
+mod hislip;
+mod iolog;
+#[cfg(test)]
+mod mock;
 mod scpi;
+mod sweep;
 mod vsg;
 mod waveform;
 
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use tauri::{AppHandle, Emitter, State};
-use dut::DutClient;
-use vsg::VsgInstrument;
-use waveform::WaveformInfo;
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, State};
+use dut::{DutClient, DutLogStop, WlanInterface};
+use iolog::{IoLogEntry, LogLevel};
+use sweep::{PowerSweepParams, StepPacing, StepTiming, SweepCheckpoint, SweepProgress, TimingSummary};
+use vsg::{
+    DownloadControl, InstrumentIdentity, InstrumentModel, RefSource, ReferenceStatus, Retrigger, SelfTestResult,
+    TriggerSource, TriggerTiming, VsgInstrument,
+};
+use waveform::{
+    ExportFormat, LoadOptions, MatVariable, Spectrum, TestSignal, WaveformInfo, WaveformMapEntry,
+    WaveformPreview, WaveformSidecar,
+};
+
+/// Slot used by commands called without one.
+const DEFAULT_SLOT: &str = "waveform";
+
+/// Idle gap appended after the burst when `load_waveform` isn't given one.
+const DEFAULT_GAP_US: f64 = 30.0;
+
+/// Bursts played per power sweep step.
+const SWEEP_BURSTS: u32 = 1000;
+
+/// Open-loop sweep waits (external trigger, default list dwell) are this
+/// many times a step's nominal playing time. The byte-count formula they
+/// replaced took each int16 as a sample and so waited twice the playing
+/// time; the factor keeps that headroom now the duration is exact.
+const SWEEP_WAIT_FACTOR: u32 = 2;
+
+/// Retries of a failed DUT command before a sweep gives up.
+const DUT_RETRIES: u32 = 3;
+
+/// Idle time after which the DUT keep-alive pings the board.
+const DUT_KEEPALIVE_IDLE: Duration = Duration::from_secs(60);
+
+/// Pause before retrying a DUT command.
+const DUT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Output power limit until `set_power_limit` changes it, in dBm.
+const DEFAULT_POWER_LIMIT_DBM: f64 = -10.0;
+
+/// A waveform held in memory under a slot name.
+struct LoadedWaveform {
+    /// Instrument bytes (big-endian interleaved int16 IQ).
+    data: Vec<u8>,
+    /// Marker bytes, one per IQ sample.
+    markers: Vec<u8>,
+    info: WaveformInfo,
+    /// Path of the file `data` was loaded from.
+    file_path: String,
+    /// `sweep::waveform_hash` of `data`.
+    hash: u64,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct WaveformSlot {
+    slot: String,
+    file_path: String,
+    info: WaveformInfo,
+    /// Bytes held for this slot (IQ data plus markers).
+    memory_bytes: usize,
+}
 
 struct AppState {
     vsg: Option<VsgInstrument>,
     dut: Option<DutClient>,
-    wfm_data: Option<Vec<u8>>,
+    /// Loaded waveforms by slot name; the slot name is also the instrument
+    /// waveform id (WFM1:<slot>).
+    waveforms: HashMap<String, LoadedWaveform>,
+    /// (band, bandwidth) → waveform file mapping used by `auto_waveform`.
+    waveform_map: Vec<WaveformMapEntry>,
+    /// Stop flag of the running playback progress monitor, if any.
+    playback_stop: Option<Arc<AtomicBool>>,
+    /// Stop flag of the DUT keep-alive thread, if any.
+    dut_keepalive_stop: Option<Arc<AtomicBool>>,
+    /// Set while a background `load_waveform` runs; cleared by its
+    /// `LoadTicket`.
+    loading: Arc<AtomicBool>,
+    /// Highest RF output power allowed (dBm, cable loss included).
+    power_limit_dbm: f64,
+    /// Whether the last liveness check of the connected VSG/DUT succeeded.
+    vsg_alive: bool,
+    dut_alive: bool,
+    /// Steps of the last sweep that finished or was cancelled, for
+    /// `export_sweep_csv`.
+    last_sweep: Vec<SweepProgress>,
+}
+
+impl AppState {
+    fn set_waveform(&mut self, slot: &str, data: Vec<u8>, info: WaveformInfo, file_path: String) {
+        let loaded = LoadedWaveform {
+            markers: waveform::info_markers(&info),
+            hash: sweep::waveform_hash(&data),
+            data,
+            info,
+            file_path,
+        };
+        self.waveforms.insert(slot.to_string(), loaded);
+    }
+
+    /// Pad the slot's waveform to the connected ARB's granularity before it
+    /// is downloaded, in case it was loaded for looser limits.
+    fn fit_waveform_to_arb(&mut self, slot: &str) -> Result<(), String> {
+        let Some(arb) = self.vsg.as_ref().map(|vsg| vsg.arb_limits()) else {
+            return Ok(());
+        };
+        let loaded = self
+            .waveforms
+            .get_mut(slot)
+            .ok_or_else(|| format!("No waveform loaded in slot '{}'", slot))?;
+        if waveform::pad_to_arb(&mut loaded.data, &mut loaded.info, arb)? > 0 {
+            loaded.markers = waveform::info_markers(&loaded.info);
+            loaded.hash = sweep::waveform_hash(&loaded.data);
+        }
+        Ok(())
+    }
+
+    fn waveform(&self, slot: &str) -> Result<&LoadedWaveform, String> {
+        self.waveforms
+            .get(slot)
+            .ok_or_else(|| format!("No waveform loaded in slot '{}'", slot))
+    }
+
+    fn stop_playback_monitor(&mut self) {
+        if let Some(stop) = self.playback_stop.take() {
+            stop.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn stop_dut_keepalive(&mut self) {
+        if let Some(stop) = self.dut_keepalive_stop.take() {
+            stop.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Returned by `connect_instrument`.
+#[derive(serde::Serialize)]
+struct InstrumentConnection {
+    identity: InstrumentIdentity,
+    model: InstrumentModel,
+    /// `None` if the reference state could not be read.
+    reference: Option<ReferenceStatus>,
+    /// Installed options from `*OPT?`; empty if they could not be read.
+    options: Vec<String>,
+    /// Resolved address and family, e.g. "192.168.1.20:5025 (IPv4)".
+    endpoint: String,
+    warnings: Vec<String>,
 }
 
 #[tauri::command]
-fn connect_instrument(ip: String, state: State<Mutex<AppState>>) -> Result<String, String> {
+#[allow(clippy::too_many_arguments)]
+fn connect_instrument(
+    ip: String,
+    reference: Option<RefSource>,
+    timeout_secs: Option<u64>,
+    check_arb_option: Option<bool>,
+    retry: Option<scpi::RetryPolicy>,
+    terminator: Option<String>,
+    app: AppHandle,
+    state: State<Mutex<AppState>>,
+) -> Result<InstrumentConnection, String> {
     let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
 
     // Disconnect existing connection first
+    app_state.stop_playback_monitor();
     if let Some(ref mut vsg) = app_state.vsg {
-        let _ = vsg.stop();
+        let _ = vsg.release();
     }
     app_state.vsg = None;
 
-    let vsg = VsgInstrument::connect(&ip, 3, true)?;
-    let inst_id = vsg.inst_id.clone();
+    let mut vsg = VsgInstrument::connect(&ip, timeout_secs.unwrap_or(3), true, terminator.as_deref())?;
+    vsg.set_power_limit(app_state.power_limit_dbm);
+    if retry.is_some() {
+        let app = app.clone();
+        vsg.set_retry_policy(
+            retry,
+            Some(Arc::new(move |event: &scpi::RetryEvent| {
+                let _ = app.emit("scpi-retry", event.clone());
+            })),
+        );
+    }
+    vsg.on_reconnect(Box::new(move |info| {
+        let _ = app.emit("vsg-reconnected", info);
+    }));
+    let identity = vsg.identity.clone();
+    let model = vsg.model_info.clone();
+    let endpoint = scpi::describe_endpoint(vsg.endpoint());
+    let mut warnings = Vec::new();
+    if !model.known {
+        warnings.push(format!(
+            "Unknown model '{}': frequency and power limits are not checked; the sample rate is held to 240 MHz",
+            model.name
+        ));
+    }
+
+    if let Some(source) = reference {
+        if let Err(e) = vsg.set_reference(source) {
+            warnings.push(format!("Could not select {:?} reference: {}", source, e));
+        }
+    }
+    let reference = match vsg.get_reference_status() {
+        Ok(status) => {
+            if status.source == RefSource::External && !status.locked {
+                warnings.push("External reference is selected but not locked".into());
+            }
+            Some(status)
+        }
+        Err(e) => {
+            warnings.push(format!("Could not read reference status: {}", e));
+            None
+        }
+    };
+    let options = match vsg.query_options() {
+        Ok(options) => options,
+        Err(e) => {
+            warnings.push(format!("Could not read installed options: {}", e));
+            Vec::new()
+        }
+    };
+    if check_arb_option.unwrap_or(true) && !options.is_empty() && !vsg::has_arb_option(&options) {
+        warnings.push(format!(
+            "No ARB/baseband option found among the installed options ({}); waveform playback may fail",
+            options.join(", ")
+        ));
+    }
     app_state.vsg = Some(vsg);
+    app_state.vsg_alive = true;
+
+    Ok(InstrumentConnection {
+        identity,
+        model,
+        reference,
+        options,
+        endpoint,
+        warnings,
+    })
+}
 
-    Ok(inst_id)
+/// Run the instrument self test (`*TST?`), which can take tens of seconds.
+#[tauri::command]
+fn vsg_self_test(state: State<Mutex<AppState>>) -> Result<SelfTestResult, String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.self_test()
 }
 
+/// Clear the instrument's I/O after it hung mid-transfer: a device clear
+/// over HiSLIP, a new connection and `*CLS` over a raw socket.
 #[tauri::command]
-fn disconnect_instrument(state: State<Mutex<AppState>>) -> Result<(), String> {
+fn reset_instrument_io(state: State<Mutex<AppState>>) -> Result<(), String> {
     let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.device_clear()
+}
 
+/// Installed instrument options (`*OPT?`).
+#[tauri::command]
+fn vsg_query_options(state: State<Mutex<AppState>>) -> Result<Vec<String>, String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.query_options()
+}
+
+/// Select the instrument's frequency reference and return the new lock state.
+#[tauri::command]
+fn set_vsg_reference(source: RefSource, state: State<Mutex<AppState>>) -> Result<ReferenceStatus, String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.set_reference(source)?;
+    vsg.get_reference_status()
+}
+
+#[tauri::command]
+fn get_vsg_reference_status(state: State<Mutex<AppState>>) -> Result<ReferenceStatus, String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.get_reference_status()
+}
+
+/// Stop all outputs and close the connection. The connection is closed
+/// either way; a stop that failed is returned as a warning so the operator
+/// can check the RF output by hand.
+#[tauri::command]
+fn disconnect_instrument(state: State<Mutex<AppState>>) -> Result<Option<String>, String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+
+    app_state.stop_playback_monitor();
+    let warning = match app_state.vsg {
+        // A stuck instrument may answer again after a device clear
+        Some(ref mut vsg) => vsg.release().err().map(|e| match vsg.device_clear() {
+            Ok(()) => format!("{} (instrument I/O cleared)", e),
+            Err(_) => e,
+        }),
+        None => None,
+    };
+    app_state.vsg = None;
+
+    Ok(warning)
+}
+
+/// Waveform files stored on the connected instrument.
+#[tauri::command]
+fn list_instrument_waveforms(state: State<Mutex<AppState>>) -> Result<vsg::WaveformCatalog, String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.list_waveforms()
+}
+
+#[tauri::command]
+fn delete_instrument_waveform(wfm_id: String, state: State<Mutex<AppState>>) -> Result<(), String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.delete_waveform(&wfm_id)
+}
+
+/// Read a waveform back from the instrument and save it to `file_path`,
+/// e.g. to check what is stored; returns the byte count.
+#[tauri::command]
+fn save_instrument_waveform(wfm_id: String, file_path: String, state: State<Mutex<AppState>>) -> Result<usize, String> {
+    let data = {
+        let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+        let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+        vsg.read_waveform(&wfm_id)?
+    };
+    std::fs::write(&file_path, &data).map_err(|e| format!("Cannot write {}: {}", file_path, e))?;
+    Ok(data.len())
+}
+
+/// Delete every waveform file on the instrument; returns the count.
+#[tauri::command]
+fn delete_all_instrument_waveforms(state: State<Mutex<AppState>>) -> Result<usize, String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.delete_all_waveforms()
+}
+
+/// Current frequency, power, output/ARB state and selected waveform.
+#[tauri::command]
+fn get_vsg_status(state: State<Mutex<AppState>>) -> Result<vsg::VsgStatus, String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.get_status()
+}
+
+/// Turn the instrument's ALC on or off before playback.
+#[tauri::command]
+fn vsg_set_alc(enabled: bool, state: State<Mutex<AppState>>) -> Result<(), String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.set_alc(enabled)
+}
+
+/// Offset the displayed power from the RF output by `db` (negative for a
+/// cable loss). Kept until playback stops or the instrument disconnects;
+/// a sweep replaces it while it runs.
+#[tauri::command]
+fn vsg_set_power_offset(db: f64, state: State<Mutex<AppState>>) -> Result<(), String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.set_power_offset(db)
+}
+
+/// Refuse RF output powers (cable loss included) above `dbm`. Applies to
+/// single plays and every step of a power sweep.
+#[tauri::command]
+fn set_power_limit(dbm: f64, state: State<Mutex<AppState>>) -> Result<(), String> {
+    if !dbm.is_finite() {
+        return Err(format!("Invalid power limit: {}", dbm));
+    }
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    app_state.power_limit_dbm = dbm;
     if let Some(ref mut vsg) = app_state.vsg {
-        let _ = vsg.stop();
+        vsg.set_power_limit(dbm);
     }
-    app_state.vsg = None;
+    Ok(())
+}
+
+/// Load a `frequency_mhz,loss_db` CSV into the instrument's flatness
+/// correction; returns the number of points. Kept until playback stops or
+/// the instrument disconnects, and a sweep meanwhile leaves the cable loss
+/// to it.
+#[tauri::command]
+fn vsg_load_loss_table(file_path: String, state: State<Mutex<AppState>>) -> Result<usize, String> {
+    let text = std::fs::read_to_string(&file_path).map_err(|e| format!("Cannot read {}: {}", file_path, e))?;
+    let table = vsg::parse_loss_table(&text)?;
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.load_loss_table(&table)?;
+    Ok(table.len())
+}
+
+/// Scale the ARB I/Q at runtime, in percent of full scale.
+#[tauri::command]
+fn vsg_set_iq_scale(percent: f64, state: State<Mutex<AppState>>) -> Result<(), String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.set_iq_scale(percent)
+}
+
+/// Longest a `discover_instruments` sweep runs.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(4);
+
+#[derive(Clone, serde::Serialize)]
+struct DiscoveryDone {
+    instruments: Vec<discovery::FoundInstrument>,
+}
 
+/// Look for SCPI instruments on `subnet` (e.g. "192.168.1.0/24", default
+/// the local /24) by connecting to `port` (default 5025) on every host and
+/// asking `*IDN?`. Runs in the background, emitting "instrument-found" per
+/// host that accepts and "discovery-done" with the full list.
+#[tauri::command]
+fn discover_instruments(subnet: Option<String>, port: Option<u16>, app: AppHandle) -> Result<(), String> {
+    let (ip, prefix) = match subnet {
+        Some(subnet) => discovery::parse_subnet(&subnet)?,
+        None => discovery::local_subnet()?,
+    };
+    let hosts = discovery::subnet_hosts(ip, prefix);
+    let port = port.unwrap_or(scpi::SOCKET_PORT);
+    std::thread::spawn(move || {
+        let found = |instrument: &discovery::FoundInstrument| {
+            let _ = app.emit("instrument-found", instrument.clone());
+        };
+        let instruments = discovery::discover(&hosts, port, DISCOVERY_TIMEOUT, &found);
+        let _ = app.emit("discovery-done", DiscoveryDone { instruments });
+    });
     Ok(())
 }
 
+#[derive(serde::Serialize)]
+struct DutConnection {
+    /// Resolved address and family, e.g. "192.168.1.30:9600 (IPv4)".
+    endpoint: String,
+    /// Why ATEInit failed; the connection is kept regardless.
+    init_warning: Option<String>,
+}
+
+/// Connect to the DUT by `host[:port]` and, unless `send_init` is false,
+/// send ATEInit. `timeout_secs` limits the connect and each command
+/// (default 5 s); `mib_timeout_secs` gives MIB reads a longer limit.
+/// `interface` fixes the radio to receive on instead of choosing it from
+/// the frequency; `supports_320` allows 320 MHz sweeps on Wi-Fi 7 boards.
+/// `keep_alive` pings an idle board so its daemon does not drop the
+/// connection.
 #[tauri::command]
-fn connect_dut(ip: String, state: State<Mutex<AppState>>) -> Result<(), String> {
+#[allow(clippy::too_many_arguments)]
+fn connect_dut(
+    ip: String,
+    timeout_secs: Option<u64>,
+    mib_timeout_secs: Option<u64>,
+    send_init: Option<bool>,
+    interface: Option<WlanInterface>,
+    supports_320: Option<bool>,
+    keep_alive: Option<bool>,
+    app: AppHandle,
+    state: State<Mutex<AppState>>,
+) -> Result<DutConnection, String> {
     let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    app_state.stop_dut_keepalive();
     app_state.dut = None;
 
-    let dut = DutClient::connect(&ip, 5)?;
+    let mut dut = DutClient::connect(&ip, timeout_secs.unwrap_or(5))?;
+    if let Some(secs) = mib_timeout_secs {
+        dut.set_mib_timeout(Duration::from_secs(secs));
+    }
+    dut.set_interface(interface.unwrap_or_default());
+    dut.set_supports_320(supports_320.unwrap_or(false));
+    let init_warning = match send_init {
+        Some(false) => None,
+        _ => dut.ate_init().err(),
+    };
+    let endpoint = scpi::describe_endpoint(dut.peer());
     app_state.dut = Some(dut);
-    Ok(())
+    app_state.dut_alive = true;
+    if keep_alive.unwrap_or(false) {
+        let stop = Arc::new(AtomicBool::new(false));
+        spawn_dut_keepalive(app, Arc::clone(&stop), DUT_KEEPALIVE_IDLE);
+        app_state.dut_keepalive_stop = Some(stop);
+    }
+    Ok(DutConnection { endpoint, init_warning })
+}
+
+/// Open RX on the DUT outside a sweep, on `chain` when given.
+#[tauri::command]
+fn dut_open_rx(cf_mhz: u32, bw_mhz: u32, chain: Option<u8>, state: State<Mutex<AppState>>) -> Result<(), String> {
+    let mut app_state = lock_for_console(&state)?;
+    let dut = app_state.dut.as_mut().ok_or("Not connected to DUT")?;
+    dut.open_rx(cf_mhz, bw_mhz, chain)
+}
+
+/// Read the DUT's RX counters on the interface for `cf_mhz`, with the
+/// OK count for `bw_mhz`.
+#[tauri::command]
+fn dut_read_mib(cf_mhz: u32, bw_mhz: u32, state: State<Mutex<AppState>>) -> Result<dut::MibResult, String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let dut = app_state.dut.as_mut().ok_or("Not connected to DUT")?;
+    dut.query_mib(cf_mhz, bw_mhz)
 }
 
 #[tauri::command]
-fn disconnect_dut(state: State<Mutex<AppState>>) -> Result<(), String> {
+fn disconnect_dut(state: State<Mutex<AppState>>, dut_log: State<DutLogFollower>) -> Result<(), String> {
+    dut_log.stop();
     let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    app_state.stop_dut_keepalive();
     app_state.dut = None;
     Ok(())
 }
 
+/// Slot names double as instrument file names inside quoted SCPI strings.
+fn validate_slot(slot: &str) -> Result<(), String> {
+    let valid = !slot.is_empty()
+        && slot.len() <= 32
+        && slot.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        return Err(format!(
+            "Invalid slot name '{}': use 1-32 letters, digits, '_' or '-'",
+            slot
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Clone, serde::Serialize)]
+struct WaveformLoadProgress {
+    slot: String,
+    bytes_read: u64,
+    total: u64,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct WaveformLoaded {
+    slot: String,
+    info: WaveformInfo,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct WaveformLoadFailed {
+    slot: String,
+    error: String,
+}
+
+/// The one running waveform load. Taking it sets `AppState::loading`;
+/// dropping it, however the worker exits, is the only place that clears
+/// the flag, so a finished load can never clear a later one's.
+struct LoadTicket(Arc<AtomicBool>);
+
+impl LoadTicket {
+    fn take(loading: &Arc<AtomicBool>) -> Result<Self, String> {
+        loading
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .map(|_| Self(Arc::clone(loading)))
+            .map_err(|_| "Another waveform is still loading".to_string())
+    }
+}
+
+impl Drop for LoadTicket {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Holds a load worker's ticket and reports a panic as a failed load.
+struct LoadingGuard {
+    app: AppHandle,
+    slot: String,
+    _ticket: LoadTicket,
+}
+
+impl Drop for LoadingGuard {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            let _ = self.app.emit(
+                "waveform-load-failed",
+                WaveformLoadFailed {
+                    slot: self.slot.clone(),
+                    error: "Loading stopped unexpectedly".into(),
+                },
+            );
+        }
+    }
+}
+
+/// Start loading a waveform file into a slot on a worker thread and return
+/// immediately.
+///
+/// Emits `waveform-load-progress` while the file is read, then either
+/// `waveform-loaded` or `waveform-load-failed`. Only one load runs at a
+/// time; the slot keeps its previous waveform if loading fails.
+///
+/// `gap_us` is the idle gap after the burst and may be fractional.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn load_waveform(
+    file_path: String,
+    bw_mhz: usize,
+    gap_us: Option<f64>,
+    options: Option<LoadOptions>,
+    repeat_in_buffer: Option<usize>,
+    slot: Option<String>,
+    app: AppHandle,
+    state: State<Mutex<AppState>>,
+) -> Result<(), String> {
+    let slot = slot.unwrap_or_else(|| DEFAULT_SLOT.to_string());
+    validate_slot(&slot)?;
+    let mut options = options.unwrap_or_default();
+    let gap_us = gap_us.unwrap_or(DEFAULT_GAP_US);
+
+    let ticket = {
+        let app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+        let ticket = LoadTicket::take(&app_state.loading)?;
+        if let Some(vsg) = app_state.vsg.as_ref() {
+            options.arb = vsg.arb_limits();
+        }
+        ticket
+    };
+
+    std::thread::spawn(move || {
+        let guard = LoadingGuard {
+            app: app.clone(),
+            slot: slot.clone(),
+            _ticket: ticket,
+        };
+        let progress = |bytes_read, total| {
+            let _ = app.emit(
+                "waveform-load-progress",
+                WaveformLoadProgress {
+                    slot: slot.clone(),
+                    bytes_read,
+                    total,
+                },
+            );
+        };
+        let result = waveform::load_waveform_file_with_progress(
+            &file_path,
+            bw_mhz,
+            gap_us,
+            &options,
+            &progress,
+        )
+        .and_then(|(mut data, mut info)| {
+            if let Some(count) = repeat_in_buffer {
+                waveform::repeat_in_buffer(&mut data, &mut info, count, options.arb)?;
+            }
+            Ok((data, info))
+        });
+
+        let state = app.state::<Mutex<AppState>>();
+        let mut app_state = match state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // The slot is set before the ticket goes, and the UI hears of it after
+        match result {
+            Ok((data, info)) => {
+                app_state.set_waveform(&slot, data, info.clone(), file_path);
+                drop(app_state);
+                drop(guard);
+                let _ = app.emit("waveform-loaded", WaveformLoaded { slot, info });
+            }
+            Err(error) => {
+                drop(app_state);
+                drop(guard);
+                let _ = app.emit("waveform-load-failed", WaveformLoadFailed { slot, error });
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Load I and Q from two raw int16 files into a slot.
 #[tauri::command]
-fn load_waveform(file_path: String, bw_mhz: usize, frame_interval_us: usize, state: State<Mutex<AppState>>) -> Result<WaveformInfo, String> {
-    let (data, info) = waveform::load_waveform_file(&file_path, bw_mhz, frame_interval_us)?;
+fn load_waveform_pair(
+    i_path: String,
+    q_path: String,
+    endianness: Option<waveform::Endianness>,
+    options: Option<LoadOptions>,
+    slot: Option<String>,
+    state: State<Mutex<AppState>>,
+) -> Result<WaveformInfo, String> {
+    let slot = slot.unwrap_or_else(|| DEFAULT_SLOT.to_string());
+    validate_slot(&slot)?;
+    let mut options = options.unwrap_or_default();
+    if endianness.is_some() {
+        options.endianness = endianness;
+    }
 
     let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
-    app_state.wfm_data = Some(data);
+    if let Some(vsg) = app_state.vsg.as_ref() {
+        options.arb = vsg.arb_limits();
+    }
+    let (data, info) = waveform::load_waveform_pair(&i_path, &q_path, &options)?;
+    let file_path = format!("{} + {}", i_path, q_path);
+    app_state.set_waveform(&slot, data, info.clone(), file_path);
+    Ok(info)
+}
+
+/// Synthesize a test signal and store it in a slot like a loaded file.
+#[tauri::command]
+fn generate_waveform(
+    signal: TestSignal,
+    duration_us: f64,
+    sample_rate_hz: f64,
+    slot: Option<String>,
+    state: State<Mutex<AppState>>,
+) -> Result<WaveformInfo, String> {
+    let slot = slot.unwrap_or_else(|| DEFAULT_SLOT.to_string());
+    validate_slot(&slot)?;
+    let (data, info) = waveform::generate_waveform(&signal, duration_us, sample_rate_hz)?;
+
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    app_state.set_waveform(&slot, data, info.clone(), info.file_name.clone());
 
     Ok(info)
 }
 
 #[tauri::command]
-fn export_waveform(file_path: String, state: State<Mutex<AppState>>) -> Result<(), String> {
+fn list_waveforms(state: State<Mutex<AppState>>) -> Result<Vec<WaveformSlot>, String> {
     let app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
 
-    let wfm_data = app_state
-        .wfm_data
-        .as_ref()
-        .ok_or("No waveform data to export")?;
+    let mut slots: Vec<WaveformSlot> = app_state
+        .waveforms
+        .iter()
+        .map(|(slot, w)| WaveformSlot {
+            slot: slot.clone(),
+            file_path: w.file_path.clone(),
+            info: w.info.clone(),
+            memory_bytes: w.data.len() + w.markers.len(),
+        })
+        .collect();
+    slots.sort_by(|a, b| a.slot.cmp(&b.slot));
+    Ok(slots)
+}
 
-    std::fs::write(&file_path, wfm_data)
-        .map_err(|e| format!("Failed to write file: {}", e))
+#[tauri::command]
+fn remove_waveform(slot: String, state: State<Mutex<AppState>>) -> Result<(), String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    app_state
+        .waveforms
+        .remove(&slot)
+        .map(|_| ())
+        .ok_or_else(|| format!("No waveform loaded in slot '{}'", slot))
 }
 
 #[tauri::command]
+fn get_waveform_preview(
+    max_points: usize,
+    slot: Option<String>,
+    state: State<Mutex<AppState>>,
+) -> Result<WaveformPreview, String> {
+    let app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let loaded = app_state.waveform(slot.as_deref().unwrap_or(DEFAULT_SLOT))?;
+
+    waveform::waveform_preview(&loaded.data, max_points)
+}
+
+#[tauri::command]
+fn compute_spectrum(
+    fft_size: usize,
+    slot: Option<String>,
+    state: State<Mutex<AppState>>,
+) -> Result<Spectrum, String> {
+    let app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let loaded = app_state.waveform(slot.as_deref().unwrap_or(DEFAULT_SLOT))?;
+
+    waveform::compute_spectrum(&loaded.data, fft_size)
+}
+
+/// 99% occupied bandwidth of a slot in MHz, for a waveform played at
+/// `sample_rate_hz`.
+#[tauri::command]
+fn occupied_bandwidth(
+    sample_rate_hz: f64,
+    slot: Option<String>,
+    state: State<Mutex<AppState>>,
+) -> Result<f64, String> {
+    let app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let loaded = app_state.waveform(slot.as_deref().unwrap_or(DEFAULT_SLOT))?;
+
+    let start = loaded.info.pre_gap_samples;
+    let end = loaded.info.sample_count - loaded.info.padding_samples;
+    let obw = waveform::occupied_bandwidth(&loaded.data[start * 4..end * 4], waveform::OBW_FFT_SIZE)?;
+    Ok(obw * sample_rate_hz / 1e6)
+}
+
+/// Drop all cached conversions; returns the bytes released.
+#[tauri::command]
+fn clear_waveform_cache() -> usize {
+    waveform::clear_cache()
+}
+
+#[tauri::command]
+fn list_mat_variables(file_path: String) -> Result<Vec<MatVariable>, String> {
+    waveform::list_mat_variables(&file_path)
+}
+
+#[tauri::command]
+fn set_waveform_mapping(entries: Vec<WaveformMapEntry>, state: State<Mutex<AppState>>) -> Result<(), String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    app_state.waveform_map = entries;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_waveform_mapping(state: State<Mutex<AppState>>) -> Result<Vec<WaveformMapEntry>, String> {
+    let app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    Ok(app_state.waveform_map.clone())
+}
+
+#[tauri::command]
+fn export_waveform(
+    file_path: String,
+    format: Option<ExportFormat>,
+    slot: Option<String>,
+    state: State<Mutex<AppState>>,
+) -> Result<(), String> {
+    let app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let loaded = app_state.waveform(slot.as_deref().unwrap_or(DEFAULT_SLOT))?;
+
+    waveform::export_waveform(&loaded.data, &file_path, format.unwrap_or_default())?;
+    waveform::write_sidecar(&file_path, &WaveformSidecar::from_info(&loaded.info))
+}
+
+/// Compare `other_path`, or the slot's loaded waveform when no second file
+/// is given, against the reference .WAVEFORM file `file_path`.
+#[tauri::command]
+fn compare_waveforms(
+    file_path: String,
+    other_path: Option<String>,
+    slot: Option<String>,
+    state: State<Mutex<AppState>>,
+) -> Result<waveform::WaveformDiff, String> {
+    match other_path {
+        Some(other) => waveform::compare_waveform_files(&file_path, &other),
+        None => {
+            let app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+            let loaded = app_state.waveform(slot.as_deref().unwrap_or(DEFAULT_SLOT))?;
+            waveform::compare_waveform_with_file(&loaded.data, &file_path)
+        }
+    }
+}
+
+/// Write the per-sample power of a slot as CSV, for checking burst/idle
+/// timing. `time_column` labels rows in µs instead of sample index.
+#[tauri::command]
+fn export_envelope(
+    file_path: String,
+    max_points: Option<usize>,
+    time_column: Option<bool>,
+    slot: Option<String>,
+    state: State<Mutex<AppState>>,
+) -> Result<(), String> {
+    let app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let loaded = app_state.waveform(slot.as_deref().unwrap_or(DEFAULT_SLOT))?;
+
+    let sample_rate_hz = if time_column.unwrap_or(false) {
+        Some(loaded.info.sample_rate_hz.ok_or(
+            "The waveform's sample rate is unknown; export with sample indices instead",
+        )?)
+    } else {
+        None
+    };
+    waveform::export_envelope(&loaded.data, &file_path, max_points, sample_rate_hz)
+}
+
+/// Write the steps of the last finished or cancelled sweep as CSV, with
+/// the time each step spent in every phase.
+#[tauri::command]
+fn export_sweep_csv(file_path: String, state: State<Mutex<AppState>>) -> Result<(), String> {
+    let app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    if app_state.last_sweep.is_empty() {
+        return Err("No sweep results to export".into());
+    }
+    sweep::export_csv(&app_state.last_sweep, &file_path)
+}
+
+/// Start playback of a slot. Returns warnings (e.g. a bandwidth that
+/// disagrees with the waveform's metadata) for the UI to show.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
 fn play_waveform(
     cf: f64,
     bw_mhz: f64,
     amp: f64,
     repeat_count: u32,
+    marker_to_event1: Option<bool>,
+    trigger_source: Option<TriggerSource>,
+    trigger_delay_s: Option<f64>,
+    retrigger: Option<Retrigger>,
+    force_download: Option<bool>,
+    download_chunk_bytes: Option<usize>,
+    slot: Option<String>,
+    channel: Option<u8>,
+    rf_blanking: Option<bool>,
+    app: AppHandle,
     state: State<Mutex<AppState>>,
-) -> Result<(), String> {
+    download_cancel: State<DownloadCancel>,
+) -> Result<Vec<String>, String> {
+    download_cancel.0.store(false, Ordering::SeqCst);
     let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    app_state.stop_playback_monitor();
 
-    if app_state.vsg.is_none() {
-        return Err("Not connected to instrument".into());
+    let slot = slot.unwrap_or_else(|| DEFAULT_SLOT.to_string());
+    let marker_to_event1 = marker_to_event1.unwrap_or(false);
+    let fs = bw_mhz * 2.0 * 1e6;
+    app_state.fit_waveform_to_arb(&slot)?;
+
+    // Destructure to borrow the slot's bytes while driving the instrument
+    let AppState { ref mut vsg, ref waveforms, .. } = *app_state;
+    let vsg = vsg.as_mut().ok_or("Not connected to instrument")?;
+    let loaded = waveforms
+        .get(&slot)
+        .ok_or_else(|| format!("No waveform loaded in slot '{}'", slot))?;
+
+    if repeat_count > 0 {
+        loaded.info.check_sequence_repeat()?;
     }
-    let wfm_data = app_state
-        .wfm_data
-        .clone()
-        .ok_or("No waveform file loaded")?;
+
+    let mut warnings = Vec::new();
+    if let Some(expected) = loaded.info.sample_rate_hz {
+        if (expected - fs).abs() > 1.0 {
+            warnings.push(format!(
+                "'{}' was built for {} MS/s but is playing at {} MS/s (BW {} MHz)",
+                loaded.info.file_name,
+                expected / 1e6,
+                fs / 1e6,
+                bw_mhz
+            ));
+        }
+    }
+
+    vsg.set_channel(channel.unwrap_or(1))?;
+    vsg.configure(cf, fs, amp)?;
+    let progress = |bytes_sent, total| {
+        let _ = app.emit(
+            "wfm-download-progress",
+            WfmDownloadProgress {
+                slot: slot.clone(),
+                bytes_sent,
+                total,
+            },
+        );
+    };
+    let downloaded = vsg.ensure_wfm(
+        &slot,
+        &loaded.data,
+        Some(&loaded.markers),
+        loaded.hash,
+        force_download.unwrap_or(false),
+        &DownloadControl {
+            chunk_bytes: download_chunk_bytes.unwrap_or(vsg::DEFAULT_DOWNLOAD_CHUNK),
+            progress: &progress,
+            cancel: &download_cancel.0,
+        },
+    )?;
+    let _ = app.emit(
+        "waveform-download",
+        WaveformDownload {
+            slot: slot.clone(),
+            downloaded,
+            skipped_total: vsg.downloads_skipped(),
+        },
+    );
+    if downloaded {
+        warnings.extend(vsg.set_header(fs, vsg.iq_scale())?);
+    }
+    // After the header, which resets the marker routing blanking uses
+    if rf_blanking.unwrap_or(false) {
+        vsg.set_rf_blanking(true)?;
+    }
+
+    if repeat_count > 0 {
+        let trigger = trigger_source.unwrap_or_default();
+        let timing = TriggerTiming {
+            delay_s: trigger_delay_s.unwrap_or(0.0),
+            retrigger: retrigger.unwrap_or_default(),
+        };
+        vsg.play_with_repeat(&slot, repeat_count, marker_to_event1, trigger, timing)?;
+
+        // The monitor times from now, which is only the start for a bus trigger
+        if trigger == TriggerSource::Bus {
+            let stop = Arc::new(AtomicBool::new(false));
+            let burst_secs = loaded.info.duration_secs_at(fs);
+            spawn_playback_monitor(app, Arc::clone(&stop), burst_secs, repeat_count);
+            app_state.playback_stop = Some(stop);
+        } else {
+            warnings.push("Playback is armed and waits for its trigger".into());
+        }
+    } else {
+        vsg.play(&slot, marker_to_event1)?;
+    }
+
+    Ok(warnings)
+}
+
+/// Sequence id used by `play_sequence`.
+const SEQUENCE_ID: &str = "waveplay_seq";
+
+/// One entry of `play_sequence`: a loaded slot and how often it repeats.
+#[derive(serde::Deserialize)]
+struct SequenceEntry {
+    slot: String,
+    repeats: u32,
+    /// Enable marker 1 on this segment.
+    #[serde(default)]
+    markers: bool,
+}
+
+/// Chain loaded slots into one sequence and play it continuously. Slots the
+/// instrument does not already hold are downloaded first. Returns warnings
+/// like `play_waveform`.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn play_sequence(
+    segments: Vec<SequenceEntry>,
+    cf: f64,
+    bw_mhz: f64,
+    amp: f64,
+    marker_to_event1: Option<bool>,
+    app: AppHandle,
+    state: State<Mutex<AppState>>,
+    download_cancel: State<DownloadCancel>,
+) -> Result<Vec<String>, String> {
+    download_cancel.0.store(false, Ordering::SeqCst);
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    app_state.stop_playback_monitor();
 
     let fs = bw_mhz * 2.0 * 1e6;
-    let vsg = app_state.vsg.as_mut().unwrap();
+    for entry in &segments {
+        app_state.fit_waveform_to_arb(&entry.slot)?;
+    }
+
+    let list: Vec<(&str, u32, bool)> = segments
+        .iter()
+        .map(|e| (e.slot.as_str(), e.repeats, e.markers))
+        .collect();
+
+    let AppState { ref mut vsg, ref waveforms, .. } = *app_state;
+    let vsg = vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.check_sequence(&list)?;
+    vsg.configure(cf, fs, amp)?;
+
+    let mut warnings = Vec::new();
+    for entry in &segments {
+        let slot = &entry.slot;
+        let loaded = waveforms
+            .get(slot)
+            .ok_or_else(|| format!("No waveform loaded in slot '{}'", slot))?;
+        if let Some(expected) = loaded.info.sample_rate_hz {
+            if (expected - fs).abs() > 1.0 {
+                warnings.push(format!(
+                    "'{}' was built for {} MS/s but is playing at {} MS/s (BW {} MHz)",
+                    loaded.info.file_name,
+                    expected / 1e6,
+                    fs / 1e6,
+                    bw_mhz
+                ));
+            }
+        }
+
+        let progress = |bytes_sent, total| {
+            let _ = app.emit(
+                "wfm-download-progress",
+                WfmDownloadProgress {
+                    slot: slot.clone(),
+                    bytes_sent,
+                    total,
+                },
+            );
+        };
+        let downloaded = vsg.ensure_wfm(
+            slot,
+            &loaded.data,
+            Some(&loaded.markers),
+            loaded.hash,
+            false,
+            &DownloadControl {
+                chunk_bytes: vsg::DEFAULT_DOWNLOAD_CHUNK,
+                progress: &progress,
+                cancel: &download_cancel.0,
+            },
+        )?;
+        let _ = app.emit(
+            "waveform-download",
+            WaveformDownload {
+                slot: slot.clone(),
+                downloaded,
+                skipped_total: vsg.downloads_skipped(),
+            },
+        );
+        if downloaded {
+            warnings.extend(vsg.set_header(fs, vsg.iq_scale())?);
+        }
+    }
+
+    vsg.play_sequence(SEQUENCE_ID, &list, marker_to_event1.unwrap_or(false))?;
+
+    Ok(warnings)
+}
+
+/// Play a waveform already stored on the instrument, e.g. from an earlier
+/// session or another tool, without downloading anything. `fs` is the ARB
+/// sample clock in Hz.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn play_instrument_waveform(
+    wfm_id: String,
+    cf: f64,
+    fs: f64,
+    amp: f64,
+    repeat_count: u32,
+    marker_to_event1: Option<bool>,
+    trigger_source: Option<TriggerSource>,
+    app: AppHandle,
+    state: State<Mutex<AppState>>,
+) -> Result<Vec<String>, String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    app_state.stop_playback_monitor();
+
+    let marker_to_event1 = marker_to_event1.unwrap_or(false);
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    let file = vsg.find_waveform(&wfm_id)?;
     vsg.configure(cf, fs, amp)?;
-    vsg.download_wfm(&wfm_data, "waveform")?;
 
+    let mut warnings = Vec::new();
     if repeat_count > 0 {
-        vsg.play_with_repeat("waveform", repeat_count)?;
+        let trigger = trigger_source.unwrap_or_default();
+        vsg.play_with_repeat(&wfm_id, repeat_count, marker_to_event1, trigger, TriggerTiming::default())?;
+
+        if trigger == TriggerSource::Bus {
+            // 4 bytes per IQ sample; a .wv header makes this a slight overestimate
+            let burst_secs = (file.size / 4) as f64 / fs;
+            let stop = Arc::new(AtomicBool::new(false));
+            spawn_playback_monitor(app, Arc::clone(&stop), burst_secs, repeat_count);
+            app_state.playback_stop = Some(stop);
+        } else {
+            warnings.push("Playback is armed and waits for its trigger".into());
+        }
     } else {
-        vsg.play("waveform")?;
+        vsg.play(&wfm_id, marker_to_event1)?;
     }
 
+    Ok(warnings)
+}
+
+#[derive(Clone, serde::Serialize)]
+struct PlaybackProgress {
+    bursts_done: u32,
+    bursts_total: u32,
+    percent: f64,
+}
+
+/// Emit `playback-progress` roughly every 500 ms while a finite sequence plays,
+/// then `playback-finished` once all bursts are done.
+///
+/// The Keysight sequence player has no cheap repetition counter query, so the
+/// burst count is estimated from elapsed time and the per-burst duration.
+/// Setting `stop` ends the monitor within one polling tick.
+fn spawn_playback_monitor(app: AppHandle, stop: Arc<AtomicBool>, burst_secs: f64, bursts_total: u32) {
+    const EMIT_INTERVAL: Duration = Duration::from_millis(500);
+    const TICK: Duration = Duration::from_millis(50);
+
+    std::thread::spawn(move || {
+        let start = Instant::now();
+        let total_secs = burst_secs * bursts_total as f64;
+        let mut last_emit = start;
+
+        while !stop.load(Ordering::SeqCst) {
+            std::thread::sleep(TICK);
+            let elapsed = start.elapsed().as_secs_f64();
+            let finished = elapsed >= total_secs;
+            if !finished && last_emit.elapsed() < EMIT_INTERVAL {
+                continue;
+            }
+            last_emit = Instant::now();
+
+            let bursts_done = if finished {
+                bursts_total
+            } else {
+                ((elapsed / burst_secs) as u32).min(bursts_total)
+            };
+            let _ = app.emit(
+                "playback-progress",
+                PlaybackProgress {
+                    bursts_done,
+                    bursts_total,
+                    percent: bursts_done as f64 * 100.0 / bursts_total as f64,
+                },
+            );
+
+            if finished {
+                let _ = app.emit("playback-finished", ());
+                break;
+            }
+        }
+    });
+}
+
+#[derive(serde::Serialize)]
+struct ConnectionStatus {
+    vsg_connected: bool,
+    /// False when connected but the instrument stopped answering.
+    vsg_alive: bool,
+    dut_connected: bool,
+    dut_alive: bool,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct ConnectionLost {
+    /// "vsg" or "dut".
+    device: &'static str,
+    error: String,
+}
+
+/// Ping the connected VSG and DUT. A connection that stops answering is
+/// reported once with a "connection-lost" event and stays connected but
+/// not alive until a ping succeeds again or it is reconnected.
+#[tauri::command]
+fn get_connection_status(app: AppHandle, state: State<Mutex<AppState>>) -> Result<ConnectionStatus, String> {
+    let mut app_state = lock_for_console(&state)?;
+    let app_state = &mut *app_state;
+    let report = |device, alive: &mut bool, result: Result<(), String>| {
+        match result {
+            Ok(()) => *alive = true,
+            Err(error) => {
+                if *alive {
+                    let _ = app.emit("connection-lost", ConnectionLost { device, error });
+                }
+                *alive = false;
+            }
+        }
+    };
+    if let Some(vsg) = app_state.vsg.as_mut() {
+        report("vsg", &mut app_state.vsg_alive, vsg.ping());
+    }
+    if let Some(dut) = app_state.dut.as_mut() {
+        // Reconnects a DUT the keep-alive or a failed command dropped
+        report("dut", &mut app_state.dut_alive, dut.ensure_connected().and_then(|()| dut.ping()));
+    }
+    Ok(ConnectionStatus {
+        vsg_connected: app_state.vsg.is_some(),
+        vsg_alive: app_state.vsg.is_some() && app_state.vsg_alive,
+        dut_connected: app_state.dut.is_some(),
+        dut_alive: app_state.dut.is_some() && app_state.dut_alive,
+    })
+}
+
+/// Ping the DUT whenever it has been idle for `idle`, until `stop` is set.
+/// A tick that finds the state locked (a sweep, or any other command in
+/// flight) is skipped, so pings never interleave with real traffic. A
+/// failed ping marks the DUT not alive and is reported as
+/// "connection-lost"; pings resume once `get_connection_status` has
+/// reconnected it and found it alive again.
+fn spawn_dut_keepalive(app: AppHandle, stop: Arc<AtomicBool>, idle: Duration) {
+    const TICK: Duration = Duration::from_secs(1);
+
+    std::thread::spawn(move || {
+        while !stop.load(Ordering::SeqCst) {
+            std::thread::sleep(TICK);
+            let state = app.state::<Mutex<AppState>>();
+            let Ok(mut app_state) = state.try_lock() else {
+                continue;
+            };
+            // The DUT may have been replaced while this thread slept
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            let app_state = &mut *app_state;
+            let Some(dut) = app_state.dut.as_mut() else {
+                break;
+            };
+            if !app_state.dut_alive || !dut.keepalive_due(idle) {
+                continue;
+            }
+            if let Err(error) = dut.ping() {
+                app_state.dut_alive = false;
+                let _ = app.emit("connection-lost", ConnectionLost { device: "dut", error });
+            }
+        }
+    });
+}
+
+/// Lock the app state for a console command without waiting: a running
+/// sweep holds the lock, and the console must not interleave with it.
+fn lock_for_console(state: &Mutex<AppState>) -> Result<MutexGuard<'_, AppState>, String> {
+    match state.try_lock() {
+        Ok(guard) => Ok(guard),
+        Err(TryLockError::WouldBlock) => Err("Instrument is busy (sweep or download in progress)".into()),
+        Err(TryLockError::Poisoned(e)) => Err(format!("Lock failed: {}", e)),
+    }
+}
+
+/// Send a raw SCPI command from the debug console.
+#[tauri::command]
+fn scpi_write(cmd: String, state: State<Mutex<AppState>>) -> Result<(), String> {
+    let mut app_state = lock_for_console(&state)?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.raw_write(&cmd)
+}
+
+/// Send a raw SCPI query from the debug console and return the response.
+#[tauri::command]
+fn scpi_query(cmd: String, state: State<Mutex<AppState>>) -> Result<String, String> {
+    let mut app_state = lock_for_console(&state)?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    vsg.raw_query(&cmd)
+}
+
+/// Run an ATE command on the DUT from the debug console and return its
+/// output. Refused while a sweep is using the DUT.
+#[tauri::command]
+fn dut_raw_command(args: Vec<String>, state: State<Mutex<AppState>>) -> Result<String, String> {
+    let mut app_state = lock_for_console(&state)?;
+    let dut = app_state.dut.as_mut().ok_or("Not connected to DUT")?;
+    dut.raw_command(args)
+}
+
+/// Emitted as `dut-file-progress` while a file is fetched from the DUT.
+#[derive(Clone, serde::Serialize)]
+struct DutFileProgress {
+    remote_path: String,
+    bytes_received: u64,
+    total: u64,
+}
+
+/// Copy `remote_path` on the DUT to `local_path`, emitting progress.
+/// Returns the file size. Refused while a sweep is using the DUT.
+#[tauri::command]
+fn dut_fetch_file(
+    remote_path: String,
+    local_path: String,
+    app: AppHandle,
+    state: State<Mutex<AppState>>,
+) -> Result<u64, String> {
+    let mut app_state = lock_for_console(&state)?;
+    let dut = app_state.dut.as_mut().ok_or("Not connected to DUT")?;
+    let progress = |bytes_received, total| {
+        let _ = app.emit(
+            "dut-file-progress",
+            DutFileProgress { remote_path: remote_path.clone(), bytes_received, total },
+        );
+    };
+    dut.fetch_file(&remote_path, std::path::Path::new(&local_path), &progress)
+}
+
+/// Emitted as `dut-log` for each line of the DUT kernel log.
+#[derive(Clone, serde::Serialize)]
+struct DutLogLine {
+    line: String,
+}
+
+/// Emitted as `dut-log-ended` when the DUT log stream stops, with the
+/// error that ended it, if any.
+#[derive(Clone, serde::Serialize)]
+struct DutLogEnded {
+    error: Option<String>,
+}
+
+/// The running DUT log follower. Kept outside `AppState` so it can be
+/// stopped while a sweep holds the state.
+#[derive(Default)]
+struct DutLogFollower(Mutex<Option<(DutLogStop, JoinHandle<()>)>>);
+
+impl DutLogFollower {
+    /// Lock the follower slot, recovering it from a poisoned lock.
+    fn lock(&self) -> MutexGuard<'_, Option<(DutLogStop, JoinHandle<()>)>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Stop the follower, if any, and wait for its thread to exit.
+    fn stop(&self) {
+        Self::stop_locked(&mut self.lock());
+    }
+
+    fn stop_locked(running: &mut Option<(DutLogStop, JoinHandle<()>)>) {
+        if let Some((stop, thread)) = running.take() {
+            stop.stop();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Stream the DUT kernel log as `dut-log` events until `stop_dut_log`,
+/// replacing a stream already running. The log has its own connection,
+/// but starting it is refused while a sweep is using the DUT. The old
+/// stream is stopped and the new one stored under one lock, so concurrent
+/// starts cannot leave a follower running unrecorded.
+#[tauri::command]
+fn start_dut_log(
+    app: AppHandle,
+    state: State<Mutex<AppState>>,
+    dut_log: State<DutLogFollower>,
+) -> Result<(), String> {
+    let mut running = dut_log.lock();
+    DutLogFollower::stop_locked(&mut running);
+    let (mut log, stop) = {
+        let app_state = lock_for_console(&state)?;
+        app_state.dut.as_ref().ok_or("Not connected to DUT")?.follow_log()?
+    };
+    let thread = std::thread::spawn(move || {
+        let error = loop {
+            match log.next_line() {
+                Ok(Some(line)) => {
+                    let _ = app.emit("dut-log", DutLogLine { line });
+                }
+                Ok(None) => break None,
+                Err(e) => break Some(e),
+            }
+        };
+        let _ = app.emit("dut-log-ended", DutLogEnded { error });
+    });
+    *running = Some((stop, thread));
     Ok(())
 }
 
+/// Stop the DUT log stream started by `start_dut_log`.
 #[tauri::command]
-fn stop_waveform(state: State<Mutex<AppState>>) -> Result<(), String> {
+fn stop_dut_log(dut_log: State<DutLogFollower>) {
+    dut_log.stop();
+}
+
+/// Switch between modulated playback and CW with the RF output left on.
+/// Without `enabled` the current state is flipped. Returns the new state.
+#[tauri::command]
+fn toggle_modulation(enabled: Option<bool>, state: State<Mutex<AppState>>) -> Result<bool, String> {
+    let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    let vsg = app_state.vsg.as_mut().ok_or("Not connected to instrument")?;
+    let enabled = match enabled {
+        Some(enabled) => enabled,
+        None => !vsg.get_status()?.modulation_on,
+    };
+    vsg.set_modulation(enabled)?;
+    Ok(enabled)
+}
+
+/// Stop playback on RF output `channel`, or on every output without one.
+#[tauri::command]
+fn stop_waveform(channel: Option<u8>, state: State<Mutex<AppState>>) -> Result<(), String> {
     let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    app_state.stop_playback_monitor();
 
     let vsg = app_state
         .vsg
         .as_mut()
         .ok_or("Not connected to instrument")?;
-    vsg.stop()
+    match channel {
+        Some(channel) => {
+            vsg.set_channel(channel)?;
+            vsg.stop()
+        }
+        None => vsg.stop_all(),
+    }
 }
 
+/// Emitted as `sweep-error` when the DUT stops answering and the sweep is
+/// aborted. `step_index` is `None` during warm-up.
 #[derive(Clone, serde::Serialize)]
-struct SweepProgress {
-    current_power: f64,
-    step_index: usize,
-    total_steps: usize,
-    rec_rx_count: Option<u32>,
-    rx_ok_count: Option<u32>,
+struct SweepError {
+    step_index: Option<usize>,
+    message: String,
+}
+
+/// Stop the instrument after a DUT failure that retries did not cure and
+/// report it. The checkpoint is kept so the sweep can be resumed.
+fn abort_sweep(
+    app: &AppHandle,
+    vsg: &mut VsgInstrument,
+    power_list: bool,
+    step_index: Option<usize>,
+    message: String,
+) -> Result<(), String> {
+    if power_list {
+        let _ = vsg.end_power_list();
+    }
+    let _ = vsg.stop();
+    let _ = app.emit(
+        "sweep-error",
+        SweepError {
+            step_index,
+            message: message.clone(),
+        },
+    );
+    Err(message)
+}
+
+#[derive(Clone, serde::Serialize)]
+struct SweepDone {
+    steps_completed: usize,
+    waveform_file: Option<String>,
+    timing: TimingSummary,
+}
+
+/// Emitted as `waveform-download` whenever a slot is sent to the
+/// instrument, so the UI can show when a download was skipped.
+#[derive(Clone, serde::Serialize)]
+struct WaveformDownload {
+    slot: String,
+    downloaded: bool,
+    /// Downloads skipped since connecting.
+    skipped_total: u32,
+}
+
+/// Emitted as `wfm-download-progress` while a waveform is sent to the
+/// instrument.
+#[derive(Clone, serde::Serialize)]
+struct WfmDownloadProgress {
+    slot: String,
+    bytes_sent: u64,
+    total: u64,
+}
+
+/// Cancels the waveform download of `play_waveform`. Kept outside
+/// `AppState` because the download runs with the state locked.
+struct DownloadCancel(AtomicBool);
+
+/// Abort a waveform download started by `play_waveform`.
+#[tauri::command]
+fn cancel_download(download_cancel: State<DownloadCancel>) {
+    download_cancel.0.store(true, Ordering::SeqCst);
+}
+
+/// Emitted as `sweep-warning` for conditions that do not stop the sweep.
+#[derive(Clone, serde::Serialize)]
+struct SweepWarning {
+    message: String,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct SweepWarmup {
+    burst_index: u32,
+    total_bursts: u32,
+}
+
+/// Path of the sweep recovery file in the app data dir, creating the dir.
+fn recovery_path(app: &AppHandle) -> Result<PathBuf, String> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data dir: {}", e))?;
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create app data dir: {}", e))?;
+    Ok(sweep::checkpoint_path(&dir))
+}
+
+/// Set how much instrument and DUT traffic goes to `logs/io.log` in the app
+/// data dir. With `live`, each entry is also emitted as an "io-log" event.
+#[tauri::command]
+fn set_log_level(app: AppHandle, level: LogLevel, live: Option<bool>) -> Result<(), String> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to resolve app data dir: {}", e))?
+        .join("logs");
+    let hook: Option<iolog::LiveHook> = if live.unwrap_or(false) {
+        Some(Box::new(move |entry: &IoLogEntry| {
+            let _ = app.emit("io-log", entry.clone());
+        }))
+    } else {
+        None
+    };
+    iolog::configure(level, &dir, hook)
 }
 
 #[tauri::command]
@@ -143,6 +1584,7 @@ fn cancel_sweep(sweep_cancel: State<Arc<AtomicBool>>) {
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 fn power_sweep(
     cf: f64,
     bw_mhz: f64,
@@ -150,95 +1592,519 @@ fn power_sweep(
     start_power: f64,
     end_power: f64,
     step: f64,
+    warmup_bursts: Option<u32>,
+    auto_waveform: Option<bool>,
+    slot: Option<String>,
+    trigger_source: Option<TriggerSource>,
+    force_download: Option<bool>,
+    alc_off: Option<bool>,
+    download_chunk_bytes: Option<usize>,
+    hardware_list: Option<bool>,
+    list_dwell_s: Option<f64>,
+    trigger_delay_s: Option<f64>,
+    retrigger: Option<Retrigger>,
+    channel: Option<u8>,
+    rf_blanking: Option<bool>,
+    dut_interface: Option<WlanInterface>,
+    dut_chain: Option<u8>,
+    app: AppHandle,
+    state: State<Mutex<AppState>>,
+    sweep_cancel: State<Arc<AtomicBool>>,
+) -> Result<(), String> {
+    let params = PowerSweepParams {
+        cf,
+        bw_mhz,
+        cable_loss,
+        start_power,
+        end_power,
+        step,
+        warmup_bursts,
+        auto_waveform,
+        slot,
+        trigger_source,
+        force_download,
+        alc_off,
+        download_chunk_bytes,
+        hardware_list,
+        list_dwell_s,
+        trigger_delay_s,
+        retrigger,
+        channel,
+        rf_blanking,
+        dut_interface,
+        dut_chain,
+    };
+    run_power_sweep(&params, None, false, &app, &state, &sweep_cancel)
+}
+
+/// Return the checkpoint of a sweep that was interrupted by a crash or
+/// restart, if one exists.
+#[tauri::command]
+fn get_recoverable_sweep(app: AppHandle) -> Result<Option<SweepCheckpoint>, String> {
+    sweep::load_checkpoint(&recovery_path(&app)?)
+}
+
+/// Continue an interrupted sweep, skipping the steps it already completed.
+///
+/// The loaded waveform must be byte-identical to the one the sweep started
+/// with. A different instrument identity is refused unless
+/// `allow_instrument_change` is set.
+#[tauri::command]
+fn resume_recovered_sweep(
+    allow_instrument_change: Option<bool>,
     app: AppHandle,
     state: State<Mutex<AppState>>,
     sweep_cancel: State<Arc<AtomicBool>>,
+) -> Result<(), String> {
+    let checkpoint = sweep::load_checkpoint(&recovery_path(&app)?)?
+        .ok_or("No interrupted sweep to resume")?;
+    let params = checkpoint.params.clone();
+    run_power_sweep(
+        &params,
+        Some(checkpoint),
+        allow_instrument_change.unwrap_or(false),
+        &app,
+        &state,
+        &sweep_cancel,
+    )
+}
+
+#[tauri::command]
+fn discard_recovered_sweep(app: AppHandle) -> Result<(), String> {
+    sweep::clear_checkpoint(&recovery_path(&app)?)
+}
+
+fn run_power_sweep(
+    params: &PowerSweepParams,
+    recovered: Option<SweepCheckpoint>,
+    allow_instrument_change: bool,
+    app: &AppHandle,
+    state: &Mutex<AppState>,
+    sweep_cancel: &Arc<AtomicBool>,
 ) -> Result<(), String> {
     // Reset cancel flag
     sweep_cancel.store(false, Ordering::SeqCst);
-    let cancel_flag = Arc::clone(&sweep_cancel);
+    let cancel_flag = Arc::clone(sweep_cancel);
+    let PowerSweepParams {
+        cf,
+        bw_mhz,
+        cable_loss,
+        start_power,
+        ..
+    } = *params;
 
     let mut app_state = state.lock().map_err(|e| format!("Lock failed: {}", e))?;
+    app_state.stop_playback_monitor();
 
-    if app_state.vsg.is_none() {
+    let Some(ref vsg) = app_state.vsg else {
         return Err("Not connected to instrument".into());
+    };
+    // A loaded loss table stands in for the cable loss (see `sweep_offset`)
+    let loss = vsg.sweep_loss_db(cf, cable_loss);
+    let powers = params.power_steps()?;
+    for &power in &powers {
+        vsg::check_power_limit(power, loss, app_state.power_limit_dbm)?;
+    }
+    let list_dwell = params.list_dwell()?;
+
+    // DUT parameters: carrier frequency and BW in MHz (integers for ATE command)
+    let cf_mhz = (cf / 1e6).round() as u32;
+    let bw = bw_mhz.round() as u32;
+    if let Some(ref dut) = app_state.dut {
+        if bw_mhz.fract() != 0.0 {
+            return Err(format!("Unsupported DUT bandwidth {} MHz", bw_mhz));
+        }
+        dut.check_bandwidth(bw)?;
+        if let Some(chain) = params.dut_chain {
+            dut::check_chain(chain)?;
+        }
+    }
+
+    let slot = params.slot.clone().unwrap_or_else(|| DEFAULT_SLOT.to_string());
+
+    // Switch the slot to the waveform mapped for this channel, unless it is already loaded
+    if params.auto_waveform.unwrap_or(false) {
+        let entry = waveform::resolve_waveform(&app_state.waveform_map, cf_mhz, bw)?.clone();
+        let current = app_state.waveforms.get(&slot).map(|w| w.file_path.as_str());
+        if current != Some(entry.file_path.as_str()) {
+            let options = LoadOptions {
+                arb: app_state.vsg.as_ref().unwrap().arb_limits(),
+                ..Default::default()
+            };
+            let (data, info) = waveform::load_waveform_file(
+                &entry.file_path,
+                entry.bw_mhz as usize,
+                entry.frame_interval_us as f64,
+                &options,
+            )?;
+            app_state.set_waveform(&slot, data, info, entry.file_path);
+        }
     }
-    let wfm_data = app_state
-        .wfm_data
-        .clone()
-        .ok_or("No waveform file loaded")?;
 
     let fs = bw_mhz * 2.0 * 1e6;
+    app_state.fit_waveform_to_arb(&slot)?;
+    let loaded = app_state.waveform(&slot)?;
+    loaded.info.check_sequence_repeat()?;
+    let wfm_data = loaded.data.clone();
+    let markers = loaded.markers.clone();
+    let waveform_file = Some(loaded.file_path.clone());
+    let waveform_hash = loaded.hash;
+    let wfm_duration = loaded.info.duration_secs_at(fs);
+    let inst_id = app_state.vsg.as_ref().unwrap().identity.raw.clone();
+
+    // Re-validate a recovered sweep against the current setup before touching hardware
+    let mut completed = Vec::new();
+    if let Some(recovered) = recovered {
+        if recovered.waveform_hash != waveform_hash {
+            return Err(format!(
+                "The loaded waveform differs from the one used by the interrupted sweep ({}); \
+                 the file may have changed since. Load the original waveform or discard the recovery.",
+                recovered.waveform_file.as_deref().unwrap_or("unknown file")
+            ));
+        }
+        if recovered.inst_id != inst_id && !allow_instrument_change {
+            return Err(format!(
+                "Instrument changed since the interrupted sweep (was '{}', now '{}'). \
+                 Resume with allow_instrument_change to continue anyway.",
+                recovered.inst_id, inst_id
+            ));
+        }
+        let had_dut = recovered.completed.iter().any(|s| s.rec_rx_count.is_some());
+        if had_dut && app_state.dut.is_none() {
+            return Err("The interrupted sweep measured a DUT; connect it before resuming".into());
+        }
+        completed = recovered.completed;
+    }
+
+    let recovery = recovery_path(app).ok();
+    let mut checkpoint = SweepCheckpoint {
+        params: params.clone(),
+        completed,
+        waveform_file: waveform_file.clone(),
+        waveform_hash,
+        inst_id,
+    };
+    if let Some(ref path) = recovery {
+        let _ = sweep::save_checkpoint(path, &checkpoint);
+    }
 
     // Destructure to allow simultaneous mutable borrows of vsg and dut
-    let AppState { ref mut vsg, ref mut dut, .. } = *app_state;
+    let AppState {
+        ref mut vsg,
+        ref mut dut,
+        ref mut last_sweep,
+        ..
+    } = *app_state;
     let vsg = vsg.as_mut().unwrap();
+    if let (Some(dut), Some(interface)) = (dut.as_mut(), params.dut_interface) {
+        dut.set_interface(interface);
+    }
+    // With an external or key trigger the rig starts each burst; the sweep
+    // only arms the sequence and waits out the dwell
+    let trigger = params.trigger_source.unwrap_or_default();
+    let self_trigger = trigger == TriggerSource::Bus;
 
-    // One-time setup: configure, download, create sequence, enable output
-    vsg.prepare_sweep(&wfm_data, "waveform", cf, fs, start_power + cable_loss, 1000)?;
+    // The instrument's power offset absorbs the cable loss, so power
+    // commands below are the level at the DUT connector
+    vsg.set_channel(params.channel.unwrap_or(1))?;
+    let mut sweep_offset = vsg.sweep_offset(cable_loss)?;
+    let vsg = &mut *sweep_offset;
 
-    // DUT parameters: carrier frequency and BW in MHz (integers for ATE command)
-    let cf_mhz = (cf / 1e6).round() as u32;
-    let bw = bw_mhz.round() as u32;
+    // One-time setup: configure, download, create sequence, enable output
+    let progress = |bytes_sent, total| {
+        let _ = app.emit(
+            "wfm-download-progress",
+            WfmDownloadProgress {
+                slot: slot.clone(),
+                bytes_sent,
+                total,
+            },
+        );
+    };
+    let (downloaded, header_warning) = vsg.prepare_sweep(
+        &wfm_data,
+        Some(&markers),
+        &slot,
+        waveform_hash,
+        cf,
+        fs,
+        start_power,
+        SWEEP_BURSTS,
+        trigger,
+        params.trigger_timing(),
+        params.force_download.unwrap_or(false),
+        params.alc_off.unwrap_or(false),
+        params.rf_blanking.unwrap_or(false),
+        &DownloadControl {
+            chunk_bytes: params.download_chunk_bytes.unwrap_or(vsg::DEFAULT_DOWNLOAD_CHUNK),
+            progress: &progress,
+            cancel: &cancel_flag,
+        },
+    )?;
+    let _ = app.emit(
+        "waveform-download",
+        WaveformDownload {
+            slot: slot.clone(),
+            downloaded,
+            skipped_total: vsg.downloads_skipped(),
+        },
+    );
+    if let Some(message) = header_warning {
+        let _ = app.emit("sweep-warning", SweepWarning { message });
+    }
 
+    // Retries are only reported per step; warm-up ones are not counted
+    let mut warmup_retries = 0;
     if let Some(ref mut dut) = dut {
-            dut.close_rx(cf_mhz)?;
+        if let Err(e) = dut.retry(DUT_RETRIES, DUT_RETRY_DELAY, &mut warmup_retries, |dut| dut.close_rx(cf_mhz)) {
+            return abort_sweep(app, vsg, false, None, e);
         }
+    }
 
-    // Calculate wait time for 1000 repetitions
-    let sample_count = wfm_data.len() / 2;
-    let wfm_duration = sample_count as f64 / fs;
-    let wait_secs = wfm_duration as u64 + 100;
-    let wait_duration = std::time::Duration::from_micros(wait_secs);
+    // A bus-triggered step ends when the ARB reports the sequence done; an
+    // external or key trigger can't be observed that way, so those steps
+    // wait out the nominal playing time plus a margin
+    let step_duration = Duration::from_secs_f64(wfm_duration * SWEEP_BURSTS as f64);
+    let trigger_delay = Duration::from_secs_f64(params.trigger_timing().delay_s.max(0.0));
+    let pacing = StepPacing {
+        self_trigger,
+        nominal: step_duration + trigger_delay,
+        timeout: step_duration * 2 + trigger_delay + Duration::from_secs(1),
+        external_wait: step_duration * SWEEP_WAIT_FACTOR + trigger_delay + Duration::from_millis(1),
+    };
 
-    // Build list of power steps
-    let mut powers = Vec::new();
-    let mut p = start_power;
-    while p <= end_power + 1e-9 {
-        powers.push(p);
-        p += step;
-    }
     let total_steps = powers.len();
 
-    for (i, &power) in powers.iter().enumerate() {
+    // Warm-up: un-measured bursts at the starting power so the DUT's AGC has
+    // settled on the channel before the first recorded step
+    let warmup_bursts = params.warmup_bursts.unwrap_or(1);
+    if warmup_bursts > 0 && !cancel_flag.load(Ordering::SeqCst) {
+        if let Some(ref mut dut) = dut {
+            if let Err(e) = dut.retry(DUT_RETRIES, DUT_RETRY_DELAY, &mut warmup_retries, |dut| dut.open_rx(cf_mhz, bw, params.dut_chain)) {
+                return abort_sweep(app, vsg, false, None, e);
+            }
+        }
+
+        vsg.set_power(start_power)?;
+        for n in 0..warmup_bursts {
+            if cancel_flag.load(Ordering::SeqCst) {
+                break;
+            }
+            let _ = app.emit(
+                "sweep-warmup",
+                SweepWarmup {
+                    burst_index: n + 1,
+                    total_bursts: warmup_bursts,
+                },
+            );
+            if self_trigger {
+                vsg.trigger_and_wait(pacing.nominal, pacing.timeout)?;
+            } else {
+                std::thread::sleep(pacing.external_wait);
+            }
+        }
+
+        // Discard the warm-up counters; the first step reopens RX from scratch
+        if let Some(ref mut dut) = dut {
+            let discarded = dut.retry(DUT_RETRIES, DUT_RETRY_DELAY, &mut warmup_retries, |dut| {
+                dut.read_mib(cf_mhz)?;
+                dut.close_rx(cf_mhz)
+            });
+            if let Err(e) = discarded {
+                return abort_sweep(app, vsg, false, None, e);
+            }
+        }
+    }
+
+    // Replay already-completed steps so the UI sees one continuous run
+    for progress in &checkpoint.completed {
+        let _ = app.emit("sweep-progress", progress.clone());
+    }
+
+    let resume_at = checkpoint.completed.len();
+
+    // Hardware list mode: the ARB plays continuously and the instrument
+    // steps the power every `list_dwell`, timed from `start_power_list`.
+    // Step n's DUT window runs from its open_rx to the end of dwell n, so
+    // the dwell must cover open_rx plus the bursts to count; with a dwell
+    // that is not a whole number of waveform durations, the bursts at each
+    // boundary straddle two power levels. A configured dwell is used as is.
+    let list_dwell = list_dwell.unwrap_or(step_duration * SWEEP_WAIT_FACTOR);
+    let mut list_start = None;
+    let mut list_ended = false;
+    if params.hardware_list.unwrap_or(false) && resume_at < total_steps && !cancel_flag.load(Ordering::SeqCst) {
+        if vsg.configure_power_list(&powers[resume_at..], list_dwell.as_secs_f64())? {
+            vsg.play(&slot, false)?;
+            vsg.start_power_list()?;
+            list_start = Some(Instant::now());
+        } else {
+            let _ = app.emit(
+                "sweep-warning",
+                SweepWarning {
+                    message: "The instrument has no power list sweep; stepping power from software".into(),
+                },
+            );
+        }
+    }
+
+    let mut clear_warned = false;
+    let mut bw_warned = false;
+    for (i, &power) in powers.iter().enumerate().skip(resume_at) {
         if cancel_flag.load(Ordering::SeqCst) {
             break;
         }
 
-        // Open DUT RX before triggering
+        let step_start = Instant::now();
+        let mut timing = StepTiming::default();
+        let mut dut_retries = 0;
+        let mut mib_cleared = None;
+        // Retries before the step, e.g. in the warm-up, are not its own
+        vsg.take_retry_time();
         if let Some(ref mut dut) = dut {
-            dut.open_rx(cf_mhz, bw)?;
+            dut.take_retry_time();
         }
 
-        vsg.set_power(power + cable_loss)?;
-        vsg.trigger()?;
-        std::thread::sleep(wait_duration);
+        // Open DUT RX and zero its counters before triggering
+        if let Some(ref mut dut) = dut {
+            let t = Instant::now();
+            let opened = dut.retry(DUT_RETRIES, DUT_RETRY_DELAY, &mut dut_retries, |dut| {
+                dut.open_rx(cf_mhz, bw, params.dut_chain)?;
+                dut.clear_mib(cf_mhz, bw)
+            });
+            match opened {
+                Ok(cleared) => mib_cleared = Some(cleared),
+                Err(e) => return abort_sweep(app, vsg, list_start.is_some(), Some(i + 1), e),
+            }
+            timing.open_rx_ms = sweep::elapsed_ms(t);
+
+            if mib_cleared == Some(false) && !clear_warned {
+                clear_warned = true;
+                let _ = app.emit(
+                    "sweep-warning",
+                    SweepWarning {
+                        message: "The DUT firmware cannot reset its MIB counters; \
+                                  each step's counts are taken relative to a reading before it"
+                            .into(),
+                    },
+                );
+            }
+        }
+
+        if let Some(start) = list_start {
+            let t = Instant::now();
+            let dwell_end = start + list_dwell * (i - resume_at + 1) as u32;
+            std::thread::sleep(dwell_end.saturating_duration_since(Instant::now()));
+            timing.dwell_ms = sweep::elapsed_ms(t);
+
+            if i + 1 < total_steps && !list_ended && !vsg.power_list_running()? {
+                list_ended = true;
+                let _ = app.emit(
+                    "sweep-warning",
+                    SweepWarning {
+                        message: format!("The power list ended early, at step {} of {}", i + 1, total_steps),
+                    },
+                );
+            }
+        } else {
+            vsg.play_step(power, &pacing, &mut timing)?;
+        }
 
         // Read MIB and close DUT RX after playback completes
-        let mut rec_rx_count = None;
-        let mut rx_ok_count = None;
+        let mut mib = dut::MibResult::default();
+        let mut ok_other_bw = None;
         if let Some(ref mut dut) = dut {
-            let mib_raw = dut.read_mib(cf_mhz)?;
-            let mib = DutClient::parse_mib_resp(&mib_raw, bw);
-            rec_rx_count = mib.rec_rx_count;
-            rx_ok_count = mib.rx_ok_count;
-            dut.close_rx(cf_mhz)?;
+            let t = Instant::now();
+            // A read that timed out may have run and cleared the counters on
+            // clear-on-read firmware, so a second read would report zeros
+            match dut.retry_if(DUT_RETRIES, DUT_RETRY_DELAY, &mut dut_retries, |e| !dut::is_timeout(e), |dut| {
+                dut.query_mib(cf_mhz, bw)
+            }) {
+                Ok(result) => mib = result,
+                Err(e) if dut::is_timeout(&e) => {
+                    let e = format!("{}; not retried, since the board may already have read out its counters", e);
+                    return abort_sweep(app, vsg, list_start.is_some(), Some(i + 1), e);
+                }
+                Err(e) => return abort_sweep(app, vsg, list_start.is_some(), Some(i + 1), e),
+            }
+            timing.read_mib_ms = sweep::elapsed_ms(t);
+
+            let t = Instant::now();
+            if let Err(e) = dut.retry(DUT_RETRIES, DUT_RETRY_DELAY, &mut dut_retries, |dut| dut.close_rx(cf_mhz)) {
+                return abort_sweep(app, vsg, list_start.is_some(), Some(i + 1), e);
+            }
+            timing.close_rx_ms = sweep::elapsed_ms(t);
+
+            // Frames decoded at another bandwidth usually mean the waveform
+            // was built or played at the wrong sample rate
+            let other = mib.ok_at_other_bw(bw);
+            if other > 0 && !bw_warned {
+                bw_warned = true;
+                let _ = app.emit(
+                    "sweep-warning",
+                    SweepWarning {
+                        message: format!(
+                            "Step {}: the DUT decoded {} frames at a bandwidth other than {} MHz; \
+                             check the waveform's sample rate",
+                            i + 1,
+                            other,
+                            bw
+                        ),
+                    },
+                );
+            }
+            ok_other_bw = Some(other);
         }
+        let dut_retry_time = dut.as_mut().map(|dut| dut.take_retry_time()).unwrap_or_default();
+        timing.retries_ms += (vsg.take_retry_time() + dut_retry_time).as_secs_f64() * 1e3;
+        timing.total_ms = sweep::elapsed_ms(step_start);
 
-        let _ = app.emit(
-            "sweep-progress",
-            SweepProgress {
-                current_power: power,
-                step_index: i + 1,
-                total_steps,
-                rec_rx_count,
-                rx_ok_count,
-            },
-        );
+        let progress = SweepProgress {
+            current_power: power,
+            step_index: i + 1,
+            total_steps,
+            rec_rx_count: mib.rec_rx_count,
+            rx_ok_count: mib.rx_ok_count,
+            rssi1: mib.rssi1,
+            rssi2: mib.rssi2,
+            fcs_err: mib.fcs_err,
+            phy_err: mib.phy_err,
+            dut_retries,
+            mib_cleared,
+            ok_other_bw,
+            timing,
+        };
+        let _ = app.emit("sweep-progress", progress.clone());
+
+        checkpoint.completed.push(progress);
+        if let Some(ref path) = recovery {
+            let _ = sweep::save_checkpoint(path, &checkpoint);
+        }
     }
 
+    if list_start.is_some() {
+        vsg.end_power_list()?;
+    }
     vsg.stop()?;
-    let _ = app.emit("sweep-done", ());
+
+    // Finished or cancelled by the user: nothing left to recover
+    if let Some(ref path) = recovery {
+        sweep::clear_checkpoint(path)?;
+    }
+
+    let step_timings: Vec<StepTiming> = checkpoint
+        .completed
+        .iter()
+        .map(|s| s.timing.clone())
+        .collect();
+    let _ = app.emit(
+        "sweep-done",
+        SweepDone {
+            steps_completed: step_timings.len(),
+            waveform_file,
+            timing: sweep::summarize_timing(&step_timings),
+        },
+    );
+    *last_sweep = checkpoint.completed;
 
     Ok(())
 }
@@ -251,21 +2117,106 @@ pub fn run() {
         .manage(Mutex::new(AppState {
             vsg: None,
             dut: None,
-            wfm_data: None,
+            waveforms: HashMap::new(),
+            waveform_map: Vec::new(),
+            playback_stop: None,
+            dut_keepalive_stop: None,
+            loading: Arc::new(AtomicBool::new(false)),
+            power_limit_dbm: DEFAULT_POWER_LIMIT_DBM,
+            vsg_alive: false,
+            dut_alive: false,
+            last_sweep: Vec::new(),
         }))
         .manage(Arc::new(AtomicBool::new(false)))
+        .manage(DownloadCancel(AtomicBool::new(false)))
+        .manage(DutLogFollower::default())
         .invoke_handler(tauri::generate_handler![
             connect_instrument,
+            discover_instruments,
             disconnect_instrument,
+            set_vsg_reference,
+            get_vsg_reference_status,
+            list_instrument_waveforms,
+            delete_instrument_waveform,
+            save_instrument_waveform,
+            delete_all_instrument_waveforms,
+            get_vsg_status,
+            vsg_self_test,
+            reset_instrument_io,
+            vsg_query_options,
+            vsg_set_alc,
+            vsg_set_iq_scale,
+            vsg_set_power_offset,
+            set_power_limit,
+            vsg_load_loss_table,
             connect_dut,
             disconnect_dut,
+            dut_open_rx,
+            dut_read_mib,
+            dut_raw_command,
+            dut_fetch_file,
+            start_dut_log,
+            stop_dut_log,
             load_waveform,
+            load_waveform_pair,
+            generate_waveform,
+            list_waveforms,
+            remove_waveform,
+            clear_waveform_cache,
+            get_waveform_preview,
+            compute_spectrum,
+            occupied_bandwidth,
+            list_mat_variables,
+            set_waveform_mapping,
+            get_waveform_mapping,
             export_waveform,
+            export_envelope,
+            export_sweep_csv,
+            compare_waveforms,
             play_waveform,
+            play_instrument_waveform,
+            play_sequence,
             stop_waveform,
+            toggle_modulation,
+            scpi_write,
+            scpi_query,
+            set_log_level,
+            get_connection_status,
+            cancel_download,
             power_sweep,
             cancel_sweep,
+            get_recoverable_sweep,
+            resume_recovered_sweep,
+            discard_recovered_sweep,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_ticket_admits_one_load_at_a_time() {
+        let loading = Arc::new(AtomicBool::new(false));
+        let first = LoadTicket::take(&loading).unwrap();
+        assert!(LoadTicket::take(&loading).is_err());
+
+        // A second load started as soon as the first finishes keeps its
+        // flag: nothing of the first load clears it later
+        drop(first);
+        let second = LoadTicket::take(&loading).unwrap();
+        assert!(LoadTicket::take(&loading).is_err());
+        drop(second);
+
+        // A worker that panics still releases its ticket
+        let ticket = LoadTicket::take(&loading).unwrap();
+        let worker = std::thread::spawn(move || {
+            let _ticket = ticket;
+            panic!("load failed");
+        });
+        assert!(worker.join().is_err());
+        assert!(LoadTicket::take(&loading).is_ok());
+    }
+}
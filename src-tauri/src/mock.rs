@@ -0,0 +1,148 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::scpi::{ScpiTransport, SOCKET_PORT};
+
+/// In-memory `ScpiTransport` that records every command and answers
+/// queries from a script. Clones share the record and the script, so a
+/// test keeps one to inspect what the instrument under test sent.
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    sent: Arc<Mutex<Vec<String>>>,
+    replies: Arc<Mutex<HashMap<String, VecDeque<String>>>>,
+    /// (command prefix, error) of writes set to fail.
+    failures: Arc<Mutex<Vec<(String, String)>>>,
+    /// (command prefix, hold) of writes set to be slow.
+    delays: Arc<Mutex<Vec<(String, Duration)>>>,
+    /// Query waiting for `read_response`.
+    pending: Option<String>,
+    timeout: Duration,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self {
+            timeout: Duration::from_secs(3),
+            ..Self::default()
+        }
+    }
+
+    /// Answer the next `query` with `reply`. Replies to the same query are
+    /// given in order; `SYST:ERR?` and `*OPC?` have a default answer once
+    /// their script runs out.
+    pub fn reply(&self, query: &str, reply: &str) -> &Self {
+        self.replies
+            .lock()
+            .unwrap()
+            .entry(query.to_string())
+            .or_default()
+            .push_back(reply.to_string());
+        self
+    }
+
+    /// Fail the next command or block starting with `prefix` with `error`.
+    pub fn fail(&self, prefix: &str, error: &str) -> &Self {
+        self.failures
+            .lock()
+            .unwrap()
+            .push((prefix.to_string(), error.to_string()));
+        self
+    }
+
+    /// Hold the next command or block starting with `prefix` for `delay`
+    /// before it is sent.
+    pub fn delay(&self, prefix: &str, delay: Duration) -> &Self {
+        self.delays.lock().unwrap().push((prefix.to_string(), delay));
+        self
+    }
+
+    /// Record `entry` as sent, failing it if a matching failure is set.
+    fn send(&self, entry: String) -> Result<(), String> {
+        let delay = {
+            let mut delays = self.delays.lock().unwrap();
+            let i = delays.iter().position(|(prefix, _)| entry.starts_with(prefix.as_str()));
+            i.map(|i| delays.remove(i).1)
+        };
+        if let Some(delay) = delay {
+            std::thread::sleep(delay);
+        }
+        let mut failures = self.failures.lock().unwrap();
+        let failure = failures.iter().position(|(prefix, _)| entry.starts_with(prefix.as_str()));
+        self.sent.lock().unwrap().push(entry);
+        match failure {
+            Some(i) => Err(failures.remove(i).1),
+            None => Ok(()),
+        }
+    }
+
+    /// Commands sent since the last call, queries included. Binary blocks
+    /// appear as the command followed by `<N bytes>`.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.sent.lock().unwrap())
+    }
+}
+
+impl ScpiTransport for MockTransport {
+    fn write_cmd(&mut self, cmd: &str) -> Result<(), String> {
+        self.pending = None;
+        self.send(cmd.to_string())?;
+        self.pending = cmd.contains('?').then(|| cmd.to_string());
+        Ok(())
+    }
+
+    fn read_response(&mut self) -> Result<String, String> {
+        let query = self.pending.take().ok_or("Read without a query")?;
+        let scripted = self.replies.lock().unwrap().get_mut(&query).and_then(VecDeque::pop_front);
+        match (scripted, query.to_ascii_uppercase().as_str()) {
+            (Some(reply), _) => Ok(reply),
+            (None, "SYST:ERR?") => Ok("+0,\"No error\"".into()),
+            (None, "*OPC?") => Ok("1".into()),
+            (None, _) => Err(format!("No reply scripted for '{}'", query)),
+        }
+    }
+
+    fn query_binary_block(&mut self, cmd: &str) -> Result<Vec<u8>, String> {
+        self.write_cmd(cmd)?;
+        Err(format!("No block scripted for '{}'", cmd))
+    }
+
+    fn write_binary_block_progress(
+        &mut self,
+        cmd: &str,
+        data: &[u8],
+        _chunk: usize,
+        progress: &dyn Fn(u64),
+        _cancel: Option<&AtomicBool>,
+    ) -> Result<(), String> {
+        self.send(format!("{}<{} bytes>", cmd, data.len()))?;
+        progress(data.len() as u64);
+        Ok(())
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), String> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn is_disconnected(&self) -> bool {
+        false
+    }
+
+    fn peer(&self) -> SocketAddr {
+        SocketAddr::new(Ipv4Addr::LOCALHOST.into(), SOCKET_PORT)
+    }
+
+    fn set_max_block_size(&mut self, _bytes: usize) {}
+
+    fn device_clear(&mut self) -> Result<(), String> {
+        self.sent.lock().unwrap().push("<device clear>".into());
+        Ok(())
+    }
+}
@@ -1,22 +1,370 @@
-use std::io::{BufRead, BufReader, Write};
-use std::net::TcpStream;
-use std::time::Duration;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::hislip::{self, HislipClient};
+use crate::iolog::{self, IoKind};
+
+/// Source name in the I/O log.
+pub(crate) const LOG_SOURCE: &str = "vsg";
+
+/// Largest binary block `query_binary_block` accepts until
+/// `set_max_block_size` changes it.
+pub(crate) const DEFAULT_MAX_BLOCK_BYTES: usize = 256 << 20;
+
+/// Most errors `err_check` reads from the queue in one call.
+const MAX_DRAINED_ERRORS: usize = 32;
+
+/// Time `err_check` may spend draining the error queue.
+const ERR_CHECK_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Error of a block write stopped through its cancel flag.
+pub const CANCELLED: &str = "Download cancelled";
+
+/// Timeout of the liveness query sent by `ping`.
+pub const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often to retry a command or read that failed with a timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct RetryPolicy {
+    /// Attempts in total, the first included.
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for each further one.
+    pub backoff_ms: u64,
+}
+
+impl RetryPolicy {
+    /// Wait before retry number `retry` (1 for the first).
+    fn backoff(&self, retry: u32) -> Duration {
+        Duration::from_millis(self.backoff_ms.saturating_mul(1 << (retry - 1).min(16)))
+    }
+}
+
+/// A transient failure about to be retried, the payload of "scpi-retry".
+#[derive(Debug, Clone, Serialize)]
+pub struct RetryEvent {
+    pub cmd: String,
+    /// Attempt about to be made, from 2.
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub error: String,
+}
+
+pub type RetryHook = Arc<dyn Fn(&RetryEvent) + Send + Sync>;
+
+/// Default port of the raw SCPI socket.
+pub const SOCKET_PORT: u16 = 5025;
+
+/// A connection that carries SCPI commands to an instrument: the raw
+/// socket (`ScpiClient`) or HiSLIP (`HislipClient`).
+pub trait ScpiTransport: Send {
+    fn write_cmd(&mut self, cmd: &str) -> Result<(), String>;
+
+    /// Read one response, without its terminator.
+    fn read_response(&mut self) -> Result<String, String>;
+
+    /// Read responses up to `term` instead of the end of the line, for
+    /// gateways with their own framing; `None` goes back to lines. Only the
+    /// raw socket has terminators to choose.
+    fn set_read_terminator(&mut self, term: Option<&str>) -> Result<(), String> {
+        match term {
+            None => Ok(()),
+            Some(_) => Err("A custom read terminator needs a socket connection".into()),
+        }
+    }
+
+    /// Read until `term` and return what came before it, with trailing line
+    /// ends removed. Reads multi-line responses such as catalogs when `term`
+    /// marks their end. Like `set_read_terminator`, only the raw socket
+    /// supports it.
+    fn read_until(&mut self, term: &str) -> Result<String, String> {
+        Err(format!("Reading up to '{}' needs a socket connection", term))
+    }
+
+    /// Send a query answered with an IEEE 488.2 arbitrary block and return
+    /// its data. Both the definite-length `#<n><length>` form and the
+    /// indefinite `#0` form, which runs to the newline, are read.
+    fn query_binary_block(&mut self, cmd: &str) -> Result<Vec<u8>, String>;
+
+    /// Send a SCPI command followed by IEEE 488.2 definite length arbitrary
+    /// block data, `chunk` bytes at a time, calling `progress` with the
+    /// bytes written so far.
+    ///
+    /// `cancel` is checked before each chunk. Stopping leaves the instrument
+    /// waiting for the rest of the block, so the transport recovers the
+    /// session first and then fails with `CANCELLED`.
+    fn write_binary_block_progress(
+        &mut self,
+        cmd: &str,
+        data: &[u8],
+        chunk: usize,
+        progress: &dyn Fn(u64),
+        cancel: Option<&AtomicBool>,
+    ) -> Result<(), String>;
+
+    fn timeout(&self) -> Duration;
+
+    /// Set the read and write timeout for subsequent operations.
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), String>;
+
+    /// Whether the connection was closed or reset by the instrument; every
+    /// further command on this connection will fail.
+    fn is_disconnected(&self) -> bool;
+
+    /// Resolved address of the instrument.
+    fn peer(&self) -> SocketAddr;
+
+    /// Limit the size of binary blocks read by `query_binary_block`.
+    fn set_max_block_size(&mut self, bytes: usize);
+
+    /// Abort pending I/O on the instrument and clear its input and output
+    /// buffers, recovering an instrument stuck mid-message.
+    fn device_clear(&mut self) -> Result<(), String>;
+
+    /// Retry timed out commands and reads by `policy`, or not at all with
+    /// `None`; returns the previous policy. Transports that cannot resume
+    /// a timed out read keep failing at once.
+    fn set_retry_policy(&mut self, policy: Option<RetryPolicy>) -> Option<RetryPolicy> {
+        let _ = policy;
+        None
+    }
+
+    /// Call `hook` before each retry.
+    fn on_retry(&mut self, hook: RetryHook) {
+        let _ = hook;
+    }
+
+    fn query(&mut self, cmd: &str) -> Result<String, String> {
+        self.write_cmd(cmd)?;
+        self.read_response()
+    }
+
+    fn write_binary_block(&mut self, cmd: &str, data: &[u8]) -> Result<(), String> {
+        self.write_binary_block_progress(cmd, data, data.len().max(1), &|_| {}, None)
+    }
+
+    /// Check that the instrument still answers, with a short timeout.
+    /// After a timeout the session is cleared, so a late answer cannot be
+    /// taken for the reply to the next query.
+    fn ping(&mut self) -> Result<(), String> {
+        let saved = self.timeout();
+        self.set_timeout(PING_TIMEOUT)?;
+        let result = self.query("*OPC?");
+        if result.is_err() && !self.is_disconnected() {
+            let _ = self.device_clear();
+        }
+        self.set_timeout(saved)?;
+        result.map(|_| ())
+    }
+
+    /// Drain the instrument error queue, failing with every error read.
+    /// Reads at most `MAX_DRAINED_ERRORS` entries within `ERR_CHECK_DEADLINE`.
+    fn err_check(&mut self) -> Result<(), String> {
+        let deadline = Instant::now() + ERR_CHECK_DEADLINE;
+        let mut errors = Vec::new();
+        loop {
+            let resp = self.query("SYST:ERR?")?;
+            match parse_error_code(&resp) {
+                Some(0) => break,
+                Some(_) => errors.push(resp),
+                None => {
+                    errors.push(format!("unexpected SYST:ERR? response {:?}", resp));
+                    return Err(iolog::error(
+                        LOG_SOURCE,
+                        format!("Instrument error check failed: {}", errors.join("; ")),
+                    ));
+                }
+            }
+            if errors.len() >= MAX_DRAINED_ERRORS {
+                errors.push("further errors left in the queue".into());
+                break;
+            }
+            if Instant::now() >= deadline {
+                errors.push(format!(
+                    "error queue not drained within {} s",
+                    ERR_CHECK_DEADLINE.as_secs()
+                ));
+                break;
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(iolog::error(LOG_SOURCE, format!("Instrument errors: {}", errors.join("; "))))
+        }
+    }
+}
+
+impl dyn ScpiTransport + '_ {
+    /// Run `f` with `timeout`, then restore the previous timeout.
+    pub fn with_timeout<T>(
+        &mut self,
+        timeout: Duration,
+        f: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let saved = self.timeout();
+        self.set_timeout(timeout)?;
+        let result = f(self);
+        self.set_timeout(saved)?;
+        result
+    }
+}
+
+/// How to reach an instrument, parsed from a connection string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// `socket://host[:port]` or a bare `host[:port]`; the raw SCPI socket.
+    Socket { host: String, port: u16 },
+    /// `hislip://host[:port][/hislip0]`.
+    Hislip {
+        host: String,
+        port: u16,
+        sub_address: String,
+    },
+}
+
+impl Endpoint {
+    pub fn parse(address: &str) -> Result<Self, String> {
+        let address = address.trim();
+        if let Some(rest) = address.strip_prefix("hislip://") {
+            let (host_port, sub_address) = match rest.split_once('/') {
+                Some((host_port, sub)) if !sub.is_empty() => (host_port, sub),
+                Some((host_port, _)) => (host_port, hislip::DEFAULT_SUB_ADDRESS),
+                None => (rest, hislip::DEFAULT_SUB_ADDRESS),
+            };
+            let (host, port) = split_host_port(host_port)?;
+            Ok(Endpoint::Hislip {
+                host,
+                port: port.unwrap_or(hislip::DEFAULT_PORT),
+                sub_address: sub_address.to_string(),
+            })
+        } else if let Some(rest) = address.strip_prefix("socket://") {
+            let (host, port) = split_host_port(rest)?;
+            Ok(Endpoint::Socket {
+                host,
+                port: port.unwrap_or(SOCKET_PORT),
+            })
+        } else if address.contains("://") {
+            Err(format!(
+                "Unsupported connection string '{}': use socket:// or hislip://",
+                address
+            ))
+        } else {
+            let (host, port) = split_host_port(address)?;
+            Ok(Endpoint::Socket {
+                host,
+                port: port.unwrap_or(SOCKET_PORT),
+            })
+        }
+    }
+}
+
+/// Split `host[:port]`. IPv6 literals need brackets when a port is given;
+/// a bare one is taken as a host without port.
+pub(crate) fn split_host_port(s: &str) -> Result<(String, Option<u16>), String> {
+    let parse_port = |p: &str| {
+        p.parse::<u16>()
+            .map_err(|_| format!("Invalid port '{}' in '{}'", p, s))
+    };
+    if let Some(rest) = s.strip_prefix('[') {
+        let (host, after) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("Missing ']' in '{}'", s))?;
+        let port = match after.strip_prefix(':') {
+            Some(p) => Some(parse_port(p)?),
+            None if after.is_empty() => None,
+            None => return Err(format!("Unexpected '{}' after the address in '{}'", after, s)),
+        };
+        return Ok((host.to_string(), port));
+    }
+    match s.split_once(':') {
+        Some((host, port)) if !port.contains(':') => Ok((host.to_string(), Some(parse_port(port)?))),
+        _ => Ok((s.to_string(), None)),
+    }
+    .and_then(|(host, port)| {
+        if host.is_empty() {
+            Err(format!("Missing host in '{}'", s))
+        } else {
+            Ok((host, port))
+        }
+    })
+}
+
+/// Connect to the instrument named by a connection string (see `Endpoint`).
+pub fn open(address: &str, timeout_secs: u64) -> Result<Box<dyn ScpiTransport>, String> {
+    Ok(match Endpoint::parse(address)? {
+        Endpoint::Socket { host, port } => Box::new(ScpiClient::connect(&host, port, timeout_secs)?),
+        Endpoint::Hislip {
+            host,
+            port,
+            sub_address,
+        } => Box::new(HislipClient::connect(&host, port, &sub_address, timeout_secs)?),
+    })
+}
 
 pub struct ScpiClient {
     stream: TcpStream,
     reader: BufReader<TcpStream>,
+    /// Current read/write timeout of the socket.
+    timeout: Duration,
+    /// Last command sent, named in timeout errors.
+    last_cmd: String,
+    /// Set once the peer closed or reset the connection.
+    disconnected: bool,
+    /// Largest binary block a query may return, guarding against a garbled
+    /// length header.
+    max_block_bytes: usize,
+    /// Address the connection was made to.
+    peer: SocketAddr,
+    retry: Option<RetryPolicy>,
+    on_retry: Option<RetryHook>,
+    /// Custom response terminator; `None` reads lines ending in LF, CR or
+    /// CRLF.
+    terminator: Option<Vec<u8>>,
+    /// The last line ended in a CR that arrived without its LF; a LF at the
+    /// start of the next read belongs to it.
+    pending_lf: bool,
+}
+
+/// Resolve `host` (an IP, bracketed or bare IPv6 literal, or DNS name) and
+/// connect to the first of its addresses that answers within `timeout`.
+pub fn connect_tcp(host: &str, port: u16, timeout: Duration) -> Result<(TcpStream, SocketAddr), String> {
+    let host = host.trim();
+    let addr = if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let resolved: Vec<SocketAddr> = addr
+        .to_socket_addrs()
+        .map_err(|e| format!("Cannot resolve '{}': {}", addr, e))?
+        .collect();
+    if resolved.is_empty() {
+        return Err(format!("'{}' did not resolve to any address", addr));
+    }
+    let mut failures = Vec::new();
+    for socket_addr in resolved {
+        match TcpStream::connect_timeout(&socket_addr, timeout) {
+            Ok(stream) => return Ok((stream, socket_addr)),
+            Err(e) => failures.push(format!("{}: {}", socket_addr, e)),
+        }
+    }
+    Err(format!("Connection to {} failed ({})", addr, failures.join("; ")))
+}
+
+/// `addr` with its address family, for connection reports.
+pub fn describe_endpoint(addr: SocketAddr) -> String {
+    let family = if addr.is_ipv4() { "IPv4" } else { "IPv6" };
+    format!("{} ({})", addr, family)
 }
 
 impl ScpiClient {
     pub fn connect(ip: &str, port: u16, timeout_secs: u64) -> Result<Self, String> {
-        let addr = format!("{}:{}", ip, port);
-        let socket_addr: std::net::SocketAddr = addr
-            .parse()
-            .map_err(|e| format!("Invalid address '{}': {}", addr, e))?;
-
-        let stream =
-            TcpStream::connect_timeout(&socket_addr, Duration::from_secs(timeout_secs))
-                .map_err(|e| format!("Connection to {} failed: {}", addr, e))?;
+        let (stream, peer) = connect_tcp(ip, port, Duration::from_secs(timeout_secs))?;
 
         stream
             .set_read_timeout(Some(Duration::from_secs(timeout_secs)))
@@ -34,66 +382,732 @@ impl ScpiClient {
                 .map_err(|e| format!("Failed to clone stream: {}", e))?,
         );
 
-        Ok(Self { stream, reader })
+        Ok(Self {
+            stream,
+            reader,
+            timeout: Duration::from_secs(timeout_secs),
+            last_cmd: String::new(),
+            disconnected: false,
+            max_block_bytes: DEFAULT_MAX_BLOCK_BYTES,
+            peer,
+            retry: None,
+            on_retry: None,
+            terminator: None,
+            pending_lf: false,
+        })
     }
 
-    pub fn write_cmd(&mut self, cmd: &str) -> Result<(), String> {
-        self.stream
-            .write_all(format!("{}\n", cmd).as_bytes())
-            .map_err(|e| format!("Write failed: {}", e))?;
-        self.stream
-            .flush()
-            .map_err(|e| format!("Flush failed: {}", e))
+    /// Read one line ending in LF, CR or CRLF, without the line end.
+    fn read_line(&mut self) -> Result<String, String> {
+        // A retry keeps reading into the same line rather than asking again,
+        // so a late answer cannot be mistaken for the next one
+        let mut line = Vec::new();
+        let mut attempt = 1;
+        loop {
+            let available = match self.reader.fill_buf() {
+                Ok(available) => available,
+                Err(e) => {
+                    if self.retry("Read", attempt, &e) {
+                        attempt += 1;
+                        continue;
+                    }
+                    return Err(self.io_error("Read", e));
+                }
+            };
+            if available.is_empty() {
+                self.disconnected = true;
+                return Err(iolog::error(
+                    LOG_SOURCE,
+                    "Read failed, connection closed by the instrument".into(),
+                ));
+            }
+            if std::mem::take(&mut self.pending_lf) && available[0] == b'\n' {
+                self.reader.consume(1);
+                continue;
+            }
+            match available.iter().position(|&b| b == b'\n' || b == b'\r') {
+                Some(end) => {
+                    line.extend_from_slice(&available[..end]);
+                    let mut used = end + 1;
+                    if available[end] == b'\r' {
+                        match available.get(end + 1) {
+                            Some(b'\n') => used += 1,
+                            Some(_) => {}
+                            // The LF may still be on its way
+                            None => self.pending_lf = true,
+                        }
+                    }
+                    self.reader.consume(used);
+                    break;
+                }
+                None => {
+                    let used = available.len();
+                    line.extend_from_slice(available);
+                    self.reader.consume(used);
+                }
+            }
+        }
+        let response = String::from_utf8_lossy(&line).trim().to_string();
+        iolog::record(LOG_SOURCE, IoKind::Read, || response.clone());
+        Ok(response)
     }
 
-    pub fn read_response(&mut self) -> Result<String, String> {
-        let mut response = String::new();
+    /// Whether attempt `attempt` failing with `e` gets another try; if so,
+    /// log the retry, report it and wait out the backoff.
+    fn retry(&mut self, what: &str, attempt: u32, e: &io::Error) -> bool {
+        let Some(policy) = self.retry else {
+            return false;
+        };
+        let transient = matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock);
+        if !transient || attempt >= policy.max_attempts {
+            return false;
+        }
+        let (error, _) = describe_io_error(what, e, self.timeout, &self.last_cmd);
+        let event = RetryEvent {
+            cmd: self.last_cmd.clone(),
+            attempt: attempt + 1,
+            max_attempts: policy.max_attempts,
+            error,
+        };
+        iolog::record(LOG_SOURCE, IoKind::Error, || {
+            format!("{}; retry {}/{}", event.error, event.attempt, event.max_attempts)
+        });
+        if let Some(hook) = &self.on_retry {
+            hook(&event);
+        }
+        std::thread::sleep(policy.backoff(attempt));
+        true
+    }
+
+    /// Write `buf`, returning how much went out along with any error.
+    fn write_counted(&mut self, mut buf: &[u8]) -> Result<(), (usize, io::Error)> {
+        let mut written = 0;
+        while !buf.is_empty() {
+            match self.stream.write(buf) {
+                Ok(0) => return Err((written, io::ErrorKind::WriteZero.into())),
+                Ok(n) => {
+                    written += n;
+                    buf = &buf[n..];
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err((written, e)),
+            }
+        }
+        self.stream.flush().map_err(|e| (written, e))
+    }
+
+    /// Describe a failed `what` ("Read", "Write", ...), naming the command
+    /// and limit when it timed out.
+    fn io_error(&mut self, what: &str, e: io::Error) -> String {
+        let (msg, lost) = describe_io_error(what, &e, self.timeout, &self.last_cmd);
+        self.disconnected |= lost;
+        iolog::error(LOG_SOURCE, msg)
+    }
+
+    /// Drop the connection, which makes the instrument discard the partial
+    /// block, and open a new one to the same address.
+    fn reopen(&mut self) -> Result<(), String> {
+        let _ = self.stream.shutdown(std::net::Shutdown::Both);
+        let stream = TcpStream::connect_timeout(&self.peer, self.timeout).map_err(|e| {
+            self.disconnected = true;
+            format!("Reconnect to {} failed: {}", self.peer, e)
+        })?;
+        let _ = stream.set_nodelay(true);
+        self.reader = BufReader::new(
+            stream
+                .try_clone()
+                .map_err(|e| format!("Failed to clone stream: {}", e))?,
+        );
+        self.stream = stream;
+        self.disconnected = false;
+        self.pending_lf = false;
+        self.set_timeout(self.timeout)
+    }
+
+    fn read_binary_block(&mut self) -> Result<Vec<u8>, String> {
+        if std::mem::take(&mut self.pending_lf) {
+            if let Ok([b'\n', ..]) = self.reader.fill_buf() {
+                self.reader.consume(1);
+            }
+        }
+        let mut header = [0u8; 2];
         self.reader
-            .read_line(&mut response)
-            .map_err(|e| format!("Read failed: {}", e))?;
-        Ok(response.trim().to_string())
+            .read_exact(&mut header)
+            .map_err(|e| self.io_error("Read block header", e))?;
+        if header[0] != b'#' || !header[1].is_ascii_digit() {
+            // Most likely a plain response or an error string; drop the rest
+            let _ = self.read_response();
+            return Err(iolog::error(LOG_SOURCE, format!(
+                "Expected a binary block from '{}', got {:?}",
+                self.last_cmd,
+                String::from_utf8_lossy(&header)
+            )));
+        }
+
+        let digits = (header[1] - b'0') as usize;
+        if digits == 0 {
+            let mut data = Vec::new();
+            let limit = self.max_block_bytes as u64 + 1;
+            (&mut self.reader)
+                .take(limit)
+                .read_until(b'\n', &mut data)
+                .map_err(|e| self.io_error("Read binary data", e))?;
+            if data.last() != Some(&b'\n') {
+                return Err(iolog::error(LOG_SOURCE, format!(
+                    "Binary block from '{}' exceeds the {} byte limit or was cut short",
+                    self.last_cmd, self.max_block_bytes
+                )));
+            }
+            data.pop();
+            return Ok(data);
+        }
+
+        let mut length = vec![0u8; digits];
+        self.reader
+            .read_exact(&mut length)
+            .map_err(|e| self.io_error("Read block header", e))?;
+        let length: usize = std::str::from_utf8(&length)
+            .ok()
+            .and_then(|l| l.parse().ok())
+            .ok_or_else(|| {
+                let msg = format!("Invalid binary block length from '{}': {:?}", self.last_cmd, length);
+                iolog::error(LOG_SOURCE, msg)
+            })?;
+        if length > self.max_block_bytes {
+            return Err(iolog::error(LOG_SOURCE, format!(
+                "Binary block of {} bytes from '{}' exceeds the {} byte limit",
+                length, self.last_cmd, self.max_block_bytes
+            )));
+        }
+
+        let mut data = vec![0u8; length];
+        self.reader
+            .read_exact(&mut data)
+            .map_err(|e| self.io_error("Read binary data", e))?;
+        // Consume the terminator that follows the data
+        self.read_response()?;
+        Ok(data)
+    }
+}
+
+impl ScpiTransport for ScpiClient {
+    fn write_cmd(&mut self, cmd: &str) -> Result<(), String> {
+        self.last_cmd = cmd.to_string();
+        iolog::record(LOG_SOURCE, IoKind::Write, || cmd.to_string());
+        let line = format!("{}\n", cmd);
+        let mut attempt = 1;
+        loop {
+            match self.write_counted(line.as_bytes()) {
+                Ok(()) => return Ok(()),
+                // Only when nothing went out, so a command is never torn or doubled
+                Err((0, e)) if self.retry("Write", attempt, &e) => attempt += 1,
+                Err((_, e)) => return Err(self.io_error("Write", e)),
+            }
+        }
+    }
+
+    fn read_response(&mut self) -> Result<String, String> {
+        match self.terminator.clone() {
+            Some(term) => Ok(self.read_until(&String::from_utf8_lossy(&term))?.trim().to_string()),
+            None => self.read_line(),
+        }
+    }
+
+    fn set_read_terminator(&mut self, term: Option<&str>) -> Result<(), String> {
+        if term == Some("") {
+            return Err("Empty read terminator".into());
+        }
+        self.terminator = term.map(|t| t.as_bytes().to_vec());
+        Ok(())
+    }
+
+    fn read_until(&mut self, term: &str) -> Result<String, String> {
+        if term.is_empty() {
+            return Err("Empty read terminator".into());
+        }
+        let term = term.as_bytes();
+        let last = term[term.len() - 1];
+        let mut data = Vec::new();
+        let mut attempt = 1;
+        while !data.ends_with(term) {
+            if data.len() > self.max_block_bytes {
+                return Err(iolog::error(LOG_SOURCE, format!(
+                    "Response to '{}' exceeds {} bytes without its terminator",
+                    self.last_cmd, self.max_block_bytes
+                )));
+            }
+            match self.reader.read_until(last, &mut data) {
+                Ok(0) => {
+                    self.disconnected = true;
+                    return Err(iolog::error(
+                        LOG_SOURCE,
+                        "Read failed, connection closed by the instrument".into(),
+                    ));
+                }
+                Ok(_) => {}
+                Err(e) if self.retry("Read", attempt, &e) => attempt += 1,
+                Err(e) => return Err(self.io_error("Read", e)),
+            }
+        }
+        data.truncate(data.len() - term.len());
+        let response = String::from_utf8_lossy(&data)
+            .trim_end_matches(['\r', '\n'])
+            .to_string();
+        iolog::record(LOG_SOURCE, IoKind::Read, || response.clone());
+        Ok(response)
     }
 
-    pub fn query(&mut self, cmd: &str) -> Result<String, String> {
+    fn query_binary_block(&mut self, cmd: &str) -> Result<Vec<u8>, String> {
         self.write_cmd(cmd)?;
-        self.read_response()
+        let data = self.read_binary_block()?;
+        iolog::record_block(LOG_SOURCE, "", &data);
+        Ok(data)
     }
 
-    /// Send a SCPI command followed by IEEE 488.2 definite length arbitrary block data.
-    pub fn write_binary_block(&mut self, cmd: &str, data: &[u8]) -> Result<(), String> {
+    fn write_binary_block_progress(
+        &mut self,
+        cmd: &str,
+        data: &[u8],
+        chunk: usize,
+        progress: &dyn Fn(u64),
+        cancel: Option<&AtomicBool>,
+    ) -> Result<(), String> {
         let data_len_str = data.len().to_string();
         let num_digits = data_len_str.len();
 
         // Format: <cmd>#<num_digits><data_length><binary_data>\n
         let header = format!("{}#{}{}", cmd, num_digits, data_len_str);
+        self.last_cmd = cmd.to_string();
+        iolog::record_block(LOG_SOURCE, cmd, data);
         self.stream
             .write_all(header.as_bytes())
-            .map_err(|e| format!("Write header failed: {}", e))?;
-        self.stream
-            .write_all(data)
-            .map_err(|e| format!("Write binary data failed: {}", e))?;
+            .map_err(|e| self.io_error("Write header", e))?;
+        let mut sent = 0;
+        for block in data.chunks(chunk) {
+            if cancel.is_some_and(|c| c.load(Ordering::SeqCst)) {
+                return Err(iolog::error(
+                    LOG_SOURCE,
+                    match self.reopen() {
+                        Ok(()) => CANCELLED.to_string(),
+                        Err(e) => format!("{}; {}", CANCELLED, e),
+                    },
+                ));
+            }
+            self.stream
+                .write_all(block)
+                .map_err(|e| self.io_error("Write binary data", e))?;
+            sent += block.len() as u64;
+            progress(sent);
+        }
         self.stream
             .write_all(b"\n")
-            .map_err(|e| format!("Write terminator failed: {}", e))?;
+            .map_err(|e| self.io_error("Write terminator", e))?;
         self.stream
             .flush()
-            .map_err(|e| format!("Flush failed: {}", e))
+            .map_err(|e| self.io_error("Flush", e))
     }
 
-    pub fn err_check(&mut self) -> Result<(), String> {
-        let mut errors = Vec::new();
-        loop {
-            let resp = self.query("SYST:ERR?")?;
-            let cleaned = resp.replace('+', "").replace('-', "");
-            if cleaned.starts_with("0,") || cleaned.contains("No error") {
-                break;
+    fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    fn set_max_block_size(&mut self, bytes: usize) {
+        self.max_block_bytes = bytes;
+    }
+
+    fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), String> {
+        // The reader shares the socket, so this covers both directions
+        self.stream
+            .set_read_timeout(Some(timeout))
+            .map_err(|e| format!("Failed to set read timeout: {}", e))?;
+        self.stream
+            .set_write_timeout(Some(timeout))
+            .map_err(|e| format!("Failed to set write timeout: {}", e))?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    /// A raw socket has no device clear message. Closing the connection
+    /// makes the instrument drop what it was reading, and `*CLS` clears the
+    /// errors it left.
+    fn device_clear(&mut self) -> Result<(), String> {
+        iolog::record(LOG_SOURCE, IoKind::Write, || "<device clear>".into());
+        self.reopen()?;
+        self.write_cmd("*CLS")
+    }
+
+    fn set_retry_policy(&mut self, policy: Option<RetryPolicy>) -> Option<RetryPolicy> {
+        std::mem::replace(&mut self.retry, policy)
+    }
+
+    fn on_retry(&mut self, hook: RetryHook) {
+        self.on_retry = Some(hook);
+    }
+}
+
+/// Describe a failed `what` ("Read", "Write", ...), naming the command and
+/// limit when it timed out. The flag tells whether the connection is gone.
+pub(crate) fn describe_io_error(what: &str, e: &io::Error, timeout: Duration, last_cmd: &str) -> (String, bool) {
+    match e.kind() {
+        io::ErrorKind::BrokenPipe
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::NotConnected
+        | io::ErrorKind::UnexpectedEof => (format!("{} failed, connection lost: {}", what, e), true),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => (
+            format!(
+                "{} timed out after {:.1} s on '{}'",
+                what,
+                timeout.as_secs_f64(),
+                last_cmd
+            ),
+            false,
+        ),
+        _ => (format!("{} failed: {}", what, e), false),
+    }
+}
+
+/// Leading error code of a SYST:ERR? response, e.g. -222 for
+/// `-222,"Data out of range"`.
+fn parse_error_code(resp: &str) -> Option<i32> {
+    resp.split(',').next()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::thread;
+
+    /// Client connected to a listener that answers the first command with
+    /// `chunks`, written separately with a pause in between.
+    fn serve(chunks: Vec<&'static [u8]>) -> ScpiClient {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream.try_clone().unwrap()).read_line(&mut line).unwrap();
+            for chunk in chunks {
+                stream.write_all(chunk).unwrap();
+                stream.flush().unwrap();
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+        ScpiClient::connect("127.0.0.1", port, 2).unwrap()
+    }
+
+    /// Client connected to a listener that answers each command with the
+    /// next of `replies`, then stops answering.
+    fn serve_replies(replies: Vec<&'static str>) -> ScpiClient {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut replies = replies.into_iter();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                if let Some(reply) = replies.next() {
+                    stream.write_all(format!("{}\n", reply).as_bytes()).unwrap();
+                }
+                line.clear();
+            }
+        });
+        ScpiClient::connect("127.0.0.1", port, 2).unwrap()
+    }
+
+    #[test]
+    fn connects_by_hostname_and_ipv6_literal() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (_, peer) = connect_tcp("localhost", port, Duration::from_secs(2)).unwrap();
+        assert_eq!(peer, listener.local_addr().unwrap());
+
+        // Skipped where the host has no IPv6 loopback
+        if let Ok(listener) = TcpListener::bind("[::1]:0") {
+            let port = listener.local_addr().unwrap().port();
+            for host in ["::1", "[::1]"] {
+                let (_, peer) = connect_tcp(host, port, Duration::from_secs(2)).unwrap();
+                assert_eq!(describe_endpoint(peer), format!("[::1]:{} (IPv6)", port));
             }
-            errors.push(resp);
-        }
-        if errors.is_empty() {
-            Ok(())
-        } else {
-            Err(format!("Instrument errors: {}", errors.join("; ")))
         }
     }
+
+    #[test]
+    fn ping_fails_when_instrument_is_silent() {
+        let mut client = serve_replies(vec!["1"]);
+        assert!(client.ping().is_ok());
+        assert!(client.ping().unwrap_err().contains("timed out"));
+        assert_eq!(client.timeout(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn late_ping_reply_is_not_taken_for_the_next() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream.try_clone().unwrap()).read_line(&mut line).unwrap();
+            thread::sleep(PING_TIMEOUT + Duration::from_millis(300));
+            let _ = stream.write_all(b"1\n");
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                if line.trim() == "*IDN?" {
+                    stream.write_all(b"ACME,SG1,0,1.0\n").unwrap();
+                }
+                line.clear();
+            }
+        });
+        let mut client = ScpiClient::connect("127.0.0.1", port, 2).unwrap();
+        assert!(client.ping().unwrap_err().contains("timed out"));
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(client.query("*IDN?").unwrap(), "ACME,SG1,0,1.0");
+    }
+
+    #[test]
+    fn parses_connection_strings() {
+        let socket = |host: &str, port| Endpoint::Socket {
+            host: host.into(),
+            port,
+        };
+        assert_eq!(Endpoint::parse("10.0.0.5").unwrap(), socket("10.0.0.5", 5025));
+        assert_eq!(Endpoint::parse("::1").unwrap(), socket("::1", 5025));
+        assert_eq!(Endpoint::parse("10.0.0.5:1234").unwrap(), socket("10.0.0.5", 1234));
+        assert_eq!(Endpoint::parse("[fe80::2]:1234").unwrap(), socket("fe80::2", 1234));
+        assert_eq!(Endpoint::parse("socket://10.0.0.5:1234").unwrap(), socket("10.0.0.5", 1234));
+        assert_eq!(Endpoint::parse("socket://[fe80::2]:5025").unwrap(), socket("fe80::2", 5025));
+        assert_eq!(
+            Endpoint::parse("hislip://mxg-lab2.local").unwrap(),
+            Endpoint::Hislip {
+                host: "mxg-lab2.local".into(),
+                port: 4880,
+                sub_address: "hislip0".into(),
+            }
+        );
+        assert_eq!(
+            Endpoint::parse("hislip://10.0.0.5:4881/hislip1").unwrap(),
+            Endpoint::Hislip {
+                host: "10.0.0.5".into(),
+                port: 4881,
+                sub_address: "hislip1".into(),
+            }
+        );
+        assert!(Endpoint::parse("vxi11://10.0.0.5").is_err());
+        assert!(Endpoint::parse("socket://10.0.0.5:http").is_err());
+        assert!(Endpoint::parse("socket://:5025").is_err());
+    }
+
+    #[test]
+    fn cancelled_block_write_reopens_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            // First connection gets the partial block and is dropped
+            let (stream, _) = listener.accept().unwrap();
+            let mut partial = Vec::new();
+            let _ = BufReader::new(stream).read_to_end(&mut partial);
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream.try_clone().unwrap()).read_line(&mut line).unwrap();
+            stream.write_all(b"1\n").unwrap();
+        });
+        let mut client = ScpiClient::connect("127.0.0.1", port, 2).unwrap();
+        let cancel = AtomicBool::new(false);
+        let stop = |_| cancel.store(true, Ordering::SeqCst);
+        let err = client
+            .write_binary_block_progress("DATA ", &[0u8; 64], 16, &stop, Some(&cancel))
+            .unwrap_err();
+        assert_eq!(err, CANCELLED);
+        assert!(!client.is_disconnected());
+        assert_eq!(client.query("*OPC?").unwrap(), "1");
+    }
+
+    #[test]
+    fn retries_reads_that_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for _ in 0..2 {
+                let mut line = String::new();
+                BufReader::new(stream.try_clone().unwrap()).read_line(&mut line).unwrap();
+                // Answer after the first read attempt has timed out
+                thread::sleep(Duration::from_millis(150));
+                stream.write_all(b"1\n").unwrap();
+            }
+        });
+        let mut client = ScpiClient::connect("127.0.0.1", port, 2).unwrap();
+        client.set_timeout(Duration::from_millis(100)).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        client.on_retry(Arc::new(move |e: &RetryEvent| seen.lock().unwrap().push(e.clone())));
+        client.set_retry_policy(Some(RetryPolicy {
+            max_attempts: 3,
+            backoff_ms: 10,
+        }));
+        assert_eq!(client.query("*OPC?").unwrap(), "1");
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].cmd.as_str(), events[0].attempt), ("*OPC?", 2));
+
+        // Without a policy the same delay fails the query
+        client.set_retry_policy(None);
+        assert!(client.query("*OPC?").unwrap_err().contains("timed out"));
+    }
+
+    #[test]
+    fn strips_lf_cr_and_crlf() {
+        // The CR of the second reply arrives apart from its LF
+        let mut client = serve(vec![b"LF\nCRLF\r", b"\nCR\rlast\r\n"]);
+        assert_eq!(client.query("*IDN?").unwrap(), "LF");
+        assert_eq!(client.read_response().unwrap(), "CRLF");
+        assert_eq!(client.read_response().unwrap(), "CR");
+        assert_eq!(client.read_response().unwrap(), "last");
+    }
+
+    #[test]
+    fn stray_lf_does_not_precede_a_block() {
+        let mut client = serve(vec![b"1\r", b"\n#13abc\n"]);
+        assert_eq!(client.query("*OPC?").unwrap(), "1");
+        assert_eq!(client.read_binary_block().unwrap(), b"abc");
+    }
+
+    #[test]
+    fn reads_until_custom_terminator() {
+        let mut client = serve(vec![b"1,\"a.wfm\"\r\n2,\"b.wfm\"\r\nEND\r\n", b"+0;-1;"]);
+        client.write_cmd("MMEM:CAT?").unwrap();
+        let transport: &mut dyn ScpiTransport = &mut client;
+        assert_eq!(
+            transport.read_until("END").unwrap(),
+            "1,\"a.wfm\"\r\n2,\"b.wfm\""
+        );
+        client.set_read_terminator(Some(";")).unwrap();
+        // The CRLF left after END is stripped from the next response
+        assert_eq!(client.read_response().unwrap(), "+0");
+        assert_eq!(client.read_response().unwrap(), "-1");
+        assert!(client.set_read_terminator(Some("")).is_err());
+    }
+
+    #[test]
+    fn device_clear_reconnects_and_clears_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut line = String::new();
+                BufReader::new(stream).read_line(&mut line).unwrap();
+                tx.send(line).unwrap();
+            }
+        });
+        let mut client = ScpiClient::connect("127.0.0.1", port, 2).unwrap();
+        client.set_read_terminator(Some(";")).unwrap();
+        client.device_clear().unwrap();
+        // The first connection is closed without another command
+        assert_eq!(rx.recv().unwrap(), "");
+        assert_eq!(rx.recv().unwrap(), "*CLS\n");
+        assert_eq!(client.peer().port(), port);
+        assert_eq!(client.terminator.as_deref(), Some(&b";"[..]));
+    }
+
+    #[test]
+    fn retry_backoff_doubles() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            backoff_ms: 100,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn parses_error_codes() {
+        assert_eq!(parse_error_code("+0,\"No error\""), Some(0));
+        assert_eq!(parse_error_code("-222,\"Data out of range\""), Some(-222));
+        assert_eq!(parse_error_code(""), None);
+        assert_eq!(parse_error_code("garbage"), None);
+    }
+
+    #[test]
+    fn err_check_drains_error_queue() {
+        let mut client = serve_replies(vec![
+            "-222,\"Data out of range\"",
+            "-113,\"Undefined header\"",
+            "+0,\"No error\"",
+        ]);
+        let err = client.err_check().unwrap_err();
+        assert!(err.contains("-222,\"Data out of range\"; -113,"), "{}", err);
+
+        let mut client = serve_replies(vec!["+0,\"No error\""]);
+        assert!(client.err_check().is_ok());
+    }
+
+    #[test]
+    fn err_check_caps_drained_errors() {
+        let mut client = serve_replies(vec!["-350,\"Queue overflow\""; 100]);
+        let err = client.err_check().unwrap_err();
+        assert_eq!(err.matches("-350").count(), MAX_DRAINED_ERRORS);
+        assert!(err.contains("further errors left"), "{}", err);
+    }
+
+    #[test]
+    fn err_check_fails_on_garbage_and_timeout() {
+        let mut client = serve_replies(vec!["-222,\"Data out of range\"", ""]);
+        let err = client.err_check().unwrap_err();
+        assert!(err.contains("Data out of range"), "{}", err);
+        assert!(err.contains("unexpected SYST:ERR? response \"\""), "{}", err);
+
+        let mut client = serve_replies(vec![]);
+        client.set_timeout(Duration::from_millis(100)).unwrap();
+        let err = client.err_check().unwrap_err();
+        assert!(err.contains("timed out"), "{}", err);
+    }
+
+    #[test]
+    fn reads_definite_length_block() {
+        let mut client = serve(vec![b"#15hel", b"lo\n"]);
+        assert_eq!(client.query_binary_block("DATA?").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn block_data_may_contain_newlines() {
+        let mut client = serve(vec![b"#14a\nb\n", b"\n"]);
+        assert_eq!(client.query_binary_block("DATA?").unwrap(), b"a\nb\n");
+    }
+
+    #[test]
+    fn reads_indefinite_length_block() {
+        let mut client = serve(vec![b"#0abc", b"def\n"]);
+        assert_eq!(client.query_binary_block("DATA?").unwrap(), b"abcdef");
+    }
+
+    #[test]
+    fn rejects_oversized_and_garbled_blocks() {
+        let mut client = serve(vec![b"#9123456789\n"]);
+        client.set_max_block_size(1024);
+        let err = client.query_binary_block("DATA?").unwrap_err();
+        assert!(err.contains("exceeds the 1024 byte limit"), "{}", err);
+
+        let mut client = serve(vec![b"#0abcdefgh\n"]);
+        client.set_max_block_size(4);
+        assert!(client.query_binary_block("DATA?").is_err());
+
+        let mut client = serve(vec![b"-113,\"Undefined header\"\n"]);
+        let err = client.query_binary_block("DATA?").unwrap_err();
+        assert!(err.contains("Expected a binary block"), "{}", err);
+    }
 }
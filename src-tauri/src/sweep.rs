@@ -0,0 +1,439 @@
+use std::fmt::Display;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dut::WlanInterface;
+use crate::vsg::{Retrigger, TriggerSource, TriggerTiming};
+
+const CHECKPOINT_FILE: &str = "sweep_recovery.json";
+
+/// Parameters of a power sweep, kept in the checkpoint so an interrupted run
+/// can be resumed with the same definition.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PowerSweepParams {
+    pub cf: f64,
+    pub bw_mhz: f64,
+    pub cable_loss: f64,
+    pub start_power: f64,
+    pub end_power: f64,
+    pub step: f64,
+    pub warmup_bursts: Option<u32>,
+    pub auto_waveform: Option<bool>,
+    /// Waveform slot to play; the default slot when unset.
+    pub slot: Option<String>,
+    /// What starts each burst; the sweep sends the triggers itself when
+    /// unset or `Bus`.
+    pub trigger_source: Option<TriggerSource>,
+    /// Download the waveform even if the instrument already holds it.
+    pub force_download: Option<bool>,
+    /// Turn ALC off and run one power search after setting the start power.
+    pub alc_off: Option<bool>,
+    /// Block size of the waveform download; `DEFAULT_DOWNLOAD_CHUNK` when unset.
+    pub download_chunk_bytes: Option<usize>,
+    /// Step power with the instrument's list sweep instead of per-step SCPI.
+    pub hardware_list: Option<bool>,
+    /// Dwell per list point in seconds; twice a sweep step's burst time
+    /// when unset.
+    pub list_dwell_s: Option<f64>,
+    /// Delay from each trigger to the burst, in seconds.
+    pub trigger_delay_s: Option<f64>,
+    /// Handling of a trigger that arrives while a step still plays.
+    pub retrigger: Option<Retrigger>,
+    /// RF output to sweep, from 1; the first when unset.
+    pub channel: Option<u8>,
+    /// Blank the carrier between bursts with marker 1.
+    pub rf_blanking: Option<bool>,
+    /// DUT radio to receive on; overrides the one set at `connect_dut`
+    /// and stays set after the sweep.
+    pub dut_interface: Option<WlanInterface>,
+    /// DUT receive chain (antenna) to open; the firmware default when unset.
+    pub dut_chain: Option<u8>,
+}
+
+impl PowerSweepParams {
+    pub fn trigger_timing(&self) -> TriggerTiming {
+        TriggerTiming {
+            delay_s: self.trigger_delay_s.unwrap_or(0.0),
+            retrigger: self.retrigger.unwrap_or_default(),
+        }
+    }
+
+    /// Power levels visited by the sweep, from start to end inclusive.
+    /// Fails on a step that is not a positive, finite number of dB, which
+    /// would never reach the end.
+    pub fn power_steps(&self) -> Result<Vec<f64>, String> {
+        if !self.step.is_finite() || self.step <= 0.0 {
+            return Err(format!("Invalid sweep step {} dB", self.step));
+        }
+        if !self.start_power.is_finite() || !self.end_power.is_finite() {
+            return Err(format!(
+                "Invalid sweep range {} to {} dBm",
+                self.start_power, self.end_power
+            ));
+        }
+        let mut powers = Vec::new();
+        let mut p = self.start_power;
+        while p <= self.end_power + 1e-9 {
+            powers.push(p);
+            p += self.step;
+        }
+        Ok(powers)
+    }
+
+    /// `list_dwell_s` as a duration, rejecting values that are not a
+    /// positive, finite number of seconds.
+    pub fn list_dwell(&self) -> Result<Option<Duration>, String> {
+        match self.list_dwell_s {
+            None => Ok(None),
+            Some(s) if s.is_finite() && s > 0.0 => {
+                Duration::try_from_secs_f64(s).map(Some).map_err(|e| format!("Invalid list dwell {} s: {}", s, e))
+            }
+            Some(s) => Err(format!("Invalid list dwell {} s", s)),
+        }
+    }
+}
+
+/// Result of one sweep step, emitted as `sweep-progress`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SweepProgress {
+    pub current_power: f64,
+    pub step_index: usize,
+    pub total_steps: usize,
+    pub rec_rx_count: Option<u32>,
+    pub rx_ok_count: Option<u32>,
+    /// RSSI of each receive chain in dBm.
+    pub rssi1: Option<i32>,
+    pub rssi2: Option<i32>,
+    pub fcs_err: Option<u32>,
+    pub phy_err: Option<u32>,
+    /// DUT commands retried during the step.
+    #[serde(default)]
+    pub dut_retries: u32,
+    /// Whether the DUT reset its counters before the step; false when
+    /// they were read instead. `None` without a DUT.
+    pub mib_cleared: Option<bool>,
+    /// Frames the DUT decoded at bandwidths other than the sweep's.
+    pub ok_other_bw: Option<u32>,
+    pub timing: StepTiming,
+}
+
+/// Recovery snapshot of an in-progress sweep, rewritten after every step.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SweepCheckpoint {
+    pub params: PowerSweepParams,
+    /// Steps completed so far; a resumed sweep continues at `completed.len()`.
+    pub completed: Vec<SweepProgress>,
+    pub waveform_file: Option<String>,
+    /// `waveform_hash` of the waveform bytes the sweep was started with.
+    pub waveform_hash: u64,
+    pub inst_id: String,
+}
+
+/// Wall-clock time spent in each phase of one sweep step, in milliseconds.
+///
+/// Phases that did not run for a step (e.g. DUT phases with no DUT connected)
+/// stay at 0.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StepTiming {
+    pub open_rx_ms: f64,
+    pub set_power_ms: f64,
+    pub trigger_ms: f64,
+    pub dwell_ms: f64,
+    pub read_mib_ms: f64,
+    pub close_rx_ms: f64,
+    /// Part of the phases above spent retrying failed instrument or DUT
+    /// commands.
+    #[serde(default)]
+    pub retries_ms: f64,
+    pub total_ms: f64,
+}
+
+/// How a software-stepped sweep waits for each step to play.
+#[derive(Clone, Copy, Debug)]
+pub struct StepPacing {
+    /// The sweep sends a bus trigger per step and watches the sequence end.
+    pub self_trigger: bool,
+    /// Playing time of a step, trigger delay included.
+    pub nominal: Duration,
+    /// Longest wait for a triggered sequence to end.
+    pub timeout: Duration,
+    /// Wait per step when an external or key trigger starts the bursts.
+    pub external_wait: Duration,
+}
+
+/// Mean and maximum of one phase over all completed steps.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PhaseStats {
+    pub mean_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Per-phase timing summary reported with `sweep-done`.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TimingSummary {
+    pub open_rx: PhaseStats,
+    pub set_power: PhaseStats,
+    pub trigger: PhaseStats,
+    pub dwell: PhaseStats,
+    pub read_mib: PhaseStats,
+    pub close_rx: PhaseStats,
+    pub retries: PhaseStats,
+    pub total: PhaseStats,
+}
+
+/// Milliseconds elapsed since `start`.
+pub fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1e3
+}
+
+fn phase_stats(steps: &[StepTiming], phase: impl Fn(&StepTiming) -> f64) -> PhaseStats {
+    if steps.is_empty() {
+        return PhaseStats::default();
+    }
+    let sum: f64 = steps.iter().map(&phase).sum();
+    let max_ms = steps.iter().map(&phase).fold(0.0f64, f64::max);
+    PhaseStats {
+        mean_ms: sum / steps.len() as f64,
+        max_ms,
+    }
+}
+
+/// Summarize per-step timings into mean/max per phase.
+pub fn summarize_timing(steps: &[StepTiming]) -> TimingSummary {
+    TimingSummary {
+        open_rx: phase_stats(steps, |t| t.open_rx_ms),
+        set_power: phase_stats(steps, |t| t.set_power_ms),
+        trigger: phase_stats(steps, |t| t.trigger_ms),
+        dwell: phase_stats(steps, |t| t.dwell_ms),
+        read_mib: phase_stats(steps, |t| t.read_mib_ms),
+        close_rx: phase_stats(steps, |t| t.close_rx_ms),
+        retries: phase_stats(steps, |t| t.retries_ms),
+        total: phase_stats(steps, |t| t.total_ms),
+    }
+}
+
+/// Write sweep results as CSV, one row per step with its DUT counters and
+/// phase timings. Counters the step did not read are left empty.
+pub fn export_csv(steps: &[SweepProgress], path: &str) -> Result<(), String> {
+    let file = std::fs::File::create(path).map_err(|e| format!("Failed to create file: {}", e))?;
+    let mut out = BufWriter::new(file);
+    write_csv(&mut out, steps)
+        .and_then(|_| out.flush())
+        .map_err(|e| format!("Failed to write file: {}", e))
+}
+
+fn write_csv(out: &mut impl Write, steps: &[SweepProgress]) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "step,power_dbm,rec_rx_count,rx_ok_count,rssi1,rssi2,fcs_err,phy_err,dut_retries,\
+         open_rx_ms,set_power_ms,trigger_ms,dwell_ms,read_mib_ms,close_rx_ms,retries_ms,total_ms"
+    )?;
+    fn opt(value: Option<impl Display>) -> String {
+        value.map(|v| v.to_string()).unwrap_or_default()
+    }
+    for s in steps {
+        let t = &s.timing;
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3}",
+            s.step_index,
+            s.current_power,
+            opt(s.rec_rx_count),
+            opt(s.rx_ok_count),
+            opt(s.rssi1),
+            opt(s.rssi2),
+            opt(s.fcs_err),
+            opt(s.phy_err),
+            s.dut_retries,
+            t.open_rx_ms,
+            t.set_power_ms,
+            t.trigger_ms,
+            t.dwell_ms,
+            t.read_mib_ms,
+            t.close_rx_ms,
+            t.retries_ms,
+            t.total_ms,
+        )?;
+    }
+    Ok(())
+}
+
+/// Location of the recovery file inside the app data dir.
+pub fn checkpoint_path(dir: &Path) -> PathBuf {
+    dir.join(CHECKPOINT_FILE)
+}
+
+/// Write the checkpoint via a temporary file so a crash mid-write never
+/// leaves a truncated snapshot behind.
+pub fn save_checkpoint(path: &Path, checkpoint: &SweepCheckpoint) -> Result<(), String> {
+    let json = serde_json::to_string(checkpoint)
+        .map_err(|e| format!("Failed to serialize sweep checkpoint: {}", e))?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json).map_err(|e| format!("Failed to write sweep checkpoint: {}", e))?;
+    std::fs::rename(&tmp, path).map_err(|e| format!("Failed to write sweep checkpoint: {}", e))
+}
+
+/// Read the checkpoint, or `None` when there is nothing to recover.
+pub fn load_checkpoint(path: &Path) -> Result<Option<SweepCheckpoint>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read sweep checkpoint: {}", e))?;
+    serde_json::from_str(&json)
+        .map(Some)
+        .map_err(|e| format!("Corrupt sweep checkpoint: {}", e))
+}
+
+pub fn clear_checkpoint(path: &Path) -> Result<(), String> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Failed to remove sweep checkpoint: {}", e)),
+    }
+}
+
+/// FNV-1a hash of the waveform bytes. Stable across builds, unlike
+/// `DefaultHasher`, so it can be persisted in the checkpoint.
+pub fn waveform_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325u64, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_mean_and_max() {
+        let steps = vec![
+            StepTiming {
+                dwell_ms: 10.0,
+                read_mib_ms: 2.0,
+                ..Default::default()
+            },
+            StepTiming {
+                dwell_ms: 30.0,
+                read_mib_ms: 6.0,
+                ..Default::default()
+            },
+        ];
+        let summary = summarize_timing(&steps);
+        assert_eq!(summary.dwell.mean_ms, 20.0);
+        assert_eq!(summary.dwell.max_ms, 30.0);
+        assert_eq!(summary.read_mib.mean_ms, 4.0);
+        assert_eq!(summary.read_mib.max_ms, 6.0);
+        assert_eq!(summary.open_rx.max_ms, 0.0);
+    }
+
+    fn sweep_params() -> PowerSweepParams {
+        PowerSweepParams {
+            cf: 5180e6,
+            bw_mhz: 20.0,
+            cable_loss: 0.0,
+            start_power: -80.0,
+            end_power: -78.0,
+            step: 0.5,
+            warmup_bursts: None,
+            auto_waveform: None,
+            slot: None,
+            trigger_source: None,
+            force_download: None,
+            alc_off: None,
+            download_chunk_bytes: None,
+            hardware_list: None,
+            list_dwell_s: None,
+            trigger_delay_s: None,
+            retrigger: None,
+            channel: None,
+            rf_blanking: None,
+            dut_interface: None,
+            dut_chain: None,
+        }
+    }
+
+    #[test]
+    fn list_dwell_validated() {
+        let mut params = sweep_params();
+        assert_eq!(params.list_dwell(), Ok(None));
+        params.list_dwell_s = Some(0.5);
+        assert_eq!(params.list_dwell(), Ok(Some(Duration::from_millis(500))));
+        for bad in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            params.list_dwell_s = Some(bad);
+            assert!(params.list_dwell().is_err());
+        }
+    }
+
+    #[test]
+    fn csv_has_timing_columns() {
+        let step = SweepProgress {
+            current_power: -79.5,
+            step_index: 2,
+            total_steps: 5,
+            rec_rx_count: Some(100),
+            rx_ok_count: Some(98),
+            rssi1: Some(-80),
+            rssi2: None,
+            fcs_err: Some(2),
+            phy_err: Some(0),
+            dut_retries: 1,
+            mib_cleared: Some(true),
+            ok_other_bw: Some(0),
+            timing: StepTiming {
+                open_rx_ms: 1.5,
+                set_power_ms: 2.0,
+                dwell_ms: 40.25,
+                retries_ms: 0.5,
+                total_ms: 44.0,
+                ..Default::default()
+            },
+        };
+        let mut out = Vec::new();
+        write_csv(&mut out, &[step]).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "step,power_dbm,rec_rx_count,rx_ok_count,rssi1,rssi2,fcs_err,phy_err,dut_retries,\
+             open_rx_ms,set_power_ms,trigger_ms,dwell_ms,read_mib_ms,close_rx_ms,retries_ms,total_ms"
+        );
+        assert_eq!(
+            lines[1],
+            "2,-79.5,100,98,-80,,2,0,1,1.500,2.000,0.000,40.250,0.000,0.000,0.500,44.000"
+        );
+    }
+
+    #[test]
+    fn power_steps_include_end() {
+        let params = sweep_params();
+        assert_eq!(params.power_steps().unwrap(), vec![-80.0, -79.5, -79.0, -78.5, -78.0]);
+    }
+
+    #[test]
+    fn power_steps_reject_bad_step() {
+        let mut params = sweep_params();
+        for bad in [0.0, -0.5, f64::NAN, f64::INFINITY] {
+            params.step = bad;
+            assert_eq!(params.power_steps().unwrap_err(), format!("Invalid sweep step {} dB", bad));
+        }
+        params.step = 0.5;
+        params.end_power = f64::INFINITY;
+        assert!(params.power_steps().is_err());
+    }
+
+    #[test]
+    fn waveform_hash_is_fnv1a() {
+        assert_eq!(waveform_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(waveform_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn summary_empty() {
+        let summary = summarize_timing(&[]);
+        assert_eq!(summary.total.mean_ms, 0.0);
+    }
+}
@@ -1,173 +1,3400 @@
-use crate::scpi::ScpiClient;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-/// Controller for Keysight EXG/MXG/PSG/M938x Vector Signal Generators.
-///
-/// Implements SCPI-based instrument control for waveform download and playback.
-/// Reference: pyarbtools VSG class in reference/pyarbtools/instruments.py
-pub struct VsgInstrument {
-    client: ScpiClient,
-    pub inst_id: String,
+use crate::scpi::{self, RetryHook, RetryPolicy, ScpiTransport};
+use crate::sweep::{self, StepPacing, StepTiming};
+use crate::waveform::{decode_iq, ArbLimits};
+
+/// Edge of the external trigger input that starts playback.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerSlope {
+    #[default]
+    Positive,
+    Negative,
 }
 
-impl VsgInstrument {
-    /// Connect to a VSG at the given IP address (port 5025).
-    /// If `reset` is true, sends *RST and waits for completion.
-    pub fn connect(ip: &str, timeout_secs: u64, reset: bool) -> Result<Self, String> {
-        let mut client = ScpiClient::connect(ip, 5025, timeout_secs)?;
+/// What starts a finite-repeat playback.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum TriggerSource {
+    /// A software trigger sent by this app.
+    #[default]
+    Bus,
+    /// The rear-panel trigger input, `delay_s` after the selected edge.
+    External { slope: TriggerSlope, delay_s: f64 },
+    /// The front-panel trigger key.
+    Key,
+}
 
-        if reset {
-            client.write_cmd("*rst")?;
-            client.query("*opc?")?;
+/// Most segments one sequence may chain.
+pub const MAX_SEQUENCE_SEGMENTS: usize = 1024;
+/// Repeat count range of a sequence entry is 1 to this.
+pub const MAX_SEGMENT_REPEATS: u32 = 65535;
+
+/// Timeout for `*rst` and its `*opc?`, which can take several seconds.
+const RESET_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Timeout for `*TST?`, which runs for tens of seconds on most generators.
+const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// `*OPT?` entries that provide an ARB/baseband generator: Keysight
+/// baseband options (E4438C 601/602, MXG 651-657) and R&S baseband boards.
+const ARB_OPTIONS: [&str; 14] = [
+    "601", "602", "651", "652", "653", "654", "655", "656", "657", "B9", "B10", "B50", "B51", "B55",
+];
+
+/// Slowest transfer rate allowed for before a download times out, in bytes/s.
+const MIN_TRANSFER_RATE: f64 = 1e6;
+
+/// Timeout for sending `bytes` in one download: the connection timeout
+/// plus the time the transfer takes at `MIN_TRANSFER_RATE`.
+fn transfer_timeout(base: Duration, bytes: usize) -> Duration {
+    base + Duration::from_secs_f64(bytes as f64 / MIN_TRANSFER_RATE)
+}
+
+/// Start of the `stop` error when the instrument could not be reached.
+pub const CONNECTION_LOST: &str =
+    "Connection lost: instrument state unknown, RF may still be on; check the front panel";
+
+/// Reconnect attempts after the instrument drops the connection.
+const RECONNECT_ATTEMPTS: u32 = 3;
+/// Pause between reconnect attempts.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Most frequency/loss pairs in a flatness correction table.
+pub const MAX_LOSS_POINTS: usize = 1601;
+
+/// R&S user correction table written by `load_loss_table`.
+const RS_LOSS_TABLE: &str = "/var/user/waveplay_loss.uco";
+
+/// Most points a hardware power list may hold.
+pub const MAX_LIST_POINTS: usize = 3201;
+
+/// "Sweeping" bit of the operation status register.
+const SWEEPING_BIT: u32 = 1 << 3;
+
+/// "Waiting for trigger" bit of the operation status register, set again
+/// once a single-triggered sequence has played out.
+const WAITING_FOR_TRIGGER_BIT: u32 = 1 << 5;
+
+/// Pause between status polls in `wait_sequence_done`.
+const STATUS_POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Longest trigger delay the ARBs accept, in seconds.
+const MAX_TRIGGER_DELAY_S: f64 = 40.0;
+
+/// What a trigger arriving while a finite-repeat playback still runs does.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Retrigger {
+    /// Dropped; only a trigger after playback has ended starts it again.
+    #[default]
+    Ignore,
+    /// Held and acted on once the current playback ends.
+    Buffered,
+}
+
+/// Delay and retrigger handling of a finite-repeat playback.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TriggerTiming {
+    /// Seconds from the trigger to the first sample. Added to an external
+    /// trigger's own delay; a bus trigger gets it as the instrument's
+    /// trigger delay.
+    pub delay_s: f64,
+    pub retrigger: Retrigger,
+}
+
+impl TriggerTiming {
+    /// The trigger with this timing's delay applied, and the delay in
+    /// seconds to set for a bus trigger.
+    fn apply(self, trigger: TriggerSource) -> Result<(TriggerSource, f64), String> {
+        if !(0.0..=MAX_TRIGGER_DELAY_S).contains(&self.delay_s) {
+            return Err(format!(
+                "Trigger delay {} s is outside 0 to {} s",
+                self.delay_s, MAX_TRIGGER_DELAY_S
+            ));
+        }
+        Ok(match trigger {
+            TriggerSource::Bus => (trigger, self.delay_s),
+            TriggerSource::External { slope, delay_s } => (
+                TriggerSource::External {
+                    slope,
+                    delay_s: delay_s + self.delay_s,
+                },
+                0.0,
+            ),
+            TriggerSource::Key if self.delay_s > 0.0 => {
+                return Err("The trigger key cannot be delayed; use a bus or external trigger".into())
+            }
+            TriggerSource::Key => (trigger, 0.0),
+        })
+    }
+}
+
+impl TriggerSource {
+    fn validate(self) -> Result<(), String> {
+        match self {
+            Self::External { delay_s, .. } if !(0.0..=MAX_TRIGGER_DELAY_S).contains(&delay_s) => Err(format!(
+                "Trigger delay {} s is outside 0 to {} s",
+                delay_s, MAX_TRIGGER_DELAY_S
+            )),
+            _ => Ok(()),
         }
+    }
+}
+
+/// Frequency reference the generator locks to.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RefSource {
+    Internal,
+    /// The rear-panel 10 MHz reference input.
+    External,
+}
+
+/// Selected reference and whether the generator is locked to it.
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReferenceStatus {
+    pub source: RefSource,
+    pub locked: bool,
+}
+
+/// Reference-unlocked bit of the questionable frequency status register.
+const REF_UNLOCKED_BIT: u32 = 1 << 1;
 
-        let inst_id = client.query("*idn?")?;
+/// Default size of each block in a chunked waveform download.
+pub const DEFAULT_DOWNLOAD_CHUNK: usize = 4 << 20;
+
+/// Progress reporting and cancellation for a waveform download.
+pub struct DownloadControl<'a> {
+    /// Bytes per block, rounded down to whole IQ samples.
+    pub chunk_bytes: usize,
+    /// Called with (bytes sent, total bytes) as the download advances.
+    pub progress: &'a dyn Fn(u64, u64),
+    /// Checked between blocks; setting it aborts the download.
+    pub cancel: &'a AtomicBool,
+}
+
+/// Manufacturers recognised when `*IDN?` puts the fields in an unusual order.
+const KNOWN_VENDORS: [&str; 5] = ["keysight", "agilent", "hewlett", "rohde", "anritsu"];
+
+/// The `*IDN?` response split into its fields. A response that does not
+/// have four fields keeps only `raw`.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct InstrumentIdentity {
+    pub manufacturer: String,
+    pub model: String,
+    pub serial: String,
+    pub firmware: String,
+    /// The response as received, trimmed.
+    pub raw: String,
+}
+
+impl InstrumentIdentity {
+    /// Split an `*IDN?` reply by position. Fields a short reply lacks are
+    /// left empty and extra ones stay in `firmware`; a reply without at
+    /// least maker and model keeps only `raw`.
+    pub fn parse(idn: &str) -> Self {
+        let raw = idn.trim().to_string();
+        let mut fields: Vec<String> = raw.split(',').map(|f| f.trim().to_string()).collect();
+        if fields.len() < 2 || fields.iter().all(|f| f.is_empty()) {
+            return Self {
+                manufacturer: String::new(),
+                model: String::new(),
+                serial: String::new(),
+                firmware: String::new(),
+                raw,
+            };
+        }
+        // Some vendors lead with the model; move a recognised maker to the front
+        let is_vendor = |f: &String| {
+            let f = f.to_lowercase();
+            KNOWN_VENDORS.iter().any(|v| f.contains(v))
+        };
+        if !is_vendor(&fields[0]) {
+            if let Some(i) = fields.iter().position(is_vendor) {
+                let vendor = fields.remove(i);
+                fields.insert(0, vendor);
+            }
+        }
+        let firmware = fields.get(3..).map(|rest| rest.join(",")).unwrap_or_default();
+        let mut fields = fields.into_iter();
+        let mut next = || fields.next().unwrap_or_default();
+        Self {
+            manufacturer: next(),
+            model: next(),
+            serial: next(),
+            firmware,
+            raw,
+        }
+    }
 
-        Ok(Self { client, inst_id })
+    /// Whether only the raw response is known.
+    pub fn is_raw_only(&self) -> bool {
+        self.manufacturer.is_empty() && self.model.is_empty()
     }
 
-    /// Configure the VSG with carrier frequency, sample rate, and output power.
-    ///
-    /// - `cf`: carrier frequency in Hz
-    /// - `fs`: ARB sample clock rate in Hz
-    /// - `amp`: output power in dBm
-    pub fn configure(&mut self, cf: f64, fs: f64, amp: f64) -> Result<(), String> {
-        if fs > 240.0 * 1e6 {
-            return Err("Sample Rate Can not be set more than 240 MHz!".into())
-        }
-        self.client
-            .write_cmd(&format!("frequency {}", cf))?;
-        self.client
-            .write_cmd(&format!("radio:arb:sclock:rate {}", fs))?;
-        self.client
-            .write_cmd(&format!("power {}", amp))?;
-        self.client.err_check()
+    /// Whether `other` is the same unit. Maker, model and serial number are
+    /// compared; firmware may change on update.
+    fn same_instrument(&self, other: &Self) -> bool {
+        if self.is_raw_only() || other.is_raw_only() {
+            return self.raw.eq_ignore_ascii_case(&other.raw);
+        }
+        self.manufacturer.eq_ignore_ascii_case(&other.manufacturer)
+            && self.model.eq_ignore_ascii_case(&other.model)
+            && self.serial.eq_ignore_ascii_case(&other.serial)
     }
+}
 
-    /// Download a pre-formatted waveform (big-endian interleaved int16 IQ) to the instrument.
-    ///
-    /// `wfm_data` should be raw bytes from a .WAVEFORM file.
-    pub fn download_wfm(&mut self, wfm_data: &[u8], wfm_id: &str) -> Result<(), String> {
-        // Stop output before downloading
-        self.client.write_cmd("output:modulation 0")?;
-        self.client.write_cmd("radio:arb:state 0")?;
+/// Output and ARB limits of a generator model, parsed from `*IDN?` and
+/// checked before commands are sent so an out-of-range setting fails with
+/// the model limit rather than a later instrument error.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct InstrumentModel {
+    pub name: String,
+    pub min_freq_hz: f64,
+    pub max_freq_hz: f64,
+    pub max_power_dbm: f64,
+    pub max_sample_rate_hz: f64,
+    /// ARB waveform memory with the largest memory option, in samples.
+    pub arb_memory_samples: u64,
+    /// RF outputs; models with more than one take channel-prefixed commands.
+    pub channels: u8,
+    /// False for unrecognised models, which get permissive limits apart
+    /// from `DEFAULT_MAX_SAMPLE_RATE_HZ`.
+    pub known: bool,
+}
 
-        // Download waveform binary data using IEEE 488.2 block format
-        let cmd = format!("mmemory:data \"WFM1:{}\",", wfm_id);
-        self.client.write_binary_block(&cmd, wfm_data)?;
+/// ARB clock limit of unrecognised models: the fixed limit `configure`
+/// enforced before limits came from the model table.
+const DEFAULT_MAX_SAMPLE_RATE_HZ: f64 = 240e6;
 
-        // Select the uploaded waveform
-        self.client
-            .write_cmd(&format!("radio:arb:waveform \"WFM1:{}\"", wfm_id))?;
+/// (model prefix, min Hz, max Hz, max dBm, max ARB Hz, ARB memory in
+/// samples, RF outputs)
+const MODEL_LIMITS: [(&str, f64, f64, f64, f64, u64, u8); 6] = [
+    ("N5182A", 100e3, 6e9, 23.0, 125e6, 64 << 20, 1),
+    ("N5182B", 9e3, 6e9, 27.0, 240e6, 1 << 30, 1),
+    ("N5172B", 9e3, 6e9, 27.0, 200e6, 512 << 20, 1),
+    ("E4438C", 250e3, 6e9, 17.0, 100e6, 64 << 20, 1),
+    ("M9381A", 1e6, 6e9, 19.0, 200e6, 1 << 30, 1),
+    ("M9384B", 1e6, 44e9, 18.0, 2.5e9, 2 << 30, 2),
+];
 
-        self.client.err_check()
+impl InstrumentModel {
+    pub fn from_identity(identity: &InstrumentIdentity) -> Self {
+        let name = if identity.is_raw_only() {
+            identity.raw.clone()
+        } else {
+            identity.model.clone()
+        };
+        match MODEL_LIMITS.iter().find(|m| name.starts_with(m.0)) {
+            Some(&(_, min_freq_hz, max_freq_hz, max_power_dbm, max_sample_rate_hz, arb_memory_samples, channels)) => {
+                Self {
+                    name,
+                    min_freq_hz,
+                    max_freq_hz,
+                    max_power_dbm,
+                    max_sample_rate_hz,
+                    arb_memory_samples,
+                    channels,
+                    known: true,
+                }
+            }
+            None => Self {
+                name,
+                min_freq_hz: 0.0,
+                max_freq_hz: f64::INFINITY,
+                max_power_dbm: f64::INFINITY,
+                max_sample_rate_hz: DEFAULT_MAX_SAMPLE_RATE_HZ,
+                arb_memory_samples: u64::MAX,
+                channels: 1,
+                known: false,
+            },
+        }
     }
 
-    /// Activate arb playback: select waveform, enable RF output, modulation, and arb state.
-    /// Plays the waveform continuously (infinite loop).
-    pub fn play(&mut self, wfm_id: &str) -> Result<(), String> {
-        self.client.write_cmd("radio:arb:trigger:type continuous")?;
-        self.client
-            .write_cmd(&format!("radio:arb:waveform \"WFM1:{}\"", wfm_id))?;
-        self.client.write_cmd("output 1")?;
-        self.client.write_cmd("output:modulation 1")?;
-        self.client.write_cmd("radio:arb:state 1")?;
-        self.client.err_check()
+    /// Unknown models may address any channel; their outputs aren't known.
+    fn check_channel(&self, channel: u8) -> Result<(), String> {
+        if channel == 0 || (self.known && channel > self.channels) {
+            return Err(format!(
+                "Channel {} does not exist; the {} has {} RF output{}",
+                channel,
+                self.name,
+                self.channels,
+                if self.channels == 1 { "" } else { "s" }
+            ));
+        }
+        Ok(())
     }
 
-    /// Activate arb playback with a finite repeat count.
-    ///
-    /// Creates a waveform sequence from the uploaded segment with the specified
-    /// repeat count, then plays the sequence.
-    ///
-    /// SCPI flow (from Keysight N5182B Programming Guide):
-    ///   1. Build sequence: `:SOURce:RADio:ARB:SEQuence "<seq>","<wfm>",<reps>,<markers>`
-    ///   2. Select sequence:  `:SOURce:RADio:ARB:WAVeform "SEQ:<seq>"`
-    ///   3. Enable output:    ARB state → modulation → RF output
-    pub fn play_with_repeat(&mut self, wfm_id: &str, count: u32) -> Result<(), String> {
-        let seq_id = format!("seq_{}", wfm_id);
-
-        // // Create a waveform sequence referencing the uploaded segment.
-        // // markers = 0 (no markers enabled)
-        self.client.write_cmd(&format!(
-            "radio:arb:sequence \"{}\",\"WFM1:{}\",{},0",
-            seq_id, wfm_id, count
-        ))?;
-
-        // Select the sequence for playback
-        self.client.write_cmd(&format!(
-            "radio:arb:waveform \"SEQ:{}\"",
-            seq_id
-        ))?;
-        self.client.write_cmd("radio:arb:trigger:source bus")?;
-        self.client.write_cmd("radio:arb:trigger:type single")?;
-
-        // Enable playback (order per Keysight documentation)
-        self.client.write_cmd("radio:arb:state 1")?;
-        self.client.write_cmd("output:modulation 1")?;
-        self.client.write_cmd("output 1")?;
-
-        self.client.write_cmd("*TRG")?;
+    fn check_frequency(&self, cf: f64) -> Result<(), String> {
+        if cf < self.min_freq_hz || cf > self.max_freq_hz {
+            return Err(format!(
+                "Carrier {} MHz is outside the {} range of {} to {} MHz",
+                cf / 1e6,
+                self.name,
+                self.min_freq_hz / 1e6,
+                self.max_freq_hz / 1e6
+            ));
+        }
+        Ok(())
+    }
 
-        self.client.err_check()
+    fn check_power(&self, amp: f64) -> Result<(), String> {
+        if amp > self.max_power_dbm {
+            return Err(format!(
+                "Power {} dBm is above the {} maximum of {} dBm",
+                amp, self.name, self.max_power_dbm
+            ));
+        }
+        Ok(())
     }
 
-    /// Set output power without reconfiguring CF/FS.
-    pub fn set_power(&mut self, amp: f64) -> Result<(), String> {
-        self.client.write_cmd(&format!("power {}", amp))?;
-        self.client.err_check()
+    fn check_sample_rate(&self, fs: f64) -> Result<(), String> {
+        if fs > self.max_sample_rate_hz {
+            return Err(format!(
+                "Sample rate {} MHz is above the {} ARB maximum of {} MHz",
+                fs / 1e6,
+                self.name,
+                self.max_sample_rate_hz / 1e6
+            ));
+        }
+        Ok(())
     }
 
-    /// One-time sweep setup: configure CF/FS/power, download wfm, create sequence,
-    /// set trigger mode to bus/single, and enable output.
-    pub fn prepare_sweep(
-        &mut self,
-        wfm_data: &[u8],
+    fn check_samples(&self, samples: u64) -> Result<(), String> {
+        if samples > self.arb_memory_samples {
+            return Err(format!(
+                "Waveform of {} samples does not fit the {} ARB memory of {} samples",
+                samples, self.name, self.arb_memory_samples
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// SCPI dialect of a signal generator vendor, chosen from the manufacturer
+/// field of `*IDN?`. Each method returns the commands for one operation so
+/// the sequences can be checked without an instrument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VsgDialect {
+    /// EXG/MXG/PSG/M938x: `radio:arb:*`, segments in `WFM1:`.
+    Keysight,
+    /// SMW/SMBV: `BB:ARB:*`, segments as .wv files in /var/user.
+    RohdeSchwarz,
+}
+
+impl VsgDialect {
+    pub fn from_identity(identity: &InstrumentIdentity) -> Self {
+        let vendor = if identity.is_raw_only() {
+            identity.raw.to_lowercase()
+        } else {
+            identity.manufacturer.to_lowercase()
+        };
+        if vendor.contains("rohde") {
+            Self::RohdeSchwarz
+        } else {
+            Self::Keysight
+        }
+    }
+
+    fn configure(self, cf: f64, fs: f64, amp: f64) -> Vec<String> {
+        match self {
+            Self::Keysight => vec![
+                format!("frequency {}", cf),
+                format!("radio:arb:sclock:rate {}", fs),
+                format!("power {}", amp),
+            ],
+            Self::RohdeSchwarz => vec![
+                format!("SOUR1:FREQ {}", cf),
+                format!("SOUR1:BB:ARB:CLOC {}", fs),
+                format!("SOUR1:POW {}", amp),
+            ],
+        }
+    }
+
+    /// Stop output before a download.
+    fn halt_arb(self) -> Vec<String> {
+        match self {
+            Self::Keysight => vec!["output:modulation 0".into(), "radio:arb:state 0".into()],
+            Self::RohdeSchwarz => vec!["SOUR1:BB:ARB:STAT OFF".into()],
+        }
+    }
+
+    /// `cmd` addressed to RF output `channel`. Keysight multi-output models
+    /// take an `rf<n>:` prefix on every channel command, while single-output
+    /// ones (`prefixed` false) keep the plain commands. R&S commands already
+    /// name SOUR1/OUTP1, so another path replaces the number. Common,
+    /// memory and status commands are shared by all channels.
+    fn on_channel(self, cmd: &str, channel: u8, prefixed: bool) -> String {
+        let lower = cmd.trim_start_matches(':').to_ascii_lowercase();
+        if cmd.starts_with('*') || ["mmem", "syst", "stat"].iter().any(|p| lower.starts_with(p)) {
+            return cmd.to_string();
+        }
+        match self {
+            Self::Keysight if prefixed => format!("rf{}:{}", channel, cmd),
+            Self::Keysight => cmd.to_string(),
+            Self::RohdeSchwarz if channel == 1 => cmd.to_string(),
+            Self::RohdeSchwarz => match cmd.strip_prefix("OUTP1") {
+                Some(rest) => format!("OUTP{}{}", channel, rest),
+                None => cmd.replacen("SOUR1:", &format!("SOUR{}:", channel), 1),
+            },
+        }
+    }
+
+    /// Switch ARB modulation on or off, leaving the RF output as it is
+    /// (CW at the carrier while off).
+    fn set_modulation(self, enabled: bool) -> Vec<String> {
+        match (self, enabled) {
+            (Self::Keysight, true) => vec!["radio:arb:state 1".into(), "output:modulation 1".into()],
+            (Self::Keysight, false) => vec!["output:modulation 0".into(), "radio:arb:state 0".into()],
+            (Self::RohdeSchwarz, true) => vec!["SOUR1:BB:ARB:STAT ON".into(), "SOUR1:IQ:STAT ON".into()],
+            (Self::RohdeSchwarz, false) => vec!["SOUR1:IQ:STAT OFF".into(), "SOUR1:BB:ARB:STAT OFF".into()],
+        }
+    }
+
+    /// Command prefix of the binary block carrying the waveform.
+    fn data_cmd(self, wfm_id: &str) -> String {
+        match self {
+            Self::Keysight => format!("mmemory:data \"WFM1:{}\",", wfm_id),
+            Self::RohdeSchwarz => format!("SOUR1:BB:ARB:WAV:DATA \"/var/user/{}.wv\",", wfm_id),
+        }
+    }
+
+    /// Query returning a stored waveform file as a binary block.
+    fn data_query(self, wfm_id: &str) -> String {
+        match self {
+            Self::Keysight => format!("mmemory:data? \"WFM1:{}\"", wfm_id),
+            Self::RohdeSchwarz => format!("MMEM:DATA? \"/var/user/{}.wv\"", wfm_id),
+        }
+    }
+
+    /// Command that appends a block to an existing segment, where the
+    /// instrument has one. R&S .wv files have to arrive in a single block.
+    fn append_cmd(self, wfm_id: &str) -> Option<String> {
+        match self {
+            Self::Keysight => Some(format!("mmemory:data:append \"WFM1:{}\",", wfm_id)),
+            Self::RohdeSchwarz => None,
+        }
+    }
+
+    fn select(self, wfm_id: &str) -> String {
+        match self {
+            Self::Keysight => format!("radio:arb:waveform \"WFM1:{}\"", wfm_id),
+            Self::RohdeSchwarz => format!("SOUR1:BB:ARB:WAV:SEL \"/var/user/{}.wv\"", wfm_id),
+        }
+    }
+
+    /// Route marker 1 to the EVENT1 (Keysight) or USER1 (R&S) connector, or
+    /// disconnect it.
+    fn route_marker1(self, enable: bool) -> Vec<String> {
+        match (self, enable) {
+            (Self::Keysight, true) => vec!["route:connectors:event1 m1".into()],
+            (Self::Keysight, false) => vec!["route:connectors:event1 none".into()],
+            (Self::RohdeSchwarz, true) => vec![
+                "SOUR1:INP:USER1:DIR OUTP".into(),
+                "OUTP:USER1:SIGN MARKA1".into(),
+            ],
+            (Self::RohdeSchwarz, false) => vec!["OUTP:USER1:SIGN NONE".into()],
+        }
+    }
+
+    /// Gate the RF output with marker 1 (high during the burst), blanking
+    /// the carrier in the idle gap. ALC is held while blanked so the level
+    /// does not droop. R&S has no marker-driven blanking here.
+    fn rf_blanking(self, enable: bool) -> Result<Vec<String>, String> {
+        Ok(match (self, enable) {
+            (Self::Keysight, true) => vec![
+                "radio:arb:mpolarity:marker1 positive".into(),
+                "radio:arb:mdestination:alchold m1".into(),
+                "radio:arb:mdestination:pulse m1".into(),
+            ],
+            (Self::Keysight, false) => vec![
+                "radio:arb:mdestination:pulse none".into(),
+                "radio:arb:mdestination:alchold none".into(),
+            ],
+            (Self::RohdeSchwarz, true) => {
+                return Err("Marker-driven RF blanking is not supported on R&S generators".into())
+            }
+            (Self::RohdeSchwarz, false) => Vec::new(),
+        })
+    }
+
+    fn play(self, wfm_id: &str, marker_to_event1: bool) -> Vec<String> {
+        self.play_selection(self.select(wfm_id), marker_to_event1)
+    }
+
+    /// Continuous playback of whatever `select_cmd` selects.
+    fn play_selection(self, select_cmd: String, marker_to_event1: bool) -> Vec<String> {
+        let mut cmds = match self {
+            Self::Keysight => vec!["radio:arb:trigger:type continuous".into()],
+            Self::RohdeSchwarz => vec!["SOUR1:BB:ARB:TRIG:SEQ AUTO".into()],
+        };
+        cmds.push(select_cmd);
+        cmds.extend(self.route_marker1(marker_to_event1));
+        cmds.extend(match self {
+            Self::Keysight => vec![
+                "output 1".into(),
+                "output:modulation 1".into(),
+                "radio:arb:state 1".into(),
+            ],
+            Self::RohdeSchwarz => vec!["SOUR1:BB:ARB:STAT ON".into(), "OUTP1 ON".into()],
+        });
+        cmds
+    }
+
+    /// Set up `count` back-to-back passes of the segment behind a single
+    /// trigger and enable output, without triggering. The first command
+    /// selects what plays.
+    fn arm_repeat(
+        self,
         wfm_id: &str,
-        cf: f64,
-        fs: f64,
-        amp: f64,
-        repeat_count: u32,
-    ) -> Result<(), String> {
-        self.configure(cf, fs, amp)?;
-        self.download_wfm(wfm_data, wfm_id)?;
+        count: u32,
+        markers: bool,
+        trigger: TriggerSource,
+        timing: TriggerTiming,
+    ) -> Result<Vec<String>, String> {
+        let (trigger, bus_delay_s) = timing.apply(trigger)?;
+        let retrigger = timing.retrigger;
+        trigger.validate()?;
+        Ok(match self {
+            Self::Keysight => {
+                let seq_id = format!("seq_{}", wfm_id);
+                let mut cmds = vec![
+                    format!(
+                        "radio:arb:sequence \"{}\",\"WFM1:{}\",{},{}",
+                        seq_id,
+                        wfm_id,
+                        count,
+                        if markers { "M1" } else { "0" }
+                    ),
+                    format!("radio:arb:waveform \"SEQ:{}\"", seq_id),
+                ];
+                cmds.extend(self.trigger_source(trigger)?);
+                if trigger == TriggerSource::Bus {
+                    cmds.extend(self.bus_trigger_delay(bus_delay_s)?);
+                }
+                cmds.extend([
+                    "radio:arb:trigger:type single".into(),
+                    format!(
+                        "radio:arb:retrigger {}",
+                        if retrigger == Retrigger::Buffered { "on" } else { "off" }
+                    ),
+                    "radio:arb:state 1".into(),
+                    "output:modulation 1".into(),
+                    "output 1".into(),
+                ]);
+                cmds
+            }
+            // No sequence needed: one trigger plays the segment `count` times.
+            // Single mode ignores triggers during playback.
+            Self::RohdeSchwarz => {
+                if retrigger == Retrigger::Buffered {
+                    return Err("R&S generators cannot buffer a retrigger; use ignore".into());
+                }
+                let mut cmds = vec![
+                    self.select(wfm_id),
+                    "SOUR1:BB:ARB:TRIG:SEQ SING".into(),
+                    "SOUR1:BB:ARB:TRIG:SLUN SEQ".into(),
+                    format!("SOUR1:BB:ARB:TRIG:SLEN {}", count),
+                ];
+                cmds.extend(self.trigger_source(trigger)?);
+                if trigger == TriggerSource::Bus {
+                    cmds.extend(self.bus_trigger_delay(bus_delay_s)?);
+                }
+                cmds.extend(["SOUR1:BB:ARB:STAT ON".into(), "OUTP1 ON".into()]);
+                cmds
+            }
+        })
+    }
 
-        let seq_id = format!("seq_{}", wfm_id);
+    /// Sequence definition chaining `segments` (id, repeats, marker 1).
+    fn sequence(self, seq_id: &str, segments: &[(&str, u32, bool)]) -> Result<String, String> {
+        if segments.is_empty() || segments.len() > MAX_SEQUENCE_SEGMENTS {
+            return Err(format!(
+                "A sequence needs 1 to {} segments, got {}",
+                MAX_SEQUENCE_SEGMENTS,
+                segments.len()
+            ));
+        }
+        if let Some((id, reps, _)) = segments.iter().find(|s| !(1..=MAX_SEGMENT_REPEATS).contains(&s.1)) {
+            return Err(format!(
+                "Segment '{}' repeats {} times; the range is 1 to {}",
+                id, reps, MAX_SEGMENT_REPEATS
+            ));
+        }
+        match self {
+            Self::Keysight => {
+                let entries: Vec<String> = segments
+                    .iter()
+                    .map(|(id, reps, markers)| format!("\"WFM1:{}\",{},{}", id, reps, if *markers { "M1" } else { "0" }))
+                    .collect();
+                Ok(format!("radio:arb:sequence \"{}\",{}", seq_id, entries.join(",")))
+            }
+            Self::RohdeSchwarz => Err("Multi-segment sequences are only supported on Keysight generators".into()),
+        }
+    }
 
-        // Create sequence with specified repeat count
-        self.client.write_cmd(&format!(
-            "radio:arb:sequence \"{}\",\"WFM1:{}\",{},0",
-            seq_id, wfm_id, repeat_count
-        ))?;
+    fn select_sequence(self, seq_id: &str) -> String {
+        format!("radio:arb:waveform \"SEQ:{}\"", seq_id)
+    }
 
-        // Select the sequence
-        self.client.write_cmd(&format!(
-            "radio:arb:waveform \"SEQ:{}\"",
-            seq_id
-        ))?;
+    /// Delay from a bus trigger to the first sample. Always sent on
+    /// Keysight so a delay from an earlier playback does not linger.
+    fn bus_trigger_delay(self, delay_s: f64) -> Result<Vec<String>, String> {
+        match self {
+            Self::Keysight => Ok(vec![
+                format!("radio:arb:trigger:delay {}", delay_s),
+                format!("radio:arb:trigger:delay:state {}", if delay_s > 0.0 { "on" } else { "off" }),
+            ]),
+            Self::RohdeSchwarz if delay_s > 0.0 => {
+                Err("R&S generators only delay external triggers; use an external trigger".into())
+            }
+            Self::RohdeSchwarz => Ok(vec![]),
+        }
+    }
 
-        // Set trigger to bus/single so we control each burst with *TRG
-        self.client.write_cmd("radio:arb:trigger:source bus")?;
-        self.client.write_cmd("radio:arb:trigger:type single")?;
+    fn trigger_source(self, trigger: TriggerSource) -> Result<Vec<String>, String> {
+        let slope = |slope| match slope {
+            TriggerSlope::Positive => "positive",
+            TriggerSlope::Negative => "negative",
+        };
+        Ok(match (self, trigger) {
+            (Self::Keysight, TriggerSource::Bus) => vec!["radio:arb:trigger:source bus".into()],
+            (Self::Keysight, TriggerSource::Key) => vec!["radio:arb:trigger:source key".into()],
+            (Self::Keysight, TriggerSource::External { slope: edge, delay_s }) => vec![
+                "radio:arb:trigger:source ext".into(),
+                format!("radio:arb:trigger:source:external:slope {}", slope(edge)),
+                format!("radio:arb:trigger:source:external:delay {}", delay_s),
+                format!(
+                    "radio:arb:trigger:source:external:delay:state {}",
+                    if delay_s > 0.0 { "on" } else { "off" }
+                ),
+            ],
+            (Self::RohdeSchwarz, TriggerSource::Bus) => vec!["SOUR1:BB:ARB:TRIG:SOUR INT".into()],
+            (Self::RohdeSchwarz, TriggerSource::Key) => {
+                return Err("R&S generators have no trigger key source; use bus or external".into())
+            }
+            (Self::RohdeSchwarz, TriggerSource::External { slope: edge, delay_s }) => vec![
+                "SOUR1:BB:ARB:TRIG:SOUR EGT1".into(),
+                format!("SOUR1:INP:TRIG:SLOP {}", if edge == TriggerSlope::Positive { "POS" } else { "NEG" }),
+                "SOUR1:BB:ARB:TRIG:DEL:UNIT TIME".into(),
+                format!("SOUR1:BB:ARB:TRIG:EXT:TDEL {}", delay_s),
+            ],
+        })
+    }
 
-        // Enable playback chain
-        self.client.write_cmd("radio:arb:state 1")?;
-        self.client.write_cmd("output:modulation 1")?;
-        self.client.write_cmd("output 1")?;
+    fn catalog_query(self) -> &'static str {
+        match self {
+            Self::Keysight => "mmemory:catalog? \"WFM1:\"",
+            Self::RohdeSchwarz => "MMEM:CAT? \"/var/user\"",
+        }
+    }
 
-        self.client.err_check()
+    /// Waveform id of a catalog entry, or `None` for files that are not
+    /// waveforms (R&S lists the whole user directory).
+    fn waveform_name(self, file: &str) -> Option<&str> {
+        match self {
+            Self::Keysight => Some(file),
+            Self::RohdeSchwarz => file.strip_suffix(".wv"),
+        }
     }
 
-    /// Send *TRG to start the prepared sequence.
-    pub fn trigger(&mut self) -> Result<(), String> {
-        self.client.write_cmd("*TRG")?;
-        self.client.err_check()
+    /// Queries for `get_status`, in `VsgStatus` field order.
+    fn status_queries(self) -> [&'static str; 7] {
+        match self {
+            Self::Keysight => [
+                "frequency?",
+                "power?",
+                "output?",
+                "output:modulation?",
+                "radio:arb:state?",
+                "radio:arb:waveform?",
+                "radio:arb:sclock:rate?",
+            ],
+            Self::RohdeSchwarz => [
+                "SOUR1:FREQ?",
+                "SOUR1:POW?",
+                "OUTP1?",
+                "SOUR1:IQ:STAT?",
+                "SOUR1:BB:ARB:STAT?",
+                "SOUR1:BB:ARB:WAV:SEL?",
+                "SOUR1:BB:ARB:CLOC?",
+            ],
+        }
     }
 
-    /// Stop playback: disable RF output, modulation, and arb state.
-    pub fn stop(&mut self) -> Result<(), String> {
-        self.client.write_cmd("output 0")?;
-        self.client.write_cmd("output:modulation 0")?;
-        self.client.write_cmd("radio:arb:state 0")?;
+    /// Waveform id from a selected-waveform query (`"WFM1:name"` or
+    /// `"/var/user/name.wv"`), or `None` if nothing is selected.
+    fn selected_waveform(self, resp: &str) -> Option<String> {
+        let path = resp.trim().trim_matches('"');
+        let name = match self {
+            Self::Keysight => path.rsplit(':').next().unwrap_or(path),
+            Self::RohdeSchwarz => {
+                let file = path.rsplit('/').next().unwrap_or(path);
+                file.strip_suffix(".wv").unwrap_or(file)
+            }
+        };
+        (!name.is_empty() && name != "NONE").then(|| name.to_string())
+    }
+
+    fn delete(self, wfm_id: &str) -> String {
+        match self {
+            Self::Keysight => format!("mmemory:delete \"WFM1:{}\"", wfm_id),
+            Self::RohdeSchwarz => format!("MMEM:DEL \"/var/user/{}.wv\"", wfm_id),
+        }
+    }
+
+    fn trigger(self) -> &'static str {
+        match self {
+            Self::Keysight => "*TRG",
+            Self::RohdeSchwarz => "SOUR1:BB:ARB:TRIG:EXEC",
+        }
+    }
+
+    /// Query telling whether a single-triggered sequence is idle again.
+    fn sequence_state_query(self) -> &'static str {
+        match self {
+            Self::Keysight => "status:operation:condition?",
+            Self::RohdeSchwarz => "SOUR1:BB:ARB:TRIG:RMOD?",
+        }
+    }
+
+    /// Parse the response to `sequence_state_query`.
+    fn sequence_idle(self, resp: &str) -> Result<bool, String> {
+        match self {
+            Self::Keysight => Ok(parse_number(resp)? as u32 & WAITING_FOR_TRIGGER_BIT != 0),
+            Self::RohdeSchwarz => Ok(!resp.trim().eq_ignore_ascii_case("RUN")),
+        }
+    }
+
+    fn set_power(self, amp: f64) -> String {
+        match self {
+            Self::Keysight => format!("power {}", amp),
+            Self::RohdeSchwarz => format!("SOUR1:POW {}", amp),
+        }
+    }
+
+    /// Header fields saved with the selected segment so it plays correctly
+    /// when picked from the front panel. `None` where the file format
+    /// already carries them (.wv).
+    fn header(self, fs: f64, scale_percent: f64) -> Option<Vec<String>> {
+        match self {
+            Self::Keysight => Some(vec![
+                format!("radio:arb:sclock:rate {}", fs),
+                format!("radio:arb:rscaling {}", scale_percent),
+                "radio:arb:mdestination:alchold none".into(),
+                "radio:arb:mdestination:pblank none".into(),
+                "radio:arb:header:save".into(),
+            ]),
+            Self::RohdeSchwarz => None,
+        }
+    }
+
+    /// Power offset so the displayed power is `db` above the RF output.
+    fn power_offset(self, db: f64) -> String {
+        match self {
+            Self::Keysight => format!("power:offset {}", db),
+            Self::RohdeSchwarz => format!("SOUR1:POW:OFFS {}", db),
+        }
+    }
+
+    /// Flatness correction raising the output by each (Hz, dB) pair.
+    fn loss_table(self, table: &[(f64, f64)]) -> Vec<String> {
+        match self {
+            Self::Keysight => {
+                let mut cmds = vec!["correction:flatness:preset".into()];
+                cmds.extend(
+                    table
+                        .iter()
+                        .map(|(f, db)| format!("correction:flatness:pair {},{}", f, db)),
+                );
+                cmds.push("correction:state 1".into());
+                cmds
+            }
+            Self::RohdeSchwarz => {
+                let join = |v: Vec<String>| v.join(",");
+                vec![
+                    format!("SOUR1:CORR:CSET \"{}\"", RS_LOSS_TABLE),
+                    format!(
+                        "SOUR1:CORR:CSET:DATA:FREQ {}",
+                        join(table.iter().map(|p| p.0.to_string()).collect())
+                    ),
+                    format!(
+                        "SOUR1:CORR:CSET:DATA:POW {}",
+                        join(table.iter().map(|p| p.1.to_string()).collect())
+                    ),
+                    format!("SOUR1:CORR:CSET:SEL \"{}\"", RS_LOSS_TABLE),
+                    "SOUR1:CORR:STAT ON".into(),
+                ]
+            }
+        }
+    }
+
+    /// Remove the power offset and any flatness correction.
+    fn clear_corrections(self) -> Vec<String> {
+        let mut cmds = vec![self.power_offset(0.0)];
+        cmds.push(match self {
+            Self::Keysight => "correction:state 0".into(),
+            Self::RohdeSchwarz => "SOUR1:CORR:STAT OFF".into(),
+        });
+        cmds
+    }
+
+    fn set_alc(self, enabled: bool) -> String {
+        match self {
+            Self::Keysight => format!("power:alc {}", if enabled { 1 } else { 0 }),
+            Self::RohdeSchwarz => format!("SOUR1:POW:ALC:STAT {}", if enabled { "ON" } else { "OFF" }),
+        }
+    }
+
+    fn power_search(self) -> &'static str {
+        match self {
+            Self::Keysight => "power:alc:search once",
+            Self::RohdeSchwarz => "SOUR1:POW:ALC:SONC",
+        }
+    }
+
+    fn iq_scale(self, percent: f64) -> Result<String, String> {
+        if !(percent > 0.0 && percent <= 100.0) {
+            return Err(format!("IQ scale must be in (0, 100] %, got {}", percent));
+        }
+        match self {
+            Self::Keysight => Ok(format!("radio:arb:rscaling {}", percent)),
+            Self::RohdeSchwarz => Err("IQ runtime scaling is not supported on R&S generators".into()),
+        }
+    }
+
+    fn set_reference(self, source: RefSource) -> String {
+        match (self, source) {
+            (Self::Keysight, RefSource::Internal) => "roscillator:source int".into(),
+            (Self::Keysight, RefSource::External) => "roscillator:source ext".into(),
+            (Self::RohdeSchwarz, RefSource::Internal) => "SOUR:ROSC:SOUR INT".into(),
+            (Self::RohdeSchwarz, RefSource::External) => "SOUR:ROSC:SOUR EXT".into(),
+        }
+    }
+
+    fn reference_query(self) -> &'static str {
+        match self {
+            Self::Keysight => "roscillator:source?",
+            Self::RohdeSchwarz => "SOUR:ROSC:SOUR?",
+        }
+    }
+
+    /// Hardware power list sweep, stepping every `dwell_s` once started.
+    /// The first command probes for the list option; `None` where the
+    /// dialect has no list support here.
+    fn power_list(self, powers: &[f64], dwell_s: f64) -> Option<Vec<String>> {
+        match self {
+            Self::Keysight => {
+                let levels: Vec<String> = powers.iter().map(|p| p.to_string()).collect();
+                Some(vec![
+                    "list:type list".into(),
+                    format!("list:power {}", levels.join(",")),
+                    "list:dwell:type step".into(),
+                    format!("sweep:dwell {}", dwell_s),
+                    "list:trigger:source immediate".into(),
+                    "trigger:source bus".into(),
+                    "initiate:continuous off".into(),
+                    "power:mode list".into(),
+                ])
+            }
+            Self::RohdeSchwarz => None,
+        }
+    }
+
+    fn stop(self) -> Vec<String> {
+        match self {
+            Self::Keysight => vec![
+                "output 0".into(),
+                "output:modulation 0".into(),
+                "radio:arb:state 0".into(),
+                "radio:arb:mdestination:pulse none".into(),
+                "radio:arb:mdestination:alchold none".into(),
+            ],
+            Self::RohdeSchwarz => vec!["OUTP1 OFF".into(), "SOUR1:BB:ARB:STAT OFF".into()],
+        }
+    }
+}
+
+/// A waveform file in the instrument's memory.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct InstrumentFile {
+    /// Waveform id, as passed to `download_wfm`.
+    pub name: String,
+    pub size: u64,
+}
+
+/// Waveform memory listing from `MMEMory:CATalog?`.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct WaveformCatalog {
+    pub used_bytes: u64,
+    pub free_bytes: u64,
+    pub files: Vec<InstrumentFile>,
+}
+
+/// ARB waveform memory use, in bytes.
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct ArbMemory {
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub free_bytes: u64,
+}
+
+impl ArbMemory {
+    /// Memory use from the catalog's used/free counts. Firmware that
+    /// reports neither is sized from the model's ARB memory instead, or
+    /// `None` for an unknown model.
+    fn from_catalog(catalog: &WaveformCatalog, model: &InstrumentModel) -> Option<Self> {
+        if catalog.used_bytes > 0 || catalog.free_bytes > 0 {
+            return Some(Self {
+                total_bytes: catalog.used_bytes + catalog.free_bytes,
+                used_bytes: catalog.used_bytes,
+                free_bytes: catalog.free_bytes,
+            });
+        }
+        if !model.known {
+            return None;
+        }
+        let total_bytes = model.arb_memory_samples.saturating_mul(4);
+        let used_bytes = catalog.files.iter().map(|f| f.size).sum::<u64>().min(total_bytes);
+        Some(Self {
+            total_bytes,
+            used_bytes,
+            free_bytes: total_bytes - used_bytes,
+        })
+    }
+
+    /// Check a download of `needed` bytes fits, counting the `replaced`
+    /// bytes of an existing segment it overwrites as free.
+    fn check_fits(&self, needed: u64, replaced: u64) -> Result<(), String> {
+        let available = self.free_bytes.saturating_add(replaced);
+        if needed > available {
+            return Err(format!(
+                "Waveform needs {:.1} MB but only {:.1} MB of ARB memory is free; \
+                 delete segments or shorten the waveform",
+                needed as f64 / 1e6,
+                available as f64 / 1e6
+            ));
+        }
         Ok(())
     }
 }
+
+/// What the instrument reports it is doing, from `get_status`.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct VsgStatus {
+    pub frequency_hz: f64,
+    pub power_dbm: f64,
+    pub output_on: bool,
+    pub modulation_on: bool,
+    pub arb_on: bool,
+    /// Selected waveform id, if any.
+    pub waveform: Option<String>,
+    pub sample_rate_hz: f64,
+    /// `None` if neither the catalog nor the model tells the memory size.
+    pub arb_memory: Option<ArbMemory>,
+}
+
+/// Parse a numeric query response such as `+2.41200000000E+09`.
+fn parse_number(resp: &str) -> Result<f64, String> {
+    resp.trim()
+        .parse()
+        .map_err(|_| format!("Bad numeric response: {:?}", resp.trim()))
+}
+
+/// Parse a boolean query response (`1`/`0`, `ON`/`OFF`).
+fn parse_state(resp: &str) -> Result<bool, String> {
+    match resp.trim().to_ascii_uppercase().as_str() {
+        "1" | "+1" | "ON" => Ok(true),
+        "0" | "+0" | "OFF" => Ok(false),
+        other => Err(format!("Bad state response: {:?}", other)),
+    }
+}
+
+/// Build a `VsgStatus` from the responses to `status_queries`.
+fn parse_status(dialect: VsgDialect, resp: &[String; 7]) -> Result<VsgStatus, String> {
+    Ok(VsgStatus {
+        frequency_hz: parse_number(&resp[0])?,
+        power_dbm: parse_number(&resp[1])?,
+        output_on: parse_state(&resp[2])?,
+        modulation_on: parse_state(&resp[3])?,
+        arb_on: parse_state(&resp[4])?,
+        waveform: dialect.selected_waveform(&resp[5]),
+        sample_rate_hz: parse_number(&resp[6])?,
+        arb_memory: None,
+    })
+}
+
+/// Outcome of the instrument's power-on self test (`*TST?`).
+#[derive(serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelfTestResult {
+    pub passed: bool,
+    /// 0 on success; otherwise an instrument-specific failure code.
+    pub code: i32,
+}
+
+fn parse_self_test(resp: &str) -> Result<SelfTestResult, String> {
+    let code: i32 = resp
+        .trim()
+        .trim_start_matches('+')
+        .parse()
+        .map_err(|_| format!("Bad self-test response: {:?}", resp.trim()))?;
+    Ok(SelfTestResult { passed: code == 0, code })
+}
+
+/// Split an `*OPT?` response into option names. Some firmware quotes the
+/// list, and a lone "0" means no options.
+fn parse_options(resp: &str) -> Vec<String> {
+    resp.trim()
+        .trim_matches('"')
+        .split(',')
+        .map(str::trim)
+        .filter(|o| !o.is_empty() && *o != "0")
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether `options` include an ARB/baseband generator. R&S reports
+/// boards with their instrument prefix on some firmware (e.g. "SMBVB10").
+pub fn has_arb_option(options: &[String]) -> bool {
+    options.iter().any(|o| {
+        let o = o.to_ascii_uppercase();
+        ARB_OPTIONS.iter().any(|arb| o == *arb || (arb.starts_with('B') && o.ends_with(arb)))
+    })
+}
+
+/// Parse a cable loss table: one `frequency_mhz,loss_db` pair per line,
+/// with an optional header row and `#` comments. Returns (Hz, dB) pairs
+/// sorted by frequency.
+pub fn parse_loss_table(text: &str) -> Result<Vec<(f64, f64)>, String> {
+    let mut table = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let parsed = match fields[..] {
+            [f, db] => f.parse::<f64>().ok().zip(db.parse::<f64>().ok()),
+            _ => None,
+        };
+        match parsed {
+            Some((f_mhz, db)) if f_mhz > 0.0 => table.push((f_mhz * 1e6, db)),
+            // A non-numeric first row is a header
+            None if table.is_empty() && n == 0 => {}
+            _ => return Err(format!("Line {}: expected 'frequency_mhz,loss_db', got '{}'", n + 1, line)),
+        }
+    }
+    if table.is_empty() || table.len() > MAX_LOSS_POINTS {
+        return Err(format!(
+            "A loss table needs 1 to {} points, got {}",
+            MAX_LOSS_POINTS,
+            table.len()
+        ));
+    }
+    table.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(table)
+}
+
+/// Loss of a sorted (Hz, dB) table at `freq_hz`, interpolated linearly
+/// between points and held at the end values beyond them.
+pub fn table_loss(table: &[(f64, f64)], freq_hz: f64) -> f64 {
+    let above = table.partition_point(|&(f, _)| f < freq_hz);
+    match (above.checked_sub(1).map(|i| table[i]), table.get(above)) {
+        (Some((f0, db0)), Some(&(f1, db1))) => db0 + (db1 - db0) * (freq_hz - f0) / (f1 - f0),
+        (Some((_, db)), None) | (None, Some(&(_, db))) => db,
+        (None, None) => 0.0,
+    }
+}
+
+/// Build a `ReferenceStatus` from the source query (`INT`/`EXT`) and the
+/// questionable frequency condition register.
+fn parse_reference(source: &str, condition: &str) -> Result<ReferenceStatus, String> {
+    let source = match source.trim().to_ascii_uppercase().as_str() {
+        s if s.starts_with("INT") => RefSource::Internal,
+        s if s.starts_with("EXT") => RefSource::External,
+        other => return Err(format!("Bad reference source response: {:?}", other)),
+    };
+    let condition = parse_number(condition)? as u32;
+    Ok(ReferenceStatus {
+        source,
+        locked: condition & REF_UNLOCKED_BIT == 0,
+    })
+}
+
+/// Parse a catalog response: `<used>,<free>` followed by one quoted
+/// `"<name>,<type>,<size>"` entry per file. Names may contain commas, so
+/// type and size are split off the end; `""` inside an entry is a quote.
+fn parse_catalog(resp: &str) -> Result<WaveformCatalog, String> {
+    let invalid = || format!("Invalid catalog response: {}", resp);
+    let (counts, mut rest) = resp.split_once('"').map_or((resp, ""), |(c, r)| (c, r));
+    let mut counts = counts.split(',').map(|v| v.trim().trim_start_matches('+'));
+    let used_bytes = counts.next().and_then(|v| v.parse().ok()).ok_or_else(invalid)?;
+    let free_bytes = counts.next().and_then(|v| v.parse().ok()).ok_or_else(invalid)?;
+
+    let mut files = Vec::new();
+    while !rest.is_empty() {
+        // `rest` starts just after an opening quote
+        let mut entry = String::new();
+        let mut chars = rest.char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((i, '"')) if rest[i + 1..].starts_with('"') => {
+                    entry.push('"');
+                    chars.next();
+                }
+                Some((i, '"')) => break i,
+                Some((_, c)) => entry.push(c),
+                None => return Err(invalid()),
+            }
+        };
+        let mut fields = entry.rsplitn(3, ',');
+        let size = fields.next().and_then(|v| v.trim().parse().ok()).ok_or_else(invalid)?;
+        let _kind = fields.next().ok_or_else(invalid)?;
+        let name = fields.next().ok_or_else(invalid)?.to_string();
+        files.push(InstrumentFile { name, size });
+
+        rest = rest[end + 1..].split_once('"').map_or("", |(_, r)| r);
+    }
+
+    Ok(WaveformCatalog {
+        used_bytes,
+        free_bytes,
+        files,
+    })
+}
+
+/// Seed of the R&S .wv checksum, XORed with every 32-bit I/Q word.
+const WV_CHECKSUM_SEED: u32 = 0xA50F_74FF;
+
+/// Pack big-endian int16 IQ into an R&S .wv file: tagged header, marker 1
+/// list from `markers` (bit 0 of each byte) and little-endian samples.
+pub fn pack_wv(wfm_data: &[u8], markers: Option<&[u8]>, clock_hz: f64) -> Vec<u8> {
+    let mut samples = Vec::with_capacity(wfm_data.len());
+    let mut checksum = WV_CHECKSUM_SEED;
+    let (mut peak, mut power) = (0f64, 0f64);
+    for (i, q) in decode_iq(wfm_data) {
+        samples.extend_from_slice(&i.to_le_bytes());
+        samples.extend_from_slice(&q.to_le_bytes());
+        checksum ^= (i as u16 as u32) | ((q as u16 as u32) << 16);
+        let p = (i as f64).powi(2) + (q as f64).powi(2);
+        peak = peak.max(p);
+        power += p;
+    }
+    let count = wfm_data.len() / 4;
+
+    // RMS and peak below full scale, in dB
+    let below_fs = |p: f64| if p > 0.0 { 10.0 * (32767.0 * 32767.0 / p).log10() } else { 0.0 };
+    let rms_offset = below_fs(power / count.max(1) as f64);
+    let peak_offset = below_fs(peak);
+
+    let mut wv = format!(
+        "{{TYPE: SMU-WV,{}}}{{COMMENT: wia-waveplay}}{{CLOCK: {}}}{{LEVEL OFFS: {:.6},{:.6}}}{{SAMPLES: {}}}",
+        checksum, clock_hz, rms_offset, peak_offset, count
+    )
+    .into_bytes();
+    if let Some(markers) = markers {
+        wv.extend_from_slice(format!("{{MARKER LIST 1: {}}}", marker_list(markers)).as_bytes());
+    }
+    wv.extend_from_slice(format!("{{WAVEFORM-{}:#", samples.len() + 1).as_bytes());
+    wv.extend_from_slice(&samples);
+    wv.push(b'}');
+    wv
+}
+
+/// `position:state` pairs at every change of marker 1, e.g. "0:1;100:0".
+fn marker_list(markers: &[u8]) -> String {
+    let mut list = Vec::new();
+    let mut last = None;
+    for (pos, byte) in markers.iter().enumerate() {
+        let state = byte & 0x01;
+        if last != Some(state) {
+            list.push(format!("{}:{}", pos, state));
+            last = Some(state);
+        }
+    }
+    list.join(";")
+}
+
+/// Reported through the `on_reconnect` hook once a dropped connection is restored.
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct Reconnected {
+    pub inst_id: String,
+    /// Downloaded waveforms the instrument lost (its ARB memory is
+    /// volatile); they are downloaded again on next use.
+    pub missing_waveforms: Vec<String>,
+}
+
+/// Check that `amp` (dBm at the DUT) plus `cable_loss` stays within the
+/// user's output power `limit`.
+pub fn check_power_limit(amp: f64, cable_loss: f64, limit: f64) -> Result<(), String> {
+    let output = amp + cable_loss;
+    if output > limit {
+        return Err(format!(
+            "Output power {} dBm ({} dBm + {} dB cable loss) exceeds the power limit of {} dBm",
+            output, amp, cable_loss, limit
+        ));
+    }
+    Ok(())
+}
+
+/// Trim a console command, refusing empty and multi-line input.
+fn check_raw_command(cmd: &str) -> Result<&str, String> {
+    let cmd = cmd.trim();
+    if cmd.is_empty() {
+        return Err("Empty SCPI command".into());
+    }
+    if cmd.contains(['\n', '\r']) {
+        return Err("SCPI console commands must be a single line".into());
+    }
+    Ok(cmd)
+}
+
+/// Callback run after a dropped connection has been restored.
+pub type ReconnectHook = Box<dyn Fn(&Reconnected) + Send>;
+
+/// Controller for Keysight EXG/MXG/PSG/M938x and R&S SMW/SMBV Vector
+/// Signal Generators.
+///
+/// Implements SCPI-based instrument control for waveform download and playback.
+/// Reference: pyarbtools VSG class in reference/pyarbtools/instruments.py
+pub struct VsgInstrument {
+    client: Box<dyn ScpiTransport>,
+    /// Address used to reconnect.
+    ip: String,
+    on_reconnect: Option<ReconnectHook>,
+    pub identity: InstrumentIdentity,
+    pub dialect: VsgDialect,
+    pub model_info: InstrumentModel,
+    /// Content key of each waveform id downloaded over this connection.
+    downloaded: HashMap<String, u64>,
+    /// ARB clock of the last `configure`, written into .wv headers.
+    clock_hz: f64,
+    /// Downloads `ensure_wfm` skipped on this connection.
+    downloads_skipped: u32,
+    /// Runtime I/Q scaling in percent, saved into segment headers.
+    iq_scale: f64,
+    /// Power offset set with `set_power_offset`, in dB.
+    power_offset_db: f64,
+    /// Whether a power offset or loss table may be active.
+    corrections_active: bool,
+    /// Table of the last `load_loss_table`, applied again after a reconnect.
+    loss_table: Option<Vec<(f64, f64)>>,
+    /// Highest RF output power allowed, in dBm.
+    power_limit_dbm: f64,
+    /// RF output the channel commands address, from 1.
+    channel: u8,
+    /// Marker 1 blanks the RF output between bursts; cleared by `stop`.
+    rf_blanking: bool,
+    /// Retry policy and hook, applied again after a reconnect.
+    retry: Option<RetryPolicy>,
+    on_retry: Option<RetryHook>,
+    /// Custom response terminator, kept for reconnects.
+    terminator: Option<String>,
+    /// The last binary block write failed, so the instrument may still be
+    /// waiting for the rest of the block.
+    block_write_failed: bool,
+    /// Time `idempotent` spent recovering and running operations again,
+    /// since the last `take_retry_time`.
+    retry_time: Duration,
+}
+
+impl VsgInstrument {
+    /// Connect to a VSG by connection string (see `scpi::Endpoint`).
+    /// If `reset` is true, sends *RST and waits for completion. Responses
+    /// end at `terminator` when given, otherwise at LF, CR or CRLF.
+    pub fn connect(ip: &str, timeout_secs: u64, reset: bool, terminator: Option<&str>) -> Result<Self, String> {
+        let mut client = scpi::open(ip, timeout_secs)?;
+        client.set_read_terminator(terminator)?;
+        let mut vsg = Self::with_client(client, ip, reset)?;
+        vsg.terminator = terminator.map(str::to_string);
+        Ok(vsg)
+    }
+
+    /// Take over an open connection to the VSG at `ip`, resetting it first
+    /// if `reset` is true.
+    fn with_client(mut client: Box<dyn ScpiTransport>, ip: &str, reset: bool) -> Result<Self, String> {
+        if reset {
+            client.with_timeout(RESET_TIMEOUT.max(client.timeout()), |client| {
+                client.write_cmd("*rst")?;
+                client.query("*opc?")
+            })?;
+        }
+
+        let identity = InstrumentIdentity::parse(&client.query("*idn?")?);
+
+        Ok(Self {
+            client,
+            ip: ip.to_string(),
+            on_reconnect: None,
+            dialect: VsgDialect::from_identity(&identity),
+            model_info: InstrumentModel::from_identity(&identity),
+            identity,
+            downloaded: HashMap::new(),
+            clock_hz: 0.0,
+            downloads_skipped: 0,
+            iq_scale: 100.0,
+            power_offset_db: 0.0,
+            corrections_active: false,
+            loss_table: None,
+            power_limit_dbm: f64::INFINITY,
+            channel: 1,
+            rf_blanking: false,
+            retry: None,
+            on_retry: None,
+            terminator: None,
+            block_write_failed: false,
+            retry_time: Duration::ZERO,
+        })
+    }
+
+    /// Retry SCPI commands and reads that time out by `policy`, calling
+    /// `hook` before each retry. Bus triggers are never retried.
+    pub fn set_retry_policy(&mut self, policy: Option<RetryPolicy>, hook: Option<RetryHook>) {
+        self.retry = policy;
+        self.on_retry = hook;
+        self.apply_retry();
+    }
+
+    fn apply_retry(&mut self) {
+        self.client.set_retry_policy(self.retry);
+        if let Some(hook) = &self.on_retry {
+            self.client.on_retry(hook.clone());
+        }
+    }
+
+    /// Call `hook` whenever a dropped connection has been restored.
+    pub fn on_reconnect(&mut self, hook: ReconnectHook) {
+        self.on_reconnect = Some(hook);
+    }
+
+    /// Reopen the connection after the instrument dropped it (e.g. a power
+    /// cycle), checking `*idn?` to make sure the same instrument answers.
+    /// Waveforms the instrument no longer holds are forgotten so they are
+    /// downloaded again.
+    fn reconnect(&mut self) -> Result<(), String> {
+        let timeout = self.client.timeout();
+        let mut last_err = String::new();
+        for attempt in 0..RECONNECT_ATTEMPTS {
+            if attempt > 0 {
+                std::thread::sleep(RECONNECT_DELAY);
+            }
+            let mut client = match scpi::open(&self.ip, timeout.as_secs().max(1)) {
+                Ok(client) => client,
+                Err(e) => {
+                    last_err = e;
+                    continue;
+                }
+            };
+            client.set_timeout(timeout)?;
+            client.set_read_terminator(self.terminator.as_deref())?;
+            let identity = InstrumentIdentity::parse(&client.query("*idn?")?);
+            if !identity.same_instrument(&self.identity) {
+                return Err(format!(
+                    "A different instrument answered at {} ('{}', expected '{}')",
+                    self.ip, identity.raw, self.identity.raw
+                ));
+            }
+            self.client = client;
+            self.apply_retry();
+            // A power-cycled instrument has lost its corrections
+            self.restore_corrections()?;
+
+            let present: Vec<String> = self.read_catalog()?.files.into_iter().map(|f| f.name).collect();
+            let mut missing: Vec<String> = self
+                .downloaded
+                .keys()
+                .filter(|id| !present.contains(id))
+                .cloned()
+                .collect();
+            missing.sort();
+            for id in &missing {
+                self.downloaded.remove(id);
+            }
+            if let Some(hook) = &self.on_reconnect {
+                hook(&Reconnected {
+                    inst_id: identity.raw,
+                    missing_waveforms: missing,
+                });
+            }
+            return Ok(());
+        }
+        Err(format!(
+            "Could not reconnect to {} after {} attempts: {}",
+            self.ip, RECONNECT_ATTEMPTS, last_err
+        ))
+    }
+
+    /// Apply the power offset and loss table set on this connection again.
+    fn restore_corrections(&mut self) -> Result<(), String> {
+        if self.power_offset_db != 0.0 {
+            self.write(&self.dialect.power_offset(self.power_offset_db))?;
+        }
+        if let Some(table) = self.loss_table.clone() {
+            self.write_all(self.dialect.loss_table(&table))?;
+        }
+        Ok(())
+    }
+
+    /// Run an operation that is safe to repeat. If the connection has
+    /// dropped, reconnect and run it once more before giving up.
+    fn idempotent<T>(&mut self, mut op: impl FnMut(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        if self.client.is_disconnected() {
+            self.reconnect()?;
+        }
+        let after_failed_block = std::mem::take(&mut self.block_write_failed);
+        let first = op(self);
+        let retry_start = Instant::now();
+        let result = match first {
+            Err(e) if self.client.is_disconnected() => self
+                .reconnect()
+                .map_err(|r| format!("{}; {}", e, r))
+                .and_then(|()| op(self)),
+            Err(e) if after_failed_block && self.clear_stuck_io(&e) => {
+                op(self).map_err(|r| format!("{} (instrument I/O cleared); {}", e, r))
+            }
+            first => return first,
+        };
+        self.retry_time += retry_start.elapsed();
+        result
+    }
+
+    /// Time spent retrying operations since the last call.
+    pub fn take_retry_time(&mut self) -> Duration {
+        std::mem::take(&mut self.retry_time)
+    }
+
+    /// Run an operation that must not be repeated, such as a trigger. A
+    /// dropped connection is restored for later commands, but the error
+    /// is returned rather than retried.
+    fn once<T>(&mut self, op: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        if self.client.is_disconnected() {
+            self.reconnect()?;
+        }
+        let after_failed_block = std::mem::take(&mut self.block_write_failed);
+        match op(self) {
+            Err(e) if self.client.is_disconnected() => match self.reconnect() {
+                Ok(()) => Err(format!("{} (reconnected; not retried)", e)),
+                Err(r) => Err(format!("{}; {}", e, r)),
+            },
+            Err(e) if after_failed_block && self.clear_stuck_io(&e) => {
+                Err(format!("{} (instrument I/O cleared; not retried)", e))
+            }
+            result => result,
+        }
+    }
+
+    /// An instrument left waiting for the rest of a block takes the next
+    /// commands as block data, so they time out. Clear its I/O when `e` is
+    /// such a timeout; returns whether it was cleared.
+    fn clear_stuck_io(&mut self, e: &str) -> bool {
+        e.contains("timed out") && self.client.device_clear().is_ok()
+    }
+
+    /// Check that the instrument still answers, without reconnecting.
+    pub fn ping(&mut self) -> Result<(), String> {
+        self.client.ping()
+    }
+
+    /// Clear the instrument's I/O, e.g. when it is stuck waiting for the
+    /// rest of a block. The connection keeps its address and settings.
+    pub fn device_clear(&mut self) -> Result<(), String> {
+        self.client.device_clear()
+    }
+
+    /// Address the instrument was reached at.
+    pub fn endpoint(&self) -> std::net::SocketAddr {
+        self.client.peer()
+    }
+
+    /// Model field of the `*IDN?` response, e.g. "N5182B" or "M9381A".
+    pub fn model(&self) -> &str {
+        &self.model_info.name
+    }
+
+    /// Waveform length rules of this model's ARB. The M938x PXI sources
+    /// need multiples of 8 samples and a longer minimum segment; the
+    /// EXG/MXG/PSG family uses the pipeline defaults.
+    pub fn arb_limits(&self) -> ArbLimits {
+        if self.model().starts_with("M938") {
+            ArbLimits {
+                granularity: 8,
+                min_len: 512,
+                ..ArbLimits::default()
+            }
+        } else {
+            ArbLimits::default()
+        }
+    }
+
+    fn write_all(&mut self, cmds: Vec<String>) -> Result<(), String> {
+        for cmd in cmds {
+            self.write(&cmd)?;
+        }
+        Ok(())
+    }
+
+    /// Send `cmd` to the selected channel.
+    fn write(&mut self, cmd: &str) -> Result<(), String> {
+        let cmd = self.on_channel(cmd);
+        self.client.write_cmd(&cmd)
+    }
+
+    /// Query `cmd` on the selected channel.
+    fn ask(&mut self, cmd: &str) -> Result<String, String> {
+        let cmd = self.on_channel(cmd);
+        self.client.query(&cmd)
+    }
+
+    fn on_channel(&self, cmd: &str) -> String {
+        let prefixed = self.model_info.channels > 1 || self.channel > 1;
+        self.dialect.on_channel(cmd, self.channel, prefixed)
+    }
+
+    /// Address RF output `channel` (from 1) with the following commands.
+    /// Outputs of a multi-output instrument are configured, played and
+    /// stopped independently.
+    pub fn set_channel(&mut self, channel: u8) -> Result<(), String> {
+        self.model_info.check_channel(channel)?;
+        self.channel = channel;
+        Ok(())
+    }
+
+    /// Configure the VSG with carrier frequency, sample rate, and output power.
+    ///
+    /// - `cf`: carrier frequency in Hz
+    /// - `fs`: ARB sample clock rate in Hz
+    /// - `amp`: output power in dBm
+    pub fn configure(&mut self, cf: f64, fs: f64, amp: f64) -> Result<(), String> {
+        self.model_info.check_frequency(cf)?;
+        self.model_info.check_sample_rate(fs)?;
+        self.check_output_power(amp)?;
+        self.idempotent(|vsg| {
+            vsg.write_all(vsg.dialect.configure(cf, fs, amp))?;
+            vsg.clock_hz = fs;
+            vsg.client.err_check()
+        })
+    }
+
+    /// Download a pre-formatted waveform (big-endian interleaved int16 IQ) to the instrument.
+    ///
+    /// `wfm_data` should be raw bytes from a .WAVEFORM file. R&S generators
+    /// receive it packed as .wv with `markers` embedded; Keysight ones get
+    /// the markers as a separate marker file.
+    ///
+    /// Keysight segments are sent as appended blocks of `ctl.chunk_bytes`
+    /// and `ctl.cancel` is checked between them. A .wv file goes as one
+    /// block written `ctl.chunk_bytes` at a time; cancelling it mid-block
+    /// makes the transport recover the session first. Either way the
+    /// partial segment is deleted. The stored size is checked against the
+    /// catalog.
+    pub fn download_wfm(
+        &mut self,
+        wfm_data: &[u8],
+        markers: Option<&[u8]>,
+        wfm_id: &str,
+        ctl: &DownloadControl,
+    ) -> Result<(), String> {
+        self.model_info.check_samples((wfm_data.len() / 4) as u64)?;
+        if ctl.cancel.load(Ordering::SeqCst) {
+            return Err(scpi::CANCELLED.into());
+        }
+        let needed = (wfm_data.len() + markers.map_or(0, <[u8]>::len)) as u64;
+        self.check_arb_space(wfm_id, needed)?;
+
+        self.once(|vsg| {
+            // Stop output before downloading
+            vsg.write_all(vsg.dialect.halt_arb())?;
+
+            // Download waveform binary data using IEEE 488.2 block format, with
+            // a timeout that grows with the transfer
+            let dialect = vsg.dialect;
+            let clock_hz = vsg.clock_hz;
+            let select = vsg.on_channel(&dialect.select(wfm_id));
+            let timeout = transfer_timeout(vsg.client.timeout(), wfm_data.len());
+            let mut block_failed = false;
+            let sent = vsg.client.with_timeout(timeout, |client| {
+                let size = Self::send_segment(client, dialect, clock_hz, wfm_data, markers, wfm_id, ctl)
+                    .inspect_err(|e| block_failed = e != scpi::CANCELLED)?;
+                // Select the uploaded waveform
+                client.write_cmd(&select)?;
+                client.err_check()?;
+                Ok(size)
+            });
+            vsg.block_write_failed = block_failed;
+            vsg.verify_size(wfm_id, sent? as u64)?;
+
+            match (vsg.dialect, markers) {
+                (VsgDialect::Keysight, Some(markers)) => vsg.download_markers(wfm_id, markers),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    /// Download a waveform and its markers under `wfm_id`, unless the same
+    /// content (`content_key`) was already downloaded under that id on this
+    /// connection and the catalog shows the segment is still there, in
+    /// which case it is only selected. `force` always downloads.
+    ///
+    /// Returns whether the waveform was downloaded.
+    pub fn ensure_wfm(
+        &mut self,
+        wfm_id: &str,
+        wfm_data: &[u8],
+        markers: Option<&[u8]>,
+        content_key: u64,
+        force: bool,
+        ctl: &DownloadControl,
+    ) -> Result<bool, String> {
+        if !force && self.downloaded.get(wfm_id) == Some(&content_key) {
+            let present = self.list_waveforms()?.files.iter().any(|f| f.name == wfm_id);
+            if present {
+                self.write(&self.dialect.select(wfm_id))?;
+                self.client.err_check()?;
+                self.downloads_skipped += 1;
+                return Ok(false);
+            }
+        }
+
+        self.downloaded.remove(wfm_id);
+        self.download_wfm(wfm_data, markers, wfm_id, ctl)?;
+        self.downloaded.insert(wfm_id.to_string(), content_key);
+        Ok(true)
+    }
+
+    /// Send the segment data; returns the size of the file it creates.
+    fn send_segment(
+        client: &mut dyn ScpiTransport,
+        dialect: VsgDialect,
+        clock_hz: f64,
+        wfm_data: &[u8],
+        markers: Option<&[u8]>,
+        wfm_id: &str,
+        ctl: &DownloadControl,
+    ) -> Result<usize, String> {
+        let cmd = dialect.data_cmd(wfm_id);
+        let chunk = (ctl.chunk_bytes / 4).max(1) * 4;
+        match dialect.append_cmd(wfm_id) {
+            Some(append) => {
+                let total = wfm_data.len() as u64;
+                let mut sent = 0;
+                for (n, block) in wfm_data.chunks(chunk).enumerate() {
+                    if n > 0 && ctl.cancel.load(Ordering::SeqCst) {
+                        return Err(Self::discard_segment(client, dialect, wfm_id));
+                    }
+                    client.write_binary_block(if n == 0 { &cmd } else { &append }, block)?;
+                    sent += block.len() as u64;
+                    (ctl.progress)(sent, total);
+                }
+                Ok(wfm_data.len())
+            }
+            None => {
+                let wv = pack_wv(wfm_data, markers, clock_hz);
+                let total = wv.len() as u64;
+                let progress = |sent| (ctl.progress)(sent, total);
+                match client.write_binary_block_progress(&cmd, &wv, chunk, &progress, Some(ctl.cancel)) {
+                    Err(e) if e == scpi::CANCELLED => Err(Self::discard_segment(client, dialect, wfm_id)),
+                    result => result.map(|()| wv.len()),
+                }
+            }
+        }
+    }
+
+    /// Delete a partially sent segment after a cancelled download.
+    fn discard_segment(client: &mut dyn ScpiTransport, dialect: VsgDialect, wfm_id: &str) -> String {
+        let _ = client.write_cmd(&dialect.delete(wfm_id));
+        let _ = client.err_check();
+        scpi::CANCELLED.into()
+    }
+
+    /// Check the catalog holds `wfm_id` with the size that was sent.
+    fn verify_size(&mut self, wfm_id: &str, expected: u64) -> Result<(), String> {
+        let catalog = self.list_waveforms()?;
+        match catalog.files.iter().find(|f| f.name == wfm_id) {
+            Some(f) if f.size == expected => Ok(()),
+            Some(f) => Err(format!(
+                "'{}' is {} bytes on the instrument, expected {}",
+                wfm_id, f.size, expected
+            )),
+            None => Err(format!("'{}' is missing from the instrument after download", wfm_id)),
+        }
+    }
+
+    /// Downloads skipped by `ensure_wfm` since connecting.
+    pub fn downloads_skipped(&self) -> u32 {
+        self.downloads_skipped
+    }
+
+    /// Download a Keysight marker file for an uploaded waveform.
+    ///
+    /// `marker_data` holds one byte per IQ sample; bits 0–3 are markers 1–4.
+    /// Must be sent after the waveform, which creates an all-low marker file.
+    fn download_markers(&mut self, wfm_id: &str, marker_data: &[u8]) -> Result<(), String> {
+        let cmd = format!("mmemory:data \"MKR1:{}\",", wfm_id);
+        if let Err(e) = self.client.write_binary_block(&cmd, marker_data) {
+            self.block_write_failed = true;
+            return Err(e);
+        }
+        self.client.err_check()
+    }
+
+    /// Blank the RF carrier between bursts by routing marker 1 to pulse
+    /// modulation, so the idle gap doesn't look like a busy channel. Takes
+    /// effect for the following plays; the commands differ across firmware,
+    /// so errors are checked right away.
+    pub fn set_rf_blanking(&mut self, enabled: bool) -> Result<(), String> {
+        self.idempotent(|vsg| {
+            vsg.write_all(vsg.dialect.rf_blanking(enabled)?)?;
+            vsg.client.err_check()?;
+            vsg.rf_blanking = enabled;
+            Ok(())
+        })
+    }
+
+    /// Activate arb playback: select waveform, enable RF output, modulation, and arb state.
+    /// Plays the waveform continuously (infinite loop).
+    ///
+    /// If `marker_to_event1` is true, marker 1 is output on the EVENT1 connector.
+    pub fn play(&mut self, wfm_id: &str, marker_to_event1: bool) -> Result<(), String> {
+        self.idempotent(|vsg| {
+            vsg.write_all(vsg.dialect.play(wfm_id, marker_to_event1))?;
+            vsg.client.err_check()
+        })
+    }
+
+    /// Activate arb playback with a finite repeat count.
+    ///
+    /// Creates a waveform sequence from the uploaded segment with the specified
+    /// repeat count, then plays the sequence.
+    ///
+    /// SCPI flow (from Keysight N5182B Programming Guide):
+    ///   1. Build sequence: `:SOURce:RADio:ARB:SEQuence "<seq>","<wfm>",<reps>,<markers>`
+    ///   2. Select sequence:  `:SOURce:RADio:ARB:WAVeform "SEQ:<seq>"`
+    ///   3. Enable output:    ARB state → modulation → RF output
+    ///
+    /// R&S generators play the segment itself with a signal duration of
+    /// `count` sequences per trigger.
+    ///
+    /// If `marker_to_event1` is true, marker 1 is enabled in the sequence and
+    /// output on the EVENT1 connector. Only a bus `trigger` is fired here
+    /// (after the `timing` delay); otherwise the sequence waits for the
+    /// external edge or trigger key.
+    pub fn play_with_repeat(
+        &mut self,
+        wfm_id: &str,
+        count: u32,
+        marker_to_event1: bool,
+        trigger: TriggerSource,
+        timing: TriggerTiming,
+    ) -> Result<(), String> {
+        self.once(|vsg| {
+            // Blanking needs the sequence's markers even when they aren't routed out
+            let markers = marker_to_event1 || vsg.rf_blanking;
+            let mut cmds = vsg
+                .dialect
+                .arm_repeat(wfm_id, count, markers, trigger, timing)?;
+            cmds.splice(1..1, vsg.dialect.route_marker1(marker_to_event1));
+            vsg.write_all(cmds)?;
+            if trigger == TriggerSource::Bus {
+                vsg.write(vsg.dialect.trigger())?;
+            }
+            vsg.client.err_check()
+        })
+    }
+
+    /// Check a sequence is valid for this instrument before downloading
+    /// its segments.
+    pub fn check_sequence(&self, segments: &[(&str, u32, bool)]) -> Result<(), String> {
+        self.dialect.sequence("check", segments).map(|_| ())
+    }
+
+    /// Define sequence `seq_id` chaining already downloaded `segments`,
+    /// each given as (waveform id, repeat count, marker 1 enabled).
+    pub fn build_sequence(&mut self, seq_id: &str, segments: &[(&str, u32, bool)]) -> Result<(), String> {
+        self.write(&self.dialect.sequence(seq_id, segments)?)?;
+        self.client.err_check()
+    }
+
+    /// Build sequence `seq_id` and play it continuously.
+    pub fn play_sequence(
+        &mut self,
+        seq_id: &str,
+        segments: &[(&str, u32, bool)],
+        marker_to_event1: bool,
+    ) -> Result<(), String> {
+        self.build_sequence(seq_id, segments)?;
+        self.write_all(
+            self.dialect
+                .play_selection(self.dialect.select_sequence(seq_id), marker_to_event1),
+        )?;
+        self.client.err_check()
+    }
+
+    /// Offset the displayed power by `db` from the RF output. With
+    /// `-cable_loss` the power commands give the level at the DUT connector.
+    pub fn set_power_offset(&mut self, db: f64) -> Result<(), String> {
+        self.corrections_active = true;
+        self.idempotent(|vsg| {
+            vsg.write(&vsg.dialect.power_offset(db))?;
+            vsg.client.err_check()?;
+            vsg.power_offset_db = db;
+            Ok(())
+        })
+    }
+
+    /// Load and enable a flatness correction that raises the output by the
+    /// cable loss at each frequency, given as (Hz, dB) pairs.
+    pub fn load_loss_table(&mut self, table: &[(f64, f64)]) -> Result<(), String> {
+        self.corrections_active = true;
+        self.write_all(self.dialect.loss_table(table))?;
+        self.client.err_check()?;
+        self.loss_table = Some(table.to_vec());
+        Ok(())
+    }
+
+    /// Loss a sweep at `freq_hz` makes up for: the loaded loss table's,
+    /// which `sweep_offset` leaves in charge, or else `cable_loss`.
+    pub fn sweep_loss_db(&self, freq_hz: f64, cable_loss: f64) -> f64 {
+        match self.loss_table {
+            Some(ref table) => table_loss(table, freq_hz),
+            None => cable_loss,
+        }
+    }
+
+    /// Offset the power by `-cable_loss` for the length of a sweep, unless
+    /// a loss table already accounts for the cable. The previous offset is
+    /// put back when the returned guard is dropped.
+    pub fn sweep_offset(&mut self, cable_loss: f64) -> Result<SweepOffset<'_>, String> {
+        let previous = if self.loss_table.is_some() {
+            None
+        } else {
+            let previous = (self.power_offset_db, self.corrections_active);
+            self.set_power_offset(-cable_loss)?;
+            Some(previous)
+        };
+        Ok(SweepOffset { vsg: self, previous })
+    }
+
+    /// Remove the power offset and loss table so they don't carry over to
+    /// the next user of the instrument.
+    pub fn clear_corrections(&mut self) -> Result<(), String> {
+        self.idempotent(|vsg| {
+            vsg.write_all(vsg.dialect.clear_corrections())?;
+            vsg.client.err_check()?;
+            vsg.power_offset_db = 0.0;
+            vsg.corrections_active = false;
+            vsg.loss_table = None;
+            Ok(())
+        })
+    }
+
+    /// Refuse output powers above `dbm`, guarding the DUT against typos.
+    pub fn set_power_limit(&mut self, dbm: f64) {
+        self.power_limit_dbm = dbm;
+    }
+
+    /// Check a power command (the level after the power offset) against
+    /// the model maximum and the power limit.
+    fn check_output_power(&self, amp: f64) -> Result<(), String> {
+        self.model_info.check_power(amp - self.power_offset_db)?;
+        check_power_limit(amp, -self.power_offset_db, self.power_limit_dbm)
+    }
+
+    /// Set output power without reconfiguring CF/FS.
+    pub fn set_power(&mut self, amp: f64) -> Result<(), String> {
+        self.check_output_power(amp)?;
+        self.idempotent(|vsg| {
+            vsg.write(&vsg.dialect.set_power(amp))?;
+            vsg.client.err_check()
+        })
+    }
+
+    /// Turn the ALC loop on or off. Bursty waveforms play cleaner with it
+    /// off, followed by `run_power_search` to level the output.
+    pub fn set_alc(&mut self, enabled: bool) -> Result<(), String> {
+        self.idempotent(|vsg| {
+            vsg.write(&vsg.dialect.set_alc(enabled))?;
+            vsg.client.err_check()
+        })
+    }
+
+    /// Run a one-shot power search at the current power and wait for it
+    /// to finish.
+    pub fn run_power_search(&mut self) -> Result<(), String> {
+        self.once(|vsg| {
+            vsg.write(vsg.dialect.power_search())?;
+            vsg.ask("*OPC?")?;
+            vsg.client.err_check()
+        })
+    }
+
+    /// Scale the ARB I/Q at runtime, in percent of full scale.
+    pub fn set_iq_scale(&mut self, percent: f64) -> Result<(), String> {
+        self.idempotent(|vsg| {
+            vsg.write(&vsg.dialect.iq_scale(percent)?)?;
+            vsg.client.err_check()?;
+            vsg.iq_scale = percent;
+            Ok(())
+        })
+    }
+
+    /// Runtime I/Q scaling last set, in percent.
+    pub fn iq_scale(&self) -> f64 {
+        self.iq_scale
+    }
+
+    /// Save the sample clock, I/Q scaling and marker routing into the
+    /// selected segment's header. Firmware that rejects a header field
+    /// doesn't fail the download: the instrument error comes back as
+    /// `Ok(Some(warning))`.
+    pub fn set_header(&mut self, fs: f64, scale_percent: f64) -> Result<Option<String>, String> {
+        let Some(cmds) = self.dialect.header(fs, scale_percent) else {
+            return Ok(None);
+        };
+        self.write_all(cmds)?;
+        Ok(self
+            .client
+            .err_check()
+            .err()
+            .map(|e| format!("The waveform header was not saved: {}", e)))
+    }
+
+    /// One-time sweep setup: configure CF/FS/power (with ALC off and one
+    /// power search if `alc_off`), download wfm (if not already
+    /// on the instrument, or `force_download`), create sequence,
+    /// set trigger mode to `trigger`/single, and enable output.
+    /// Returns whether the waveform was downloaded, and the warning from
+    /// `set_header` if its header could not be saved.
+    #[allow(clippy::too_many_arguments)]
+    pub fn prepare_sweep(
+        &mut self,
+        wfm_data: &[u8],
+        markers: Option<&[u8]>,
+        wfm_id: &str,
+        content_key: u64,
+        cf: f64,
+        fs: f64,
+        amp: f64,
+        repeat_count: u32,
+        trigger: TriggerSource,
+        timing: TriggerTiming,
+        force_download: bool,
+        alc_off: bool,
+        rf_blanking: bool,
+        ctl: &DownloadControl,
+    ) -> Result<(bool, Option<String>), String> {
+        // Reject a bad delay before anything is downloaded
+        self.dialect.arm_repeat(wfm_id, repeat_count, false, trigger, timing)?;
+        self.configure(cf, fs, amp)?;
+        if alc_off {
+            self.set_alc(false)?;
+            self.run_power_search()?;
+        }
+        let downloaded = self.ensure_wfm(wfm_id, wfm_data, markers, content_key, force_download, ctl)?;
+        let header_warning = if downloaded {
+            self.set_header(fs, self.iq_scale)?
+        } else {
+            None
+        };
+        // After the header, which resets the marker routing blanking uses
+        if rf_blanking {
+            self.set_rf_blanking(true)?;
+        }
+
+        // Sequence with the repeat count, armed for each burst's trigger
+        self.write_all(
+            self.dialect
+                .arm_repeat(wfm_id, repeat_count, self.rf_blanking, trigger, timing)?,
+        )?;
+        self.client.err_check()?;
+        Ok((downloaded, header_warning))
+    }
+
+    /// Load a hardware power list of `powers` (dBm), stepping every
+    /// `dwell_s` once `start_power_list` runs. Returns `Ok(false)` without
+    /// changing anything when the instrument lacks list sweeps, detected by
+    /// an error after the first LIST command; the caller then steps power
+    /// from software.
+    pub fn configure_power_list(&mut self, powers: &[f64], dwell_s: f64) -> Result<bool, String> {
+        if powers.is_empty() || powers.len() > MAX_LIST_POINTS {
+            return Err(format!(
+                "A power list needs 1 to {} points, got {}",
+                MAX_LIST_POINTS,
+                powers.len()
+            ));
+        }
+        if dwell_s <= 0.0 {
+            return Err(format!("List dwell must be positive, got {} s", dwell_s));
+        }
+        for &p in powers {
+            self.check_output_power(p)?;
+        }
+        let Some(cmds) = self.dialect.power_list(powers, dwell_s) else {
+            return Ok(false);
+        };
+
+        self.write(&cmds[0])?;
+        if self.client.err_check().is_err() {
+            return Ok(false);
+        }
+        self.write_all(cmds[1..].to_vec())?;
+        self.client.err_check()?;
+        Ok(true)
+    }
+
+    /// Start the loaded power list.
+    pub fn start_power_list(&mut self) -> Result<(), String> {
+        self.once(|vsg| {
+            vsg.write_all(vec!["initiate:immediate".into(), "*TRG".into()])?;
+            vsg.client.err_check()
+        })
+    }
+
+    /// Whether the power list is still stepping.
+    pub fn power_list_running(&mut self) -> Result<bool, String> {
+        self.idempotent(|vsg| {
+            let condition = parse_number(&vsg.ask("status:operation:condition?")?)? as u32;
+            Ok(condition & SWEEPING_BIT != 0)
+        })
+    }
+
+    /// Return to fixed power after a list sweep.
+    pub fn end_power_list(&mut self) -> Result<(), String> {
+        self.idempotent(|vsg| {
+            vsg.write("power:mode fixed")?;
+            vsg.client.err_check()
+        })
+    }
+
+    /// Start the prepared sequence (*TRG, or a trigger execute on R&S).
+    pub fn trigger(&mut self) -> Result<(), String> {
+        self.once(|vsg| {
+            // A retried trigger could fire the burst twice
+            let policy = vsg.client.set_retry_policy(None);
+            let result = vsg.write(vsg.dialect.trigger());
+            vsg.client.set_retry_policy(policy);
+            result?;
+            vsg.client.err_check()
+        })
+    }
+
+    /// Block until the triggered sequence has played out and the ARB waits
+    /// for its next trigger, or fail after `timeout`. Call it right after
+    /// the trigger; `nominal` is how long the sequence plays.
+    ///
+    /// The idle state still reads as set right after the trigger, so idle
+    /// counts as done once the sequence was seen running, or once `nominal`
+    /// has passed: a sequence shorter than a poll may never be seen running.
+    pub fn wait_sequence_done(&mut self, nominal: Duration, timeout: Duration) -> Result<(), String> {
+        let start = Instant::now();
+        let deadline = start + timeout;
+        let mut started = false;
+        loop {
+            let resp = self.ask(self.dialect.sequence_state_query())?;
+            let idle = self.dialect.sequence_idle(&resp)?;
+            if idle && (started || start.elapsed() >= nominal) {
+                return Ok(());
+            }
+            started |= !idle;
+            if Instant::now() >= deadline {
+                return Err(format!(
+                    "Sequence still playing after {:.1} s",
+                    timeout.as_secs_f64()
+                ));
+            }
+            std::thread::sleep(STATUS_POLL_INTERVAL);
+        }
+    }
+
+    /// `trigger`, then `wait_sequence_done`.
+    pub fn trigger_and_wait(&mut self, nominal: Duration, timeout: Duration) -> Result<(), String> {
+        self.trigger()?;
+        self.wait_sequence_done(nominal, timeout)
+    }
+
+    /// Play one software-stepped sweep step at `power`, recording the
+    /// set_power, trigger, dwell and retry time in `timing`. With an
+    /// external trigger the dwell is waited out instead of observed.
+    pub fn play_step(&mut self, power: f64, pacing: &StepPacing, timing: &mut StepTiming) -> Result<(), String> {
+        let t = Instant::now();
+        self.set_power(power)?;
+        timing.set_power_ms = sweep::elapsed_ms(t);
+
+        let t = Instant::now();
+        if pacing.self_trigger {
+            self.trigger()?;
+        }
+        timing.trigger_ms = sweep::elapsed_ms(t);
+
+        let t = Instant::now();
+        if pacing.self_trigger {
+            self.wait_sequence_done(pacing.nominal, pacing.timeout)?;
+        } else {
+            std::thread::sleep(pacing.external_wait);
+        }
+        timing.dwell_ms = sweep::elapsed_ms(t);
+
+        timing.retries_ms += self.take_retry_time().as_secs_f64() * 1e3;
+        Ok(())
+    }
+
+    /// Waveform files in the instrument's memory, with their sizes.
+    pub fn list_waveforms(&mut self) -> Result<WaveformCatalog, String> {
+        self.idempotent(Self::read_catalog)
+    }
+
+    fn read_catalog(&mut self) -> Result<WaveformCatalog, String> {
+        let resp = self.ask(self.dialect.catalog_query())?;
+        let mut catalog = parse_catalog(&resp)?;
+        let dialect = self.dialect;
+        catalog.files.retain_mut(|f| match dialect.waveform_name(&f.name) {
+            Some(name) => {
+                f.name = name.to_string();
+                true
+            }
+            None => false,
+        });
+        Ok(catalog)
+    }
+
+    /// Catalog entry of `wfm_id`, or an error listing what the instrument
+    /// holds instead.
+    pub fn find_waveform(&mut self, wfm_id: &str) -> Result<InstrumentFile, String> {
+        let files = self.list_waveforms()?.files;
+        if let Some(file) = files.iter().find(|f| f.name == wfm_id) {
+            return Ok(file.clone());
+        }
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        Err(format!(
+            "'{}' is not on the instrument; it holds: {}",
+            wfm_id,
+            if names.is_empty() { "nothing".to_string() } else { names.join(", ") }
+        ))
+    }
+
+    /// Read waveform `wfm_id` back from the instrument: the I/Q segment on
+    /// Keysight, the whole .wv file on R&S.
+    pub fn read_waveform(&mut self, wfm_id: &str) -> Result<Vec<u8>, String> {
+        let file = self.find_waveform(wfm_id)?;
+        let timeout = transfer_timeout(self.client.timeout(), file.size as usize);
+        let query = self.dialect.data_query(wfm_id);
+        self.idempotent(|vsg| {
+            // The catalog size bounds the block, with room for a .wv header
+            vsg.client.set_max_block_size(file.size as usize + 4096);
+            vsg.client
+                .with_timeout(timeout, |client| client.query_binary_block(&query))
+        })
+    }
+
+    /// Delete one waveform file from the instrument's memory.
+    pub fn delete_waveform(&mut self, wfm_id: &str) -> Result<(), String> {
+        self.downloaded.remove(wfm_id);
+        self.idempotent(|vsg| {
+            vsg.write(&vsg.dialect.delete(wfm_id))?;
+            vsg.client.err_check()
+        })
+    }
+
+    /// Delete every waveform file; returns how many were removed.
+    pub fn delete_all_waveforms(&mut self) -> Result<usize, String> {
+        let files = self.list_waveforms()?.files;
+        for file in &files {
+            self.delete_waveform(&file.name)?;
+        }
+        Ok(files.len())
+    }
+
+    /// Query frequency, power, output, modulation and ARB state, the
+    /// selected waveform and the sample clock.
+    pub fn get_status(&mut self) -> Result<VsgStatus, String> {
+        let mut status = self.idempotent(|vsg| {
+            let queries = vsg.dialect.status_queries();
+            let mut resp: [String; 7] = Default::default();
+            for (r, q) in resp.iter_mut().zip(queries) {
+                *r = vsg.ask(q)?;
+            }
+            parse_status(vsg.dialect, &resp)
+        })?;
+        status.arb_memory = self.query_arb_memory()?;
+        Ok(status)
+    }
+
+    /// Total, used and free ARB memory, from the catalog or, if the
+    /// firmware leaves those counts out, the model's memory size.
+    pub fn query_arb_memory(&mut self) -> Result<Option<ArbMemory>, String> {
+        let catalog = self.list_waveforms()?;
+        Ok(ArbMemory::from_catalog(&catalog, &self.model_info))
+    }
+
+    /// Fail before a download of `needed` bytes that would not fit in the
+    /// free ARB memory, so it doesn't stop halfway through.
+    fn check_arb_space(&mut self, wfm_id: &str, needed: u64) -> Result<(), String> {
+        let catalog = self.list_waveforms()?;
+        let Some(memory) = ArbMemory::from_catalog(&catalog, &self.model_info) else {
+            return Ok(());
+        };
+        let replaced = catalog.files.iter().find(|f| f.name == wfm_id).map_or(0, |f| f.size);
+        memory.check_fits(needed, replaced)
+    }
+
+    /// Select the frequency reference. Selecting EXT with no signal on the
+    /// input raises an instrument error, which is returned here.
+    pub fn set_reference(&mut self, source: RefSource) -> Result<(), String> {
+        self.idempotent(|vsg| {
+            vsg.write(&vsg.dialect.set_reference(source))?;
+            vsg.client.err_check()
+        })
+    }
+
+    /// Selected reference and its lock state.
+    pub fn get_reference_status(&mut self) -> Result<ReferenceStatus, String> {
+        self.idempotent(|vsg| {
+            let source = vsg.ask(vsg.dialect.reference_query())?;
+            let condition = vsg.ask("STAT:QUES:FREQ:COND?")?;
+            parse_reference(&source, &condition)
+        })
+    }
+
+    /// Switch between modulated playback and plain CW without touching the
+    /// RF output or the rest of the configuration. `stop` remains the full
+    /// teardown.
+    pub fn set_modulation(&mut self, enabled: bool) -> Result<(), String> {
+        self.idempotent(|vsg| {
+            vsg.write_all(vsg.dialect.set_modulation(enabled))?;
+            vsg.client.err_check()
+        })
+    }
+
+    /// Run the instrument self test. The read timeout is raised for the
+    /// duration, as `*TST?` can take tens of seconds to answer.
+    pub fn self_test(&mut self) -> Result<SelfTestResult, String> {
+        let resp = self.once(|vsg| {
+            let timeout = SELF_TEST_TIMEOUT.max(vsg.client.timeout());
+            vsg.client.with_timeout(timeout, |client| client.query("*TST?"))
+        })?;
+        parse_self_test(&resp)
+    }
+
+    /// Installed options from `*OPT?`.
+    pub fn query_options(&mut self) -> Result<Vec<String>, String> {
+        self.idempotent(|vsg| vsg.client.query("*OPT?").map(|resp| parse_options(&resp)))
+    }
+
+    /// Send a raw SCPI command for debugging and report any instrument
+    /// errors it caused. Binary block payloads are not supported.
+    pub fn raw_write(&mut self, cmd: &str) -> Result<(), String> {
+        let cmd = check_raw_command(cmd)?;
+        self.once(|vsg| {
+            vsg.client.write_cmd(cmd)?;
+            vsg.client.err_check()
+        })
+    }
+
+    /// Send a raw SCPI query for debugging and return the response.
+    pub fn raw_query(&mut self, cmd: &str) -> Result<String, String> {
+        let cmd = check_raw_command(cmd)?;
+        self.once(|vsg| vsg.client.query(cmd))
+    }
+
+    /// `stop_all`, then remove any power offset or loss table, before the
+    /// connection is closed for the next user.
+    pub fn release(&mut self) -> Result<(), String> {
+        let stopped = self.stop_all();
+        self.clear_after_stop(stopped)
+    }
+
+    /// Clear the power offset and loss table after a stop with result
+    /// `stopped`, unless none is active or the connection is gone.
+    fn clear_after_stop(&mut self, stopped: Result<(), String>) -> Result<(), String> {
+        if !self.corrections_active || self.client.is_disconnected() {
+            return stopped;
+        }
+        match (stopped, self.clear_corrections()) {
+            (Ok(()), cleared) => cleared,
+            (Err(e), Ok(())) => Err(e),
+            (Err(e), Err(c)) => Err(format!("{}; {}", e, c)),
+        }
+    }
+
+    /// `stop` every RF output, leaving the first one selected.
+    pub fn stop_all(&mut self) -> Result<(), String> {
+        let last = self.model_info.channels.max(self.channel);
+        let mut errors = Vec::new();
+        for channel in (1..=last).rev() {
+            self.channel = channel;
+            if let Err(e) = self.stop() {
+                errors.push(e);
+                // Nothing more gets through without a connection
+                if self.client.is_disconnected() {
+                    break;
+                }
+            }
+        }
+        self.channel = 1;
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    /// Stop playback on the selected channel: disable RF output,
+    /// modulation, and arb state, then clear the power offset and loss
+    /// table so they don't carry over to the next playback.
+    ///
+    /// Every command is attempted even if one fails, and the error lists
+    /// what could not be turned off. If the connection is gone and cannot
+    /// be restored the error starts with `CONNECTION_LOST`, as the RF
+    /// output may still be on.
+    pub fn stop(&mut self) -> Result<(), String> {
+        let stopped = match self.idempotent(Self::stop_once) {
+            Err(e) if self.client.is_disconnected() => return Err(format!("{} ({})", CONNECTION_LOST, e)),
+            result => result,
+        };
+        self.clear_after_stop(stopped)
+    }
+
+    fn stop_once(&mut self) -> Result<(), String> {
+        let mut failed = Vec::new();
+        for cmd in self.dialect.stop() {
+            if let Err(e) = self.write(&cmd) {
+                failed.push(e);
+            }
+        }
+        self.rf_blanking = false;
+        if self.client.is_disconnected() {
+            return Err(failed.join("; "));
+        }
+        if let Err(e) = self.client.err_check() {
+            failed.push(e);
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Stop incomplete, RF may still be on: {}", failed.join("; ")))
+        }
+    }
+}
+
+/// A sweep's power offset, giving access to the instrument while it is
+/// applied. Dropping it restores the offset set before the sweep, unless a
+/// `stop` has cleared the corrections since.
+pub struct SweepOffset<'a> {
+    vsg: &'a mut VsgInstrument,
+    /// Offset and `corrections_active` before the sweep; `None` when the
+    /// sweep left the offset alone.
+    previous: Option<(f64, bool)>,
+}
+
+impl Deref for SweepOffset<'_> {
+    type Target = VsgInstrument;
+
+    fn deref(&self) -> &VsgInstrument {
+        self.vsg
+    }
+}
+
+impl DerefMut for SweepOffset<'_> {
+    fn deref_mut(&mut self) -> &mut VsgInstrument {
+        self.vsg
+    }
+}
+
+impl Drop for SweepOffset<'_> {
+    fn drop(&mut self) {
+        if !self.vsg.corrections_active {
+            return;
+        }
+        if let Some((db, active)) = self.previous {
+            // Cached even if the write fails, so a reconnect restores it
+            let _ = self.vsg.set_power_offset(db);
+            self.vsg.power_offset_db = db;
+            self.vsg.corrections_active = active;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+
+    const MXG_IDN: &str = "Keysight Technologies,N5182B,MY12345678,B.03.10";
+    const MXG_CATALOG: &str = "mmemory:catalog? \"WFM1:\"";
+
+    /// VSG on a mock transport, with the connection's commands cleared.
+    fn mock_vsg(idn: &str) -> (VsgInstrument, MockTransport) {
+        let mock = MockTransport::new();
+        mock.reply("*idn?", idn);
+        let vsg = VsgInstrument::with_client(Box::new(mock.clone()), "mock", false).unwrap();
+        mock.take();
+        (vsg, mock)
+    }
+
+    /// Script the catalog before and after downloading 16 bytes as "slot".
+    fn script_download(mock: &MockTransport) {
+        mock.reply(MXG_CATALOG, "0,100000000")
+            .reply(MXG_CATALOG, "16,99999984,\"slot,WFM1,16\"");
+    }
+
+    #[test]
+    fn configure_sequence_and_error_check() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        vsg.configure(2.412e9, 40e6, -10.0).unwrap();
+        assert_eq!(
+            mock.take(),
+            ["frequency 2412000000", "radio:arb:sclock:rate 40000000", "power -10", "SYST:ERR?"]
+        );
+
+        // The queue is drained and its errors fail the command
+        mock.reply("SYST:ERR?", "-222,\"Data out of range\"");
+        let err = vsg.configure(2.412e9, 40e6, -10.0).unwrap_err();
+        assert!(err.contains("-222"), "{}", err);
+        assert_eq!(mock.take()[3..], ["SYST:ERR?", "SYST:ERR?"]);
+    }
+
+    #[test]
+    fn download_sequence() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        script_download(&mock);
+        let cancel = AtomicBool::new(false);
+        let ctl = DownloadControl {
+            chunk_bytes: 1 << 20,
+            progress: &|_, _| {},
+            cancel: &cancel,
+        };
+        vsg.download_wfm(&[0; 16], Some(&[1; 4]), "slot", &ctl).unwrap();
+        assert_eq!(
+            mock.take(),
+            [
+                MXG_CATALOG,
+                "output:modulation 0",
+                "radio:arb:state 0",
+                "mmemory:data \"WFM1:slot\",<16 bytes>",
+                "radio:arb:waveform \"WFM1:slot\"",
+                "SYST:ERR?",
+                MXG_CATALOG,
+                "mmemory:data \"MKR1:slot\",<4 bytes>",
+                "SYST:ERR?",
+            ]
+        );
+
+        // A size mismatch in the catalog fails the download
+        mock.reply(MXG_CATALOG, "0,100000000")
+            .reply(MXG_CATALOG, "8,99999992,\"slot,WFM1,8\"");
+        let err = vsg.download_wfm(&[0; 16], None, "slot", &ctl).unwrap_err();
+        assert!(err.contains("is 8 bytes on the instrument, expected 16"), "{}", err);
+    }
+
+    #[test]
+    fn ensure_wfm_skips_only_a_segment_still_listed() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        let cancel = AtomicBool::new(false);
+        let ctl = DownloadControl {
+            chunk_bytes: 1 << 20,
+            progress: &|_, _| {},
+            cancel: &cancel,
+        };
+        script_download(&mock);
+        assert!(vsg.ensure_wfm("slot", &[0; 16], None, 7, false, &ctl).unwrap());
+        mock.take();
+
+        // Same content, still in the catalog: only selected
+        mock.reply(MXG_CATALOG, "16,99999984,\"slot,WFM1,16\"");
+        assert!(!vsg.ensure_wfm("slot", &[0; 16], None, 7, false, &ctl).unwrap());
+        assert_eq!(mock.take(), [MXG_CATALOG, "radio:arb:waveform \"WFM1:slot\"", "SYST:ERR?"]);
+        assert_eq!(vsg.downloads_skipped(), 1);
+
+        // Same content, but deleted on the instrument
+        mock.reply(MXG_CATALOG, "16,99999984,\"other,WFM1,16\"");
+        script_download(&mock);
+        assert!(vsg.ensure_wfm("slot", &[0; 16], None, 7, false, &ctl).unwrap());
+        assert!(mock.take().contains(&"mmemory:data \"WFM1:slot\",<16 bytes>".to_string()));
+
+        // Other content under the same id skips the catalog check
+        script_download(&mock);
+        assert!(vsg.ensure_wfm("slot", &[0; 16], None, 8, false, &ctl).unwrap());
+        assert_eq!(mock.take().iter().filter(|c| *c == MXG_CATALOG).count(), 2);
+
+        // Forced: downloaded although the catalog would match
+        script_download(&mock);
+        assert!(vsg.ensure_wfm("slot", &[0; 16], None, 8, true, &ctl).unwrap());
+        assert!(mock.take().contains(&"mmemory:data \"WFM1:slot\",<16 bytes>".to_string()));
+        assert_eq!(vsg.downloads_skipped(), 1);
+
+        // A failed download forgets the content, so the next call downloads
+        mock.reply(MXG_CATALOG, "0,100000000")
+            .fail("mmemory:data", "Write binary data failed");
+        assert!(vsg.ensure_wfm("slot", &[0; 16], None, 9, false, &ctl).is_err());
+        mock.take();
+        script_download(&mock);
+        assert!(vsg.ensure_wfm("slot", &[0; 16], None, 8, false, &ctl).unwrap());
+        assert_eq!(vsg.downloads_skipped(), 1);
+    }
+
+    #[test]
+    fn timeout_after_failed_block_clears_io() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        mock.reply(MXG_CATALOG, "0,100000000")
+            .fail("mmemory:data", "Write binary data timed out after 3.0 s on 'mmemory:data'")
+            .fail("frequency", "Write timed out after 3.0 s on 'frequency 2412000000'");
+        let cancel = AtomicBool::new(false);
+        let ctl = DownloadControl {
+            chunk_bytes: 1 << 20,
+            progress: &|_, _| {},
+            cancel: &cancel,
+        };
+        assert!(vsg.download_wfm(&[0; 16], None, "slot", &ctl).is_err());
+        mock.take();
+
+        // The instrument read the command as block data; after the clear it
+        // is sent again
+        vsg.configure(2.412e9, 40e6, -10.0).unwrap();
+        assert_eq!(
+            mock.take(),
+            [
+                "frequency 2412000000",
+                "<device clear>",
+                "frequency 2412000000",
+                "radio:arb:sclock:rate 40000000",
+                "power -10",
+                "SYST:ERR?",
+            ]
+        );
+        assert_eq!(vsg.identity.raw, MXG_IDN);
+
+        // Other timeouts are not cleared
+        mock.fail("frequency", "Write timed out after 3.0 s on 'frequency 2412000000'");
+        assert!(vsg.configure(2.412e9, 40e6, -10.0).is_err());
+        assert_eq!(mock.take(), ["frequency 2412000000"]);
+    }
+
+    #[test]
+    fn play_sequences() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        vsg.play("slot", true).unwrap();
+        assert_eq!(
+            mock.take(),
+            [
+                "radio:arb:trigger:type continuous",
+                "radio:arb:waveform \"WFM1:slot\"",
+                "route:connectors:event1 m1",
+                "output 1",
+                "output:modulation 1",
+                "radio:arb:state 1",
+                "SYST:ERR?",
+            ]
+        );
+
+        vsg.play_with_repeat("slot", 5, false, TriggerSource::Bus, TriggerTiming::default())
+            .unwrap();
+        assert_eq!(
+            mock.take(),
+            [
+                "radio:arb:sequence \"seq_slot\",\"WFM1:slot\",5,0",
+                "route:connectors:event1 none",
+                "radio:arb:waveform \"SEQ:seq_slot\"",
+                "radio:arb:trigger:source bus",
+                "radio:arb:trigger:delay 0",
+                "radio:arb:trigger:delay:state off",
+                "radio:arb:trigger:type single",
+                "radio:arb:retrigger off",
+                "radio:arb:state 1",
+                "output:modulation 1",
+                "output 1",
+                "*TRG",
+                "SYST:ERR?",
+            ]
+        );
+    }
+
+    #[test]
+    fn stop_sequence_reports_instrument_errors() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        vsg.stop().unwrap();
+        assert_eq!(
+            mock.take(),
+            [
+                "output 0",
+                "output:modulation 0",
+                "radio:arb:state 0",
+                "radio:arb:mdestination:pulse none",
+                "radio:arb:mdestination:alchold none",
+                "SYST:ERR?",
+            ]
+        );
+
+        mock.reply("SYST:ERR?", "-221,\"Settings conflict\"");
+        let err = vsg.stop().unwrap_err();
+        assert!(err.starts_with("Stop incomplete") && err.contains("-221"), "{}", err);
+    }
+
+    #[test]
+    fn sweep_offset_restores_previous_offset() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        vsg.set_power_offset(-2.0).unwrap();
+        mock.take();
+        {
+            let sweep = vsg.sweep_offset(3.0).unwrap();
+            assert_eq!(sweep.power_offset_db, -3.0);
+        }
+        assert_eq!(mock.take(), ["power:offset -3", "SYST:ERR?", "power:offset -2", "SYST:ERR?"]);
+        assert_eq!((vsg.power_offset_db, vsg.corrections_active), (-2.0, true));
+
+        // The loss table already covers the cable
+        vsg.load_loss_table(&[(2412e6, 1.5)]).unwrap();
+        mock.take();
+        drop(vsg.sweep_offset(3.0).unwrap());
+        assert!(mock.take().is_empty());
+
+        vsg.release().unwrap();
+        assert!(mock.take().contains(&"correction:state 0".to_string()));
+        assert!(!vsg.corrections_active);
+    }
+
+    #[test]
+    fn stop_clears_corrections() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        vsg.set_power_offset(-2.0).unwrap();
+        vsg.load_loss_table(&[(2412e6, 1.5)]).unwrap();
+        mock.take();
+        vsg.stop().unwrap();
+        let sent = mock.take();
+        assert_eq!(sent[0], "output 0");
+        assert_eq!(sent[sent.len() - 3..], ["power:offset 0", "correction:state 0", "SYST:ERR?"]);
+        assert_eq!((vsg.power_offset_db, vsg.corrections_active), (0.0, false));
+        assert_eq!(vsg.sweep_loss_db(2412e6, 3.0), 3.0);
+
+        // Nothing left to clear
+        vsg.stop().unwrap();
+        assert!(mock.take().iter().all(|c| !c.starts_with("correction:")));
+
+        // A sweep that ends with a stop does not bring its offset back
+        vsg.set_power_offset(-2.0).unwrap();
+        {
+            let mut sweep = vsg.sweep_offset(3.0).unwrap();
+            sweep.stop().unwrap();
+        }
+        mock.take();
+        assert_eq!((vsg.power_offset_db, vsg.corrections_active), (0.0, false));
+    }
+
+    #[test]
+    fn sweep_power_limit_uses_loss_table() {
+        let (mut vsg, _mock) = mock_vsg(MXG_IDN);
+        assert_eq!(vsg.sweep_loss_db(5180e6, 3.0), 3.0);
+        assert!(check_power_limit(-5.0, vsg.sweep_loss_db(5180e6, 3.0), 0.0).is_ok());
+
+        // The table, not the cable loss, sets the level at the output
+        vsg.load_loss_table(&[(2412e6, 1.5), (5180e6, 6.0)]).unwrap();
+        assert_eq!(vsg.sweep_loss_db(5180e6, 3.0), 6.0);
+        let err = check_power_limit(-5.0, vsg.sweep_loss_db(5180e6, 3.0), 0.0).unwrap_err();
+        assert!(err.starts_with("Output power 1 dBm"), "{}", err);
+    }
+
+    #[test]
+    fn corrections_restored_after_reconnect() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        vsg.restore_corrections().unwrap();
+        assert!(mock.take().is_empty());
+
+        vsg.set_power_offset(-3.5).unwrap();
+        vsg.load_loss_table(&[(2412e6, 1.5)]).unwrap();
+        mock.take();
+        vsg.restore_corrections().unwrap();
+        assert_eq!(
+            mock.take(),
+            [
+                "power:offset -3.5",
+                "correction:flatness:preset",
+                "correction:flatness:pair 2412000000,1.5",
+                "correction:state 1",
+            ]
+        );
+
+        vsg.clear_corrections().unwrap();
+        mock.take();
+        vsg.restore_corrections().unwrap();
+        assert!(mock.take().is_empty());
+    }
+
+    #[test]
+    fn prepare_sweep_sequence() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        script_download(&mock);
+        let cancel = AtomicBool::new(false);
+        let ctl = DownloadControl {
+            chunk_bytes: 1 << 20,
+            progress: &|_, _| {},
+            cancel: &cancel,
+        };
+        let prepared = vsg
+            .prepare_sweep(
+                &[0; 16],
+                None,
+                "slot",
+                7,
+                5.18e9,
+                80e6,
+                -20.0,
+                10,
+                TriggerSource::Bus,
+                TriggerTiming::default(),
+                false,
+                true,
+                true,
+                &ctl,
+            )
+            .unwrap();
+        assert_eq!(prepared, (true, None));
+        assert_eq!(
+            mock.take(),
+            [
+                "frequency 5180000000",
+                "radio:arb:sclock:rate 80000000",
+                "power -20",
+                "SYST:ERR?",
+                "power:alc 0",
+                "SYST:ERR?",
+                "power:alc:search once",
+                "*OPC?",
+                "SYST:ERR?",
+                MXG_CATALOG,
+                "output:modulation 0",
+                "radio:arb:state 0",
+                "mmemory:data \"WFM1:slot\",<16 bytes>",
+                "radio:arb:waveform \"WFM1:slot\"",
+                "SYST:ERR?",
+                MXG_CATALOG,
+                "radio:arb:sclock:rate 80000000",
+                "radio:arb:rscaling 100",
+                "radio:arb:mdestination:alchold none",
+                "radio:arb:mdestination:pblank none",
+                "radio:arb:header:save",
+                "SYST:ERR?",
+                "radio:arb:mpolarity:marker1 positive",
+                "radio:arb:mdestination:alchold m1",
+                "radio:arb:mdestination:pulse m1",
+                "SYST:ERR?",
+                "radio:arb:sequence \"seq_slot\",\"WFM1:slot\",10,M1",
+                "radio:arb:waveform \"SEQ:seq_slot\"",
+                "radio:arb:trigger:source bus",
+                "radio:arb:trigger:delay 0",
+                "radio:arb:trigger:delay:state off",
+                "radio:arb:trigger:type single",
+                "radio:arb:retrigger off",
+                "radio:arb:state 1",
+                "output:modulation 1",
+                "output 1",
+                "SYST:ERR?",
+            ]
+        );
+
+        // The same content is only selected the second time
+        mock.reply(MXG_CATALOG, "16,99999984,\"slot,WFM1,16\"");
+        let prepared = vsg
+            .prepare_sweep(
+                &[0; 16],
+                None,
+                "slot",
+                7,
+                5.18e9,
+                80e6,
+                -20.0,
+                10,
+                TriggerSource::Bus,
+                TriggerTiming::default(),
+                false,
+                false,
+                false,
+                &ctl,
+            )
+            .unwrap();
+        assert_eq!(prepared, (false, None));
+        let sent = mock.take();
+        assert_eq!(sent[4..7], [MXG_CATALOG, "radio:arb:waveform \"WFM1:slot\"", "SYST:ERR?"]);
+        assert!(!sent.iter().any(|c| c.starts_with("mmemory:data")));
+    }
+
+    fn model(idn: &str) -> InstrumentModel {
+        InstrumentModel::from_identity(&InstrumentIdentity::parse(idn))
+    }
+
+    #[test]
+    fn dialect_from_idn() {
+        let rs = "Rohde&Schwarz,SMW200A,1412.0000K02/101234,5.00.044";
+        let dialect = |idn| VsgDialect::from_identity(&InstrumentIdentity::parse(idn));
+        assert_eq!(dialect(rs), VsgDialect::RohdeSchwarz);
+        let ks = "Keysight Technologies,N5182B,MY12345678,B.03.10";
+        assert_eq!(dialect(ks), VsgDialect::Keysight);
+        assert_eq!(dialect("Agilent Technologies,E4438C,0,1"), VsgDialect::Keysight);
+        assert_eq!(dialect("Rohde&Schwarz SMBV100A"), VsgDialect::RohdeSchwarz);
+    }
+
+    #[test]
+    fn keysight_commands() {
+        let ks = VsgDialect::Keysight;
+        assert_eq!(
+            ks.configure(2.4e9, 40e6, -10.0),
+            ["frequency 2400000000", "radio:arb:sclock:rate 40000000", "power -10"]
+        );
+        assert_eq!(
+            ks.arm_repeat("slot", 5, true, TriggerSource::Bus, TriggerTiming::default()).unwrap(),
+            [
+                "radio:arb:sequence \"seq_slot\",\"WFM1:slot\",5,M1",
+                "radio:arb:waveform \"SEQ:seq_slot\"",
+                "radio:arb:trigger:source bus",
+                "radio:arb:trigger:delay 0",
+                "radio:arb:trigger:delay:state off",
+                "radio:arb:trigger:type single",
+                "radio:arb:retrigger off",
+                "radio:arb:state 1",
+                "output:modulation 1",
+                "output 1",
+            ]
+        );
+        assert_eq!(ks.data_cmd("slot"), "mmemory:data \"WFM1:slot\",");
+        assert_eq!(ks.data_query("slot"), "mmemory:data? \"WFM1:slot\"");
+        assert_eq!(ks.append_cmd("slot").unwrap(), "mmemory:data:append \"WFM1:slot\",");
+        assert_eq!(VsgDialect::RohdeSchwarz.append_cmd("slot"), None);
+        assert_eq!(ks.trigger(), "*TRG");
+        assert_eq!(ks.set_modulation(false), ["output:modulation 0", "radio:arb:state 0"]);
+        assert_eq!(ks.rf_blanking(true).unwrap().last().unwrap(), "radio:arb:mdestination:pulse m1");
+        // Blanking must not outlive a stop
+        assert!(ks.stop().contains(&"radio:arb:mdestination:pulse none".to_string()));
+        assert!(!ks.set_modulation(true).iter().any(|c| c.starts_with("output ")));
+    }
+
+    #[test]
+    fn rohde_schwarz_commands() {
+        let rs = VsgDialect::RohdeSchwarz;
+        assert_eq!(
+            rs.configure(5.18e9, 80e6, 0.0),
+            ["SOUR1:FREQ 5180000000", "SOUR1:BB:ARB:CLOC 80000000", "SOUR1:POW 0"]
+        );
+        assert_eq!(rs.data_cmd("a"), "SOUR1:BB:ARB:WAV:DATA \"/var/user/a.wv\",");
+        assert_eq!(
+            rs.play("a", false),
+            [
+                "SOUR1:BB:ARB:TRIG:SEQ AUTO",
+                "SOUR1:BB:ARB:WAV:SEL \"/var/user/a.wv\"",
+                "OUTP:USER1:SIGN NONE",
+                "SOUR1:BB:ARB:STAT ON",
+                "OUTP1 ON",
+            ]
+        );
+        let armed = rs.arm_repeat("a", 3, false, TriggerSource::Bus, TriggerTiming::default()).unwrap();
+        assert_eq!(armed[0], rs.select("a"));
+        assert!(armed.contains(&"SOUR1:BB:ARB:TRIG:SLEN 3".to_string()));
+        assert_eq!(rs.set_power(-3.5), "SOUR1:POW -3.5");
+        assert_eq!(rs.stop(), ["OUTP1 OFF", "SOUR1:BB:ARB:STAT OFF"]);
+        assert!(rs.rf_blanking(true).is_err());
+        assert!(rs.rf_blanking(false).unwrap().is_empty());
+        assert_eq!(rs.set_modulation(false), ["SOUR1:IQ:STAT OFF", "SOUR1:BB:ARB:STAT OFF"]);
+        assert_eq!(rs.set_modulation(true), ["SOUR1:BB:ARB:STAT ON", "SOUR1:IQ:STAT ON"]);
+    }
+
+    #[test]
+    fn external_trigger_commands() {
+        let ext = TriggerSource::External {
+            slope: TriggerSlope::Negative,
+            delay_s: 1e-6,
+        };
+        let armed = VsgDialect::Keysight.arm_repeat("a", 1, false, ext, TriggerTiming::default()).unwrap();
+        assert_eq!(
+            armed[2..6],
+            [
+                "radio:arb:trigger:source ext",
+                "radio:arb:trigger:source:external:slope negative",
+                "radio:arb:trigger:source:external:delay 0.000001",
+                "radio:arb:trigger:source:external:delay:state on",
+            ]
+        );
+        assert!(!armed.iter().any(|c| c == "*TRG"));
+        let armed = VsgDialect::RohdeSchwarz.arm_repeat("a", 1, false, ext, TriggerTiming::default()).unwrap();
+        assert!(armed.contains(&"SOUR1:BB:ARB:TRIG:SOUR EGT1".to_string()));
+
+        let late = TriggerSource::External {
+            slope: TriggerSlope::Positive,
+            delay_s: 41.0,
+        };
+        assert!(VsgDialect::Keysight.arm_repeat("a", 1, false, late, TriggerTiming::default()).is_err());
+        let key = VsgDialect::Keysight
+            .arm_repeat("a", 1, false, TriggerSource::Key, TriggerTiming::default())
+            .unwrap();
+        assert_eq!(key[2], "radio:arb:trigger:source key");
+        assert!(VsgDialect::RohdeSchwarz
+            .arm_repeat("a", 1, false, TriggerSource::Key, TriggerTiming::default())
+            .is_err());
+    }
+
+    #[test]
+    fn trigger_delay_and_retrigger() {
+        let timing = TriggerTiming {
+            delay_s: 200e-6,
+            retrigger: Retrigger::Buffered,
+        };
+        let (bus, delay_s) = timing.apply(TriggerSource::Bus).unwrap();
+        assert_eq!((bus, delay_s), (TriggerSource::Bus, 200e-6));
+        let ext = TriggerSource::External {
+            slope: TriggerSlope::Positive,
+            delay_s: 1e-3,
+        };
+        let (delayed, delay_s) = timing.apply(ext).unwrap();
+        match delayed {
+            TriggerSource::External { delay_s, .. } => assert!((delay_s - 1.2e-3).abs() < 1e-12),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(delay_s, 0.0);
+        assert!(timing.apply(TriggerSource::Key).is_err());
+        for delay_s in [-1e-6, 41.0, f64::NAN] {
+            let bad = TriggerTiming { delay_s, ..timing };
+            assert!(bad.apply(TriggerSource::Bus).is_err());
+        }
+
+        // The bus trigger delay is the instrument's, not a wait on the host
+        let armed = VsgDialect::Keysight
+            .arm_repeat("a", 1, false, TriggerSource::Bus, timing)
+            .unwrap();
+        assert_eq!(armed[3..5], ["radio:arb:trigger:delay 0.0002", "radio:arb:trigger:delay:state on"]);
+        assert!(armed.contains(&"radio:arb:retrigger on".to_string()));
+        let ext_armed = VsgDialect::Keysight.arm_repeat("a", 1, false, ext, timing).unwrap();
+        assert!(!ext_armed.iter().any(|c| c.starts_with("radio:arb:trigger:delay")));
+        assert!(VsgDialect::RohdeSchwarz
+            .arm_repeat("a", 1, false, TriggerSource::Bus, timing)
+            .is_err());
+        let unbuffered = TriggerTiming { retrigger: Retrigger::Ignore, ..timing };
+        assert!(VsgDialect::RohdeSchwarz
+            .arm_repeat("a", 1, false, TriggerSource::Bus, unbuffered)
+            .is_err());
+        assert!(VsgDialect::RohdeSchwarz
+            .arm_repeat("a", 1, false, ext, unbuffered)
+            .is_ok());
+        let buffered = TriggerTiming { delay_s: 0.0, ..timing };
+        assert!(VsgDialect::RohdeSchwarz
+            .arm_repeat("a", 1, false, TriggerSource::Bus, buffered)
+            .is_err());
+    }
+
+    #[test]
+    fn alc_and_iq_scale_commands() {
+        assert_eq!(VsgDialect::Keysight.set_alc(false), "power:alc 0");
+        assert_eq!(VsgDialect::RohdeSchwarz.set_alc(true), "SOUR1:POW:ALC:STAT ON");
+        assert_eq!(VsgDialect::Keysight.power_search(), "power:alc:search once");
+        assert_eq!(VsgDialect::Keysight.iq_scale(70.0).unwrap(), "radio:arb:rscaling 70");
+        assert!(VsgDialect::Keysight.iq_scale(0.0).is_err());
+        assert!(VsgDialect::Keysight.iq_scale(120.0).is_err());
+        assert!(VsgDialect::RohdeSchwarz.iq_scale(70.0).is_err());
+    }
+
+    #[test]
+    fn status_parsing() {
+        let resp = [
+            "+2.41200000000E+09\n",
+            "-1.00000000000E+01",
+            "1",
+            "0",
+            "1",
+            "\"WFM1:slot0\"",
+            "+1.60000000000E+08",
+        ]
+        .map(String::from);
+        let status = parse_status(VsgDialect::Keysight, &resp).unwrap();
+        assert_eq!(status.frequency_hz, 2.412e9);
+        assert_eq!(status.power_dbm, -10.0);
+        assert!(status.output_on && !status.modulation_on && status.arb_on);
+        assert_eq!(status.waveform.as_deref(), Some("slot0"));
+        assert_eq!(status.sample_rate_hz, 160e6);
+
+        let sel = VsgDialect::RohdeSchwarz.selected_waveform("\"/var/user/slot0.wv\"");
+        assert_eq!(sel.as_deref(), Some("slot0"));
+        assert_eq!(VsgDialect::Keysight.selected_waveform("\"\""), None);
+        assert!(parse_state("ON").unwrap());
+
+        let mut bad = resp.clone();
+        bad[2] = "maybe".into();
+        assert!(parse_status(VsgDialect::Keysight, &bad).is_err());
+    }
+
+    #[test]
+    fn reference_commands_and_status() {
+        assert_eq!(VsgDialect::Keysight.set_reference(RefSource::External), "roscillator:source ext");
+        assert_eq!(VsgDialect::RohdeSchwarz.set_reference(RefSource::Internal), "SOUR:ROSC:SOUR INT");
+
+        let ext = parse_reference("EXT\n", "+0").unwrap();
+        assert_eq!(ext, ReferenceStatus { source: RefSource::External, locked: true });
+        assert!(!parse_reference("EXT", "+2").unwrap().locked);
+        assert_eq!(parse_reference("INT", "0").unwrap().source, RefSource::Internal);
+        assert!(parse_reference("AUTO", "0").is_err());
+    }
+
+    #[test]
+    fn model_limits() {
+        let mxg = model("Agilent Technologies, N5182B, MY53050000, B.01.86");
+        assert!(mxg.known);
+        assert_eq!(mxg.name, "N5182B");
+        assert!(mxg.check_frequency(2.412e9).is_ok());
+        let err = mxg.check_frequency(7e9).unwrap_err();
+        assert!(err.contains("N5182B") && err.contains("6000 MHz"), "{}", err);
+        assert!(mxg.check_power(30.0).is_err());
+        assert!(mxg.check_sample_rate(240e6).is_ok());
+
+        let esg = model("Agilent Technologies,E4438C,MY4500,C.05.83");
+        assert!(esg.check_sample_rate(125e6).is_err());
+        assert!(esg.check_samples(64 << 20).is_ok());
+        assert!(esg.check_samples((64 << 20) + 1).is_err());
+        assert!(esg.check_frequency(100e3).is_err());
+
+        let other = model("Rohde&Schwarz,SMW200A,1412.0000K02/0,5.00");
+        assert!(!other.known);
+        assert!(other.check_frequency(20e9).is_ok());
+        assert!(other.check_sample_rate(240e6).is_ok());
+        assert!(other.check_sample_rate(300e6).is_err());
+    }
+
+    #[test]
+    fn sequence_commands() {
+        let ks = VsgDialect::Keysight;
+        assert_eq!(
+            ks.sequence("mix", &[("mcs0", 10, true), ("mcs7", 3, false)]).unwrap(),
+            "radio:arb:sequence \"mix\",\"WFM1:mcs0\",10,M1,\"WFM1:mcs7\",3,0"
+        );
+        assert!(ks.sequence("mix", &[]).is_err());
+        assert!(ks.sequence("mix", &[("a", 0, false)]).is_err());
+        assert!(ks.sequence("mix", &[("a", MAX_SEGMENT_REPEATS + 1, false)]).is_err());
+        let many = vec![("a", 1, false); MAX_SEQUENCE_SEGMENTS + 1];
+        assert!(ks.sequence("mix", &many).is_err());
+        assert!(VsgDialect::RohdeSchwarz.sequence("mix", &[("a", 1, false)]).is_err());
+        assert_eq!(
+            ks.play_selection(ks.select_sequence("mix"), false)[1],
+            "radio:arb:waveform \"SEQ:mix\""
+        );
+    }
+
+    #[test]
+    fn power_list_commands() {
+        let cmds = VsgDialect::Keysight.power_list(&[-60.0, -59.5], 0.2).unwrap();
+        assert_eq!(cmds[0], "list:type list");
+        assert_eq!(cmds[1], "list:power -60,-59.5");
+        assert_eq!(cmds[3], "sweep:dwell 0.2");
+        assert_eq!(cmds.last().unwrap(), "power:mode list");
+        assert!(VsgDialect::RohdeSchwarz.power_list(&[-60.0], 0.2).is_none());
+    }
+
+    #[test]
+    fn wait_sequence_done_waits_for_start() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        mock.reply("status:operation:condition?", "+32")
+            .reply("status:operation:condition?", "+8")
+            .reply("status:operation:condition?", "+8")
+            .reply("status:operation:condition?", "+32");
+        mock.take();
+        // Seen running, so done before the nominal time
+        vsg.wait_sequence_done(Duration::from_secs(10), Duration::from_secs(1)).unwrap();
+        assert_eq!(mock.take().len(), 4);
+    }
+
+    #[test]
+    fn wait_sequence_done_accepts_a_sequence_ended_before_the_first_poll() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        for _ in 0..1000 {
+            mock.reply("status:operation:condition?", "+32");
+        }
+        let start = Instant::now();
+        vsg.wait_sequence_done(Duration::from_millis(10), Duration::from_secs(1)).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        // Still running past the timeout fails
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        for _ in 0..1000 {
+            mock.reply("status:operation:condition?", "+8");
+        }
+        assert!(vsg.wait_sequence_done(Duration::ZERO, Duration::from_millis(20)).is_err());
+    }
+
+    #[test]
+    fn sweep_step_timing_follows_delays() {
+        let (mut vsg, mock) = mock_vsg(MXG_IDN);
+        let ms = Duration::from_millis;
+        let pacing = StepPacing {
+            self_trigger: true,
+            nominal: Duration::ZERO,
+            timeout: Duration::from_secs(2),
+            external_wait: Duration::ZERO,
+        };
+        // The power command hits an instrument still stuck in a failed
+        // block; the retry after the device clear is slow
+        vsg.block_write_failed = true;
+        mock.fail("power ", "Read timed out").delay("SYST:ERR?", ms(100));
+        mock.delay("*TRG", ms(200));
+        mock.reply("status:operation:condition?", "+8")
+            .reply("status:operation:condition?", "+32");
+        mock.delay("status:operation:condition?", ms(300));
+
+        let mut timing = StepTiming::default();
+        vsg.play_step(-40.0, &pacing, &mut timing).unwrap();
+        let within = |phase: f64, delay: f64| phase >= delay && phase < delay + 90.0;
+        assert!(within(timing.set_power_ms, 100.0), "{:?}", timing);
+        assert!(within(timing.retries_ms, 100.0), "{:?}", timing);
+        assert!(timing.retries_ms <= timing.set_power_ms, "{:?}", timing);
+        assert!(within(timing.trigger_ms, 200.0), "{:?}", timing);
+        assert!(timing.dwell_ms >= 300.0, "{:?}", timing);
+        assert!(mock.take().contains(&"<device clear>".to_string()));
+
+        // External trigger: the dwell is the fixed wait
+        let pacing = StepPacing {
+            self_trigger: false,
+            external_wait: ms(50),
+            ..pacing
+        };
+        let mut timing = StepTiming::default();
+        vsg.play_step(-40.0, &pacing, &mut timing).unwrap();
+        assert!(timing.dwell_ms >= 50.0 && timing.trigger_ms < 50.0, "{:?}", timing);
+        assert_eq!(timing.retries_ms, 0.0);
+        assert!(mock.take().iter().all(|c| c != "*TRG"));
+    }
+
+    #[test]
+    fn bus_trigger_delay_commands() {
+        assert_eq!(
+            VsgDialect::Keysight.bus_trigger_delay(0.0002).unwrap(),
+            ["radio:arb:trigger:delay 0.0002", "radio:arb:trigger:delay:state on"]
+        );
+        assert_eq!(
+            VsgDialect::Keysight.bus_trigger_delay(0.0).unwrap(),
+            ["radio:arb:trigger:delay 0", "radio:arb:trigger:delay:state off"]
+        );
+        assert!(VsgDialect::RohdeSchwarz.bus_trigger_delay(0.0).unwrap().is_empty());
+        assert_eq!(
+            VsgDialect::RohdeSchwarz.bus_trigger_delay(0.0002).unwrap_err(),
+            "R&S generators only delay external triggers; use an external trigger"
+        );
+    }
+
+    #[test]
+    fn sequence_idle_state() {
+        let ks = VsgDialect::Keysight;
+        assert!(ks.sequence_idle("+32").unwrap());
+        assert!(!ks.sequence_idle("+8").unwrap());
+        assert!(ks.sequence_idle("junk").is_err());
+        assert!(VsgDialect::RohdeSchwarz.sequence_idle("STOP").unwrap());
+        assert!(!VsgDialect::RohdeSchwarz.sequence_idle("RUN\n").unwrap());
+    }
+
+    #[test]
+    fn header_commands() {
+        let cmds = VsgDialect::Keysight.header(160e6, 70.0).unwrap();
+        assert_eq!(cmds[0], "radio:arb:sclock:rate 160000000");
+        assert_eq!(cmds[1], "radio:arb:rscaling 70");
+        assert_eq!(cmds.last().unwrap(), "radio:arb:header:save");
+        assert!(VsgDialect::RohdeSchwarz.header(160e6, 100.0).is_none());
+    }
+
+    #[test]
+    fn loss_table_parsing_and_commands() {
+        let table = parse_loss_table("freq_mhz,loss_db\n# bench 3\n5180, 2.5\n2412,1.5\n").unwrap();
+        assert_eq!(table, [(2412e6, 1.5), (5180e6, 2.5)]);
+        assert!(parse_loss_table("freq_mhz,loss_db\n").is_err());
+        let err = parse_loss_table("2412,1.5\n5180\n").unwrap_err();
+        assert!(err.starts_with("Line 2"), "{}", err);
+
+        assert_eq!(table_loss(&table, 1e9), 1.5);
+        assert_eq!(table_loss(&table, 3796e6), 2.0);
+        assert_eq!(table_loss(&table, 5180e6), 2.5);
+        assert_eq!(table_loss(&table, 6e9), 2.5);
+
+        let cmds = VsgDialect::Keysight.loss_table(&table);
+        assert_eq!(cmds[1], "correction:flatness:pair 2412000000,1.5");
+        assert_eq!(cmds.last().unwrap(), "correction:state 1");
+        let rs = VsgDialect::RohdeSchwarz.loss_table(&table);
+        assert_eq!(rs[2], "SOUR1:CORR:CSET:DATA:POW 1.5,2.5");
+        assert_eq!(VsgDialect::Keysight.power_offset(-2.5), "power:offset -2.5");
+        assert_eq!(VsgDialect::RohdeSchwarz.clear_corrections()[1], "SOUR1:CORR:STAT OFF");
+    }
+
+    #[test]
+    fn transfer_timeout_scales_with_size() {
+        let base = Duration::from_secs(3);
+        assert_eq!(transfer_timeout(base, 0), base);
+        assert_eq!(transfer_timeout(base, 300_000_000), Duration::from_secs(303));
+    }
+
+    #[test]
+    fn catalog_parsing() {
+        let resp = "+81920,+1234567,\"SLOT1,BIN,40000\",\"burst, 20MHz,BIN,16384\"";
+        let catalog = parse_catalog(resp).unwrap();
+        assert_eq!((catalog.used_bytes, catalog.free_bytes), (81920, 1234567));
+        assert_eq!(
+            catalog.files,
+            [
+                InstrumentFile { name: "SLOT1".into(), size: 40000 },
+                InstrumentFile { name: "burst, 20MHz".into(), size: 16384 },
+            ]
+        );
+
+        let quoted = parse_catalog("10,20,\"say \"\"hi\"\",BIN,10\"").unwrap();
+        assert_eq!(quoted.files[0].name, "say \"hi\"");
+        assert!(parse_catalog("0,0").unwrap().files.is_empty());
+        assert!(parse_catalog("0,0,\"open,BIN,1").is_err());
+        assert!(parse_catalog("garbage").is_err());
+        assert!(parse_catalog("+0,").is_err());
+
+        assert_eq!(VsgDialect::RohdeSchwarz.waveform_name("a.wv"), Some("a"));
+        assert_eq!(VsgDialect::RohdeSchwarz.waveform_name("notes.txt"), None);
+    }
+
+    #[test]
+    fn wv_packing() {
+        // (32767, 0), two zero samples, (0, -32767): RMS 3.01 dB below full scale
+        let mut wfm = vec![0x7F, 0xFF, 0x00, 0x00];
+        wfm.extend_from_slice(&[0; 8]);
+        wfm.extend_from_slice(&[0x00, 0x00, 0x80, 0x01]);
+        let wv = pack_wv(&wfm, Some(&[1, 1, 0, 0]), 40e6);
+
+        let checksum = WV_CHECKSUM_SEED ^ 0x0000_7FFF ^ 0x8001_0000;
+        let header = format!(
+            "{{TYPE: SMU-WV,{}}}{{COMMENT: wia-waveplay}}{{CLOCK: 40000000}}\
+             {{LEVEL OFFS: 3.010300,0.000000}}{{SAMPLES: 4}}{{MARKER LIST 1: 0:1;2:0}}{{WAVEFORM-17:#",
+            checksum
+        );
+        assert_eq!(String::from_utf8_lossy(&wv[..header.len()]), header);
+        let mut body = vec![0xFF, 0x7F, 0x00, 0x00];
+        body.extend_from_slice(&[0; 8]);
+        body.extend_from_slice(&[0x00, 0x00, 0x01, 0x80, b'}']);
+        assert_eq!(&wv[header.len()..], &body[..]);
+    }
+
+    #[test]
+    fn marker_list_transitions() {
+        assert_eq!(marker_list(&[1, 1, 0, 0, 1]), "0:1;2:0;4:1");
+        assert_eq!(marker_list(&[0, 0]), "0:0");
+    }
+
+    #[test]
+    fn same_instrument_ignores_firmware() {
+        let id = InstrumentIdentity::parse("Agilent Technologies, N5182B, MY53050123, B.01.80");
+        let same = |other| id.same_instrument(&InstrumentIdentity::parse(other));
+        assert!(same("Agilent Technologies,N5182B,MY53050123,B.01.86"));
+        assert!(!same("Agilent Technologies, N5182B, MY53050999, B.01.80"));
+        assert!(!same("Rohde&Schwarz,SMBV100A,1407.6004k02/262195,3.1.19"));
+    }
+
+    #[test]
+    fn identity_parsing() {
+        let id = InstrumentIdentity::parse("  Keysight Technologies , N5182B,MY53050123 ,B.01.80\n");
+        assert_eq!(id.manufacturer, "Keysight Technologies");
+        assert_eq!(id.model, "N5182B");
+        assert_eq!(id.serial, "MY53050123");
+        assert_eq!(id.firmware, "B.01.80");
+        assert_eq!(id.raw, "Keysight Technologies , N5182B,MY53050123 ,B.01.80");
+
+        // Model first: the maker is moved to the front
+        let id = InstrumentIdentity::parse("MG3710A,Anritsu,6200000001,1.0");
+        assert_eq!((id.manufacturer.as_str(), id.model.as_str()), ("Anritsu", "MG3710A"));
+
+        let id = InstrumentIdentity::parse("SMBV100A 262195");
+        assert!(id.is_raw_only());
+        assert_eq!(id.raw, "SMBV100A 262195");
+        assert!(InstrumentIdentity::parse(" , ,").is_raw_only());
+        assert!(!InstrumentModel::from_identity(&id).known);
+
+        // Three fields: no firmware version
+        let id = InstrumentIdentity::parse("Keysight Technologies,N5182B,MY53050123");
+        assert_eq!(id.model, "N5182B");
+        assert_eq!(id.serial, "MY53050123");
+        assert_eq!(id.firmware, "");
+        assert!(InstrumentModel::from_identity(&id).known);
+
+        let id = InstrumentIdentity::parse("Anritsu,MG3710A");
+        assert_eq!((id.manufacturer.as_str(), id.serial.as_str()), ("Anritsu", ""));
+        let id = InstrumentIdentity::parse("Keysight Technologies,N5182B,MY53050123,B.01.80,opt");
+        assert_eq!(id.firmware, "B.01.80,opt");
+    }
+
+    #[test]
+    fn power_limit_includes_cable_loss() {
+        assert!(check_power_limit(-20.0, 5.0, -10.0).is_ok());
+        assert!(check_power_limit(-15.0, 5.0, -10.0).is_ok());
+        let err = check_power_limit(30.0, 2.0, -10.0).unwrap_err();
+        assert!(err.contains("32 dBm") && err.contains("-10 dBm"), "{}", err);
+    }
+
+    #[test]
+    fn raw_commands_are_single_lines() {
+        assert_eq!(check_raw_command("  *idn?\n").unwrap(), "*idn?");
+        assert!(check_raw_command("   ").is_err());
+        assert!(check_raw_command("*rst\n*idn?").is_err());
+    }
+
+    #[test]
+    fn arb_memory_from_catalog_header() {
+        let mxg = model("Agilent Technologies,N5182A,0,1");
+        let unknown = model("Acme,X1,0,1");
+
+        let catalog = parse_catalog("+134217728,+125829120,\"a,BIN,134217728\"").unwrap();
+        let memory = ArbMemory::from_catalog(&catalog, &unknown).unwrap();
+        assert_eq!(memory.total_bytes, 260046848);
+        assert_eq!(memory.free_bytes, 125829120);
+
+        // No used/free counts: fall back to the model's memory
+        let catalog = parse_catalog("0,0,\"a,BIN,1000\"").unwrap();
+        let memory = ArbMemory::from_catalog(&catalog, &mxg).unwrap();
+        assert_eq!(memory.total_bytes, 4 * (64 << 20));
+        assert_eq!(memory.used_bytes, 1000);
+        assert_eq!(ArbMemory::from_catalog(&catalog, &unknown), None);
+
+        let memory = ArbMemory {
+            total_bytes: 400_000_000,
+            used_bytes: 280_000_000,
+            free_bytes: 120_000_000,
+        };
+        let err = memory.check_fits(256_000_000, 0).unwrap_err();
+        assert!(err.contains("256.0 MB") && err.contains("120.0 MB"), "{}", err);
+        assert!(memory.check_fits(256_000_000, 140_000_000).is_ok());
+    }
+
+    #[test]
+    fn channel_commands() {
+        let ks = VsgDialect::Keysight;
+        assert_eq!(ks.on_channel("frequency 2400000000", 1, false), "frequency 2400000000");
+        assert_eq!(ks.on_channel("frequency 2400000000", 1, true), "rf1:frequency 2400000000");
+        assert_eq!(ks.on_channel("output 1", 2, true), "rf2:output 1");
+        assert_eq!(ks.on_channel("*TRG", 2, true), "*TRG");
+        assert_eq!(ks.on_channel("mmemory:delete \"WFM1:a\"", 2, true), "mmemory:delete \"WFM1:a\"");
+        assert_eq!(ks.on_channel("status:operation:condition?", 2, true), "status:operation:condition?");
+
+        let rs = VsgDialect::RohdeSchwarz;
+        assert_eq!(rs.on_channel("SOUR1:BB:ARB:STAT ON", 1, false), "SOUR1:BB:ARB:STAT ON");
+        assert_eq!(rs.on_channel("SOUR1:BB:ARB:STAT ON", 2, false), "SOUR2:BB:ARB:STAT ON");
+        assert_eq!(rs.on_channel("OUTP1 OFF", 2, false), "OUTP2 OFF");
+        assert_eq!(rs.on_channel("MMEM:CAT? \"/var/user\"", 2, false), "MMEM:CAT? \"/var/user\"");
+
+        let vxg = model("Keysight Technologies,M9384B,MY59250001,A.01.00");
+        assert_eq!(vxg.channels, 2);
+        assert!(vxg.check_channel(2).is_ok());
+        assert!(vxg.check_channel(3).is_err());
+        assert!(model("Agilent Technologies,N5182B,0,1").check_channel(2).is_err());
+        assert!(model("Acme,X1,0,1").check_channel(2).is_ok());
+        assert!(model("Acme,X1,0,1").check_channel(0).is_err());
+    }
+
+    #[test]
+    fn self_test_and_options() {
+        assert_eq!(parse_self_test("+0\n").unwrap(), SelfTestResult { passed: true, code: 0 });
+        assert_eq!(parse_self_test("1").unwrap(), SelfTestResult { passed: false, code: 1 });
+        assert!(parse_self_test("").is_err());
+
+        let ks = parse_options("\"1EA,653,UNT\"\n");
+        assert_eq!(ks, ["1EA", "653", "UNT"]);
+        assert!(has_arb_option(&ks));
+        assert!(parse_options("0").is_empty());
+        assert!(!has_arb_option(&parse_options("1EA,UNT")));
+        assert!(has_arb_option(&parse_options("SMBVB10,SMBVK40")));
+        assert!(!has_arb_option(&parse_options("K22")));
+    }
+}
@@ -1,38 +1,671 @@
-use std::path::Path;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 use matfile::{MatFile, NumericData};
 
 const GRAN: usize = 2;
 const MIN_LEN: usize = 60;
+/// 32 MSa, the base ARB memory of the EXG/MXG.
+const MAX_LEN: usize = 32 << 20;
+
+/// Waveform length rules of the ARB that will play the waveform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArbLimits {
+    /// Sample count must be a multiple of this.
+    pub granularity: usize,
+    /// Smallest sample count the ARB accepts.
+    pub min_len: usize,
+    /// Largest segment the ARB memory holds. Only checked where the
+    /// pipeline grows a waveform on request (`repeat_in_buffer`).
+    pub max_len: usize,
+}
+
+impl Default for ArbLimits {
+    /// EXG/MXG limits, which the pipeline assumes when no instrument is known.
+    fn default() -> Self {
+        Self {
+            granularity: GRAN,
+            min_len: MIN_LEN,
+            max_len: MAX_LEN,
+        }
+    }
+}
+
+/// Channel bandwidths the load pipeline accepts, in MHz.
+pub const SUPPORTED_BW_MHZ: [usize; 4] = [20, 40, 80, 160];
 
 #[derive(serde::Serialize, Clone)]
 pub struct WaveformInfo {
     pub file_name: String,
     pub file_size: usize,
     pub sample_count: usize,
+    /// Burst samples in the source file (the selected path for matrices).
+    pub original_sample_count: usize,
+    /// Burst samples kept by `trim`, before decimation, tiling and padding;
+    /// equal to `original_sample_count` when nothing was trimmed.
+    pub trimmed_sample_count: usize,
+    /// Source samples per output sample (1 = not decimated).
+    pub decimation: usize,
+    /// Zero samples appended for the frame interval and granularity.
+    pub padding_samples: usize,
+    /// How many times a short burst was repeated to reach the minimum
+    /// segment length (1 = not tiled).
+    pub tile_count: usize,
+    /// Passes of burst + gap stored back to back by `repeat_in_buffer`
+    /// (1 = a single pass). `pre_gap_samples` and `padding_samples` are
+    /// the gaps of each pass.
+    pub buffer_repeats: usize,
+    /// Zero samples before the burst (`LoadOptions::pre_gap_us`).
+    pub pre_gap_samples: usize,
+    /// Rows (MIMO paths) in the source matrix; 1 for vectors and flat files.
+    pub path_count: usize,
+    /// Quantization of the float samples; `None` for int16 inputs.
+    pub quantization: Option<Quantization>,
+    /// Multiplier applied to the float samples before int16 rounding.
+    /// `None` for pre-quantized .WAVEFORM files without a sidecar.
+    pub scale_factor: Option<f64>,
+    /// ARB sample rate the waveform was built for.
+    pub sample_rate_hz: Option<f64>,
+    /// Sample rate recorded in the source file, for formats that store one
+    /// (R&S .iq.tar).
+    pub source_sample_rate_hz: Option<f64>,
+    /// Playback time of the whole waveform at `sample_rate_hz`.
+    pub duration_us: Option<f64>,
+    /// Playback time of the burst alone, without the idle gap.
+    pub burst_duration_us: Option<f64>,
+    pub bw_mhz: Option<usize>,
+    /// Idle gap appended after the burst, in µs.
+    pub frame_interval_us: Option<f64>,
+    /// File the IQ samples originally came from.
+    pub source_file: Option<String>,
+    /// Levels over the burst alone, before the frame gap is appended.
+    pub levels: LevelStats,
+    /// Levels over the full output including padding, as the VSG plays it.
+    pub padded_levels: LevelStats,
+    /// Percentage of burst samples clipped by `clip_papr_db`.
+    pub clipped_percent: f64,
+    /// IQ samples with I or Q saturated at int16 full scale by the scale
+    /// factor or `gain_db`.
+    pub clamped_samples: usize,
+    /// How far the worst clamped value went past full scale, in dB; `None`
+    /// when nothing clamped.
+    pub clamp_overshoot_db: Option<f64>,
+    /// Offset subtracted by `remove_dc`, if it was requested.
+    pub dc_removed: Option<DcOffset>,
+    /// 99% occupied bandwidth of the burst at `sample_rate_hz`, if
+    /// `measure_obw` was set.
+    pub obw_mhz: Option<f64>,
+    /// Share of burst samples with a non-zero I or Q.
+    pub active_fraction: f64,
+    /// Set when `active_fraction` is below `LoadOptions::min_active_fraction`.
+    pub activity_warning: Option<String>,
+    /// Ratio of the largest to the smallest non-zero sample magnitude in
+    /// the burst, in dB; `None` for a burst without non-zero samples.
+    pub dynamic_range_db: Option<f64>,
+}
+
+impl WaveformInfo {
+    /// Fill `duration_us` and `burst_duration_us` from `sample_rate_hz`;
+    /// `burst_samples` is the part before the idle gap.
+    fn set_durations(&mut self, burst_samples: usize) {
+        let to_us = |n: usize| self.sample_rate_hz.map(|fs| n as f64 / fs * 1e6);
+        self.duration_us = to_us(self.sample_count);
+        self.burst_duration_us = to_us(burst_samples);
+    }
+
+    /// Seconds one pass takes when played at `fs`: the recorded duration
+    /// when the waveform was built for that rate.
+    pub fn duration_secs_at(&self, fs: f64) -> f64 {
+        match (self.duration_us, self.sample_rate_hz) {
+            (Some(us), Some(rate)) if rate == fs => us * 1e-6,
+            _ => self.sample_count as f64 / fs,
+        }
+    }
+
+    /// Fail for a waveform that already repeats in its buffer, whose bursts
+    /// a sequence repeat count would multiply.
+    pub fn check_sequence_repeat(&self) -> Result<(), String> {
+        if self.buffer_repeats > 1 {
+            return Err(format!(
+                "'{}' already holds {} repetitions in its buffer; play it continuously or reload it without repeat_in_buffer",
+                self.file_name, self.buffer_repeats
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Mean I and Q removed from a burst, in source units, and its magnitude
+/// after scaling relative to int16 full scale.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct DcOffset {
+    pub i: f64,
+    pub q: f64,
+    pub dbfs: f64,
+}
+
+/// Signal level statistics of int16 IQ samples, relative to int16 full scale.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct LevelStats {
+    /// Largest |I| or |Q|.
+    pub peak_dbfs: f64,
+    /// Largest instantaneous envelope power I² + Q².
+    pub peak_power_dbfs: f64,
+    /// Mean envelope power.
+    pub rms_dbfs: f64,
+    /// Peak-to-average power ratio (crest factor) in dB.
+    pub papr_db: f64,
+}
+
+/// Decimated magnitude envelope of the loaded waveform for plotting.
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct WaveformPreview {
+    /// IQ samples covered by each bin; bin `k` starts at sample `k * samples_per_bin`.
+    pub samples_per_bin: usize,
+    pub total_samples: usize,
+    /// Smallest |I + jQ| in each bin, relative to int16 full scale.
+    pub min: Vec<f32>,
+    /// Largest |I + jQ| in each bin, relative to int16 full scale.
+    pub max: Vec<f32>,
+}
+
+/// Averaged power spectrum of the loaded waveform, DC in the middle.
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct Spectrum {
+    /// Bin centre in cycles per sample, from -0.5 up to (but excluding) 0.5.
+    pub freq: Vec<f64>,
+    /// Power per bin in dB relative to a full-scale complex tone.
+    pub power_db: Vec<f64>,
+    /// Number of Welch segments averaged.
+    pub segments: usize,
+}
+
+/// How float samples are scaled to int16 in `gen_wfm`.
+#[derive(serde::Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Normalization {
+    /// Python gen_waveform.py heuristic: ×2047, ×443 or ×1 depending on the
+    /// max absolute value, then ×32767/2047.
+    #[default]
+    Legacy,
+    /// Scale so the largest |I| or |Q| hits 32767.
+    Peak,
+    /// Scale so the complex RMS of the burst hits `target_dbfs`; samples that
+    /// would overflow are clamped.
+    Rms { target_dbfs: f64 },
+    /// Multiply by a fixed factor.
+    FixedScale { scale: f64 },
+}
+
+/// How `gen_wfm` turns scaled samples into int16.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Quantization {
+    /// Round to nearest, clamping to the int16 range.
+    #[default]
+    Round,
+    /// Truncate toward zero like the numpy int16 cast in gen_waveform.py,
+    /// for byte-level A/B against its output. Out-of-range values, which
+    /// numpy would wrap, still clamp and are counted.
+    LegacyCompat,
+}
+
+/// Metadata written next to exported waveforms as `<file>.json`, so raw
+/// .WAVEFORM files keep their sample rate and bandwidth.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct WaveformSidecar {
+    pub sample_rate_hz: Option<f64>,
+    pub bw_mhz: Option<usize>,
+    pub frame_interval_us: Option<f64>,
+    pub scale_factor: Option<f64>,
+    pub source_file: Option<String>,
+}
+
+impl WaveformSidecar {
+    pub fn from_info(info: &WaveformInfo) -> Self {
+        Self {
+            sample_rate_hz: info.sample_rate_hz,
+            bw_mhz: info.bw_mhz,
+            frame_interval_us: info.frame_interval_us,
+            scale_factor: info.scale_factor,
+            source_file: info.source_file.clone(),
+        }
+    }
+}
+
+/// Synthetic test signal for `generate_waveform`. Frequencies are baseband
+/// offsets from the carrier in Hz.
+#[derive(serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TestSignal {
+    /// Single complex tone (CW at the carrier plus the offset).
+    Tone { offset_hz: f64 },
+    /// `tones` equal-amplitude tones spaced `spacing_hz` apart, centred on
+    /// the carrier, with Schroeder phases to keep the PAPR low.
+    Multitone { tones: usize, spacing_hz: f64 },
+    /// Linear chirp from -span/2 to +span/2 over the whole duration.
+    Chirp { span_hz: f64 },
+}
+
+/// File format for `export_waveform`.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// The instrument bytes as-is (big-endian interleaved int16).
+    #[default]
+    Raw,
+    /// Two columns `i,q` of signed int16 values with a header row.
+    Csv,
+    /// Level-5 .mat with a 1×N complex double variable `waveform`,
+    /// normalized so int16 full scale is 1.0.
+    Mat,
+}
+
+/// Arrangement of I and Q in raw .WAVEFORM files.
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IqLayout {
+    /// I0 Q0 I1 Q1 …
+    #[default]
+    Interleaved,
+    /// All I samples, then all Q samples.
+    Planar,
+}
+
+/// Part of the source burst to keep, for `LoadOptions::trim`.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "unit", rename_all = "snake_case")]
+pub enum TrimRange {
+    /// `count` samples from sample `start` (0-based); to the end if unset.
+    Samples { start: usize, count: Option<usize> },
+    /// From `start_us` to `stop_us` at the sample rate of the load (2 × BW,
+    /// or the sidecar rate for .WAVEFORM files); to the end if unset.
+    Micros { start_us: f64, stop_us: Option<f64> },
+}
+
+/// Length of the raised-cosine edges for `LoadOptions::ramp`.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "unit", rename_all = "snake_case")]
+pub enum RampLength {
+    Samples { len: usize },
+    /// At fs = 2 × BW, rounded to whole samples.
+    Micros { len_us: f64 },
+}
+
+/// Byte order of multi-byte samples in raw input files.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// Optional knobs of the load pipeline. Every field defaults to the
+/// behavior of a plain load, so the frontend only sends what it changes.
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct LoadOptions {
+    /// Sample byte order for raw float32 (.bin/.iq) and int16 (.WAVEFORM)
+    /// files. Defaults to little-endian for float32 and big-endian for .WAVEFORM.
+    pub endianness: Option<Endianness>,
+    /// I/Q arrangement of .WAVEFORM files.
+    pub iq_layout: IqLayout,
+    /// Variable to load from a .mat file. Defaults to the first numeric
+    /// array with more than one element.
+    pub variable_name: Option<String>,
+    /// Row of an M×N .mat or .npy matrix to load (0-based).
+    pub path_index: usize,
+    /// Keep only this part of the burst; applied before any other processing.
+    /// Sample indices and times refer to the source rate.
+    pub trim: Option<TrimRange>,
+    /// Low-pass and keep every Nth sample of an oversampled capture, so the
+    /// source rate is N × 2 × BW (float formats only).
+    pub decimation: Option<usize>,
+    pub normalization: Normalization,
+    /// Rounding used when the scaled float samples become int16.
+    pub quantization: Quantization,
+    /// Hard-clip the complex magnitude to this PAPR (dB above the burst RMS)
+    /// before quantization. Unset leaves the samples untouched.
+    pub clip_papr_db: Option<f64>,
+    /// Shift the baseband by this many Hz (at fs = 2 × BW) before
+    /// quantization. Must be within ±fs/2.
+    pub freq_offset_hz: Option<f64>,
+    /// Digital gain applied on top of the normalization, in dB. Samples
+    /// pushed past full scale are clamped.
+    pub gain_db: f64,
+    /// Subtract the mean of I and of Q over the burst (float formats only).
+    pub remove_dc: bool,
+    /// Idle time before the burst at fs = 2 × BW, e.g. for the DUT's AGC to
+    /// settle after RF turns on (float formats only). The trailing gap is
+    /// the frame interval passed to the loader.
+    pub pre_gap_us: f64,
+    /// Exchange I and Q. Applied right after `trim`, before `invert_spectrum`,
+    /// so the two together map (I, Q) to (Q, −I).
+    pub swap_iq: bool,
+    /// Mirror the spectrum by negating Q (complex conjugate). Applied after
+    /// `swap_iq` and before everything else but `trim`. An int16 Q of
+    /// −32768 saturates to 32767.
+    pub invert_spectrum: bool,
+    /// Fade the burst in and out with raised-cosine edges of this length,
+    /// so it doesn't switch hard against the frame gap (float formats only).
+    pub ramp: Option<RampLength>,
+    /// Reject bursts shorter than the minimum segment length instead of
+    /// tiling them.
+    pub strict_min_len: bool,
+    /// Fail the load when more than this percentage of burst samples clamp
+    /// during quantization. Unset only reports the count.
+    pub max_clamped_percent: Option<f64>,
+    /// Estimate the occupied bandwidth of the result into
+    /// `WaveformInfo::obw_mhz`.
+    pub measure_obw: bool,
+    /// Warn when less than this share of the burst samples is non-zero.
+    /// Defaults to `MIN_ACTIVE_FRACTION`.
+    pub min_active_fraction: Option<f64>,
+    /// Length rules to pad and validate against; set from the connected
+    /// instrument rather than by the frontend.
+    #[serde(skip)]
+    pub arb: ArbLimits,
+}
+
+/// Summary of one variable in a .mat file, for picking what to load.
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct MatVariable {
+    pub name: String,
+    pub dims: Vec<usize>,
+    pub is_complex: bool,
+}
+
+/// Wi-Fi band a carrier frequency falls into.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Band {
+    #[serde(rename = "2.4g")]
+    Ghz24,
+    #[serde(rename = "5g")]
+    Ghz5,
+    #[serde(rename = "6g")]
+    Ghz6,
+}
+
+impl Band {
+    pub fn from_cf_mhz(cf_mhz: u32) -> Self {
+        if cf_mhz < 5000 {
+            Band::Ghz24
+        } else if cf_mhz < 5955 {
+            Band::Ghz5
+        } else {
+            Band::Ghz6
+        }
+    }
+}
+
+impl std::fmt::Display for Band {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Band::Ghz24 => write!(f, "2.4 GHz"),
+            Band::Ghz5 => write!(f, "5 GHz"),
+            Band::Ghz6 => write!(f, "6 GHz"),
+        }
+    }
+}
+
+/// One entry of the (band, bandwidth) → waveform file mapping.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct WaveformMapEntry {
+    pub band: Band,
+    pub bw_mhz: u32,
+    pub file_path: String,
+    pub frame_interval_us: usize,
+}
+
+/// Find the mapped waveform for a channel.
+pub fn resolve_waveform(
+    mapping: &[WaveformMapEntry],
+    cf_mhz: u32,
+    bw_mhz: u32,
+) -> Result<&WaveformMapEntry, String> {
+    let band = Band::from_cf_mhz(cf_mhz);
+    mapping
+        .iter()
+        .find(|e| e.band == band && e.bw_mhz == bw_mhz)
+        .ok_or_else(|| {
+            format!(
+                "No waveform mapped for band {}, bandwidth {} MHz",
+                band, bw_mhz
+            )
+        })
+}
+
+/// Load a waveform file. Dispatches by extension: .mat, .npy, .bin/.iq or .WAVEFORM.
+pub fn load_waveform_file(
+    file_path: &str,
+    bw_mhz: usize,
+    gap_us: f64,
+    opts: &LoadOptions,
+) -> Result<(Vec<u8>, WaveformInfo), String> {
+    load_waveform_file_with_progress(file_path, bw_mhz, gap_us, opts, &|_, _| {})
 }
 
-/// Load a waveform file. Dispatches by extension: .mat or .WAVEFORM.
-pub fn load_waveform_file(file_path: &str, bw_mhz: usize, frame_interval_us: usize) -> Result<(Vec<u8>, WaveformInfo), String> {
+/// `load_waveform_file`, calling `progress(bytes_read, total_bytes)` as the
+/// file is read.
+pub fn load_waveform_file_with_progress(
+    file_path: &str,
+    bw_mhz: usize,
+    gap_us: f64,
+    opts: &LoadOptions,
+    progress: &dyn Fn(u64, u64),
+) -> Result<(Vec<u8>, WaveformInfo), String> {
     let path = Path::new(file_path);
 
     if !path.exists() {
         return Err(format!("File not found: {}", file_path));
     }
 
+    if !SUPPORTED_BW_MHZ.contains(&bw_mhz) {
+        return Err(format!(
+            "Unsupported bandwidth {} MHz. Supported: {:?}",
+            bw_mhz, SUPPORTED_BW_MHZ
+        ));
+    }
+
+    let key = cache_key(path, bw_mhz, gap_us, opts);
+    if let Some(hit) = key.as_ref().and_then(cache_get) {
+        let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        progress(size, size);
+        return Ok(hit);
+    }
+
     let ext = path
         .extension()
         .and_then(|e| e.to_str())
         .unwrap_or("")
         .to_lowercase();
 
-    match ext.as_str() {
-        "mat" => load_mat_file(path, bw_mhz, frame_interval_us),
-        "waveform" => load_waveform_raw(path),
+    let (wfm_bytes, mut info) = match ext.as_str() {
+        "mat" => load_mat_file(path, bw_mhz, gap_us, opts, progress),
+        "npy" => load_npy_file(path, bw_mhz, gap_us, opts, progress),
+        "bin" | "iq" => load_float32_raw(path, bw_mhz, gap_us, opts, progress),
+        "tar" => load_iq_tar(path, bw_mhz, gap_us, opts, progress),
+        "waveform" => load_waveform_raw(path, opts, progress),
         _ => Err(format!(
-            "Unsupported file format: .{}. Supported: .mat, .WAVEFORM",
+            "Unsupported file format: .{}. Supported: .mat, .npy, .bin, .iq, .iq.tar, .WAVEFORM",
             ext
         )),
+    }?;
+    if info.source_file.is_none() {
+        info.source_file = Some(file_path.to_string());
+    }
+    check_activity(&wfm_bytes, &mut info, opts)?;
+    if let Some(key) = key {
+        cache_put(key, wfm_bytes.clone(), info.clone());
+    }
+    Ok((wfm_bytes, info))
+}
+
+/// Upper bound on the converted bytes held by the load cache.
+const CACHE_MAX_BYTES: usize = 512 << 20;
+
+/// Identifies one conversion: the file as it is on disk plus every
+/// parameter that affects the output.
+#[derive(Clone, Debug, PartialEq)]
+struct CacheKey {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    len: u64,
+    /// .WAVEFORM metadata comes from the sidecar, so it is part of the file.
+    sidecar_modified: Option<SystemTime>,
+    bw_mhz: usize,
+    gap_us: f64,
+    /// `LoadOptions` has float fields, so it's compared by its Debug form.
+    options: String,
+}
+
+struct CacheEntry {
+    key: CacheKey,
+    data: Vec<u8>,
+    info: WaveformInfo,
+}
+
+/// Converted waveforms, least recently used first.
+static CACHE: Mutex<Vec<CacheEntry>> = Mutex::new(Vec::new());
+
+fn cache_key(path: &Path, bw_mhz: usize, gap_us: f64, opts: &LoadOptions) -> Option<CacheKey> {
+    let path = path.canonicalize().ok()?;
+    let meta = std::fs::metadata(&path).ok()?;
+    let sidecar_modified = std::fs::metadata(sidecar_path(&path))
+        .and_then(|m| m.modified())
+        .ok();
+    Some(CacheKey {
+        modified: meta.modified().ok(),
+        len: meta.len(),
+        path,
+        sidecar_modified,
+        bw_mhz,
+        gap_us,
+        options: format!("{:?}", opts),
+    })
+}
+
+fn cache_get(key: &CacheKey) -> Option<(Vec<u8>, WaveformInfo)> {
+    let mut entries = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let pos = entries.iter().position(|e| e.key == *key)?;
+    // Move to the most recently used end
+    let entry = entries.remove(pos);
+    let hit = (entry.data.clone(), entry.info.clone());
+    entries.push(entry);
+    Some(hit)
+}
+
+fn cache_put(key: CacheKey, data: Vec<u8>, info: WaveformInfo) {
+    let mut entries = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache_insert(&mut entries, CacheEntry { key, data, info }, CACHE_MAX_BYTES);
+}
+
+/// Add an entry, dropping stale versions of the same file and then the
+/// least recently used entries until the total fits in `max_bytes`.
+fn cache_insert(entries: &mut Vec<CacheEntry>, entry: CacheEntry, max_bytes: usize) {
+    let (path, modified, len) = (&entry.key.path, entry.key.modified, entry.key.len);
+    entries.retain(|e| e.key.path != *path || (e.key.modified == modified && e.key.len == len));
+    entries.retain(|e| e.key != entry.key);
+    if entry.data.len() > max_bytes {
+        return;
+    }
+    let mut total: usize = entries.iter().map(|e| e.data.len()).sum::<usize>() + entry.data.len();
+    while total > max_bytes {
+        total -= entries.remove(0).data.len();
+    }
+    entries.push(entry);
+}
+
+/// Empty the load cache; returns the number of bytes released.
+pub fn clear_cache() -> usize {
+    let mut entries = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let freed = entries.iter().map(|e| e.data.len()).sum();
+    entries.clear();
+    freed
+}
+
+/// `<file>.json` next to a waveform file.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".json");
+    PathBuf::from(name)
+}
+
+/// Write the metadata sidecar for an exported waveform.
+pub fn write_sidecar(wfm_path: &str, sidecar: &WaveformSidecar) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(sidecar)
+        .map_err(|e| format!("Failed to serialize sidecar: {}", e))?;
+    std::fs::write(sidecar_path(Path::new(wfm_path)), json)
+        .map_err(|e| format!("Failed to write sidecar: {}", e))
+}
+
+/// Read the sidecar of a waveform file; `None` when there is none.
+fn read_sidecar(wfm_path: &Path) -> Result<Option<WaveformSidecar>, String> {
+    let path = sidecar_path(wfm_path);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read sidecar {}: {}", path.display(), e))?;
+    serde_json::from_str(&json)
+        .map(Some)
+        .map_err(|e| format!("Invalid sidecar {}: {}", path.display(), e))
+}
+
+/// Size of the reads behind load progress reporting.
+const READ_CHUNK: usize = 4 << 20;
+
+/// Read a whole file in `READ_CHUNK` pieces, reporting progress after each.
+fn read_file(path: &Path, progress: &dyn Fn(u64, u64)) -> Result<Vec<u8>, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let total = file
+        .metadata()
+        .map_err(|e| format!("Failed to read file: {}", e))?
+        .len();
+
+    let mut reader = ProgressReader::new(file, total, progress);
+    let mut data = Vec::with_capacity(total as usize);
+    reader
+        .read_to_end(&mut data)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    Ok(data)
+}
+
+/// Reader adapter that reads at most `READ_CHUNK` bytes at a time and
+/// reports the running byte count every `READ_CHUNK` bytes and at EOF.
+struct ProgressReader<'a, R> {
+    inner: R,
+    read: u64,
+    reported: u64,
+    total: u64,
+    progress: &'a dyn Fn(u64, u64),
+}
+
+impl<'a, R: Read> ProgressReader<'a, R> {
+    fn new(inner: R, total: u64, progress: &'a dyn Fn(u64, u64)) -> Self {
+        progress(0, total);
+        Self {
+            inner,
+            read: 0,
+            reported: 0,
+            total,
+            progress,
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(READ_CHUNK);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.read += n as u64;
+        if n == 0 || self.read - self.reported >= READ_CHUNK as u64 {
+            self.reported = self.read;
+            (self.progress)(self.read, self.total);
+        }
+        Ok(n)
     }
 }
 
@@ -40,161 +673,3585 @@ pub fn load_waveform_file(file_path: &str, bw_mhz: usize, frame_interval_us: usi
 ///
 /// Mirrors the Python implementation in reference/gen_waveform.py:
 ///   import_mat() -> gen_wfm() -> interleaved big-endian int16 IQ bytes
-fn load_mat_file(path: &Path, bw_mhz: usize, frame_interval_us: usize) -> Result<(Vec<u8>, WaveformInfo), String> {
-    let file =
-        std::fs::File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
-    let mat = MatFile::parse(file).map_err(|e| format!("Failed to parse .mat file: {}", e))?;
-
-    // Find the data variable: skip MATLAB metadata variables (__header__, __version__, etc.)
-    // and pick the first numeric array with more than 1 element.
-    let array = mat
-        .arrays()
-        .iter()
-        .find(|a| {
-            let name = a.name();
-            !(name.starts_with("__") && name.ends_with("__"))
-                && a.size().iter().product::<usize>() > 1
-        })
-        .ok_or("No suitable data array found in .mat file")?;
-
-    let dims = array.size();
-    let (raw_real, raw_imag) = extract_f64_data(array.data())?;
-
-    // Handle multi-dimensional arrays: take only the first row (path1).
-    // MATLAB stores data column-major, so for an M×N matrix the first row
-    // is at indices 0, M, 2M, 3M, …
-    let (mut real, mut imag) = if dims.len() >= 2 && dims[0] > 1 {
-        let num_rows = dims[0];
-        let total_cols: usize = dims[1..].iter().product();
-        let real: Vec<f64> = (0..total_cols).map(|c| raw_real[c * num_rows]).collect();
-        let imag: Vec<f64> = (0..total_cols).map(|c| raw_imag[c * num_rows]).collect();
-        (real, imag)
+fn load_mat_file(
+    path: &Path,
+    bw_mhz: usize,
+    gap_us: f64,
+    opts: &LoadOptions,
+    progress: &dyn Fn(u64, u64),
+) -> Result<(Vec<u8>, WaveformInfo), String> {
+    let variable_name = opts.variable_name.as_deref();
+    let (dims, raw_real, raw_imag, full_scale) = if is_hdf5(path)? {
+        // The HDF5 library does its own I/O; report only completion
+        let array = read_mat_v73(path, variable_name)?;
+        let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        progress(size, size);
+        array
     } else {
-        (raw_real, raw_imag)
+        read_mat_v5(path, variable_name, progress)?
+    };
+
+    let (real, imag, num_rows) = select_mat_path(&dims, raw_real, raw_imag, opts.path_index)?;
+
+    let opts = &prequantized_options(opts, full_scale);
+    let (wfm_bytes, mut info) = finish_iq(display_name(path), real, imag, bw_mhz, gap_us, opts)
+        .map_err(|e| format!("{} (.mat variable is {})", e, shape_string(&dims)))?;
+    info.path_count = num_rows;
+    Ok((wfm_bytes, info))
+}
+
+/// Pick the samples of one path from column-major .mat data; also returns
+/// the number of paths.
+///
+/// A vector (1×N or N×1) is a single path whatever its orientation. Only
+/// when both dimensions exceed 1 is it an M×N matrix of M paths, where row
+/// p is at indices p, M + p, 2M + p, …
+fn select_mat_path(
+    dims: &[usize],
+    real: Vec<f64>,
+    imag: Vec<f64>,
+    path_index: usize,
+) -> Result<(Vec<f64>, Vec<f64>, usize), String> {
+    if real.is_empty() {
+        return Err(format!(".mat variable is {} and holds no samples", shape_string(dims)));
+    }
+    let is_matrix = dims.len() >= 2 && dims[0] > 1 && dims[1..].iter().product::<usize>() > 1;
+    let num_rows = if is_matrix { dims[0] } else { 1 };
+    check_path_index(path_index, num_rows, dims)?;
+    if num_rows == 1 {
+        return Ok((real, imag, 1));
+    }
+    let total_cols: usize = dims[1..].iter().product();
+    let real = (0..total_cols).map(|c| real[c * num_rows + path_index]).collect();
+    let imag = (0..total_cols).map(|c| imag[c * num_rows + path_index]).collect();
+    Ok((real, imag, num_rows))
+}
+
+/// MATLAB-style shape such as "2×1000".
+fn shape_string(dims: &[usize]) -> String {
+    let shape: Vec<String> = dims.iter().map(|d| d.to_string()).collect();
+    shape.join("×")
+}
+
+/// Integer .mat data is already quantized: under the legacy heuristic it
+/// would be scaled again (−32768/32767 lands just above 1.0 and trips the
+/// ×443 branch), so map it straight back to int16 units instead. An explicit
+/// normalization still applies.
+fn prequantized_options(opts: &LoadOptions, full_scale: Option<f64>) -> LoadOptions {
+    let mut opts = opts.clone();
+    if full_scale.is_some() && opts.normalization == Normalization::Legacy {
+        opts.normalization = Normalization::FixedScale { scale: 32767.0 };
+    }
+    opts
+}
+
+/// Append zero samples to big-endian IQ bytes until the length meets the
+/// ARB granularity; returns the samples added. Padding goes at the end,
+/// lengthening the idle gap rather than splitting the burst.
+fn pad_bytes_to_arb(data: &mut Vec<u8>, arb: ArbLimits) -> Result<usize, String> {
+    let len = data.len() / 4;
+    if len < arb.min_len {
+        return Err(format!("Waveform length {} must be at least {}", len, arb.min_len));
+    }
+    let padded = len.next_multiple_of(arb.granularity);
+    data.resize(padded * 4, 0);
+    Ok(padded - len)
+}
+
+/// Share of non-zero burst samples below which a load is flagged.
+pub const MIN_ACTIVE_FRACTION: f64 = 0.05;
+
+/// Fail on a burst that is all zeros or one repeated sample, e.g. a .mat
+/// variable that was never filled, and flag a burst that is mostly zeros.
+/// Sets `active_fraction`, `activity_warning` and `dynamic_range_db`.
+fn check_activity(wfm_data: &[u8], info: &mut WaveformInfo, opts: &LoadOptions) -> Result<(), String> {
+    if info.sample_count == 0 {
+        return Ok(());
+    }
+    // Over the burst alone: the idle gaps are zeros whatever the burst holds
+    let start = info.pre_gap_samples.min(info.sample_count - 1);
+    let end = info.sample_count.saturating_sub(info.padding_samples).max(start + 1);
+    let burst = &wfm_data[start * 4..end * 4];
+
+    let mut samples = decode_iq(burst);
+    let Some(first) = samples.next() else {
+        return Ok(());
     };
+    if samples.all(|s| s == first) {
+        if first == (0, 0) {
+            return Err(format!(
+                "'{}' is all zeros; check the selected variable or path",
+                info.file_name
+            ));
+        }
+        return Err(format!(
+            "'{}' is constant (every sample is I={}, Q={}); check the selected variable or path",
+            info.file_name, first.0, first.1
+        ));
+    }
+
+    let mut active = 0;
+    let (mut min_power, mut max_power) = (f64::INFINITY, 0.0f64);
+    for (i, q) in decode_iq(burst).filter(|&s| s != (0, 0)) {
+        active += 1;
+        let power = (i as f64).powi(2) + (q as f64).powi(2);
+        min_power = min_power.min(power);
+        max_power = max_power.max(power);
+    }
+    info.dynamic_range_db = (active > 0).then(|| 10.0 * (max_power / min_power).log10());
+    let threshold = opts.min_active_fraction.unwrap_or(MIN_ACTIVE_FRACTION);
+    info.active_fraction = active as f64 / (end - start) as f64;
+    info.activity_warning = (info.active_fraction < threshold).then(|| {
+        format!(
+            "Only {:.2}% of the burst samples are non-zero",
+            info.active_fraction * 100.0
+        )
+    });
+    Ok(())
+}
+
+/// Pad an already converted waveform for another ARB, e.g. one loaded
+/// before the instrument was connected. Updates the counts, durations and
+/// padded levels in `info`; returns the samples added.
+pub fn pad_to_arb(data: &mut Vec<u8>, info: &mut WaveformInfo, arb: ArbLimits) -> Result<usize, String> {
+    let len = data.len() / 4;
+    if info.buffer_repeats > 1 && (!len.is_multiple_of(arb.granularity) || len < arb.min_len) {
+        return Err(format!(
+            "'{}' was repeated in its buffer for another ARB; reload it with this instrument connected",
+            info.file_name
+        ));
+    }
+    let added = pad_bytes_to_arb(data, arb)?;
+    if added > 0 {
+        let burst_samples = info.burst_duration_us.zip(info.sample_rate_hz);
+        info.sample_count += added;
+        info.padding_samples += added;
+        info.file_size = data.len();
+        info.padded_levels = level_stats(data);
+        if let Some((us, fs)) = burst_samples {
+            info.set_durations((us * 1e-6 * fs).round() as usize);
+        }
+    }
+    Ok(added)
+}
+
+/// Store `count` back-to-back passes of the waveform, burst and gap, in the
+/// buffer itself for instruments without sequence support. The result
+/// must fit in `arb.max_len`.
+pub fn repeat_in_buffer(
+    data: &mut Vec<u8>,
+    info: &mut WaveformInfo,
+    count: usize,
+    arb: ArbLimits,
+) -> Result<(), String> {
+    if count == 0 {
+        return Err("repeat_in_buffer must be at least 1".into());
+    }
+    let total = info
+        .sample_count
+        .checked_mul(count)
+        .filter(|&n| n <= arb.max_len)
+        .ok_or_else(|| {
+            format!(
+                "Repeating {} samples {} times exceeds the ARB memory of {} samples",
+                info.sample_count, count, arb.max_len
+            )
+        })?;
+    if count == 1 {
+        return Ok(());
+    }
 
-    // Append zeros for frame interval (matches Python: frame_interval_us * BW_Mhz * 2)
-    let zero_count = frame_interval_us * bw_mhz * 2;
-    real.resize(real.len() + zero_count, 0.0);
-    imag.resize(imag.len() + zero_count, 0.0);
+    let mut repeated = Vec::with_capacity(total * 4);
+    for _ in 0..count {
+        repeated.extend_from_slice(data);
+    }
+    *data = repeated;
+
+    info.sample_count = total;
+    info.file_size = data.len();
+    info.buffer_repeats *= count;
+    info.duration_us = info.duration_us.map(|us| us * count as f64);
+    Ok(())
+}
+
+/// Resolve a trim range to `start..end` sample indices of a burst of
+/// `total` samples. `fs` is needed for ranges given in µs.
+fn trim_bounds(range: TrimRange, total: usize, fs: Option<f64>) -> Result<(usize, usize), String> {
+    let (start, end) = match range {
+        TrimRange::Samples { start, count } => {
+            let end = match count {
+                Some(count) => start.saturating_add(count),
+                None => total,
+            };
+            (start, end)
+        }
+        TrimRange::Micros { start_us, stop_us } => {
+            let fs = fs.ok_or(
+                "Trimming by time needs the sample rate, which this file does not record",
+            )?;
+            let to_index = |us: f64| -> Result<usize, String> {
+                if !(us.is_finite() && us >= 0.0) {
+                    return Err(format!("Invalid trim time {} µs", us));
+                }
+                Ok((us * 1e-6 * fs).round() as usize)
+            };
+            let start = to_index(start_us)?;
+            let end = match stop_us {
+                Some(stop_us) => to_index(stop_us)?,
+                None => total,
+            };
+            (start, end)
+        }
+    };
 
-    // Pad for granularity
-    if real.len() % GRAN != 0 {
-        real.push(0.0);
-        imag.push(0.0);
+    if start >= end || end > total {
+        return Err(format!(
+            "Trim range {}..{} is out of bounds: the file has {} samples",
+            start, end, total
+        ));
     }
+    Ok((start, end))
+}
 
-    if real.len() < MIN_LEN {
+/// Check `path_index` against the number of rows of a (possibly 1-D) array.
+fn check_path_index(path_index: usize, num_rows: usize, dims: &[usize]) -> Result<(), String> {
+    if path_index >= num_rows {
         return Err(format!(
-            "Waveform length {} must be at least {}",
-            real.len(),
-            MIN_LEN
+            "Path index {} out of range: array is {} ({} path{})",
+            path_index,
+            shape_string(dims),
+            num_rows,
+            if num_rows == 1 { "" } else { "s" }
         ));
     }
+    Ok(())
+}
 
-    let sample_count = real.len();
-    let wfm_bytes = gen_wfm(&real, &imag);
+/// MATLAB dimensions plus the column-major real/imag data of a .mat
+/// variable, and the integer full scale if the data was pre-quantized.
+type MatArray = (Vec<usize>, Vec<f64>, Vec<f64>, Option<f64>);
 
-    let file_name = path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("unknown")
-        .to_string();
+/// Read the data variable of a level-5 (v4–v7) .mat file.
+fn read_mat_v5(
+    path: &Path,
+    variable_name: Option<&str>,
+    progress: &dyn Fn(u64, u64),
+) -> Result<MatArray, String> {
+    let mat = parse_mat_v5(path, progress)?;
 
-    let info = WaveformInfo {
-        file_name,
-        file_size: wfm_bytes.len(),
-        sample_count,
+    let array = match variable_name {
+        Some(name) => mat.find_by_name(name).ok_or_else(|| {
+            missing_variable(name, mat.arrays().iter().map(|a| a.name()))
+        })?,
+        // Find the data variable: skip MATLAB metadata variables (__header__, __version__, etc.)
+        // and pick the first numeric array with more than 1 element.
+        None => mat
+            .arrays()
+            .iter()
+            .find(|a| !is_mat_metadata(a.name()) && a.size().iter().product::<usize>() > 1)
+            .ok_or("No suitable data array found in .mat file")?,
     };
 
-    Ok((wfm_bytes, info))
+    let (real, imag, full_scale) = extract_f64_data(array.data())?;
+    Ok((array.size().clone(), real, imag, full_scale))
 }
 
-/// Extract real and imaginary parts as Vec<f64> from NumericData.
-fn extract_f64_data(data: &NumericData) -> Result<(Vec<f64>, Vec<f64>), String> {
+fn parse_mat_v5(path: &Path, progress: &dyn Fn(u64, u64)) -> Result<MatFile, String> {
+    let file =
+        std::fs::File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let total = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let reader = std::io::BufReader::new(ProgressReader::new(file, total, progress));
+    MatFile::parse(reader).map_err(|e| format!("Failed to parse .mat file: {}", e))
+}
+
+fn is_mat_metadata(name: &str) -> bool {
+    name.starts_with("__") && name.ends_with("__")
+}
+
+fn missing_variable<'a>(name: &str, available: impl Iterator<Item = &'a str>) -> String {
+    let names: Vec<&str> = available.filter(|n| !is_mat_metadata(n)).collect();
+    format!(
+        "Variable '{}' not found in .mat file. Available: {}",
+        name,
+        names.join(", ")
+    )
+}
+
+/// List the variables of a .mat file (v5 or v7.3) with their dimensions.
+pub fn list_mat_variables(file_path: &str) -> Result<Vec<MatVariable>, String> {
+    let path = Path::new(file_path);
+    if !path.exists() {
+        return Err(format!("File not found: {}", file_path));
+    }
+
+    if is_hdf5(path)? {
+        let file = hdf5::File::open(path)
+            .map_err(|e| format!("Failed to parse v7.3 .mat file: {}", e))?;
+        let names = file
+            .member_names()
+            .map_err(|e| format!("Failed to parse v7.3 .mat file: {}", e))?;
+        Ok(names
+            .iter()
+            .filter(|n| !n.starts_with('#'))
+            .filter_map(|n| {
+                let dataset = file.dataset(n).ok()?;
+                let is_complex = matches!(
+                    dataset.dtype().and_then(|t| t.to_descriptor()),
+                    Ok(hdf5::types::TypeDescriptor::Compound(_))
+                );
+                Some(MatVariable {
+                    name: n.clone(),
+                    dims: dataset.shape().into_iter().rev().collect(),
+                    is_complex,
+                })
+            })
+            .collect())
+    } else {
+        let mat = parse_mat_v5(path, &|_, _| {})?;
+        Ok(mat
+            .arrays()
+            .iter()
+            .filter(|a| !is_mat_metadata(a.name()))
+            .map(|a| MatVariable {
+                name: a.name().to_string(),
+                dims: a.size().clone(),
+                is_complex: is_complex_data(a.data()),
+            })
+            .collect())
+    }
+}
+
+fn is_complex_data(data: &NumericData) -> bool {
     match data {
-        NumericData::Double { real, imag } => {
-            let imag_vec = imag
-                .as_ref()
-                .cloned()
-                .unwrap_or_else(|| vec![0.0; real.len()]);
-            Ok((real.clone(), imag_vec))
+        NumericData::Double { imag, .. } => imag.is_some(),
+        NumericData::Single { imag, .. } => imag.is_some(),
+        NumericData::Int8 { imag, .. } => imag.is_some(),
+        NumericData::UInt8 { imag, .. } => imag.is_some(),
+        NumericData::Int16 { imag, .. } => imag.is_some(),
+        NumericData::UInt16 { imag, .. } => imag.is_some(),
+        NumericData::Int32 { imag, .. } => imag.is_some(),
+        NumericData::UInt32 { imag, .. } => imag.is_some(),
+        NumericData::Int64 { imag, .. } => imag.is_some(),
+        NumericData::UInt64 { imag, .. } => imag.is_some(),
+    }
+}
+
+/// HDF5 signature. MATLAB v7.3 files place it after a 512-byte user block;
+/// the HDF5 spec allows it at offset 0 or any power of two from 512 up.
+const HDF5_MAGIC: &[u8; 8] = b"\x89HDF\r\n\x1a\n";
+
+/// Whether `path` is an HDF5 container (MATLAB -v7.3 .mat file).
+fn is_hdf5(path: &Path) -> Result<bool, String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file =
+        std::fs::File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut magic = [0u8; 8];
+    for offset in [0u64, 512, 1024, 2048] {
+        if offset + 8 > len {
+            break;
         }
-        NumericData::Single { real, imag } => {
-            let real_f64: Vec<f64> = real.iter().map(|&v| v as f64).collect();
-            let imag_f64: Vec<f64> = imag
-                .as_ref()
-                .map(|v| v.iter().map(|&x| x as f64).collect())
-                .unwrap_or_else(|| vec![0.0; real.len()]);
-            Ok((real_f64, imag_f64))
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut magic))
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        if &magic == HDF5_MAGIC {
+            return Ok(true);
         }
-        _ => Err("Unsupported data type in .mat file. Expected double or single precision float.".into()),
     }
+    Ok(false)
+}
+
+/// MATLAB's on-disk layout of a complex element in v7.3 files.
+#[derive(hdf5::H5Type, Clone, Copy)]
+#[repr(C)]
+struct MatComplex {
+    real: f64,
+    imag: f64,
 }
 
-/// Convert real/imag float arrays to interleaved big-endian int16 IQ bytes.
+/// Read the data variable of a MATLAB v7.3 (HDF5) .mat file.
 ///
-/// Mirrors Python gen_waveform.py: trans_wfm() + trans_wfm_iq() + interleave.
-fn gen_wfm(real: &[f64], imag: &[f64]) -> Vec<u8> {
-    // Determine auto-scaling based on max absolute value (mirrors trans_wfm)
-    let max_val = real
-        .iter()
-        .chain(imag.iter())
-        .map(|v| v.abs())
-        .fold(0.0f64, f64::max);
+/// Picks the first top-level dataset with more than one element, skipping
+/// MATLAB's internal `#refs#`/`#subsystem#` groups.
+fn read_mat_v73(path: &Path, variable_name: Option<&str>) -> Result<MatArray, String> {
+    let file = hdf5::File::open(path)
+        .map_err(|e| format!("Failed to parse v7.3 .mat file: {}", e))?;
+    let names = file
+        .member_names()
+        .map_err(|e| format!("Failed to parse v7.3 .mat file: {}", e))?;
 
-    let scale = if max_val < 1.0 {
-        2047.0
-    } else if max_val < 10.0 {
-        443.0
-    } else {
-        1.0
+    let dataset = match variable_name {
+        Some(name) => file.dataset(name).map_err(|_| {
+            missing_variable(name, names.iter().map(String::as_str).filter(|n| !n.starts_with('#')))
+        })?,
+        None => names
+            .iter()
+            .filter(|n| !n.starts_with('#'))
+            .filter_map(|n| file.dataset(n).ok())
+            .find(|d| d.size() > 1)
+            .ok_or("No suitable data array found in .mat file")?,
     };
 
-    // Combined factor: scale * (32767 / 2047), mirrors trans_wfm_iq
-    let factor = scale * 32767.0 / 2047.0;
+    let (real, imag) = match dataset.read_raw::<MatComplex>() {
+        Ok(samples) => samples.iter().map(|c| (c.real, c.imag)).unzip(),
+        Err(_) => {
+            let real = dataset.read_raw::<f64>().map_err(|e| {
+                format!("Unsupported data type in v7.3 .mat file: {}", e)
+            })?;
+            let imag = vec![0.0; real.len()];
+            (real, imag)
+        }
+    };
 
-    // Interleave I/Q as big-endian int16
-    let mut result = Vec::with_capacity(real.len() * 4);
-    for i in 0..real.len() {
-        let i_val = (real[i] * factor).round().clamp(-32768.0, 32767.0) as i16;
-        let q_val = (imag[i] * factor).round().clamp(-32768.0, 32767.0) as i16;
-        result.extend_from_slice(&i_val.to_be_bytes());
-        result.extend_from_slice(&q_val.to_be_bytes());
-    }
+    Ok(v73_array(dataset.shape(), real, imag))
+}
 
-    result
+/// A v7.3 dataset of HDF5 `shape` as a `MatArray`. HDF5 stores the array
+/// row-major with the dimensions reversed, so the flat data is already in
+/// MATLAB's column-major order and only the shape needs reversing.
+fn v73_array(shape: Vec<usize>, real: Vec<f64>, imag: Vec<f64>) -> MatArray {
+    (shape.into_iter().rev().collect(), real, imag, None)
 }
 
-/// Load a pre-formatted .WAVEFORM file (raw big-endian interleaved int16 IQ).
-fn load_waveform_raw(path: &Path) -> Result<(Vec<u8>, WaveformInfo), String> {
-    let data =
-        std::fs::read(path).map_err(|e| format!("Failed to read file: {}", e))?;
+/// Parsed header of a NumPy .npy file.
+struct NpyHeader {
+    /// Type string such as `<c8`, `<c16`, `<f4` or `>f8`.
+    descr: String,
+    fortran_order: bool,
+    shape: Vec<usize>,
+}
 
-    if data.len() < 4 {
-        return Err(
-            "Waveform file is too small (must contain at least one IQ sample pair)".into(),
-        );
+/// Parse the magic, version and dict header of a .npy file.
+///
+/// Returns the header and the offset of the array data.
+fn parse_npy_header(data: &[u8]) -> Result<(NpyHeader, usize), String> {
+    if data.len() < 10 || &data[..6] != b"\x93NUMPY" {
+        return Err("Not a valid .npy file (bad magic)".into());
     }
+    let (header_len, start) = match data[6] {
+        1 => (u16::from_le_bytes([data[8], data[9]]) as usize, 10),
+        2 | 3 if data.len() >= 12 => (
+            u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize,
+            12,
+        ),
+        v => return Err(format!("Unsupported .npy version {}", v)),
+    };
+    let end = start + header_len;
+    let header = data
+        .get(start..end)
+        .and_then(|h| std::str::from_utf8(h).ok())
+        .ok_or("Truncated .npy header")?;
 
-    if data.len() % 4 != 0 {
-        return Err(format!(
-            "Invalid waveform file: size {} is not a multiple of 4 bytes",
-            data.len()
-        ));
-    }
+    // Header is a Python dict literal, e.g.
+    // {'descr': '<c8', 'fortran_order': False, 'shape': (2, 1000), }
+    let value_of = |key: &str| -> Option<&str> {
+        let idx = header.find(&format!("'{}'", key))?;
+        let rest = &header[idx + key.len() + 2..];
+        Some(rest[rest.find(':')? + 1..].trim_start())
+    };
 
-    let file_name = path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("unknown")
+    let descr = value_of("descr")
+        .and_then(|v| v.strip_prefix('\''))
+        .and_then(|v| v.split('\'').next())
+        .ok_or("Missing 'descr' in .npy header")?
         .to_string();
+    let fortran_order = value_of("fortran_order")
+        .map(|v| v.starts_with("True"))
+        .ok_or("Missing 'fortran_order' in .npy header")?;
+    let shape = value_of("shape")
+        .and_then(|v| v.strip_prefix('('))
+        .and_then(|v| v.split(')').next())
+        .ok_or("Missing 'shape' in .npy header")?
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| d.parse::<usize>().map_err(|e| format!("Bad .npy shape '{}': {}", d, e)))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let info = WaveformInfo {
-        file_name,
-        file_size: data.len(),
-        sample_count: data.len() / 4,
+    Ok((
+        NpyHeader {
+            descr,
+            fortran_order,
+            shape,
+        },
+        end,
+    ))
+}
+
+/// Load a NumPy .npy array saved with `np.save`.
+///
+/// Accepts complex64/complex128 arrays and float32/float64 arrays with a
+/// 2×N (I row, Q row) or N×2 (I column, Q column) layout. For 2-D complex
+/// arrays the first dimension is the path index and path 0 is taken, like
+/// the .mat loader.
+fn load_npy_file(
+    path: &Path,
+    bw_mhz: usize,
+    gap_us: f64,
+    opts: &LoadOptions,
+    progress: &dyn Fn(u64, u64),
+) -> Result<(Vec<u8>, WaveformInfo), String> {
+    let data = read_file(path, progress)?;
+    let (header, offset) = parse_npy_header(&data)?;
+
+    let (order, kind) = header.descr.split_at(1);
+    let big_endian = match order {
+        "<" | "=" | "|" => false,
+        ">" => true,
+        _ => return Err(format!("Unsupported .npy dtype '{}'", header.descr)),
     };
+    let elem_size: usize = match kind {
+        "c8" | "f4" => 4,
+        "c16" | "f8" => 8,
+        _ => {
+            return Err(format!(
+                "Unsupported .npy dtype '{}'. Expected complex64, complex128, float32 or float64",
+                header.descr
+            ))
+        }
+    };
+    let is_complex = kind.starts_with('c');
 
-    Ok((data, info))
+    let values: Vec<f64> = data[offset..]
+        .chunks_exact(elem_size)
+        .map(|b| match (elem_size, big_endian) {
+            (4, false) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            (4, true) => f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            (_, false) => f64::from_le_bytes(b.try_into().unwrap()),
+            (_, true) => f64::from_be_bytes(b.try_into().unwrap()),
+        })
+        .collect();
+
+    let count: usize = header.shape.iter().product();
+    let scalars_per_elem = if is_complex { 2 } else { 1 };
+    if values.len() < count * scalars_per_elem {
+        return Err(format!(
+            "Truncated .npy data: shape {:?} needs {} values, file has {}",
+            header.shape,
+            count * scalars_per_elem,
+            values.len()
+        ));
+    }
+
+    // Flat index of element (r, c) of a 2-D array in the file's storage order
+    let (rows, cols) = match header.shape.as_slice() {
+        [n] => (1, *n),
+        [r, c] => (*r, *c),
+        _ => return Err(format!("Unsupported .npy shape {:?}: expected 1-D or 2-D", header.shape)),
+    };
+    let at = |r: usize, c: usize| {
+        if header.fortran_order {
+            c * rows + r
+        } else {
+            r * cols + c
+        }
+    };
+
+    let complex_matrix = is_complex && rows > 1 && cols > 1;
+    let path_count = if complex_matrix { rows } else { 1 };
+    check_path_index(opts.path_index, path_count, &header.shape)?;
+
+    let (real, imag): (Vec<f64>, Vec<f64>) = if is_complex {
+        let elem = |idx: usize| (values[2 * idx], values[2 * idx + 1]);
+        if complex_matrix {
+            (0..cols).map(|c| elem(at(opts.path_index, c))).unzip()
+        } else {
+            (0..count).map(elem).unzip()
+        }
+    } else if rows == 2 {
+        (0..cols).map(|c| (values[at(0, c)], values[at(1, c)])).unzip()
+    } else if cols == 2 {
+        (0..rows).map(|r| (values[at(r, 0)], values[at(r, 1)])).unzip()
+    } else {
+        return Err(format!(
+            "Real-valued .npy arrays must be 2×N or N×2 (I and Q), got shape {:?}",
+            header.shape
+        ));
+    };
+
+    let (wfm_bytes, mut info) = finish_iq(display_name(path), real, imag, bw_mhz, gap_us, opts)?;
+    info.path_count = path_count;
+    Ok((wfm_bytes, info))
+}
+
+/// Load interleaved float32 IQ with no header (e.g. GNU Radio file sinks).
+///
+/// Samples are little-endian unless `opts.endianness` says otherwise.
+fn load_float32_raw(
+    path: &Path,
+    bw_mhz: usize,
+    gap_us: f64,
+    opts: &LoadOptions,
+    progress: &dyn Fn(u64, u64),
+) -> Result<(Vec<u8>, WaveformInfo), String> {
+    let endianness = opts.endianness.unwrap_or(Endianness::Little);
+    let data = read_file(path, progress)?;
+
+    if data.is_empty() || data.len() % 8 != 0 {
+        return Err(format!(
+            "Invalid float32 IQ file: size {} is not a non-zero multiple of 8 bytes",
+            data.len()
+        ));
+    }
+
+    let decode = |b: &[u8]| {
+        let bytes = [b[0], b[1], b[2], b[3]];
+        match endianness {
+            Endianness::Little => f32::from_le_bytes(bytes),
+            Endianness::Big => f32::from_be_bytes(bytes),
+        }
+    };
+    let (real, imag): (Vec<f64>, Vec<f64>) = data
+        .chunks_exact(8)
+        .map(|c| (decode(&c[..4]) as f64, decode(&c[4..]) as f64))
+        .unzip();
+
+    finish_iq(display_name(path), real, imag, bw_mhz, gap_us, opts)
+}
+
+/// Load a Rohde & Schwarz iq-tar capture: a tar holding an XML description
+/// and one binary member of little-endian interleaved I/Q, channel after
+/// channel within each sample. `path_index` selects the channel.
+fn load_iq_tar(
+    path: &Path,
+    bw_mhz: usize,
+    gap_us: f64,
+    opts: &LoadOptions,
+    progress: &dyn Fn(u64, u64),
+) -> Result<(Vec<u8>, WaveformInfo), String> {
+    let data = read_file(path, progress)?;
+    let members = tar_members(&data)?;
+    let invalid = |msg: String| format!("Not a valid iq.tar: {}", msg);
+
+    let xml = members
+        .iter()
+        .find(|(name, _)| name.to_lowercase().ends_with(".xml"))
+        .map(|(_, body)| String::from_utf8_lossy(body))
+        .ok_or_else(|| invalid("no XML metadata member".into()))?;
+    let field = |tag: &str| xml_text(&xml, tag).ok_or_else(|| invalid(format!("missing <{}>", tag)));
+
+    let data_name = field("DataFilename")?;
+    let base_name = |name: &str| name.rsplit('/').next().unwrap_or_default().to_string();
+    let payload = members
+        .iter()
+        .find(|(name, _)| base_name(name) == base_name(data_name))
+        .map(|(_, body)| *body)
+        .ok_or_else(|| {
+            invalid(format!(
+                "the XML names the data member '{}', which the archive does not contain",
+                data_name
+            ))
+        })?;
+    let samples: usize = field("Samples")?
+        .parse()
+        .map_err(|_| invalid("bad <Samples>".into()))?;
+    let clock: f64 = field("Clock")?
+        .parse()
+        .map_err(|_| invalid("bad <Clock>".into()))?;
+    let channels: usize = match xml_text(&xml, "NumberOfChannels") {
+        Some(n) => n.parse().map_err(|_| invalid("bad <NumberOfChannels>".into()))?,
+        None => 1,
+    };
+
+    let format = field("Format")?;
+    let data_type = field("DataType")?;
+    let width = match (format, data_type) {
+        ("complex", "float32") => 4,
+        ("complex", "int16") => 2,
+        _ => {
+            return Err(format!(
+                "Unsupported data format inside iq.tar: {} {} (expected complex float32 or int16)",
+                format, data_type
+            ))
+        }
+    };
+
+    check_path_index(opts.path_index, channels, &[channels, samples])?;
+    let stride = 2 * width * channels;
+    let expected = samples
+        .checked_mul(stride)
+        .ok_or_else(|| invalid(format!("{} samples of {} channels overflow the data size", samples, channels)))?;
+    if channels == 0 || payload.len() < expected {
+        return Err(invalid(format!(
+            "data member holds {} bytes, expected {} for {} samples",
+            payload.len(),
+            expected,
+            samples
+        )));
+    }
+
+    let offset = 2 * width * opts.path_index;
+    let (real, imag): (Vec<f64>, Vec<f64>) = payload[..expected]
+        .chunks_exact(stride)
+        .map(|c| {
+            let c = &c[offset..offset + 2 * width];
+            if width == 4 {
+                let at = |i: usize| f32::from_le_bytes([c[i], c[i + 1], c[i + 2], c[i + 3]]) as f64;
+                (at(0), at(4))
+            } else {
+                let at = |i: usize| i16::from_le_bytes([c[i], c[i + 1]]) as f64 / 32767.0;
+                (at(0), at(2))
+            }
+        })
+        .unzip();
+
+    let full_scale = (width == 2).then_some(32767.0);
+    let opts = &prequantized_options(opts, full_scale);
+    let (wfm_bytes, mut info) = finish_iq(display_name(path), real, imag, bw_mhz, gap_us, opts)?;
+    info.path_count = channels;
+    info.source_sample_rate_hz = Some(clock);
+    Ok((wfm_bytes, info))
+}
+
+/// Regular-file members of a ustar archive as (name, contents).
+fn tar_members(data: &[u8]) -> Result<Vec<(String, &[u8])>, String> {
+    let mut members = Vec::new();
+    let mut pos = 0;
+    while pos + 512 <= data.len() {
+        let header = &data[pos..pos + 512];
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let field = |range: std::ops::Range<usize>| {
+            let raw = &header[range];
+            let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+            String::from_utf8_lossy(&raw[..end]).trim().to_string()
+        };
+        let size = usize::from_str_radix(&field(124..136), 8)
+            .map_err(|_| "Not a valid iq.tar: corrupt tar header".to_string())?;
+        let mut name = field(0..100);
+        let prefix = field(345..500);
+        if !prefix.is_empty() {
+            name = format!("{}/{}", prefix, name);
+        }
+        let body_start = pos + 512;
+        let body_end = body_start + size;
+        if body_end > data.len() {
+            return Err("Not a valid iq.tar: archive is truncated".into());
+        }
+        if matches!(header[156], b'0' | 0) {
+            members.push((name, &data[body_start..body_end]));
+        }
+        pos = body_start + size.next_multiple_of(512);
+    }
+    if members.is_empty() {
+        return Err("Not a valid iq.tar: no files in archive".into());
+    }
+    Ok(members)
+}
+
+/// Trimmed text of the first `<tag …>…</tag>` element in `xml`.
+fn xml_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{}", tag);
+    let mut from = 0;
+    while let Some(i) = xml[from..].find(&open) {
+        let start = from + i + open.len();
+        // Skip longer tag names sharing the prefix
+        if xml[start..].starts_with(['>', ' ', '\t', '\n', '\r']) {
+            let body = start + xml[start..].find('>')? + 1;
+            let end = body + xml[body..].find(&format!("</{}>", tag))?;
+            return Some(xml[body..end].trim());
+        }
+        from = start;
+    }
+    None
+}
+
+/// File name component of `path` for WaveformInfo.
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+/// Shared tail of the float loaders: append the frame gap, pad to
+/// granularity, check the length and quantize. The samples play at
+/// 2 × `bw_mhz`.
+fn finish_iq(
+    file_name: String,
+    real: Vec<f64>,
+    imag: Vec<f64>,
+    bw_mhz: usize,
+    post_gap_us: f64,
+    opts: &LoadOptions,
+) -> Result<(Vec<u8>, WaveformInfo), String> {
+    finish_iq_at(file_name, real, imag, bw_mhz as f64 * 2e6, Some(bw_mhz), post_gap_us, opts)
+}
+
+/// `finish_iq` for samples that play at `fs`, which sizes the gaps, ramps
+/// and frequency shift; `bw_mhz` is only recorded.
+fn finish_iq_at(
+    file_name: String,
+    mut real: Vec<f64>,
+    mut imag: Vec<f64>,
+    fs: f64,
+    bw_mhz: Option<usize>,
+    post_gap_us: f64,
+    opts: &LoadOptions,
+) -> Result<(Vec<u8>, WaveformInfo), String> {
+    if !(fs.is_finite() && fs > 0.0) {
+        return Err(format!("Invalid sample rate {} Hz", fs));
+    }
+    let decimation = opts.decimation.unwrap_or(1);
+    if decimation == 0 {
+        return Err("Decimation factor must be at least 1".into());
+    }
+    let original_len = real.len();
+    if let Some(range) = opts.trim {
+        let source_fs = fs * decimation as f64;
+        let (start, end) = trim_bounds(range, original_len, Some(source_fs))?;
+        real.truncate(end);
+        real.drain(..start);
+        imag.truncate(end);
+        imag.drain(..start);
+    }
+    let trimmed_len = real.len();
+    if opts.swap_iq {
+        std::mem::swap(&mut real, &mut imag);
+    }
+    if opts.invert_spectrum {
+        imag.iter_mut().for_each(|q| *q = -*q);
+    }
+
+    if decimation > 1 {
+        let kept = trimmed_len / decimation;
+        if kept < opts.arb.min_len {
+            return Err(format!(
+                "Decimating {} samples by {} leaves {}; at least {} are needed",
+                trimmed_len, decimation, kept, opts.arb.min_len
+            ));
+        }
+        let taps = decimation_taps(decimation);
+        real = decimate(&real, &taps, decimation);
+        imag = decimate(&imag, &taps, decimation);
+    }
+    let burst_len = real.len();
+
+    // Before anything is appended, so the zero gap stays exactly zero
+    let dc = if opts.remove_dc {
+        Some(remove_dc(&mut real, &mut imag))
+    } else {
+        None
+    };
+
+    let clipped = match opts.clip_papr_db {
+        Some(papr_db) => clip_to_papr(&mut real, &mut imag, papr_db)?,
+        None => 0,
+    };
+
+    // Idle gaps at the ARB sample rate; only the total is rounded up to
+    // the granularity, by the trailing gap
+    let arb = opts.arb;
+    let pre_count = gap_samples(opts.pre_gap_us, fs, 1)?;
+    let zero_count = gap_samples(post_gap_us, fs, arb.granularity)?;
+
+    // Repeat a burst too short for the arb before the gap, so the frame interval stays correct
+    let mut tile_count = 1;
+    let padded_len = (pre_count + burst_len + zero_count).next_multiple_of(arb.granularity);
+    if padded_len < arb.min_len && !opts.strict_min_len && burst_len > 0 {
+        tile_count = tiles_needed(burst_len, arb);
+        real = real.repeat(tile_count);
+        imag = imag.repeat(tile_count);
+    }
+    let tiled_len = real.len();
+
+    // Rotating the zero gap is a no-op, so only the burst is touched; the
+    // phase follows the sample index from the start of the burst
+    if let Some(offset_hz) = opts.freq_offset_hz {
+        freq_shift(&mut real, &mut imag, offset_hz, fs)?;
+    }
+
+    // Over the whole tiled burst: the tiles play back to back, so only the
+    // outer edges meet the zero gap
+    if let Some(ramp) = opts.ramp {
+        let len = match ramp {
+            RampLength::Samples { len } => len,
+            RampLength::Micros { len_us } => {
+                if !(len_us.is_finite() && len_us >= 0.0) {
+                    return Err(format!("Invalid ramp length {} µs", len_us));
+                }
+                (len_us * fs / 1e6).round() as usize
+            }
+        };
+        apply_ramp(&mut real, &mut imag, len)?;
+    }
+
+    // Scale from the burst alone so the idle gap length doesn't shift RMS normalization
+    let mut factor = scale_factor(&real, &imag, &opts.normalization)?;
+    if opts.gain_db != 0.0 {
+        factor *= db_to_gain(opts.gain_db)?;
+    }
+
+    // The granularity pad lengthens the trailing gap, keeping the burst contiguous
+    real.splice(..0, std::iter::repeat_n(0.0, pre_count));
+    imag.splice(..0, std::iter::repeat_n(0.0, pre_count));
+    let padded_len = (real.len() + zero_count).next_multiple_of(arb.granularity);
+    real.resize(padded_len, 0.0);
+    imag.resize(padded_len, 0.0);
+
+    if real.len() < arb.min_len {
+        return Err(format!(
+            "Waveform length {} must be at least {}",
+            real.len(),
+            arb.min_len
+        ));
+    }
+
+    let sample_count = real.len();
+    let (wfm_bytes, clamp) = gen_wfm(&real, &imag, factor, opts.quantization);
+    clamp.check(tiled_len, opts.max_clamped_percent)?;
+    let burst_bytes = &wfm_bytes[pre_count * 4..(pre_count + tiled_len) * 4];
+    let levels = level_stats(burst_bytes);
+    let padded_levels = level_stats(&wfm_bytes);
+    let obw_mhz = if opts.measure_obw {
+        Some(occupied_bandwidth(burst_bytes, OBW_FFT_SIZE)? * fs / 1e6)
+    } else {
+        None
+    };
+
+    let mut info = WaveformInfo {
+        file_name,
+        file_size: wfm_bytes.len(),
+        sample_count,
+        original_sample_count: original_len,
+        trimmed_sample_count: trimmed_len,
+        decimation,
+        padding_samples: sample_count - pre_count - tiled_len,
+        tile_count,
+        buffer_repeats: 1,
+        pre_gap_samples: pre_count,
+        path_count: 1,
+        clamped_samples: clamp.samples,
+        clamp_overshoot_db: clamp.worst_overshoot_db,
+        obw_mhz,
+        active_fraction: 1.0,
+        activity_warning: None,
+        dynamic_range_db: None,
+        dc_removed: dc.map(|(i, q)| DcOffset {
+            i,
+            q,
+            dbfs: to_db(i.hypot(q) * factor / 32767.0),
+        }),
+        quantization: Some(opts.quantization),
+        scale_factor: Some(factor),
+        sample_rate_hz: Some(fs),
+        source_sample_rate_hz: None,
+        duration_us: None,
+        burst_duration_us: None,
+        bw_mhz,
+        frame_interval_us: Some(post_gap_us),
+        source_file: None,
+        clipped_percent: if burst_len > 0 {
+            clipped as f64 * 100.0 / burst_len as f64
+        } else {
+            0.0
+        },
+        levels,
+        padded_levels,
+    };
+    info.set_durations(tiled_len);
+
+    Ok((wfm_bytes, info))
+}
+
+/// Real and imaginary samples plus the integer full scale, if any.
+type MatData = (Vec<f64>, Vec<f64>, Option<f64>);
+
+/// Extract real and imaginary parts as Vec<f64> from NumericData.
+///
+/// Integer arrays are assumed to be pre-quantized and are divided, in f64,
+/// by the full scale of their width (32767 for 16-bit, 2147483647 for
+/// 32-bit), which is returned alongside so the caller can quantize them back
+/// one-to-one. Unsigned arrays are offset binary: their midpoint (32768 or
+/// 2147483648) is subtracted first, so 0 maps to negative full scale.
+fn extract_f64_data(data: &NumericData) -> Result<MatData, String> {
+    fn convert<T: Copy + Into<f64>>(
+        real: &[T],
+        imag: &Option<Vec<T>>,
+        midpoint: f64,
+        full_scale: f64,
+    ) -> (Vec<f64>, Vec<f64>) {
+        let scale = |v: T| (v.into() - midpoint) / full_scale;
+        let real_f64: Vec<f64> = real.iter().map(|&v| scale(v)).collect();
+        let imag_f64: Vec<f64> = imag
+            .as_ref()
+            .map(|v| v.iter().map(|&x| scale(x)).collect())
+            .unwrap_or_else(|| vec![0.0; real.len()]);
+        (real_f64, imag_f64)
+    }
+
+    const INT16_FULL_SCALE: f64 = i16::MAX as f64;
+    const INT32_FULL_SCALE: f64 = i32::MAX as f64;
+    const UINT16_MIDPOINT: f64 = 32768.0;
+    const UINT32_MIDPOINT: f64 = 2147483648.0;
+
+    match data {
+        NumericData::Double { real, imag } => {
+            let imag_vec = imag
+                .as_ref()
+                .cloned()
+                .unwrap_or_else(|| vec![0.0; real.len()]);
+            Ok((real.clone(), imag_vec, None))
+        }
+        NumericData::Single { real, imag } => {
+            let (real, imag) = convert(real, imag, 0.0, 1.0);
+            Ok((real, imag, None))
+        }
+        NumericData::Int16 { real, imag } => {
+            let (real, imag) = convert(real, imag, 0.0, INT16_FULL_SCALE);
+            Ok((real, imag, Some(INT16_FULL_SCALE)))
+        }
+        NumericData::UInt16 { real, imag } => {
+            let (real, imag) = convert(real, imag, UINT16_MIDPOINT, INT16_FULL_SCALE);
+            Ok((real, imag, Some(INT16_FULL_SCALE)))
+        }
+        NumericData::Int32 { real, imag } => {
+            let (real, imag) = convert(real, imag, 0.0, INT32_FULL_SCALE);
+            Ok((real, imag, Some(INT32_FULL_SCALE)))
+        }
+        NumericData::UInt32 { real, imag } => {
+            let (real, imag) = convert(real, imag, UINT32_MIDPOINT, INT32_FULL_SCALE);
+            Ok((real, imag, Some(INT32_FULL_SCALE)))
+        }
+        _ => Err("Unsupported data type in .mat file. Expected double, single, int16 or int32.".into()),
+    }
+}
+
+/// Zero samples for an idle gap of `gap_us` at `fs`, rounded to the nearest
+/// sample and then up to the ARB granularity.
+fn gap_samples(gap_us: f64, fs: f64, granularity: usize) -> Result<usize, String> {
+    if !(gap_us.is_finite() && gap_us >= 0.0) {
+        return Err(format!("Invalid idle gap {} µs", gap_us));
+    }
+    Ok(((gap_us * fs / 1e6).round() as usize).next_multiple_of(granularity))
+}
+
+/// Smallest repeat count that makes `burst_len` samples at least
+/// `arb.min_len` long and a multiple of `arb.granularity`.
+fn tiles_needed(burst_len: usize, arb: ArbLimits) -> usize {
+    let mut k = arb.min_len.div_ceil(burst_len);
+    while !(burst_len * k).is_multiple_of(arb.granularity) {
+        k += 1;
+    }
+    k
+}
+
+/// Multiplier from float samples to int16 units for the given normalization.
+///
+/// The legacy mode mirrors Python gen_waveform.py: trans_wfm() picks a scale
+/// from the max absolute value and trans_wfm_iq() multiplies by 32767/2047.
+fn scale_factor(real: &[f64], imag: &[f64], normalization: &Normalization) -> Result<f64, String> {
+    let max_val = real
+        .iter()
+        .chain(imag.iter())
+        .map(|v| v.abs())
+        .fold(0.0f64, f64::max);
+
+    match *normalization {
+        Normalization::Legacy => {
+            let scale = if max_val < 1.0 {
+                2047.0
+            } else if max_val < 10.0 {
+                443.0
+            } else {
+                1.0
+            };
+            Ok(scale * 32767.0 / 2047.0)
+        }
+        Normalization::Peak => {
+            if max_val == 0.0 {
+                return Err("Cannot peak-normalize an all-zero waveform".into());
+            }
+            Ok(32767.0 / max_val)
+        }
+        Normalization::Rms { target_dbfs } => {
+            let rms = complex_rms(real, imag);
+            if rms == 0.0 {
+                return Err("Cannot RMS-normalize an all-zero waveform".into());
+            }
+            Ok(32767.0 * 10f64.powf(target_dbfs / 20.0) / rms)
+        }
+        Normalization::FixedScale { scale } => {
+            if !(scale.is_finite() && scale > 0.0) {
+                return Err(format!("Invalid fixed scale factor {}", scale));
+            }
+            Ok(scale)
+        }
+    }
+}
+
+/// Subtract the mean of each rail; returns the removed (I, Q) means.
+fn remove_dc(real: &mut [f64], imag: &mut [f64]) -> (f64, f64) {
+    if real.is_empty() {
+        return (0.0, 0.0);
+    }
+    let n = real.len() as f64;
+    let mean_i = real.iter().sum::<f64>() / n;
+    let mean_q = imag.iter().sum::<f64>() / n;
+    real.iter_mut().for_each(|v| *v -= mean_i);
+    imag.iter_mut().for_each(|v| *v -= mean_q);
+    (mean_i, mean_q)
+}
+
+/// Half-length of the decimation filter in output samples.
+const DECIMATION_TAPS_PER_PHASE: usize = 8;
+
+/// Hamming-windowed sinc low-pass with its cutoff at the decimated
+/// Nyquist rate, normalized to unity gain at DC.
+fn decimation_taps(factor: usize) -> Vec<f64> {
+    let half = (DECIMATION_TAPS_PER_PHASE * factor) as isize;
+    let cutoff = 0.5 / factor as f64;
+    let pi = std::f64::consts::PI;
+    let mut taps: Vec<f64> = (-half..=half)
+        .map(|n| {
+            let n = n as f64;
+            let sinc = if n == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * pi * cutoff * n).sin() / (pi * n)
+            };
+            let window = 0.54 + 0.46 * (pi * n / half as f64).cos();
+            sinc * window
+        })
+        .collect();
+    let sum: f64 = taps.iter().sum();
+    taps.iter_mut().for_each(|t| *t /= sum);
+    taps
+}
+
+/// Filter with the centred `taps` (zero outside the burst) and keep every
+/// `factor`th sample starting at the first.
+fn decimate(samples: &[f64], taps: &[f64], factor: usize) -> Vec<f64> {
+    let half = taps.len() / 2;
+    (0..samples.len() / factor)
+        .map(|k| {
+            let centre = k * factor;
+            taps.iter()
+                .enumerate()
+                .filter_map(|(j, t)| {
+                    let idx = (centre + j).checked_sub(half)?;
+                    samples.get(idx).map(|s| s * t)
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// Raised-cosine gain of sample `k` (0-based) of an `len`-sample ramp-up.
+/// Never exactly 0 or 1, so the ramp is all transition.
+fn ramp_gain(k: usize, len: usize) -> f64 {
+    0.5 * (1.0 - (std::f64::consts::PI * (k + 1) as f64 / (len + 1) as f64).cos())
+}
+
+/// Taper the first and last `len` samples with a raised-cosine window,
+/// scaling I and Q alike so the phase is untouched.
+fn apply_ramp(real: &mut [f64], imag: &mut [f64], len: usize) -> Result<(), String> {
+    if len * 2 > real.len() {
+        return Err(format!(
+            "Ramp length {} samples is more than half the {}-sample burst",
+            len,
+            real.len()
+        ));
+    }
+    let n = real.len();
+    for k in 0..len {
+        let gain = ramp_gain(k, len);
+        real[k] *= gain;
+        imag[k] *= gain;
+        real[n - 1 - k] *= gain;
+        imag[n - 1 - k] *= gain;
+    }
+    Ok(())
+}
+
+/// Multiply the samples by exp(j·2π·f·n/fs).
+fn freq_shift(real: &mut [f64], imag: &mut [f64], offset_hz: f64, fs: f64) -> Result<(), String> {
+    if !(offset_hz.is_finite() && offset_hz.abs() < fs / 2.0) {
+        return Err(format!(
+            "Frequency offset {} Hz must be within ±fs/2 = ±{} Hz",
+            offset_hz,
+            fs / 2.0
+        ));
+    }
+    let w = 2.0 * std::f64::consts::PI * offset_hz / fs;
+    for (n, (i, q)) in real.iter_mut().zip(imag.iter_mut()).enumerate() {
+        let (s, c) = (w * n as f64).sin_cos();
+        let (re, im) = (*i * c - *q * s, *i * s + *q * c);
+        *i = re;
+        *q = im;
+    }
+    Ok(())
+}
+
+/// Limit |I + jQ| to `papr_db` above the RMS, scaling I and Q of each
+/// clipped sample by the same factor so its phase is kept.
+///
+/// Returns the number of clipped samples.
+fn clip_to_papr(real: &mut [f64], imag: &mut [f64], papr_db: f64) -> Result<usize, String> {
+    if !(papr_db.is_finite() && papr_db > 0.0) {
+        return Err(format!("Invalid clip PAPR {} dB", papr_db));
+    }
+    let limit = complex_rms(real, imag) * 10f64.powf(papr_db / 20.0);
+    let mut clipped = 0;
+    for (i, q) in real.iter_mut().zip(imag.iter_mut()) {
+        let mag = i.hypot(*q);
+        if mag > limit {
+            let k = limit / mag;
+            *i *= k;
+            *q *= k;
+            clipped += 1;
+        }
+    }
+    Ok(clipped)
+}
+
+/// RMS of the complex samples, sqrt(mean(I² + Q²)).
+fn complex_rms(real: &[f64], imag: &[f64]) -> f64 {
+    if real.is_empty() {
+        return 0.0;
+    }
+    let power: f64 = real.iter().zip(imag).map(|(i, q)| i * i + q * q).sum();
+    (power / real.len() as f64).sqrt()
+}
+
+/// Linear amplitude factor for a gain in dB.
+fn db_to_gain(gain_db: f64) -> Result<f64, String> {
+    if !gain_db.is_finite() {
+        return Err(format!("Invalid gain {} dB", gain_db));
+    }
+    Ok(10f64.powf(gain_db / 20.0))
+}
+
+/// Scale int16 IQ bytes in place by a linear gain, clamping at full scale.
+/// Returns the number of clamped samples.
+fn apply_gain_iq(wfm_data: &mut [u8], gain: f64) -> ClampStats {
+    let mut clamp = ClampStats::default();
+    let mut worst = 1.0;
+    for chunk in wfm_data.chunks_exact_mut(4) {
+        let mut hit = false;
+        for half in chunk.chunks_exact_mut(2) {
+            let v = i16::from_be_bytes([half[0], half[1]]) as f64 * gain;
+            half.copy_from_slice(&quantize(v, Quantization::Round, &mut hit, &mut worst).to_be_bytes());
+        }
+        clamp.samples += hit as usize;
+    }
+    clamp.finish(worst)
+}
+
+/// Saturation seen by `gen_wfm`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct ClampStats {
+    /// IQ samples with I or Q outside the int16 range.
+    samples: usize,
+    /// Largest |value| / full scale among them, in dB.
+    worst_overshoot_db: Option<f64>,
+}
+
+impl ClampStats {
+    /// Record the worst |value| / full scale ratio once counting is done.
+    fn finish(mut self, worst: f64) -> Self {
+        if self.samples > 0 {
+            self.worst_overshoot_db = Some(to_db(worst));
+        }
+        self
+    }
+
+    /// Error when more than `max_percent` of `burst_len` samples clamped.
+    fn check(&self, burst_len: usize, max_percent: Option<f64>) -> Result<(), String> {
+        let Some(limit) = max_percent else {
+            return Ok(());
+        };
+        let percent = self.samples as f64 * 100.0 / burst_len.max(1) as f64;
+        if percent > limit {
+            return Err(format!(
+                "{} of {} samples ({:.2}%) clamp at int16 full scale, worst {:.2} dB over; \
+                 the limit is {}%. Lower the gain or change the normalization.",
+                self.samples,
+                burst_len,
+                percent,
+                self.worst_overshoot_db.unwrap_or(0.0),
+                limit
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Convert real/imag float arrays to interleaved big-endian int16 IQ bytes,
+/// counting the samples that had to be clamped.
+///
+/// Mirrors Python gen_waveform.py: trans_wfm_iq() + interleave.
+fn gen_wfm(real: &[f64], imag: &[f64], factor: f64, mode: Quantization) -> (Vec<u8>, ClampStats) {
+    let mut clamp = ClampStats::default();
+    let mut worst = 1.0;
+
+    // Interleave I/Q as big-endian int16
+    let mut result = Vec::with_capacity(real.len() * 4);
+    for i in 0..real.len() {
+        let mut hit = false;
+        let i_val = quantize(real[i] * factor, mode, &mut hit, &mut worst);
+        let q_val = quantize(imag[i] * factor, mode, &mut hit, &mut worst);
+        clamp.samples += hit as usize;
+        result.extend_from_slice(&i_val.to_be_bytes());
+        result.extend_from_slice(&q_val.to_be_bytes());
+    }
+
+    (result, clamp.finish(worst))
+}
+
+/// Round to int16, saturating. An out-of-range value sets `hit` and raises
+/// `worst` to its magnitude relative to full scale.
+fn quantize(v: f64, mode: Quantization, hit: &mut bool, worst: &mut f64) -> i16 {
+    let r = match mode {
+        Quantization::Round => v.round(),
+        Quantization::LegacyCompat => v.trunc(),
+    };
+    if !(-32768.0..=32767.0).contains(&r) {
+        *hit = true;
+        *worst = worst.max(r.abs() / if r < 0.0 { 32768.0 } else { 32767.0 });
+    }
+    r.clamp(-32768.0, 32767.0) as i16
+}
+
+/// Marker 1 bit in a Keysight marker file byte (bits 0–3 are markers 1–4).
+const MARKER1: u8 = 0x01;
+
+/// Marker file contents: one byte per IQ sample with marker 1 high for the
+/// first `burst_len` samples and low during the appended idle gap.
+pub fn gen_markers(sample_count: usize, burst_len: usize) -> Vec<u8> {
+    let mut markers = vec![MARKER1; burst_len.min(sample_count)];
+    markers.resize(sample_count, 0);
+    markers
+}
+
+/// Marker file for a loaded waveform: `gen_markers` for each pass stored
+/// by `repeat_in_buffer`, low during the leading gap.
+pub fn info_markers(info: &WaveformInfo) -> Vec<u8> {
+    let reps = info.buffer_repeats.max(1);
+    let after_pre = (info.sample_count / reps).saturating_sub(info.pre_gap_samples);
+    let mut pass = vec![0; info.pre_gap_samples];
+    pass.extend(gen_markers(after_pre, after_pre.saturating_sub(info.padding_samples)));
+    pass.repeat(reps)
+}
+
+/// Decode interleaved big-endian int16 IQ bytes back into sample pairs.
+pub fn decode_iq(wfm_data: &[u8]) -> impl Iterator<Item = (i16, i16)> + '_ {
+    wfm_data.chunks_exact(4).map(|c| {
+        (
+            i16::from_be_bytes([c[0], c[1]]),
+            i16::from_be_bytes([c[2], c[3]]),
+        )
+    })
+}
+
+/// Convert a linear ratio to full scale into dB, flooring silence at -200 dBFS.
+fn to_db(ratio: f64) -> f64 {
+    if ratio > 0.0 {
+        20.0 * ratio.log10()
+    } else {
+        -200.0
+    }
+}
+
+/// Peak, envelope peak, RMS and PAPR of waveform bytes.
+pub fn level_stats(wfm_data: &[u8]) -> LevelStats {
+    let full_scale = 32767.0f64;
+    let mut peak = 0i32;
+    let mut peak_power = 0.0f64;
+    let mut power = 0.0f64;
+    let mut count = 0usize;
+    for (i, q) in decode_iq(wfm_data) {
+        peak = peak.max((i as i32).abs()).max((q as i32).abs());
+        let p = (i as f64).powi(2) + (q as f64).powi(2);
+        peak_power = peak_power.max(p);
+        power += p;
+        count += 1;
+    }
+    let mean_power = if count > 0 { power / count as f64 } else { 0.0 };
+    let papr_db = if mean_power > 0.0 {
+        10.0 * (peak_power / mean_power).log10()
+    } else {
+        0.0
+    };
+    LevelStats {
+        peak_dbfs: to_db(peak as f64 / full_scale),
+        peak_power_dbfs: to_db(peak_power.sqrt() / full_scale),
+        rms_dbfs: to_db(mean_power.sqrt() / full_scale),
+        papr_db,
+    }
+}
+
+/// Min/max magnitude envelope of waveform bytes in at most `max_points` bins.
+pub fn waveform_preview(wfm_data: &[u8], max_points: usize) -> Result<WaveformPreview, String> {
+    if max_points == 0 {
+        return Err("Preview needs at least one point".into());
+    }
+    let total_samples = wfm_data.len() / 4;
+    let samples_per_bin = total_samples.div_ceil(max_points).max(1);
+
+    let mut min = Vec::with_capacity(total_samples.div_ceil(samples_per_bin));
+    let mut max = Vec::with_capacity(min.capacity());
+    for chunk in wfm_data.chunks(samples_per_bin * 4) {
+        let (lo, hi) = decode_iq(chunk).fold((f32::MAX, 0.0f32), |(lo, hi), (i, q)| {
+            let mag = (i as f32).hypot(q as f32) / 32767.0;
+            (lo.min(mag), hi.max(mag))
+        });
+        if hi >= lo {
+            min.push(lo);
+            max.push(hi);
+        }
+    }
+
+    Ok(WaveformPreview {
+        samples_per_bin,
+        total_samples,
+        min,
+        max,
+    })
+}
+
+// ---- Test signal generators ----
+
+/// Complex sample at index k.
+type SampleFn = Box<dyn Fn(usize) -> (f64, f64)>;
+
+/// Synthesize a test signal of `duration_us` at `sample_rate_hz`, peak
+/// normalized to full scale. No idle gap is appended.
+pub fn generate_waveform(
+    signal: &TestSignal,
+    duration_us: f64,
+    sample_rate_hz: f64,
+) -> Result<(Vec<u8>, WaveformInfo), String> {
+    if !(sample_rate_hz.is_finite() && sample_rate_hz > 0.0) {
+        return Err(format!("Invalid sample rate {} Hz", sample_rate_hz));
+    }
+    let n = (duration_us * 1e-6 * sample_rate_hz).round();
+    if n.is_nan() || n < MIN_LEN as f64 {
+        return Err(format!(
+            "Duration {} us at {} Hz gives {} samples; at least {} are needed",
+            duration_us, sample_rate_hz, n, MIN_LEN
+        ));
+    }
+    let n = n as usize;
+    let nyquist = sample_rate_hz / 2.0;
+    let two_pi = 2.0 * std::f64::consts::PI;
+
+    let cis = |p: f64| (p.cos(), p.sin());
+    let (name, sample): (String, SampleFn) = match *signal {
+        TestSignal::Tone { offset_hz } => {
+            if offset_hz.abs() >= nyquist {
+                return Err(format!("Tone offset {} Hz must be below fs/2 = {} Hz", offset_hz, nyquist));
+            }
+            let w = two_pi * offset_hz / sample_rate_hz;
+            (
+                format!("tone_{:+.3}MHz", offset_hz / 1e6),
+                Box::new(move |k| cis(w * k as f64)),
+            )
+        }
+        TestSignal::Multitone { tones, spacing_hz } => {
+            let half_span = spacing_hz.abs() * (tones as f64 - 1.0) / 2.0;
+            if tones == 0 || half_span >= nyquist {
+                return Err(format!(
+                    "{} tones at {} Hz spacing must fit within ±{} Hz",
+                    tones, spacing_hz, nyquist
+                ));
+            }
+            let freqs: Vec<f64> = (0..tones)
+                .map(|t| (t as f64 * spacing_hz - half_span) / sample_rate_hz)
+                .collect();
+            (
+                format!("multitone_{}x{:.3}MHz", tones, spacing_hz / 1e6),
+                Box::new(move |k| {
+                    freqs.iter().enumerate().fold((0.0, 0.0), |(i, q), (t, f)| {
+                        let schroeder = std::f64::consts::PI * (t * t) as f64 / tones as f64;
+                        let (ci, cq) = cis(two_pi * f * k as f64 + schroeder);
+                        (i + ci, q + cq)
+                    })
+                }),
+            )
+        }
+        TestSignal::Chirp { span_hz } => {
+            if span_hz.abs() / 2.0 >= nyquist {
+                return Err(format!("Chirp span {} Hz must be below fs = {} Hz", span_hz, sample_rate_hz));
+            }
+            let f0 = -span_hz / 2.0 / sample_rate_hz;
+            let rate = span_hz / sample_rate_hz / n as f64;
+            (
+                format!("chirp_{:.3}MHz", span_hz / 1e6),
+                Box::new(move |k| {
+                    let k = k as f64;
+                    cis(two_pi * (f0 * k + rate * k * k / 2.0))
+                }),
+            )
+        }
+    };
+
+    let (real, imag): (Vec<f64>, Vec<f64>) = (0..n).map(sample).unzip();
+
+    let opts = LoadOptions {
+        normalization: Normalization::Peak,
+        strict_min_len: true,
+        ..Default::default()
+    };
+    let file_name = format!("{}_{}us@{}MSps", name, duration_us, sample_rate_hz / 1e6);
+    let (wfm_bytes, mut info) = finish_iq_at(file_name, real, imag, sample_rate_hz, None, 0.0, &opts)?;
+    info.source_file = Some(info.file_name.clone());
+    Ok((wfm_bytes, info))
+}
+
+// ---- Export ----
+
+/// Write waveform bytes to `path` in the given format.
+pub fn export_waveform(wfm_data: &[u8], path: &str, format: ExportFormat) -> Result<(), String> {
+    let file = std::fs::File::create(path).map_err(|e| format!("Failed to create file: {}", e))?;
+    let mut out = BufWriter::new(file);
+    match format {
+        ExportFormat::Raw => out.write_all(wfm_data),
+        ExportFormat::Csv => write_csv(&mut out, wfm_data),
+        ExportFormat::Mat => write_mat_v5(&mut out, wfm_data),
+    }
+    .and_then(|_| out.flush())
+    .map_err(|e| format!("Failed to write file: {}", e))
+}
+
+/// Write the instantaneous power (I² + Q², in dBFS) of instrument bytes as
+/// CSV, one row per sample or, with `max_points`, the peak of each of up to
+/// that many bins. The first column is the (first) sample index, or its
+/// time in µs when `sample_rate_hz` is given. Rows are streamed to disk.
+pub fn export_envelope(
+    wfm_data: &[u8],
+    path: &str,
+    max_points: Option<usize>,
+    sample_rate_hz: Option<f64>,
+) -> Result<(), String> {
+    let total_samples = wfm_data.len() / 4;
+    let samples_per_bin = match max_points {
+        Some(0) => return Err("Envelope needs at least one point".into()),
+        Some(points) => total_samples.div_ceil(points).max(1),
+        None => 1,
+    };
+
+    let file = std::fs::File::create(path).map_err(|e| format!("Failed to create file: {}", e))?;
+    let mut out = BufWriter::new(file);
+    write_envelope(&mut out, wfm_data, samples_per_bin, sample_rate_hz)
+        .and_then(|_| out.flush())
+        .map_err(|e| format!("Failed to write file: {}", e))
+}
+
+fn write_envelope(
+    out: &mut impl Write,
+    wfm_data: &[u8],
+    samples_per_bin: usize,
+    sample_rate_hz: Option<f64>,
+) -> std::io::Result<()> {
+    match sample_rate_hz {
+        Some(_) => writeln!(out, "time_us,power_dbfs")?,
+        None => writeln!(out, "sample,power_dbfs")?,
+    }
+    for (bin, chunk) in wfm_data.chunks(samples_per_bin * 4).enumerate() {
+        let peak = decode_iq(chunk)
+            .map(|(i, q)| (i as f64).hypot(q as f64))
+            .fold(0.0f64, f64::max);
+        let power_dbfs = to_db(peak / 32767.0);
+        let index = bin * samples_per_bin;
+        match sample_rate_hz {
+            Some(fs) => writeln!(out, "{:.4},{:.2}", index as f64 / fs * 1e6, power_dbfs)?,
+            None => writeln!(out, "{},{:.2}", index, power_dbfs)?,
+        }
+    }
+    Ok(())
+}
+
+/// Sample-level comparison of two int16 IQ waveforms (big-endian
+/// interleaved), e.g. a re-export against a golden file.
+#[derive(serde::Serialize, Clone, Debug, PartialEq)]
+pub struct WaveformDiff {
+    pub sample_count_a: usize,
+    pub sample_count_b: usize,
+    /// Samples within the common length whose I or Q differ.
+    pub differing_samples: usize,
+    /// Largest |a − b| of any I or Q value in the common length, in LSB.
+    pub max_abs_diff: u32,
+    /// Power of a − b relative to the power of the reference `a` over the
+    /// common length; `None` when they match or `a` is silent there.
+    pub rms_error_db: Option<f64>,
+}
+
+/// Compare two .WAVEFORM files, reading both in `READ_CHUNK` pieces.
+pub fn compare_waveform_files(path_a: &str, path_b: &str) -> Result<WaveformDiff, String> {
+    let open = |path: &str| -> Result<(std::fs::File, u64), String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let len = file
+            .metadata()
+            .map_err(|e| format!("Failed to read {}: {}", path, e))?
+            .len();
+        Ok((file, len))
+    };
+    let (file_a, len_a) = open(path_a)?;
+    let (file_b, len_b) = open(path_b)?;
+    compare_streams(file_a, len_a as usize, file_b, len_b as usize)
+}
+
+/// Compare loaded instrument bytes (`b`) against a reference .WAVEFORM
+/// file (`a`).
+pub fn compare_waveform_with_file(wfm_data: &[u8], path: &str) -> Result<WaveformDiff, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let len = file
+        .metadata()
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?
+        .len();
+    compare_streams(file, len as usize, wfm_data, wfm_data.len())
+}
+
+fn compare_streams(
+    mut a: impl Read,
+    len_a: usize,
+    mut b: impl Read,
+    len_b: usize,
+) -> Result<WaveformDiff, String> {
+    for len in [len_a, len_b] {
+        if len % 4 != 0 {
+            return Err(format!(
+                "Invalid waveform file: size {} is not a multiple of 4 bytes",
+                len
+            ));
+        }
+    }
+
+    let mut diff = WaveformDiff {
+        sample_count_a: len_a / 4,
+        sample_count_b: len_b / 4,
+        differing_samples: 0,
+        max_abs_diff: 0,
+        rms_error_db: None,
+    };
+    let (mut error_power, mut ref_power) = (0.0f64, 0.0f64);
+    let mut remaining = len_a.min(len_b);
+    let mut buf_a = vec![0u8; READ_CHUNK.min(remaining)];
+    let mut buf_b = vec![0u8; buf_a.len()];
+    while remaining > 0 {
+        let n = buf_a.len().min(remaining);
+        a.read_exact(&mut buf_a[..n])
+            .and_then(|_| b.read_exact(&mut buf_b[..n]))
+            .map_err(|e| format!("Failed to read waveform: {}", e))?;
+        for ((ia, qa), (ib, qb)) in decode_iq(&buf_a[..n]).zip(decode_iq(&buf_b[..n])) {
+            let (di, dq) = (ia as i32 - ib as i32, qa as i32 - qb as i32);
+            if di != 0 || dq != 0 {
+                diff.differing_samples += 1;
+                diff.max_abs_diff = diff.max_abs_diff.max(di.unsigned_abs()).max(dq.unsigned_abs());
+                error_power += (di * di + dq * dq) as f64;
+            }
+            ref_power += (ia as f64).powi(2) + (qa as f64).powi(2);
+        }
+        remaining -= n;
+    }
+
+    if error_power > 0.0 && ref_power > 0.0 {
+        diff.rms_error_db = Some(10.0 * (error_power / ref_power).log10());
+    }
+    Ok(diff)
+}
+
+/// Stream `i,q` rows so large waveforms never exist as one string.
+fn write_csv(out: &mut impl Write, wfm_data: &[u8]) -> std::io::Result<()> {
+    writeln!(out, "i,q")?;
+    for (i, q) in decode_iq(wfm_data) {
+        writeln!(out, "{},{}", i, q)?;
+    }
+    Ok(())
+}
+
+// MAT-file level 5 data types and array class
+const MI_INT8: u32 = 1;
+const MI_INT32: u32 = 5;
+const MI_UINT32: u32 = 6;
+const MI_DOUBLE: u32 = 9;
+const MI_MATRIX: u32 = 14;
+const MX_DOUBLE_CLASS: u32 = 6;
+const MAT_COMPLEX_FLAG: u32 = 0x0800;
+const MAT_VAR_NAME: &[u8] = b"waveform";
+
+/// Write a little-endian level-5 .mat holding `waveform` as a 1×N complex
+/// double row vector, so `load_mat_file` reads it back as path 0.
+fn write_mat_v5(out: &mut impl Write, wfm_data: &[u8]) -> std::io::Result<()> {
+    let n = wfm_data.len() / 4;
+
+    let mut header = [b' '; 128];
+    let text = b"MATLAB 5.0 MAT-file, Platform: wia-waveplay";
+    header[..text.len()].copy_from_slice(text);
+    header[116..124].fill(0); // no subsystem data
+    header[124..126].copy_from_slice(&0x0100u16.to_le_bytes());
+    header[126..128].copy_from_slice(b"IM");
+    out.write_all(&header)?;
+
+    let tag = |out: &mut dyn Write, ty: u32, len: usize| -> std::io::Result<()> {
+        out.write_all(&ty.to_le_bytes())?;
+        out.write_all(&(len as u32).to_le_bytes())
+    };
+    let name_padded = MAT_VAR_NAME.len().div_ceil(8) * 8;
+    let part_len = n * 8;
+    // flags (8+8) + dims (8+8) + name (8+padded) + real and imag (8+len each)
+    let matrix_len = 16 + 16 + 8 + name_padded + 2 * (8 + part_len);
+
+    tag(out, MI_MATRIX, matrix_len)?;
+    tag(out, MI_UINT32, 8)?;
+    out.write_all(&(MX_DOUBLE_CLASS | MAT_COMPLEX_FLAG).to_le_bytes())?;
+    out.write_all(&[0; 4])?;
+    tag(out, MI_INT32, 8)?;
+    out.write_all(&1i32.to_le_bytes())?;
+    out.write_all(&(n as i32).to_le_bytes())?;
+    tag(out, MI_INT8, MAT_VAR_NAME.len())?;
+    out.write_all(MAT_VAR_NAME)?;
+    out.write_all(&vec![0; name_padded - MAT_VAR_NAME.len()])?;
+
+    tag(out, MI_DOUBLE, part_len)?;
+    for (i, _) in decode_iq(wfm_data) {
+        out.write_all(&(i as f64 / 32767.0).to_le_bytes())?;
+    }
+    tag(out, MI_DOUBLE, part_len)?;
+    for (_, q) in decode_iq(wfm_data) {
+        out.write_all(&(q as f64 / 32767.0).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+// ---- Spectrum analysis ----
+
+/// Welch power spectrum of waveform bytes: Hann window, 50% overlap.
+///
+/// Waveforms shorter than `fft_size` are zero-padded into a single segment.
+pub fn compute_spectrum(wfm_data: &[u8], fft_size: usize) -> Result<Spectrum, String> {
+    let (power, segments) = welch_power(wfm_data, fft_size)?;
+    let freq = (0..fft_size)
+        .map(|k| k as f64 / fft_size as f64 - 0.5)
+        .collect();
+    let power_db = power
+        .iter()
+        .map(|&p| if p > 0.0 { 10.0 * p.log10() } else { -200.0 })
+        .collect();
+
+    Ok(Spectrum {
+        freq,
+        power_db,
+        segments,
+    })
+}
+
+/// Share of the total power inside the occupied bandwidth.
+const OBW_FRACTION: f64 = 0.99;
+
+/// FFT size used for occupied bandwidth estimates.
+pub const OBW_FFT_SIZE: usize = 1024;
+
+/// Largest FFT size accepted, bounding the buffers and the result sent to
+/// the UI.
+pub const MAX_FFT_SIZE: usize = 1 << 16;
+
+/// Occupied bandwidth of instrument bytes as a fraction of the sample rate:
+/// the span between the frequencies below and above which (1 − 99%)/2 of
+/// the averaged spectrum's power lies.
+pub fn occupied_bandwidth(wfm_data: &[u8], fft_size: usize) -> Result<f64, String> {
+    let (power, _) = welch_power(wfm_data, fft_size)?;
+    let total: f64 = power.iter().sum();
+    if total <= 0.0 {
+        return Err("Cannot measure the bandwidth of an all-zero waveform".into());
+    }
+
+    let tail = total * (1.0 - OBW_FRACTION) / 2.0;
+    let mut cumulative = 0.0;
+    let mut lower = 0;
+    let mut upper = fft_size - 1;
+    for (k, p) in power.iter().enumerate() {
+        if cumulative <= tail {
+            lower = k;
+        }
+        cumulative += p;
+        if cumulative < total - tail {
+            upper = k + 1;
+        }
+    }
+    Ok((upper.min(fft_size - 1) - lower + 1) as f64 / fft_size as f64)
+}
+
+/// Welch-averaged power per bin (Hann window, 50% overlap), fftshifted so
+/// bin 0 is −fs/2 and scaled so a full-scale complex tone on a bin centre
+/// reads 1. Also returns the number of segments averaged.
+fn welch_power(wfm_data: &[u8], fft_size: usize) -> Result<(Vec<f64>, usize), String> {
+    if fft_size < 2 || !fft_size.is_power_of_two() || fft_size > MAX_FFT_SIZE {
+        return Err(format!(
+            "FFT size {} must be a power of two from 2 to {}",
+            fft_size, MAX_FFT_SIZE
+        ));
+    }
+    let samples: Vec<(f64, f64)> = decode_iq(wfm_data)
+        .map(|(i, q)| (i as f64 / 32767.0, q as f64 / 32767.0))
+        .collect();
+    if samples.is_empty() {
+        return Err("Waveform is empty".into());
+    }
+
+    let window: Vec<f64> = (0..fft_size)
+        .map(|n| 0.5 - 0.5 * (2.0 * std::f64::consts::PI * n as f64 / fft_size as f64).cos())
+        .collect();
+    // Normalize so a full-scale complex tone on a bin centre reads 0 dB
+    let window_gain: f64 = window.iter().sum::<f64>().powi(2);
+
+    let hop = fft_size / 2;
+    let starts: Vec<usize> = if samples.len() <= fft_size {
+        vec![0]
+    } else {
+        (0..=(samples.len() - fft_size) / hop).map(|k| k * hop).collect()
+    };
+
+    let mut acc = vec![0.0f64; fft_size];
+    let mut re = vec![0.0f64; fft_size];
+    let mut im = vec![0.0f64; fft_size];
+    for &start in &starts {
+        for n in 0..fft_size {
+            let (i, q) = samples.get(start + n).copied().unwrap_or((0.0, 0.0));
+            re[n] = i * window[n];
+            im[n] = q * window[n];
+        }
+        fft_in_place(&mut re, &mut im);
+        for k in 0..fft_size {
+            acc[k] += re[k] * re[k] + im[k] * im[k];
+        }
+    }
+
+    let segments = starts.len();
+    let half = fft_size / 2;
+    // fftshift: negative frequencies first
+    let power = (0..fft_size)
+        .map(|k| acc[(k + half) % fft_size] / segments as f64 / window_gain)
+        .collect();
+    Ok((power, segments))
+}
+
+/// Iterative radix-2 forward FFT; `re.len()` must be a power of two.
+fn fft_in_place(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * std::f64::consts::PI / len as f64;
+        let (w_im, w_re) = angle.sin_cos();
+        for start in (0..n).step_by(len) {
+            let (mut cur_re, mut cur_im) = (1.0, 0.0);
+            for k in 0..len / 2 {
+                let a = start + k;
+                let b = a + len / 2;
+                let t_re = re[b] * cur_re - im[b] * cur_im;
+                let t_im = re[b] * cur_im + im[b] * cur_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+                let next_re = cur_re * w_re - cur_im * w_im;
+                cur_im = cur_re * w_im + cur_im * w_re;
+                cur_re = next_re;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Convert int16 IQ bytes in the given layout to big-endian interleaved.
+/// `data.len()` must be a multiple of 4.
+fn to_canonical_iq(data: Vec<u8>, endianness: Endianness, layout: IqLayout) -> Vec<u8> {
+    if endianness == Endianness::Big && layout == IqLayout::Interleaved {
+        return data;
+    }
+    match layout {
+        IqLayout::Interleaved => {
+            let mut out = Vec::with_capacity(data.len());
+            for v in data.chunks_exact(2) {
+                out.extend_from_slice(&read_i16(v, endianness).to_be_bytes());
+            }
+            out
+        }
+        IqLayout::Planar => {
+            let (i_half, q_half) = data.split_at(data.len() / 2);
+            interleave_iq(i_half, q_half, endianness)
+        }
+    }
+}
+
+fn read_i16(b: &[u8], endianness: Endianness) -> i16 {
+    match endianness {
+        Endianness::Little => i16::from_le_bytes([b[0], b[1]]),
+        Endianness::Big => i16::from_be_bytes([b[0], b[1]]),
+    }
+}
+
+/// Interleave separate int16 I and Q sample runs of equal length into
+/// big-endian I0 Q0 I1 Q1 ….
+fn interleave_iq(i_data: &[u8], q_data: &[u8], endianness: Endianness) -> Vec<u8> {
+    let mut out = Vec::with_capacity(i_data.len() + q_data.len());
+    for (i, q) in i_data.chunks_exact(2).zip(q_data.chunks_exact(2)) {
+        out.extend_from_slice(&read_i16(i, endianness).to_be_bytes());
+        out.extend_from_slice(&read_i16(q, endianness).to_be_bytes());
+    }
+    out
+}
+
+/// Load I and Q from two raw int16 files of equal length, e.g. fixtures
+/// exported one component per file. No sidecar is read.
+pub fn load_waveform_pair(
+    i_path: &str,
+    q_path: &str,
+    opts: &LoadOptions,
+) -> Result<(Vec<u8>, WaveformInfo), String> {
+    let (i_path, q_path) = (Path::new(i_path), Path::new(q_path));
+    let i_data = read_file(i_path, &|_, _| {})?;
+    let q_data = read_file(q_path, &|_, _| {})?;
+
+    if i_data.len() != q_data.len() {
+        return Err(format!(
+            "I and Q files differ in length: {} is {} bytes, {} is {} bytes",
+            display_name(i_path),
+            i_data.len(),
+            display_name(q_path),
+            q_data.len()
+        ));
+    }
+    if i_data.len() < 2 || i_data.len() % 2 != 0 {
+        return Err(format!(
+            "Invalid I/Q file pair: size {} is not a whole number of int16 samples",
+            i_data.len()
+        ));
+    }
+    if opts.decimation.unwrap_or(1) != 1 {
+        return Err("Decimation is not supported for pre-quantized int16 files".into());
+    }
+
+    let data = interleave_iq(&i_data, &q_data, opts.endianness.unwrap_or(Endianness::Big));
+    let file_name = format!("{} + {}", display_name(i_path), display_name(q_path));
+    let (data, mut info) = finish_raw(data, file_name, WaveformSidecar::default(), opts)?;
+    check_activity(&data, &mut info, opts)?;
+    Ok((data, info))
+}
+
+/// Load a raw int16 IQ .WAVEFORM file.
+///
+/// The canonical layout is big-endian interleaved, which the VSG expects;
+/// other layouts described by `opts` are converted to it.
+fn load_waveform_raw(
+    path: &Path,
+    opts: &LoadOptions,
+    progress: &dyn Fn(u64, u64),
+) -> Result<(Vec<u8>, WaveformInfo), String> {
+    let data = read_file(path, progress)?;
+
+    if data.len() < 4 {
+        return Err(
+            "Waveform file is too small (must contain at least one IQ sample pair)".into(),
+        );
+    }
+
+    if data.len() % 4 != 0 {
+        return Err(match opts.iq_layout {
+            IqLayout::Interleaved => format!(
+                "Invalid waveform file: size {} is not a multiple of 4 bytes",
+                data.len()
+            ),
+            IqLayout::Planar => format!(
+                "Invalid planar waveform file: size {} does not split into equal I and Q halves",
+                data.len()
+            ),
+        });
+    }
+
+    if opts.decimation.unwrap_or(1) != 1 {
+        return Err("Decimation is not supported for pre-quantized .WAVEFORM files".into());
+    }
+
+    let data = to_canonical_iq(data, opts.endianness.unwrap_or(Endianness::Big), opts.iq_layout);
+    let sidecar = read_sidecar(path)?.unwrap_or_default();
+    finish_raw(data, display_name(path), sidecar, opts)
+}
+
+/// Apply `LoadOptions::swap_iq` and `invert_spectrum` to canonical int16
+/// IQ bytes in place.
+fn orient_iq_bytes(data: &mut [u8], swap_iq: bool, invert_spectrum: bool) {
+    if !swap_iq && !invert_spectrum {
+        return;
+    }
+    for chunk in data.chunks_exact_mut(4) {
+        let (mut i, mut q) = (
+            i16::from_be_bytes([chunk[0], chunk[1]]),
+            i16::from_be_bytes([chunk[2], chunk[3]]),
+        );
+        if swap_iq {
+            std::mem::swap(&mut i, &mut q);
+        }
+        if invert_spectrum {
+            q = q.saturating_neg();
+        }
+        chunk[..2].copy_from_slice(&i.to_be_bytes());
+        chunk[2..].copy_from_slice(&q.to_be_bytes());
+    }
+}
+
+/// Trim, pad and scale canonical int16 IQ bytes described by `sidecar`.
+fn finish_raw(
+    mut data: Vec<u8>,
+    file_name: String,
+    sidecar: WaveformSidecar,
+    opts: &LoadOptions,
+) -> Result<(Vec<u8>, WaveformInfo), String> {
+    if opts.pre_gap_us != 0.0 {
+        return Err("A leading gap is not supported for pre-quantized int16 files".into());
+    }
+    let original_len = data.len() / 4;
+    if let Some(range) = opts.trim {
+        let (start, end) = trim_bounds(range, original_len, sidecar.sample_rate_hz)?;
+        data.truncate(end * 4);
+        data.drain(..start * 4);
+    }
+    let trimmed_len = data.len() / 4;
+    orient_iq_bytes(&mut data, opts.swap_iq, opts.invert_spectrum);
+    let padding_samples = pad_bytes_to_arb(&mut data, opts.arb)?;
+
+    let clamp = if opts.gain_db != 0.0 {
+        apply_gain_iq(&mut data, db_to_gain(opts.gain_db)?)
+    } else {
+        ClampStats::default()
+    };
+    clamp.check(trimmed_len, opts.max_clamped_percent)?;
+    let levels = level_stats(&data[..trimmed_len * 4]);
+    let padded_levels = level_stats(&data);
+
+    let mut info = WaveformInfo {
+        file_name,
+        file_size: data.len(),
+        sample_count: data.len() / 4,
+        original_sample_count: original_len,
+        trimmed_sample_count: trimmed_len,
+        decimation: 1,
+        padding_samples,
+        tile_count: 1,
+        buffer_repeats: 1,
+        pre_gap_samples: 0,
+        path_count: 1,
+        clamped_samples: clamp.samples,
+        clamp_overshoot_db: clamp.worst_overshoot_db,
+        dc_removed: None,
+        obw_mhz: None,
+        active_fraction: 1.0,
+        activity_warning: None,
+        dynamic_range_db: None,
+        quantization: None,
+        scale_factor: sidecar.scale_factor,
+        sample_rate_hz: sidecar.sample_rate_hz,
+        source_sample_rate_hz: None,
+        duration_us: None,
+        burst_duration_us: None,
+        bw_mhz: sidecar.bw_mhz,
+        frame_interval_us: sidecar.frame_interval_us,
+        source_file: sidecar.source_file,
+        clipped_percent: 0.0,
+        padded_levels,
+        levels,
+    };
+    // The gap length is only known for an uncut file with its sidecar; the
+    // granularity pad may leave this one sample long
+    let burst_samples = match (opts.trim, sidecar.sample_rate_hz, sidecar.frame_interval_us) {
+        (None, Some(fs), Some(gap_us)) => {
+            let gap = gap_samples(gap_us, fs, opts.arb.granularity).unwrap_or(0);
+            info.sample_count.saturating_sub(gap)
+        }
+        _ => trimmed_len,
+    };
+    info.set_durations(burst_samples);
+
+    Ok((data, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quiet(_: u64, _: u64) {}
+
+    /// Write `samples` as interleaved float32 IQ to a temp file.
+    fn write_f32_fixture(name: &str, samples: &[(f32, f32)], be: bool) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let mut bytes = Vec::new();
+        for &(i, q) in samples {
+            if be {
+                bytes.extend_from_slice(&i.to_be_bytes());
+                bytes.extend_from_slice(&q.to_be_bytes());
+            } else {
+                bytes.extend_from_slice(&i.to_le_bytes());
+                bytes.extend_from_slice(&q.to_le_bytes());
+            }
+        }
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn float32_le_converts_to_int16_be() {
+        let mut samples = vec![(0.5f32, -0.25f32), (0.0, 0.125)];
+        samples.resize(MIN_LEN, (0.0, 0.0));
+        let path = write_f32_fixture("wia_f32_le.bin", &samples, false);
+
+        let (bytes, info) = load_float32_raw(&path, 20, 0.0, &LoadOptions::default(), &quiet).unwrap();
+        assert_eq!(info.sample_count, MIN_LEN);
+        // max |x| < 1 → factor 32767: 0.5 → 16384, -0.25 → -8192, 0.125 → 4096
+        assert_eq!(&bytes[..8], &[0x40, 0x00, 0xE0, 0x00, 0x00, 0x00, 0x10, 0x00]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn frame_gap_40mhz_50us() {
+        let samples: Vec<_> = (0..MIN_LEN).map(|k| (0.5f32, if k % 2 == 0 { 0.5 } else { -0.5 })).collect();
+        let path = write_f32_fixture("wia_gap_40m.bin", &samples, false);
+
+        let opts = LoadOptions::default();
+        let (bytes, info) =
+            load_waveform_file(path.to_str().unwrap(), 40, 50.0, &opts).unwrap();
+        // 50 µs at 80 MS/s (2 × 40 MHz) = 4000 zero IQ pairs
+        assert_eq!(info.padding_samples, 4000);
+        assert_eq!(info.sample_count, MIN_LEN + 4000);
+        let tail = &bytes[MIN_LEN * 4..];
+        assert_eq!(tail.len(), 4000 * 4);
+        assert!(tail.iter().all(|&b| b == 0));
+        assert!(bytes[(MIN_LEN - 1) * 4..MIN_LEN * 4].iter().any(|&b| b != 0));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn peak_normalization_hits_full_scale() {
+        let real = [0.1, -0.4, 0.2];
+        let imag = [0.0, 0.3, -0.1];
+        let factor = scale_factor(&real, &imag, &Normalization::Peak).unwrap();
+        let (bytes, _) = gen_wfm(&real, &imag, factor, Quantization::Round);
+        let samples: Vec<(i16, i16)> = decode_iq(&bytes).collect();
+        assert_eq!(samples[1].0, -32767);
+    }
+
+    #[test]
+    fn rms_normalization_hits_target() {
+        let real: Vec<f64> = (0..1000).map(|n| (n as f64 * 0.1).cos()).collect();
+        let imag: Vec<f64> = (0..1000).map(|n| (n as f64 * 0.1).sin()).collect();
+        let norm = Normalization::Rms { target_dbfs: -12.0 };
+        let factor = scale_factor(&real, &imag, &norm).unwrap();
+        let levels = level_stats(&gen_wfm(&real, &imag, factor, Quantization::Round).0);
+        assert!((levels.rms_dbfs + 12.0).abs() < 0.01, "rms {} dBFS", levels.rms_dbfs);
+    }
+
+    /// Sample a tone with `period` samples per cycle at full scale.
+    fn tone_bytes(n: usize, period: f64, complex: bool) -> Vec<u8> {
+        let w = 2.0 * std::f64::consts::PI / period;
+        let real: Vec<f64> = (0..n).map(|k| (w * k as f64).cos()).collect();
+        let imag: Vec<f64> = (0..n)
+            .map(|k| if complex { (w * k as f64).sin() } else { 0.0 })
+            .collect();
+        gen_wfm(&real, &imag, 32767.0, Quantization::Round).0
+    }
+
+    #[test]
+    fn complex_tone_has_flat_envelope() {
+        let levels = level_stats(&tone_bytes(1000, 20.0, true));
+        assert!(levels.papr_db.abs() < 0.01, "papr {}", levels.papr_db);
+        assert!(levels.rms_dbfs.abs() < 0.01);
+        assert!(levels.peak_dbfs.abs() < 0.01);
+    }
+
+    #[test]
+    fn real_tone_papr_is_3db() {
+        // cos on I only: peak power 1, mean power 1/2
+        let levels = level_stats(&tone_bytes(1000, 20.0, false));
+        assert!((levels.papr_db - 3.0103).abs() < 0.01, "papr {}", levels.papr_db);
+        assert!((levels.rms_dbfs + 3.0103).abs() < 0.01);
+    }
+
+    #[test]
+    fn padding_lowers_rms_but_not_peak() {
+        let mut bytes = tone_bytes(1000, 20.0, true);
+        bytes.resize(bytes.len() * 2, 0);
+        let levels = level_stats(&bytes);
+        // Half the samples are zero → mean power halves
+        assert!((levels.rms_dbfs + 3.0103).abs() < 0.01);
+        assert!(levels.peak_power_dbfs.abs() < 0.01);
+        assert!((levels.papr_db - 3.0103).abs() < 0.01);
+    }
+
+    #[test]
+    fn raw_waveform_reports_levels() {
+        let path = std::env::temp_dir().join("wia_levels.WAVEFORM");
+        std::fs::write(&path, tone_bytes(MIN_LEN, 20.0, false)).unwrap();
+        let (_, info) = load_waveform_raw(&path, &LoadOptions::default(), &quiet).unwrap();
+        assert_eq!(info.scale_factor, None);
+        assert!((info.levels.papr_db - 3.0103).abs() < 0.05);
+        assert_eq!(info.levels, info.padded_levels);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn preview_decimates_to_max_points() {
+        let mut bytes = tone_bytes(1000, 20.0, true);
+        bytes.resize(bytes.len() + 1000 * 4, 0);
+        let preview = waveform_preview(&bytes, 300).unwrap();
+        assert_eq!(preview.total_samples, 2000);
+        assert_eq!(preview.samples_per_bin, 7);
+        assert!(preview.max.len() <= 300);
+        assert_eq!(preview.min.len(), preview.max.len());
+        // Burst bins sit at full scale, gap bins are silent
+        assert!((preview.max[0] - 1.0).abs() < 1e-3);
+        assert_eq!(*preview.max.last().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn preview_keeps_short_waveforms_unbinned() {
+        let bytes = tone_bytes(10, 20.0, true);
+        let preview = waveform_preview(&bytes, 1000).unwrap();
+        assert_eq!(preview.samples_per_bin, 1);
+        assert_eq!(preview.max.len(), 10);
+        assert!(waveform_preview(&bytes, 0).is_err());
+    }
+
+    #[test]
+    fn spectrum_finds_tone_bin() {
+        // Period 16 samples → +1/16 cycles/sample
+        let bytes = tone_bytes(4096, 16.0, true);
+        let spec = compute_spectrum(&bytes, 256).unwrap();
+        assert_eq!(spec.segments, 31);
+        assert_eq!(spec.freq.len(), 256);
+        assert_eq!(spec.freq[0], -0.5);
+        let peak = (0..256)
+            .max_by(|&a, &b| spec.power_db[a].total_cmp(&spec.power_db[b]))
+            .unwrap();
+        assert_eq!(spec.freq[peak], 1.0 / 16.0);
+        assert!(spec.power_db[peak].abs() < 0.01, "peak {} dB", spec.power_db[peak]);
+    }
+
+    #[test]
+    fn spectrum_zero_pads_short_waveforms() {
+        let bytes = tone_bytes(100, 16.0, true);
+        let spec = compute_spectrum(&bytes, 512).unwrap();
+        assert_eq!(spec.segments, 1);
+        assert_eq!(spec.power_db.len(), 512);
+        assert!(compute_spectrum(&bytes, 500).is_err());
+        assert!(compute_spectrum(&bytes, MAX_FFT_SIZE).is_ok());
+        let err = compute_spectrum(&bytes, MAX_FFT_SIZE * 2).unwrap_err();
+        assert!(err.contains("65536"), "{}", err);
+        assert!(compute_spectrum(&bytes, 1 << 62).is_err());
+    }
+
+    #[test]
+    fn clip_limits_complex_magnitude() {
+        // One outlier of magnitude 5 among three samples of 0.5
+        let mut real = vec![3.0, 0.5, 0.5, 0.5];
+        let mut imag = vec![4.0, 0.0, 0.0, 0.0];
+        let rms = complex_rms(&real, &imag);
+        let clipped = clip_to_papr(&mut real, &mut imag, 3.0).unwrap();
+        assert_eq!(clipped, 1);
+        let limit = rms * 10f64.powf(3.0 / 20.0);
+        assert!((real[0].hypot(imag[0]) - limit).abs() < 1e-12);
+        // Phase preserved: I/Q ratio unchanged
+        assert!((imag[0] / real[0] - 4.0 / 3.0).abs() < 1e-12);
+        assert_eq!(real[1], 0.5);
+    }
+
+    #[test]
+    fn unset_clip_reports_zero() {
+        let samples = vec![(0.5f32, -0.25f32); MIN_LEN];
+        let path = write_f32_fixture("wia_noclip.bin", &samples, false);
+        let (_, info) = load_float32_raw(&path, 20, 0.0, &LoadOptions::default(), &quiet).unwrap();
+        assert_eq!(info.clipped_percent, 0.0);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn markers_follow_burst() {
+        let samples: Vec<_> = (0..MIN_LEN).map(|k| (0.5f32, if k % 2 == 0 { 0.5 } else { -0.5 })).collect();
+        let path = write_f32_fixture("wia_markers.bin", &samples, false);
+        let (_, info) =
+            load_waveform_file(path.to_str().unwrap(), 20, 1.0, &LoadOptions::default()).unwrap();
+        let markers = gen_markers(info.sample_count, info.sample_count - info.padding_samples);
+        assert_eq!(markers.len(), MIN_LEN + 40);
+        assert!(markers[..MIN_LEN].iter().all(|&m| m == MARKER1));
+        assert!(markers[MIN_LEN..].iter().all(|&m| m == 0));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn planar_le_waveform_round_trips_to_canonical() {
+        let samples: [(i16, i16); 3] = [(1, -2), (0x1234, 0x5678), (-32768, 32767)];
+        let mut planar = Vec::new();
+        for &(i, _) in &samples {
+            planar.extend_from_slice(&i.to_le_bytes());
+        }
+        for &(_, q) in &samples {
+            planar.extend_from_slice(&q.to_le_bytes());
+        }
+        let mut canonical = Vec::new();
+        for &(i, q) in &samples {
+            canonical.extend_from_slice(&i.to_be_bytes());
+            canonical.extend_from_slice(&q.to_be_bytes());
+        }
+
+        let out = to_canonical_iq(planar, Endianness::Little, IqLayout::Planar);
+        assert_eq!(out, canonical);
+        assert_eq!(decode_iq(&out).collect::<Vec<_>>(), samples);
+        // Canonical input passes through untouched
+        let same = to_canonical_iq(canonical.clone(), Endianness::Big, IqLayout::Interleaved);
+        assert_eq!(same, canonical);
+    }
+
+    #[test]
+    fn planar_waveform_rejects_uneven_halves() {
+        let path = std::env::temp_dir().join("wia_planar_bad.WAVEFORM");
+        std::fs::write(&path, [0u8; 6]).unwrap();
+        let opts = LoadOptions {
+            iq_layout: IqLayout::Planar,
+            endianness: Some(Endianness::Little),
+            ..Default::default()
+        };
+        let err = load_waveform_raw(&path, &opts, &quiet).err().unwrap();
+        assert!(err.contains("equal I and Q halves"), "{}", err);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn csv_export_streams_int16_rows() {
+        let bytes = [0x7F, 0xFF, 0x80, 0x00, 0x00, 0x01, 0xFF, 0xFF];
+        let mut out = Vec::new();
+        write_csv(&mut out, &bytes).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "i,q\n32767,-32768\n1,-1\n");
+    }
+
+    #[test]
+    fn mat_export_layout() {
+        let bytes = [0x7F, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01];
+        let mut out = Vec::new();
+        write_mat_v5(&mut out, &bytes).unwrap();
+        let u32_at = |off: usize| u32::from_le_bytes(out[off..off + 4].try_into().unwrap());
+        let f64_at = |off: usize| f64::from_le_bytes(out[off..off + 8].try_into().unwrap());
+
+        assert_eq!(&out[126..128], b"IM");
+        assert_eq!(u32_at(128), MI_MATRIX);
+        assert_eq!(u32_at(132) as usize, out.len() - 136);
+        assert_eq!(u32_at(144), MX_DOUBLE_CLASS | MAT_COMPLEX_FLAG);
+        assert_eq!((u32_at(160), u32_at(164)), (1, 2));
+        assert_eq!(&out[176..184], b"waveform");
+        // real part tag, then I values normalized to full scale
+        assert_eq!((u32_at(184), u32_at(188)), (MI_DOUBLE, 16));
+        assert_eq!(f64_at(192), 1.0);
+        assert_eq!(f64_at(200), 0.0);
+        assert_eq!((u32_at(208), u32_at(212)), (MI_DOUBLE, 16));
+        assert_eq!(f64_at(224), -1.0);
+    }
+
+    #[test]
+    fn short_burst_is_tiled_before_gap() {
+        let samples = vec![(0.5f32, 0.5f32); 7];
+        let path = write_f32_fixture("wia_tile.bin", &samples, false);
+
+        let (bytes, info) = load_float32_raw(&path, 20, 0.0, &LoadOptions::default(), &quiet).unwrap();
+        // 7 × 10 = 70 is the first multiple of 7 that is ≥ 60 and even
+        assert_eq!(info.tile_count, 10);
+        assert_eq!(info.sample_count, 70);
+        assert_eq!(info.padding_samples, 0);
+        assert_eq!(bytes[..7 * 4], bytes[63 * 4..]);
+
+        let strict = LoadOptions {
+            strict_min_len: true,
+            ..Default::default()
+        };
+        assert!(load_float32_raw(&path, 20, 0.0, &strict, &quiet).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn tiled_burst_gets_single_gap() {
+        let samples: Vec<_> = (0..5).map(|k| (0.5f32, if k % 2 == 0 { 0.5 } else { -0.5 })).collect();
+        let path = write_f32_fixture("wia_tile_gap.bin", &samples, false);
+        let opts = LoadOptions::default();
+        // 20 MHz, 1 µs → 40 zero samples: 45 < 60, so tile 5 × 12 = 60 then add the gap once
+        let (bytes, info) =
+            load_waveform_file(path.to_str().unwrap(), 20, 1.0, &opts).unwrap();
+        assert_eq!(info.tile_count, 12);
+        assert_eq!(info.padding_samples, 40);
+        assert_eq!(info.sample_count, 100);
+        assert!(bytes[60 * 4..].iter().all(|&b| b == 0));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn generated_tone_lands_on_its_bin() {
+        // 2.5 MHz at 40 MS/s = 1/16 cycles per sample
+        let signal = TestSignal::Tone { offset_hz: 2.5e6 };
+        let (bytes, info) = generate_waveform(&signal, 102.4, 40e6).unwrap();
+        assert_eq!(info.sample_count, 4096);
+        assert_eq!(info.file_name, "tone_+2.500MHz_102.4us@40MSps");
+        assert!(info.levels.papr_db.abs() < 0.01);
+        let spec = compute_spectrum(&bytes, 256).unwrap();
+        let peak = (0..256)
+            .max_by(|&a, &b| spec.power_db[a].total_cmp(&spec.power_db[b]))
+            .unwrap();
+        assert_eq!(spec.freq[peak], 1.0 / 16.0);
+    }
+
+    #[test]
+    fn generator_validates_parameters() {
+        let tone = |offset_hz| TestSignal::Tone { offset_hz };
+        assert!(generate_waveform(&tone(20e6), 10.0, 40e6).is_err());
+        assert!(generate_waveform(&tone(1e6), 1.0, 40e6).is_err());
+        let comb = TestSignal::Multitone { tones: 9, spacing_hz: 5e6 };
+        assert!(generate_waveform(&comb, 10.0, 40e6).is_err());
+        let comb = TestSignal::Multitone { tones: 8, spacing_hz: 1e6 };
+        let (_, info) = generate_waveform(&comb, 10.0, 40e6).unwrap();
+        assert_eq!(info.sample_count, 400);
+        let chirp = TestSignal::Chirp { span_hz: 30e6 };
+        let (_, info) = generate_waveform(&chirp, 10.0, 40e6).unwrap();
+        assert!(info.levels.papr_db.abs() < 0.01);
+    }
+
+    #[test]
+    fn read_file_reports_chunks() {
+        let path = std::env::temp_dir().join("wia_progress.bin");
+        std::fs::write(&path, vec![7u8; READ_CHUNK * 2 + 10]).unwrap();
+        let calls = std::cell::RefCell::new(Vec::new());
+        let data = read_file(&path, &|read, total| calls.borrow_mut().push((read, total))).unwrap();
+        assert_eq!(data.len(), READ_CHUNK * 2 + 10);
+        let total = data.len() as u64;
+        let calls = calls.into_inner();
+        assert_eq!(calls.first(), Some(&(0, total)));
+        assert_eq!(calls.last(), Some(&(total, total)));
+        assert!(calls.windows(2).all(|w| w[0].0 <= w[1].0));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sidecar_sits_next_to_the_waveform() {
+        let path = Path::new("/data/run1/ofdm.WAVEFORM");
+        assert_eq!(sidecar_path(path), PathBuf::from("/data/run1/ofdm.WAVEFORM.json"));
+    }
+
+    #[test]
+    fn raw_waveform_without_sidecar_has_no_rate() {
+        let path = std::env::temp_dir().join("wia_nosidecar.WAVEFORM");
+        std::fs::write(&path, tone_bytes(MIN_LEN, 20.0, true)).unwrap();
+        let (_, info) =
+            load_waveform_file(path.to_str().unwrap(), 20, 0.0, &LoadOptions::default()).unwrap();
+        assert_eq!(info.sample_rate_hz, None);
+        assert_eq!(info.bw_mhz, None);
+        assert_eq!(info.source_file.as_deref(), path.to_str());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn freq_offset_moves_dc_to_the_offset() {
+        // DC burst at 20 MHz BW (fs = 40 MS/s) shifted by +2.5 MHz = 1/16 cycles per sample
+        let samples = vec![(0.5f32, 0.0f32); 4096];
+        let path = write_f32_fixture("wia_shift.bin", &samples, false);
+        let opts = LoadOptions {
+            freq_offset_hz: Some(2.5e6),
+            ..Default::default()
+        };
+        let (bytes, _) = load_float32_raw(&path, 20, 0.0, &opts, &quiet).unwrap();
+        let spec = compute_spectrum(&bytes, 256).unwrap();
+        let peak = (0..256)
+            .max_by(|&a, &b| spec.power_db[a].total_cmp(&spec.power_db[b]))
+            .unwrap();
+        assert_eq!(spec.freq[peak], 1.0 / 16.0);
+
+        let too_far = LoadOptions {
+            freq_offset_hz: Some(-20e6),
+            ..Default::default()
+        };
+        let err = load_float32_raw(&path, 20, 0.0, &too_far, &quiet).err().unwrap();
+        assert!(err.contains("±fs/2"), "{}", err);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn gain_clamps_and_counts() {
+        let samples = vec![(0.5f32, -0.25f32), (0.9, 0.0), (0.1, 0.1)];
+        let mut padded = samples.clone();
+        padded.resize(MIN_LEN, (0.0, 0.0));
+        let path = write_f32_fixture("wia_gain.bin", &padded, false);
+
+        let (plain, info) = load_float32_raw(&path, 20, 0.0, &LoadOptions::default(), &quiet).unwrap();
+        assert_eq!(info.clamped_samples, 0);
+        let zero_db = LoadOptions {
+            gain_db: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(load_float32_raw(&path, 20, 0.0, &zero_db, &quiet).unwrap().0, plain);
+
+        // +6.02 dB doubles: 0.9 → 1.8 saturates, 0.5 → 1.0 rounds to 32767 (in range)
+        let loud = LoadOptions {
+            gain_db: 20.0 * 2f64.log10(),
+            ..Default::default()
+        };
+        let (bytes, info) = load_float32_raw(&path, 20, 0.0, &loud, &quiet).unwrap();
+        assert_eq!(info.clamped_samples, 1);
+        let iq: Vec<(i16, i16)> = decode_iq(&bytes).take(2).collect();
+        assert_eq!(iq, vec![(32767, -16384), (32767, 0)]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn gain_on_raw_waveform() {
+        let mut bytes = Vec::new();
+        for (i, q) in [(20000i16, -100i16), (1000, 1000)] {
+            bytes.extend_from_slice(&i.to_be_bytes());
+            bytes.extend_from_slice(&q.to_be_bytes());
+        }
+        let clamp = apply_gain_iq(&mut bytes, 2.0);
+        assert_eq!(clamp.samples, 1);
+        let iq: Vec<(i16, i16)> = decode_iq(&bytes).collect();
+        assert_eq!(iq, vec![(32767, -200), (2000, 2000)]);
+    }
+
+    #[test]
+    fn remove_dc_keeps_gap_zero() {
+        let samples: Vec<(f32, f32)> = (0..MIN_LEN)
+            .map(|n| (if n % 2 == 0 { 0.3 } else { 0.1 }, -0.05))
+            .collect();
+        let path = write_f32_fixture("wia_dc.bin", &samples, false);
+        let opts = LoadOptions {
+            remove_dc: true,
+            ..Default::default()
+        };
+        let (bytes, info) =
+            load_waveform_file(path.to_str().unwrap(), 20, 1.0, &opts).unwrap();
+        let dc = info.dc_removed.unwrap();
+        assert!((dc.i - 0.2).abs() < 1e-6 && (dc.q + 0.05).abs() < 1e-6);
+        // ±0.1 around zero on I, nothing left on Q
+        let iq: Vec<(i16, i16)> = decode_iq(&bytes).collect();
+        assert_eq!(iq[0], (3277, 0));
+        assert_eq!(iq[1], (-3277, 0));
+        assert!(bytes[MIN_LEN * 4..].iter().all(|&b| b == 0));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn legacy_normalization_factors() {
+        let legacy = |max: f64| scale_factor(&[max], &[0.0], &Normalization::Legacy).unwrap();
+        assert_eq!(legacy(0.5), 32767.0);
+        assert_eq!(legacy(5.0), 443.0 * 32767.0 / 2047.0);
+        assert_eq!(legacy(50.0), 32767.0 / 2047.0);
+    }
+
+    #[test]
+    fn rejects_unsupported_bandwidth() {
+        let samples = vec![(0.5f32, 0.5f32); MIN_LEN];
+        let path = write_f32_fixture("wia_bad_bw.bin", &samples, false);
+        let opts = LoadOptions::default();
+        assert!(load_waveform_file(path.to_str().unwrap(), 30, 0.0, &opts).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn float32_be_matches_le() {
+        let mut samples = vec![(0.5f32, -0.25f32)];
+        samples.resize(MIN_LEN, (0.0, 0.0));
+        let le = write_f32_fixture("wia_f32_cmp_le.iq", &samples, false);
+        let be = write_f32_fixture("wia_f32_cmp_be.iq", &samples, true);
+
+        let be_opts = LoadOptions {
+            endianness: Some(Endianness::Big),
+            ..Default::default()
+        };
+        let (le_bytes, _) = load_float32_raw(&le, 20, 0.0, &LoadOptions::default(), &quiet).unwrap();
+        let (be_bytes, _) = load_float32_raw(&be, 20, 0.0, &be_opts, &quiet).unwrap();
+        assert_eq!(le_bytes, be_bytes);
+        std::fs::remove_file(le).unwrap();
+        std::fs::remove_file(be).unwrap();
+    }
+
+    /// Build a version 1.0 .npy file from a header dict and raw data bytes.
+    fn npy_bytes(dict: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = dict.to_string();
+        // Pad so magic + len + header is a multiple of 64, ending in '\n'
+        while !(10 + header.len() + 1).is_multiple_of(64) {
+            header.push(' ');
+        }
+        header.push('\n');
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn load_npy_fixture(name: &str, bytes: &[u8]) -> Vec<u8> {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        let (wfm, info) = load_npy_file(&path, 20, 0.0, &LoadOptions::default(), &quiet).unwrap();
+        assert_eq!(info.sample_count, MIN_LEN);
+        std::fs::remove_file(path).unwrap();
+        wfm
+    }
+
+    #[test]
+    fn npy_complex64() {
+        let mut data = Vec::new();
+        for n in 0..MIN_LEN {
+            let (i, q) = if n == 0 { (0.5f32, -0.25f32) } else { (0.0, 0.0) };
+            data.extend_from_slice(&i.to_le_bytes());
+            data.extend_from_slice(&q.to_le_bytes());
+        }
+        let dict = format!("{{'descr': '<c8', 'fortran_order': False, 'shape': ({},), }}", MIN_LEN);
+        let wfm = load_npy_fixture("wia_c8.npy", &npy_bytes(&dict, &data));
+        assert_eq!(&wfm[..4], &[0x40, 0x00, 0xE0, 0x00]);
+    }
+
+    #[test]
+    fn npy_complex128_takes_path0() {
+        // shape (2, N), C order: path 0 is the first N elements
+        let mut data = Vec::new();
+        for path in 0..2 {
+            for n in 0..MIN_LEN {
+                let (i, q) = match (path, n) {
+                    (0, 0) => (0.5f64, -0.25f64),
+                    (1, _) => (0.9, 0.9),
+                    _ => (0.0, 0.0),
+                };
+                data.extend_from_slice(&i.to_le_bytes());
+                data.extend_from_slice(&q.to_le_bytes());
+            }
+        }
+        let dict = format!("{{'descr': '<c16', 'fortran_order': False, 'shape': (2, {}), }}", MIN_LEN);
+        let wfm = load_npy_fixture("wia_c16.npy", &npy_bytes(&dict, &data));
+        assert_eq!(&wfm[..8], &[0x40, 0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        // Path 1 is selectable, path 2 is out of range
+        let path = std::env::temp_dir().join("wia_c16_path1.npy");
+        std::fs::write(&path, npy_bytes(&dict, &data)).unwrap();
+        let mut opts = LoadOptions {
+            path_index: 1,
+            normalization: Normalization::FixedScale { scale: 10000.0 },
+            ..Default::default()
+        };
+        let (wfm, info) = load_npy_file(&path, 20, 0.0, &opts, &quiet).unwrap();
+        assert_eq!(info.path_count, 2);
+        assert_eq!(decode_iq(&wfm).next(), Some((9000, 9000)));
+        opts.path_index = 2;
+        let err = load_npy_file(&path, 20, 0.0, &opts, &quiet).err().unwrap();
+        assert_eq!(err, "Path index 2 out of range: array is 2×60 (2 paths)");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn npy_float_2xn() {
+        let mut data = Vec::new();
+        for row in 0..2 {
+            for n in 0..MIN_LEN {
+                let v = match (row, n) {
+                    (0, 0) => 0.5f32,
+                    (1, 0) => -0.25,
+                    _ => 0.0,
+                };
+                data.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        let dict = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': (2, {}), }}", MIN_LEN);
+        let wfm = load_npy_fixture("wia_f4_2xn.npy", &npy_bytes(&dict, &data));
+        assert_eq!(&wfm[..4], &[0x40, 0x00, 0xE0, 0x00]);
+    }
+
+    #[test]
+    fn hdf5_signature_after_user_block() {
+        let path = std::env::temp_dir().join("wia_v73.mat");
+        let mut bytes = vec![0u8; 512];
+        bytes[..19].copy_from_slice(b"MATLAB 7.3 MAT-file");
+        bytes.extend_from_slice(HDF5_MAGIC);
+        bytes.resize(1024, 0);
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(is_hdf5(&path).unwrap());
+
+        std::fs::write(&path, b"MATLAB 5.0 MAT-file").unwrap();
+        assert!(!is_hdf5(&path).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn v73_layout_matches_v7() {
+        // A 2×n complex matrix: HDF5 keeps MATLAB's column-major data but
+        // reports the shape as n×2
+        let n = MIN_LEN;
+        let row0: Vec<f64> = (0..n).map(|c| c as f64 / n as f64 - 0.5).collect();
+        let row1 = vec![0.25; n];
+        let re: Vec<f64> = (0..n).flat_map(|c| [row0[c], row1[c]]).collect();
+        let im: Vec<f64> = re.iter().map(|v| -v).collect();
+
+        let (dims, v73_re, v73_im, full_scale) = v73_array(vec![n, 2], re.clone(), im.clone());
+        assert_eq!(dims, [2, n]);
+        assert_eq!(full_scale, None);
+        let v73 = select_mat_path(&dims, v73_re, v73_im, 0).unwrap();
+        let v7 = select_mat_path(&[2, n], re.clone(), im.clone(), 0).unwrap();
+        assert_eq!(v73, v7);
+        assert_eq!(v73.0, row0);
+        assert_eq!(v73.2, 2);
+        assert_eq!(select_mat_path(&dims, re, im, 1).unwrap().0, row1);
+    }
+
+    #[test]
+    fn float32_rejects_partial_sample() {
+        let path = std::env::temp_dir().join("wia_f32_bad.bin");
+        std::fs::write(&path, [0u8; 12]).unwrap();
+        assert!(load_float32_raw(&path, 20, 0.0, &LoadOptions::default(), &quiet).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn int16_mat_data_matches_normalized_double() {
+        let ints: Vec<i16> = (0..MIN_LEN as i32).map(|n| (n * 1093 - 32000) as i16).collect();
+        let int_data = NumericData::Int16 { real: ints.clone(), imag: Some(ints.iter().rev().copied().collect()) };
+        let (real, imag, full_scale) = extract_f64_data(&int_data).unwrap();
+        assert_eq!(full_scale, Some(32767.0));
+        let opts = prequantized_options(&LoadOptions::default(), full_scale);
+        let (from_int, _) = finish_iq("int16".into(), real, imag, 20, 0.0, &opts).unwrap();
+
+        let doubles: Vec<f64> = ints.iter().map(|&v| v as f64 / 32767.0).collect();
+        let double_data = NumericData::Double { real: doubles.clone(), imag: Some(doubles.iter().rev().copied().collect()) };
+        let (real, imag, full_scale) = extract_f64_data(&double_data).unwrap();
+        assert_eq!(full_scale, None);
+        let opts = prequantized_options(&LoadOptions::default(), full_scale);
+        let (from_double, _) = finish_iq("double".into(), real, imag, 20, 0.0, &opts).unwrap();
+
+        assert_eq!(from_int, from_double);
+        assert_eq!(&from_int[..2], &(-32000i16).to_be_bytes());
+        assert_eq!(&from_int[2..4], &ints[MIN_LEN - 1].to_be_bytes());
+    }
+
+    #[test]
+    fn int16_mat_data_keeps_negative_full_scale() {
+        let ints = vec![i16::MIN; MIN_LEN];
+        let (real, imag, full_scale) =
+            extract_f64_data(&NumericData::Int16 { real: ints, imag: None }).unwrap();
+        let opts = prequantized_options(&LoadOptions::default(), full_scale);
+        let (wfm, info) = finish_iq("int16".into(), real, imag, 20, 0.0, &opts).unwrap();
+        assert_eq!(&wfm[..4], &[0x80, 0x00, 0x00, 0x00]);
+        assert_eq!(info.clamped_samples, 0);
+    }
+
+    #[test]
+    fn integer_mat_data_round_trips() {
+        // Quantize through the legacy default and read back (I, Q) per sample.
+        let load = |data: NumericData| -> Vec<(i16, i16)> {
+            let (real, imag, full_scale) = extract_f64_data(&data).unwrap();
+            let opts = prequantized_options(&LoadOptions::default(), full_scale);
+            let (wfm, _) = finish_iq("int".into(), real, imag, 20, 0.0, &opts).unwrap();
+            decode_iq(&wfm).collect()
+        };
+        let expected: Vec<i16> = (0..MIN_LEN as i32).map(|n| (n * 1093 - 32000) as i16).collect();
+        let iq: Vec<(i16, i16)> = expected.iter().map(|&v| (v, v.saturating_neg())).collect();
+        let q: Vec<i16> = expected.iter().map(|&v| v.saturating_neg()).collect();
+
+        let data = NumericData::Int16 { real: expected.clone(), imag: Some(q.clone()) };
+        assert_eq!(load(data), iq);
+
+        let offset = |v: &[i16]| v.iter().map(|&x| (x as i32 + 32768) as u16).collect::<Vec<_>>();
+        let data = NumericData::UInt16 { real: offset(&expected), imag: Some(offset(&q)) };
+        assert_eq!(load(data), iq);
+
+        let widen = |v: &[i16]| v.iter().map(|&x| ((x as i32) << 16) + (x as i32)).collect::<Vec<_>>();
+        let data = NumericData::Int32 { real: widen(&expected), imag: Some(widen(&q)) };
+        assert_eq!(load(data), iq);
+
+        let offset = |v: &[i16]| widen(v).iter().map(|&x| (x as i64 + (1 << 31)) as u32).collect::<Vec<_>>();
+        let data = NumericData::UInt32 { real: offset(&expected), imag: Some(offset(&q)) };
+        assert_eq!(load(data), iq);
+
+        // Full-scale ends and midpoints of the unsigned formats.
+        let data = NumericData::UInt16 { real: [0, 32768, 65535].repeat(MIN_LEN / 3 + 1), imag: None };
+        assert_eq!(&load(data)[..3], &[(-32768, 0), (0, 0), (32767, 0)]);
+        // −2^31 is only 1.5e-5 int16 units past −32767 and rounds to it.
+        let data = NumericData::UInt32 { real: [0, 1 << 31, u32::MAX].repeat(MIN_LEN / 3 + 1), imag: None };
+        assert_eq!(&load(data)[..3], &[(-32767, 0), (0, 0), (32767, 0)]);
+
+        // Small int32 values scale proportionally instead of rounding to 0.
+        let data = NumericData::Int32 { real: vec![1 << 20; MIN_LEN], imag: None };
+        assert_eq!(load(data)[0], (16, 0));
+    }
+
+    #[test]
+    fn trim_bounds_resolve_samples_and_micros() {
+        let samples = TrimRange::Samples { start: 10, count: Some(100) };
+        assert_eq!(trim_bounds(samples, 1000, None).unwrap(), (10, 110));
+        let to_end = TrimRange::Samples { start: 10, count: None };
+        assert_eq!(trim_bounds(to_end, 1000, None).unwrap(), (10, 1000));
+        // 40 MS/s: 1 µs = 40 samples
+        let micros = TrimRange::Micros { start_us: 1.0, stop_us: Some(2.5) };
+        assert_eq!(trim_bounds(micros, 1000, Some(40e6)).unwrap(), (40, 100));
+        assert!(trim_bounds(micros, 1000, None).is_err());
+    }
+
+    #[test]
+    fn trim_out_of_range_reports_total() {
+        let past_end = TrimRange::Samples { start: 900, count: Some(200) };
+        let err = trim_bounds(past_end, 1000, None).err().unwrap();
+        assert!(err.contains("1000 samples"), "{}", err);
+        let empty = TrimRange::Samples { start: 1000, count: None };
+        assert!(trim_bounds(empty, 1000, None).is_err());
+    }
+
+    #[test]
+    fn trim_slices_float_burst_before_padding() {
+        let samples: Vec<(f32, f32)> = (0..200).map(|n| (n as f32 / 256.0, 0.0)).collect();
+        let path = write_f32_fixture("wia_trim.bin", &samples, false);
+        let opts = LoadOptions {
+            trim: Some(TrimRange::Samples { start: 100, count: Some(61) }),
+            ..Default::default()
+        };
+        let (wfm, info) = load_float32_raw(&path, 20, 0.0, &opts, &quiet).unwrap();
+        assert_eq!(info.original_sample_count, 200);
+        assert_eq!(info.trimmed_sample_count, 61);
+        assert_eq!(info.sample_count, 62);
+        assert_eq!(info.padding_samples, 1);
+        // Sample 100 = 100/256 → ×32767
+        assert_eq!(&wfm[..2], &((100.0f64 / 256.0 * 32767.0).round() as i16).to_be_bytes());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn trim_raw_waveform_keeps_granularity() {
+        let path = std::env::temp_dir().join("wia_trim.WAVEFORM");
+        let bytes = tone_bytes(200, 20.0, true);
+        std::fs::write(&path, &bytes).unwrap();
+        let opts = LoadOptions {
+            trim: Some(TrimRange::Samples { start: 50, count: Some(99) }),
+            ..Default::default()
+        };
+        let (data, info) = load_waveform_raw(&path, &opts, &quiet).unwrap();
+        assert_eq!(info.original_sample_count, 200);
+        assert_eq!(info.trimmed_sample_count, 99);
+        assert_eq!(info.sample_count, 100);
+        assert_eq!(&data[..4], &bytes[200..204]);
+        assert_eq!(&data[396..], &[0; 4]);
+
+        let too_short = LoadOptions {
+            trim: Some(TrimRange::Samples { start: 0, count: Some(10) }),
+            ..Default::default()
+        };
+        assert!(load_waveform_raw(&path, &too_short, &quiet).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn ramp_attenuates_burst_edges() {
+        let mut real = vec![1.0; 100];
+        let mut imag = vec![-0.5; 100];
+        apply_ramp(&mut real, &mut imag, 4).unwrap();
+        let expected = [0.0954915, 0.3454915, 0.6545085, 0.9045085];
+        for (k, w) in expected.iter().enumerate() {
+            assert!((real[k] - w).abs() < 1e-6);
+            assert!((imag[k] + 0.5 * w).abs() < 1e-6);
+            assert!((real[99 - k] - w).abs() < 1e-6);
+            assert!((imag[99 - k] + 0.5 * w).abs() < 1e-6);
+        }
+        assert!(real[4..96].iter().all(|&v| v == 1.0));
+        assert!(apply_ramp(&mut real, &mut imag, 51).is_err());
+    }
+
+    #[test]
+    fn ramp_spans_tiled_burst_and_leaves_gap_zero() {
+        // 10-sample DC burst tiled 6× to reach MIN_LEN; the ramp covers the
+        // ends of the tiled run, not each tile
+        let opts = LoadOptions {
+            normalization: Normalization::FixedScale { scale: 10000.0 },
+            ramp: Some(RampLength::Samples { len: 3 }),
+            ..Default::default()
+        };
+        let (wfm, info) =
+            finish_iq("ramp".into(), vec![1.0; 10], vec![0.0; 10], 20, 0.0, &opts).unwrap();
+        assert_eq!(info.tile_count, 6);
+        let i_at = |n: usize| i16::from_be_bytes([wfm[n * 4], wfm[n * 4 + 1]]);
+        assert_eq!(i_at(0), (10000.0 * ramp_gain(0, 3)).round() as i16);
+        assert_eq!(i_at(59), i_at(0));
+        assert_eq!(i_at(10), 10000);
+        assert_eq!(i_at(9), 10000);
+    }
+
+    fn cache_entry(path: &str, len: u64, size: usize) -> CacheEntry {
+        let (data, info) = generate_waveform(&TestSignal::Tone { offset_hz: 0.0 }, 2.0, 40e6).unwrap();
+        CacheEntry {
+            key: CacheKey {
+                path: PathBuf::from(path),
+                modified: None,
+                len,
+                sidecar_modified: None,
+                bw_mhz: 20,
+                gap_us: 0.0,
+                options: String::new(),
+            },
+            data: data[..size].to_vec(),
+            info,
+        }
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let mut entries = Vec::new();
+        cache_insert(&mut entries, cache_entry("/a", 1, 100), 250);
+        cache_insert(&mut entries, cache_entry("/b", 1, 100), 250);
+        cache_insert(&mut entries, cache_entry("/c", 1, 100), 250);
+        let paths: Vec<_> = entries.iter().map(|e| e.key.path.to_str().unwrap()).collect();
+        assert_eq!(paths, ["/b", "/c"]);
+
+        // Too big to cache at all
+        cache_insert(&mut entries, cache_entry("/d", 1, 300), 250);
+        assert_eq!(entries.len(), 2);
+    }
+
+    #[test]
+    fn cache_drops_stale_versions_of_a_file() {
+        let mut entries = Vec::new();
+        cache_insert(&mut entries, cache_entry("/a", 1, 100), 1000);
+        cache_insert(&mut entries, cache_entry("/b", 1, 100), 1000);
+        cache_insert(&mut entries, cache_entry("/a", 2, 100), 1000);
+        let keys: Vec<_> = entries.iter().map(|e| (e.key.path.to_str().unwrap(), e.key.len)).collect();
+        assert_eq!(keys, [("/b", 1), ("/a", 2)]);
+    }
+
+    #[test]
+    fn reload_hits_cache_until_file_changes() {
+        // Not constant, which the load would reject
+        let mut samples = vec![(0.25f32, -0.25f32); MIN_LEN];
+        samples[0] = (0.0, 0.0);
+        let path = write_f32_fixture("wia_cache.bin", &samples, false);
+        let opts = LoadOptions::default();
+        let (first, _) = load_waveform_file(path.to_str().unwrap(), 20, 0.0, &opts).unwrap();
+        let key = cache_key(&path, 20, 0.0, &opts).unwrap();
+        assert_eq!(cache_get(&key).unwrap().0, first);
+
+        // Different parameters miss
+        let gained = LoadOptions { gain_db: -6.0, ..Default::default() };
+        assert!(cache_get(&cache_key(&path, 20, 0.0, &gained).unwrap()).is_none());
+
+        // Growing the file changes the key; the reload replaces the stale entry
+        let mut samples = vec![(0.5f32, -0.5f32); MIN_LEN + 2];
+        samples[0] = (0.0, 0.0);
+        write_f32_fixture("wia_cache.bin", &samples, false);
+        assert!(cache_get(&cache_key(&path, 20, 0.0, &opts).unwrap()).is_none());
+        let (second, _) = load_waveform_file(path.to_str().unwrap(), 20, 0.0, &opts).unwrap();
+        assert_ne!(first, second);
+        assert!(cache_get(&key).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn tar_bytes(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tar = Vec::new();
+        for (name, body) in members {
+            let mut header = [0u8; 512];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[124..135].copy_from_slice(format!("{:011o}", body.len()).as_bytes());
+            header[156] = b'0';
+            tar.extend_from_slice(&header);
+            tar.extend_from_slice(body);
+            tar.resize(tar.len().next_multiple_of(512), 0);
+        }
+        tar.resize(tar.len() + 1024, 0);
+        tar
+    }
+
+    fn iq_tar_xml(samples: usize, data_type: &str, channels: usize) -> String {
+        format!(
+            "<?xml version=\"1.0\"?>\n<RS_IQ_TAR_FileFormat fileFormatVersion=\"1\">\n\
+             <Name>FSW</Name>\n<Samples>{}</Samples>\n<Clock unit=\"Hz\">32000000</Clock>\n\
+             <Format>complex</Format>\n<DataType>{}</DataType>\n\
+             <NumberOfChannels>{}</NumberOfChannels>\n<DataFilename>File.complex.1ch.{}</DataFilename>\n\
+             </RS_IQ_TAR_FileFormat>\n",
+            samples, data_type, channels, data_type
+        )
+    }
+
+    fn write_iq_tar(name: &str, xml: &str, data_name: &str, payload: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, tar_bytes(&[("capture.xml", xml.as_bytes()), (data_name, payload)])).unwrap();
+        path
+    }
+
+    #[test]
+    fn iq_tar_float32_and_int16_agree() {
+        let mut f32_payload = Vec::new();
+        let mut i16_payload = Vec::new();
+        for n in 0..MIN_LEN {
+            let v = (n as i16 - 30) * 1000;
+            f32_payload.extend_from_slice(&(v as f32 / 32767.0).to_le_bytes());
+            f32_payload.extend_from_slice(&(-(v as f32) / 32767.0).to_le_bytes());
+            i16_payload.extend_from_slice(&v.to_le_bytes());
+            i16_payload.extend_from_slice(&(-v).to_le_bytes());
+        }
+        let f32_path = write_iq_tar(
+            "wia_f32.iq.tar",
+            &iq_tar_xml(MIN_LEN, "float32", 1),
+            "File.complex.1ch.float32",
+            &f32_payload,
+        );
+        let i16_path = write_iq_tar(
+            "wia_i16.iq.tar",
+            &iq_tar_xml(MIN_LEN, "int16", 1),
+            "File.complex.1ch.int16",
+            &i16_payload,
+        );
+        let opts = LoadOptions::default();
+        let (from_f32, info) = load_iq_tar(&f32_path, 20, 0.0, &opts, &quiet).unwrap();
+        let (from_i16, _) = load_iq_tar(&i16_path, 20, 0.0, &opts, &quiet).unwrap();
+        assert_eq!(from_f32, from_i16);
+        assert_eq!(&from_f32[..4], &[0x8A, 0xD0, 0x75, 0x30]);
+        assert_eq!(info.source_sample_rate_hz, Some(32e6));
+        assert_eq!(info.sample_rate_hz, Some(40e6));
+        std::fs::remove_file(f32_path).unwrap();
+        std::fs::remove_file(i16_path).unwrap();
+    }
+
+    #[test]
+    fn iq_tar_selects_channel() {
+        let mut payload = Vec::new();
+        for _ in 0..MIN_LEN {
+            for v in [0.25f32, 0.0, -0.5, 0.0] {
+                payload.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        let path = write_iq_tar("wia_2ch.iq.tar", &iq_tar_xml(MIN_LEN, "float32", 2), "File.complex.1ch.float32", &payload);
+        let opts = LoadOptions { path_index: 1, ..Default::default() };
+        let (wfm, info) = load_iq_tar(&path, 20, 0.0, &opts, &quiet).unwrap();
+        assert_eq!(info.path_count, 2);
+        assert_eq!(&wfm[..2], &(-16384i16).to_be_bytes());
+        let opts = LoadOptions { path_index: 2, ..Default::default() };
+        assert!(load_iq_tar(&path, 20, 0.0, &opts, &quiet).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn iq_tar_errors_distinguish_archive_and_format() {
+        let path = std::env::temp_dir().join("wia_junk.iq.tar");
+        std::fs::write(&path, b"not a tar archive").unwrap();
+        let err = load_iq_tar(&path, 20, 0.0, &LoadOptions::default(), &quiet).err().unwrap();
+        assert!(err.starts_with("Not a valid iq.tar"), "{}", err);
+
+        let path = write_iq_tar("wia_f64.iq.tar", &iq_tar_xml(MIN_LEN, "float64", 1), "File.complex.1ch.float64", &[0; 16 * MIN_LEN]);
+        let err = load_iq_tar(&path, 20, 0.0, &LoadOptions::default(), &quiet).err().unwrap();
+        assert!(err.starts_with("Unsupported data format inside iq.tar"), "{}", err);
+        std::fs::remove_file(path).unwrap();
+
+        let path = write_iq_tar("wia_nodata.iq.tar", &iq_tar_xml(MIN_LEN, "int16", 1), "other.bin", &[0; 4 * MIN_LEN]);
+        let err = load_iq_tar(&path, 20, 0.0, &LoadOptions::default(), &quiet).err().unwrap();
+        assert!(err.contains("data member 'File.complex.1ch.int16', which the archive does not contain"), "{}", err);
+        std::fs::remove_file(path).unwrap();
+
+        let path = write_iq_tar("wia_huge.iq.tar", &iq_tar_xml(usize::MAX / 2, "int16", 1), "File.complex.1ch.int16", &[0; 16]);
+        let err = load_iq_tar(&path, 20, 0.0, &LoadOptions::default(), &quiet).err().unwrap();
+        assert!(err.starts_with("Not a valid iq.tar") && err.contains("overflow"), "{}", err);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(std::env::temp_dir().join("wia_junk.iq.tar")).unwrap();
+    }
+
+    #[test]
+    fn xml_text_skips_longer_tag_names() {
+        let xml = "<SamplesPerSymbol>4</SamplesPerSymbol><Samples> 100 </Samples>";
+        assert_eq!(xml_text(xml, "Samples"), Some("100"));
+        assert_eq!(xml_text(xml, "Clock"), None);
+    }
+
+    #[test]
+    fn durations_split_burst_and_gap() {
+        // 40 MS/s: 100 samples = 2.5 µs burst, 10 µs gap = 400 samples
+        let opts = LoadOptions::default();
+        let (_, info) = finish_iq("d".into(), vec![0.5; 100], vec![0.0; 100], 20, 10.0, &opts).unwrap();
+        assert_eq!(info.sample_count, 500);
+        assert!((info.duration_us.unwrap() - 12.5).abs() < 1e-9);
+        assert!((info.burst_duration_us.unwrap() - 2.5).abs() < 1e-9);
+        assert!((info.duration_secs_at(40e6) - 12.5e-6).abs() < 1e-15);
+        // Played at another rate, the recorded duration no longer applies
+        assert!((info.duration_secs_at(80e6) - 6.25e-6).abs() < 1e-15);
+    }
+
+    #[test]
+    fn granularity_pad_extends_the_gap() {
+        // 61-sample burst + 20 µs × 20 MHz × 2 = 800 gap samples = 861
+        for (granularity, expected) in [(2, 862), (4, 864), (8, 864)] {
+            let opts = LoadOptions {
+                arb: ArbLimits { granularity, min_len: MIN_LEN, max_len: MAX_LEN },
+                ..Default::default()
+            };
+            let (wfm, info) =
+                finish_iq("g".into(), vec![0.5; 61], vec![0.5; 61], 20, 20.0, &opts).unwrap();
+            assert_eq!(info.sample_count, expected, "granularity {}", granularity);
+            assert_eq!(info.padding_samples, expected - 61);
+            assert!(wfm[..61 * 4].chunks_exact(4).all(|c| c != [0; 4]));
+            assert!(wfm[61 * 4..].iter().all(|&b| b == 0));
+        }
+    }
+
+    #[test]
+    fn tiling_meets_granularity_and_min_len() {
+        let arb = |granularity, min_len| ArbLimits { granularity, min_len, max_len: MAX_LEN };
+        assert_eq!(tiles_needed(7, arb(2, 60)), 10);
+        assert_eq!(tiles_needed(7, arb(4, 60)), 12);
+        assert_eq!(tiles_needed(7, arb(8, 512)), 80);
+        assert_eq!(tiles_needed(10, arb(8, 60)), 8);
+    }
+
+    #[test]
+    fn pad_loaded_waveform_for_stricter_arb() {
+        let opts = LoadOptions::default();
+        let (mut wfm, mut info) =
+            finish_iq("p".into(), vec![0.5; 100], vec![0.0; 100], 20, 0.0, &opts).unwrap();
+        assert_eq!(pad_to_arb(&mut wfm, &mut info, ArbLimits::default()).unwrap(), 0);
+
+        let m938x = ArbLimits { granularity: 8, min_len: 60, max_len: MAX_LEN };
+        assert_eq!(pad_to_arb(&mut wfm, &mut info, m938x).unwrap(), 4);
+        assert_eq!(wfm.len(), 104 * 4);
+        assert_eq!(info.sample_count, 104);
+        assert_eq!(info.padding_samples, 4);
+        assert!((info.duration_us.unwrap() - 2.6).abs() < 1e-9);
+        assert!((info.burst_duration_us.unwrap() - 2.5).abs() < 1e-9);
+
+        let too_long = ArbLimits { granularity: 8, min_len: 512, max_len: MAX_LEN };
+        assert!(pad_to_arb(&mut wfm, &mut info, too_long).is_err());
+    }
+
+    #[test]
+    fn decimation_keeps_passband_and_rejects_alias() {
+        // 4× oversampled: keep a tone at 0.05 cycles/sample, drop one at 0.3
+        let tone = |f: f64| -> (Vec<f64>, Vec<f64>) {
+            (0..4000)
+                .map(|n| {
+                    let p = 2.0 * std::f64::consts::PI * f * n as f64;
+                    (p.cos() * 0.5, p.sin() * 0.5)
+                })
+                .unzip()
+        };
+        let taps = decimation_taps(4);
+        let rms = |v: &[f64]| (v.iter().map(|x| x * x).sum::<f64>() / v.len() as f64).sqrt();
+
+        let (real, imag) = tone(0.05);
+        let (dr, di) = (decimate(&real, &taps, 4), decimate(&imag, &taps, 4));
+        assert_eq!(dr.len(), 1000);
+        assert_eq!(di.len(), 1000);
+        // Away from the edges, where the filter sees zeros
+        assert!((rms(&dr[50..950]) - 0.5 / 2f64.sqrt()).abs() < 0.01);
+
+        let (real, _) = tone(0.3);
+        assert!(rms(&decimate(&real, &taps, 4)[50..950]) < 0.005);
+    }
+
+    #[test]
+    fn decimation_reported_and_checked_against_min_len() {
+        let opts = LoadOptions {
+            decimation: Some(4),
+            ..Default::default()
+        };
+        let (_, info) = finish_iq("d".into(), vec![0.5; 400], vec![0.0; 400], 20, 0.0, &opts).unwrap();
+        assert_eq!(info.decimation, 4);
+        assert_eq!(info.trimmed_sample_count, 400);
+        assert_eq!(info.sample_count, 100);
+
+        let err = finish_iq("d".into(), vec![0.5; 200], vec![0.0; 200], 20, 0.0, &opts).err().unwrap();
+        assert!(err.contains("leaves 50"), "{}", err);
+    }
+
+    #[test]
+    fn envelope_rows_and_bins() {
+        let mut bytes = Vec::new();
+        for (i, q) in [(32767i16, 0i16), (0, 0), (0, -16384), (0, 0)] {
+            bytes.extend_from_slice(&i.to_be_bytes());
+            bytes.extend_from_slice(&q.to_be_bytes());
+        }
+        let mut out = Vec::new();
+        write_envelope(&mut out, &bytes, 1, None).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "sample,power_dbfs\n0,0.00\n1,-200.00\n2,-6.02\n3,-200.00\n"
+        );
+
+        // Bins keep their peak so short bursts stay visible
+        let mut out = Vec::new();
+        write_envelope(&mut out, &bytes, 2, Some(40e6)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "time_us,power_dbfs\n0.0000,0.00\n0.0500,-6.02\n");
+    }
+
+    #[test]
+    fn overdriven_input_reports_clamping() {
+        // 4 of 100 samples at 2× full scale (+6.02 dB), one at -4×
+        let mut real = vec![0.5; 100];
+        real[..4].copy_from_slice(&[2.0, 2.0, 2.0, 2.0]);
+        let mut imag = vec![0.0; 100];
+        imag[0] = -4.0;
+        let (bytes, clamp) = gen_wfm(&real, &imag, 32767.0, Quantization::Round);
+        assert_eq!(clamp.samples, 4);
+        assert!((clamp.worst_overshoot_db.unwrap() - 20.0 * (4.0 * 32767.0 / 32768.0f64).log10()).abs() < 1e-9);
+        assert_eq!(decode_iq(&bytes).next(), Some((32767, -32768)));
+        assert_eq!(gen_wfm(&[0.5], &[0.5], 32767.0, Quantization::Round).1, ClampStats::default());
+
+        let fixed = Normalization::FixedScale { scale: 32767.0 };
+        let opts = LoadOptions {
+            normalization: fixed.clone(),
+            max_clamped_percent: Some(5.0),
+            ..Default::default()
+        };
+        let (_, info) = finish_iq("c".into(), real.clone(), imag.clone(), 20, 0.0, &opts).unwrap();
+        assert_eq!(info.clamped_samples, 4);
+        assert!(info.clamp_overshoot_db.unwrap() > 12.0);
+
+        let strict = LoadOptions {
+            normalization: fixed,
+            max_clamped_percent: Some(3.0),
+            ..Default::default()
+        };
+        let err = finish_iq("c".into(), real, imag, 20, 0.0, &strict).err().unwrap();
+        assert!(err.contains("4 of 100 samples (4.00%)"), "{}", err);
+    }
+
+    #[test]
+    fn gap_samples_round_then_align() {
+        assert_eq!(gap_samples(30.0, 40e6, 2).unwrap(), 1200);
+        // 0.3 µs × 30.72 MS/s = 9.216 → 9 → 10
+        assert_eq!(gap_samples(0.3, 30.72e6, 2).unwrap(), 10);
+        // 1.5 µs × 61.44 MS/s = 92.16 → 92 → 96
+        assert_eq!(gap_samples(1.5, 61.44e6, 8).unwrap(), 96);
+        // 10 µs × 23.04 MS/s = 230.4 → 230 → 232
+        assert_eq!(gap_samples(10.0, 23.04e6, 4).unwrap(), 232);
+        assert_eq!(gap_samples(0.0, 40e6, 8).unwrap(), 0);
+        assert!(gap_samples(-1.0, 40e6, 2).is_err());
+        assert!(gap_samples(f64::NAN, 40e6, 2).is_err());
+    }
+
+    #[test]
+    fn finish_iq_at_uses_the_given_rate() {
+        // 10 µs gap and 1 µs ramp at 30.72 MS/s, which no BW × 2 gives
+        let opts = LoadOptions {
+            ramp: Some(RampLength::Micros { len_us: 1.0 }),
+            ..Default::default()
+        };
+        let (_, info) = finish_iq_at("r".into(), vec![0.5; 100], vec![0.0; 100], 30.72e6, None, 10.0, &opts).unwrap();
+        assert_eq!(info.padding_samples, 308);
+        assert_eq!((info.sample_rate_hz, info.bw_mhz), (Some(30.72e6), None));
+        assert!((info.burst_duration_us.unwrap() - 100.0 / 30.72).abs() < 1e-9);
+        assert!(finish_iq_at("r".into(), vec![0.5; 100], vec![0.0; 100], 0.0, None, 10.0, &opts).is_err());
+    }
+
+    #[test]
+    fn mat_vectors_are_single_paths_in_either_orientation() {
+        let real: Vec<f64> = (0..6).map(f64::from).collect();
+        let imag: Vec<f64> = real.iter().map(|v| -v).collect();
+
+        let (r, i, paths) = select_mat_path(&[1, 6], real.clone(), imag.clone(), 0).unwrap();
+        assert_eq!((r, i, paths), (real.clone(), imag.clone(), 1));
+        let (r, i, paths) = select_mat_path(&[6, 1], real.clone(), imag.clone(), 0).unwrap();
+        assert_eq!((r, i, paths), (real.clone(), imag.clone(), 1));
+        assert!(select_mat_path(&[6, 1], real.clone(), imag.clone(), 1).is_err());
+
+        // 2×3, column-major: row 1 is elements 1, 3, 5
+        let (r, i, paths) = select_mat_path(&[2, 3], real.clone(), imag, 1).unwrap();
+        assert_eq!(r, vec![1.0, 3.0, 5.0]);
+        assert_eq!(i, vec![-1.0, -3.0, -5.0]);
+        assert_eq!(paths, 2);
+    }
+
+    #[test]
+    fn empty_mat_variable_names_its_shape() {
+        let err = select_mat_path(&[0, 1], vec![], vec![], 0).err().unwrap();
+        assert!(err.contains("0×1"), "{}", err);
+    }
+
+    #[test]
+    fn obw_of_multitone_matches_its_span() {
+        // 11 tones 1 MHz apart at 40 MS/s span 10 MHz = 0.25 fs
+        let signal = TestSignal::Multitone { tones: 11, spacing_hz: 1e6 };
+        let (bytes, _) = generate_waveform(&signal, 200.0, 40e6).unwrap();
+        let obw = occupied_bandwidth(&bytes, OBW_FFT_SIZE).unwrap();
+        assert!((obw - 0.25).abs() < 0.02, "obw {}", obw);
+
+        let (tone, _) = generate_waveform(&TestSignal::Tone { offset_hz: 5e6 }, 200.0, 40e6).unwrap();
+        assert!(occupied_bandwidth(&tone, OBW_FFT_SIZE).unwrap() < 0.01);
+        assert!(occupied_bandwidth(&[0; 400], OBW_FFT_SIZE).is_err());
+    }
+
+    #[test]
+    fn obw_reported_on_request() {
+        let (real, imag): (Vec<f64>, Vec<f64>) = (0..4096)
+            .map(|n| {
+                let p = 2.0 * std::f64::consts::PI * 0.1 * n as f64;
+                (p.cos() * 0.5, p.sin() * 0.5)
+            })
+            .unzip();
+        let (_, info) = finish_iq("o".into(), real.clone(), imag.clone(), 20, 0.0, &LoadOptions::default()).unwrap();
+        assert_eq!(info.obw_mhz, None);
+        let opts = LoadOptions { measure_obw: true, ..Default::default() };
+        let (_, info) = finish_iq("o".into(), real, imag, 20, 0.0, &opts).unwrap();
+        assert!(info.obw_mhz.unwrap() < 0.5);
+    }
+
+    #[test]
+    fn iq_pair_interleaves_and_checks_lengths() {
+        let dir = std::env::temp_dir();
+        let (i_path, q_path, short_path) = (
+            dir.join("wia_pair_i.bin"),
+            dir.join("wia_pair_q.bin"),
+            dir.join("wia_pair_short.bin"),
+        );
+        let i: Vec<u8> = (0..MIN_LEN as i16).flat_map(|v| v.to_le_bytes()).collect();
+        let q: Vec<u8> = (0..MIN_LEN as i16).flat_map(|v| (-v).to_le_bytes()).collect();
+        std::fs::write(&i_path, &i).unwrap();
+        std::fs::write(&q_path, &q).unwrap();
+        std::fs::write(&short_path, &q[..8]).unwrap();
+
+        let opts = LoadOptions {
+            endianness: Some(Endianness::Little),
+            ..Default::default()
+        };
+        let (bytes, info) =
+            load_waveform_pair(i_path.to_str().unwrap(), q_path.to_str().unwrap(), &opts).unwrap();
+        assert_eq!(info.file_name, "wia_pair_i.bin + wia_pair_q.bin");
+        assert_eq!(info.sample_count, MIN_LEN);
+        let samples: Vec<_> = decode_iq(&bytes).collect();
+        assert_eq!(samples[3], (3, -3));
+        // Same interleaver as a planar file holding I then Q
+        let planar = [i.clone(), q.clone()].concat();
+        assert_eq!(to_canonical_iq(planar, Endianness::Little, IqLayout::Planar), bytes);
+
+        let err = load_waveform_pair(i_path.to_str().unwrap(), short_path.to_str().unwrap(), &opts)
+            .err()
+            .unwrap();
+        assert!(err.contains(&format!("{} bytes", i.len())) && err.contains("8 bytes"), "{}", err);
+        for p in [i_path, q_path, short_path] {
+            std::fs::remove_file(p).unwrap();
+        }
+    }
+
+    #[test]
+    fn repeat_in_buffer_tiles_burst_and_gap() {
+        let real: Vec<f64> = (0..100).map(|n| (n as f64 * 0.3).cos() * 0.5).collect();
+        let imag = vec![0.0; 100];
+        let (mut bytes, mut info) = finish_iq("r".into(), real, imag, 20, 1.0, &LoadOptions::default()).unwrap();
+        let single = bytes.clone();
+        let pass = info.sample_count;
+        let pass_us = info.duration_us.unwrap();
+
+        repeat_in_buffer(&mut bytes, &mut info, 3, ArbLimits::default()).unwrap();
+        assert_eq!(bytes.capacity(), bytes.len());
+        assert_eq!(bytes, single.repeat(3));
+        assert_eq!((info.sample_count, info.buffer_repeats, info.file_size), (pass * 3, 3, bytes.len()));
+        assert!((info.duration_us.unwrap() - 3.0 * pass_us).abs() < 1e-9);
+
+        let markers = info_markers(&info);
+        assert_eq!(markers.len(), info.sample_count);
+        assert_eq!(&markers[pass..pass + 100], &vec![MARKER1; 100][..]);
+        assert_eq!(markers[pass + 100], 0);
+        assert!(info.check_sequence_repeat().unwrap_err().contains("3 repetitions"));
+
+        let small = ArbLimits { max_len: pass * 5, ..Default::default() };
+        let err = repeat_in_buffer(&mut bytes, &mut info, 2, small).err().unwrap();
+        assert!(err.contains("exceeds"), "{}", err);
+        assert!(repeat_in_buffer(&mut bytes, &mut info, 0, small).is_err());
+        let m938 = ArbLimits { granularity: 8, min_len: 512, max_len: MAX_LEN };
+        assert!(pad_to_arb(&mut bytes, &mut info, m938).is_err());
+    }
+
+    #[test]
+    fn compare_identical_shifted_and_rescaled() {
+        let tone: Vec<(i16, i16)> = (0..5000)
+            .map(|n| {
+                let p = n as f64 * 0.05;
+                ((p.cos() * 16000.0) as i16, (p.sin() * 16000.0) as i16)
+            })
+            .collect();
+        let encode = |samples: &[(i16, i16)]| -> Vec<u8> {
+            samples
+                .iter()
+                .flat_map(|&(i, q)| [i.to_be_bytes(), q.to_be_bytes()].concat())
+                .collect()
+        };
+        let golden = encode(&tone);
+        let path = std::env::temp_dir().join("wia_compare_golden.wfm");
+        std::fs::write(&path, &golden).unwrap();
+        let path_str = path.to_str().unwrap();
+
+        let same = compare_waveform_files(path_str, path_str).unwrap();
+        assert_eq!((same.differing_samples, same.max_abs_diff, same.rms_error_db), (0, 0, None));
+        assert_eq!(same.sample_count_a, 5000);
+
+        let shifted = encode(&tone[1..]);
+        let diff = compare_waveform_with_file(&shifted, path_str).unwrap();
+        assert_eq!((diff.sample_count_a, diff.sample_count_b), (5000, 4999));
+        assert!(diff.differing_samples > 4900);
+
+        let halved: Vec<_> = tone.iter().map(|&(i, q)| (i / 2, q / 2)).collect();
+        let diff = compare_waveform_with_file(&encode(&halved), path_str).unwrap();
+        // Error is half the reference amplitude: −6 dB
+        assert!((diff.rms_error_db.unwrap() + 6.02).abs() < 0.05, "{:?}", diff);
+        assert!((7999..=8001).contains(&diff.max_abs_diff));
+
+        assert!(compare_waveform_with_file(&golden[..6], path_str).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn invert_spectrum_mirrors_a_tone() {
+        let peak_freq = |bytes: &[u8]| {
+            let spectrum = compute_spectrum(bytes, 256).unwrap();
+            let k = (0..spectrum.power_db.len())
+                .max_by(|&a, &b| spectrum.power_db[a].total_cmp(&spectrum.power_db[b]))
+                .unwrap();
+            spectrum.freq[k]
+        };
+        let (real, imag): (Vec<f64>, Vec<f64>) = (0..2048)
+            .map(|n| {
+                let p = 2.0 * std::f64::consts::PI * 0.125 * n as f64;
+                (p.cos() * 0.5, p.sin() * 0.5)
+            })
+            .unzip();
+        let load = |swap_iq, invert_spectrum| {
+            let opts = LoadOptions { swap_iq, invert_spectrum, ..Default::default() };
+            finish_iq("t".into(), real.clone(), imag.clone(), 20, 0.0, &opts).unwrap().0
+        };
+        let plain = load(false, false);
+        assert_eq!(peak_freq(&plain), 0.125);
+        assert_eq!(peak_freq(&load(false, true)), -0.125);
+        // Swapping alone also mirrors; swap then invert maps (I, Q) to (Q, −I)
+        assert_eq!(peak_freq(&load(true, false)), -0.125);
+        let both = load(true, true);
+        assert_eq!(peak_freq(&both), 0.125);
+        let first = decode_iq(&plain).nth(5).unwrap();
+        assert_eq!(decode_iq(&both).nth(5).unwrap(), (first.1, -first.0));
+
+        // Raw int16 input is decoded, transformed and re-encoded
+        let mut raw = plain.clone();
+        orient_iq_bytes(&mut raw, false, true);
+        assert_eq!(peak_freq(&raw), -0.125);
+        let mut edge = [0x00, 0x01, 0x80, 0x00];
+        orient_iq_bytes(&mut edge, false, true);
+        assert_eq!(edge, [0x00, 0x01, 0x7F, 0xFF]);
+    }
+
+    #[test]
+    fn pre_gap_leads_the_burst() {
+        let burst: Vec<f64> = (0..200).map(|n| (n as f64 * 0.2).cos() * 0.5).collect();
+        let (plain, plain_info) =
+            finish_iq("g".into(), burst.clone(), vec![0.0; 200], 20, 5.0, &LoadOptions::default()).unwrap();
+        // 1.01 µs at 40 MS/s → 40.4, rounds to 40 samples
+        let opts = LoadOptions { pre_gap_us: 1.01, ..Default::default() };
+        let (wfm, info) = finish_iq("g".into(), burst, vec![0.0; 200], 20, 5.0, &opts).unwrap();
+
+        assert_eq!(info.pre_gap_samples, 40);
+        assert_eq!(info.padding_samples, plain_info.padding_samples);
+        assert_eq!(info.sample_count, plain_info.sample_count + 40);
+        assert_eq!(&wfm[..160], &[0u8; 160][..]);
+        assert_eq!(&wfm[160..], &plain[..]);
+        assert_eq!(info.levels, plain_info.levels);
+        assert_eq!(info.burst_duration_us, plain_info.burst_duration_us);
+        assert!((info.duration_us.unwrap() - (1.0 + 5.0 + 5.0)).abs() < 1e-9);
+
+        let markers = info_markers(&info);
+        assert_eq!((markers[39], markers[40], markers[239], markers[240]), (0, MARKER1, MARKER1, 0));
+
+        // Both gaps count toward the minimum length, so a short burst with a
+        // long enough lead is not tiled
+        let opts = LoadOptions { pre_gap_us: 1.0, ..Default::default() };
+        let (_, info) = finish_iq("s".into(), vec![0.5; 30], vec![0.0; 30], 20, 0.0, &opts).unwrap();
+        assert_eq!((info.tile_count, info.sample_count), (1, 70));
+    }
+
+    #[test]
+    fn legacy_compat_truncates_in_every_scale_branch() {
+        // Golden files from testdata/legacy/gen_golden.py, which follows the
+        // gen_waveform.py conversion, for the ×2047 (max < 1), ×443
+        // (max < 10) and ×1 branches
+        let cases: [(&[f64], &[f64], &[u8]); 3] = [
+            (
+                &[0.1, -0.3, 0.77, -0.999],
+                &[0.05, 0.61, -0.42, 0.0],
+                include_bytes!("../testdata/legacy/x2047.WAVEFORM"),
+            ),
+            (
+                &[2.5, -3.7, 4.1, -0.6],
+                &[1.3, -4.5, 0.2, 4.4],
+                include_bytes!("../testdata/legacy/x443.WAVEFORM"),
+            ),
+            (
+                &[1000.7, -1500.0, 12.3, -2000.9],
+                &[-333.3, 25.5, 1096.8, 0.9],
+                include_bytes!("../testdata/legacy/x1.WAVEFORM"),
+            ),
+        ];
+        for (real, imag, golden) in cases {
+            let factor = scale_factor(real, imag, &Normalization::Legacy).unwrap();
+            let (bytes, clamp) = gen_wfm(real, imag, factor, Quantization::LegacyCompat);
+            assert_eq!(bytes, golden, "{:?}", real);
+            assert_eq!(clamp.samples, 0);
+        }
+
+        // The default mode rounds the same input: 3276.7 → 3277
+        let (round, _) = gen_wfm(&[0.1], &[0.0], 32767.0, Quantization::Round);
+        assert_eq!(decode_iq(&round).next().unwrap().0, 3277);
+
+        let opts = LoadOptions { quantization: Quantization::LegacyCompat, ..Default::default() };
+        let (_, info) = finish_iq("q".into(), vec![0.5; 100], vec![0.0; 100], 20, 0.0, &opts).unwrap();
+        assert_eq!(info.quantization, Some(Quantization::LegacyCompat));
+        assert_eq!(info.scale_factor, Some(32767.0));
+    }
+
+    #[test]
+    fn zero_and_sparse_bursts() {
+        let path = std::env::temp_dir().join("wia_zero.WAVEFORM");
+        std::fs::write(&path, vec![0u8; 400]).unwrap();
+        let err = load_waveform_file(path.to_str().unwrap(), 20, 0.0, &LoadOptions::default())
+            .err()
+            .unwrap();
+        assert!(err.contains("all zeros"), "{}", err);
+        std::fs::write(&path, [0x10, 0x00, 0xF0, 0x00].repeat(100)).unwrap();
+        let err = load_waveform_file(path.to_str().unwrap(), 20, 0.0, &LoadOptions::default())
+            .err()
+            .unwrap();
+        assert!(err.contains("constant (every sample is I=4096, Q=-4096)"), "{}", err);
+        std::fs::remove_file(path).unwrap();
+
+        // A float burst with an idle gap, whose zeros must not
+        // hide that the burst itself is constant
+        let path = std::env::temp_dir().join("wia_constant_gap.bin");
+        let iq: Vec<u8> = [0.5f32, -0.25].repeat(200).iter().flat_map(|v| v.to_le_bytes()).collect();
+        std::fs::write(&path, iq).unwrap();
+        let err = load_waveform_file(path.to_str().unwrap(), 20, 30.0, &LoadOptions::default())
+            .err()
+            .unwrap();
+        assert!(err.contains("is constant"), "{}", err);
+        std::fs::remove_file(path).unwrap();
+
+        // 10 of 1000 burst samples active = 1%
+        let mut real = vec![0.0; 1000];
+        for n in 0..10 {
+            real[n * 100] = 0.5;
+        }
+        let check = |real: &[f64], min_active_fraction| {
+            let opts = LoadOptions { min_active_fraction, ..Default::default() };
+            let (wfm, mut info) =
+                finish_iq("s".into(), real.to_vec(), vec![0.0; 1000], 20, 10.0, &opts).unwrap();
+            check_activity(&wfm, &mut info, &opts).map(|_| info)
+        };
+        let info = check(&real, None).unwrap();
+        assert_eq!(info.active_fraction, 0.01);
+        assert_eq!(info.dynamic_range_db, Some(0.0));
+        assert!(info.activity_warning.unwrap().contains("1.00%"));
+        assert_eq!(check(&real, Some(0.01)).unwrap().activity_warning, None);
+
+        // a tenth of the peak amplitude is 20 dB down
+        real[500] = 0.05;
+        let dr = check(&real, None).unwrap().dynamic_range_db.unwrap();
+        assert!((dr - 20.0).abs() < 0.1, "{}", dr);
+    }
 }
@@ -0,0 +1,46 @@
+"""Write the legacy-compat golden files checked by waveform.rs.
+
+Follows gen_waveform.py: trans_wfm() scales by 2047, 443 or 1 from the
+largest magnitude, trans_wfm_iq() multiplies by 32767/2047 and casts to
+int16 (truncating toward zero), and the samples are interleaved I, Q as
+big-endian int16. Only the standard library is needed:
+
+    python3 gen_golden.py
+"""
+
+import os
+import struct
+
+CASES = {
+    "x2047": ([0.1, -0.3, 0.77, -0.999], [0.05, 0.61, -0.42, 0.0]),
+    "x443": ([2.5, -3.7, 4.1, -0.6], [1.3, -4.5, 0.2, 4.4]),
+    "x1": ([1000.7, -1500.0, 12.3, -2000.9], [-333.3, 25.5, 1096.8, 0.9]),
+}
+
+
+def trans_wfm(values):
+    peak = max(abs(v) for v in values)
+    if peak < 1:
+        return [v * 2047 for v in values]
+    if peak < 10:
+        return [v * 443 for v in values]
+    return list(values)
+
+
+def trans_wfm_iq(values):
+    return [int(v * 32767 / 2047) for v in values]
+
+
+def main():
+    here = os.path.dirname(os.path.abspath(__file__))
+    for name, (real, imag) in CASES.items():
+        scaled = trans_wfm(real + imag)
+        i = trans_wfm_iq(scaled[: len(real)])
+        q = trans_wfm_iq(scaled[len(real):])
+        data = b"".join(struct.pack(">hh", a, b) for a, b in zip(i, q))
+        with open(os.path.join(here, name + ".WAVEFORM"), "wb") as f:
+            f.write(data)
+
+
+if __name__ == "__main__":
+    main()
@@ -3,12 +3,21 @@ import { listen } from "@tauri-apps/api/event";
 import { open, save } from "@tauri-apps/plugin-dialog";
 
 let ipInput: HTMLInputElement;
+let findBtn: HTMLButtonElement;
+let instrumentList: HTMLDataListElement;
 let connectBtn: HTMLButtonElement;
 let disconnectBtn: HTMLButtonElement;
+let selfTestBtn: HTMLButtonElement;
+let clearIoBtn: HTMLButtonElement;
 let connectionStatus: HTMLElement;
 let dutIpInput: HTMLInputElement;
+let dutIfaceSelect: HTMLSelectElement;
+let dut320Check: HTMLInputElement;
+let dutKeepAliveCheck: HTMLInputElement;
 let dutConnectBtn: HTMLButtonElement;
 let dutDisconnectBtn: HTMLButtonElement;
+let dutMibBtn: HTMLButtonElement;
+let dutLogBtn: HTMLButtonElement;
 let dutStatus: HTMLElement;
 let fileNameLabel: HTMLElement;
 let browseBtn: HTMLButtonElement;
@@ -16,11 +25,25 @@ let exportBtn: HTMLButtonElement;
 let cfInput: HTMLInputElement;
 let bwInput: HTMLInputElement;
 let frameIntervalInput: HTMLInputElement;
+let previewCanvas: HTMLCanvasElement;
+let pathRow: HTMLElement;
+let pathSelect: HTMLSelectElement;
+let refSelect: HTMLSelectElement;
+let retrySelect: HTMLSelectElement;
+let spectrumCanvas: HTMLCanvasElement;
 let ampInput: HTMLInputElement;
 let cableLossInput: HTMLInputElement;
+let powerLimitInput: HTMLInputElement;
 let playBtn: HTMLButtonElement;
 let stopBtn: HTMLButtonElement;
+let modBtn: HTMLButtonElement;
+let consoleTarget: HTMLSelectElement;
+let scpiInput: HTMLInputElement;
+let scpiSendBtn: HTMLButtonElement;
+let ioLogLevelSelect: HTMLSelectElement;
+let ioLogLiveCheck: HTMLInputElement;
 let repeatCheck: HTMLInputElement;
+let markerCheck: HTMLInputElement;
 let repeatCountInput: HTMLInputElement;
 let logArea: HTMLElement;
 let sweepStartInput: HTMLInputElement;
@@ -31,6 +54,7 @@ let sweepStopBtn: HTMLButtonElement;
 
 let isConnected = false;
 let isDutConnected = false;
+let isDutLogging = false;
 let wfmLoaded = false;
 let isMatSource = false;
 let isSweeping = false;
@@ -40,14 +64,132 @@ interface WaveformInfo {
   file_name: string;
   file_size: number;
   sample_count: number;
+  original_sample_count: number;
+  trimmed_sample_count: number;
+  decimation: number;
+  padding_samples: number;
+  tile_count: number;
+  buffer_repeats: number;
+  pre_gap_samples: number;
+  path_count: number;
+  sample_rate_hz: number | null;
+  source_sample_rate_hz: number | null;
+  duration_us: number | null;
+  burst_duration_us: number | null;
+  bw_mhz: number | null;
+  frame_interval_us: number | null;
+  source_file: string | null;
+  quantization: "round" | "legacy-compat" | null;
+  scale_factor: number | null;
+  levels: LevelStats;
+  padded_levels: LevelStats;
+  clipped_percent: number;
+  clamped_samples: number;
+  clamp_overshoot_db: number | null;
+  dc_removed: { i: number; q: number; dbfs: number } | null;
+  obw_mhz: number | null;
+  active_fraction: number;
+  activity_warning: string | null;
+  dynamic_range_db: number | null;
 }
 
-interface SweepProgress {
+interface LevelStats {
+  peak_dbfs: number;
+  peak_power_dbfs: number;
+  rms_dbfs: number;
+  papr_db: number;
+}
+
+interface ReferenceStatus {
+  source: "internal" | "external";
+  locked: boolean;
+}
+
+interface InstrumentModel {
+  name: string;
+  min_freq_hz: number;
+  max_freq_hz: number;
+  max_power_dbm: number;
+  max_sample_rate_hz: number;
+  arb_memory_samples: number;
+  known: boolean;
+}
+
+interface InstrumentIdentity {
+  manufacturer: string;
+  model: string;
+  serial: string;
+  firmware: string;
+  raw: string;
+}
+
+interface InstrumentConnection {
+  identity: InstrumentIdentity;
+  model: InstrumentModel;
+  reference: ReferenceStatus | null;
+  options: string[];
+  endpoint: string;
+  warnings: string[];
+}
+
+interface FoundInstrument {
+  ip: string;
+  idn: string | null;
+  latency_ms: number;
+}
+
+interface ConnectionStatus {
+  vsg_connected: boolean;
+  vsg_alive: boolean;
+  dut_connected: boolean;
+  dut_alive: boolean;
+}
+
+interface WaveformPreview {
+  samples_per_bin: number;
+  total_samples: number;
+  min: number[];
+  max: number[];
+}
+
+interface Spectrum {
+  freq: number[];
+  power_db: number[];
+  segments: number;
+}
+
+interface MibCounters {
+  rec_rx_count: number | null;
+  rx_ok_count: number | null;
+  rssi1: number | null;
+  rssi2: number | null;
+  fcs_err: number | null;
+  phy_err: number | null;
+}
+
+interface MibResult extends MibCounters {
+  ok_by_bw: Record<string, number>;
+  ok_by_mcs: Record<string, number> | null;
+}
+
+interface SweepProgress extends MibCounters {
   current_power: number;
   step_index: number;
   total_steps: number;
-  rec_rx_count: number | null;
-  rx_ok_count: number | null;
+  dut_retries: number;
+  mib_cleared: boolean | null;
+  ok_other_bw: number | null;
+}
+
+function formatMib(mib: MibCounters): string {
+  let text = `RX=${mib.rec_rx_count ?? "?"}, OK=${mib.rx_ok_count ?? "?"}`;
+  if (mib.rssi1 !== null || mib.rssi2 !== null) {
+    text += `, RSSI ${mib.rssi1 ?? "?"}/${mib.rssi2 ?? "?"} dBm`;
+  }
+  if (mib.fcs_err !== null || mib.phy_err !== null) {
+    text += `, FCS err ${mib.fcs_err ?? "?"}, PHY err ${mib.phy_err ?? "?"}`;
+  }
+  return text;
 }
 
 function log(msg: string, type: "info" | "error" | "success" = "info") {
@@ -62,13 +204,26 @@ function log(msg: string, type: "info" | "error" | "success" = "info") {
 function updateUI() {
   connectBtn.disabled = isConnected || isSweeping;
   disconnectBtn.disabled = !isConnected || isSweeping;
+  selfTestBtn.disabled = !isConnected || isSweeping;
+  clearIoBtn.disabled = !isConnected || isSweeping;
   ipInput.disabled = isConnected;
+  findBtn.disabled = isConnected;
+  refSelect.disabled = isConnected;
+  retrySelect.disabled = isConnected;
   dutConnectBtn.disabled = isDutConnected || isSweeping;
   dutDisconnectBtn.disabled = !isDutConnected || isSweeping;
+  dutMibBtn.disabled = !isDutConnected || isSweeping;
+  dutLogBtn.disabled = !isDutConnected || (isSweeping && !isDutLogging);
+  dutLogBtn.textContent = isDutLogging ? "Stop log" : "Follow log";
   dutIpInput.disabled = isDutConnected;
+  dutIfaceSelect.disabled = isDutConnected;
+  dut320Check.disabled = isDutConnected;
+  dutKeepAliveCheck.disabled = isDutConnected;
   browseBtn.disabled = isSweeping;
   playBtn.disabled = !isConnected || !wfmLoaded || isSweeping;
   stopBtn.disabled = !isConnected || isSweeping;
+  modBtn.disabled = !isConnected || isSweeping;
+  scpiSendBtn.disabled = !(consoleTarget.value === "dut" ? isDutConnected : isConnected) || isSweeping;
   exportBtn.disabled = !wfmLoaded || !isMatSource;
   sweepBtn.disabled = !isConnected || !wfmLoaded || isSweeping;
   sweepStopBtn.disabled = !isSweeping;
@@ -85,11 +240,33 @@ async function connect() {
   log(`Connecting to ${ip}...`);
 
   try {
-    const idn = await invoke<string>("connect_instrument", { ip });
+    const reference = refSelect.value || null;
+    const retry = retrySelect.value ? { max_attempts: Number(retrySelect.value), backoff_ms: 200 } : null;
+    const conn = await invoke<InstrumentConnection>("connect_instrument", { ip, reference, retry });
+    const id = conn.identity;
     isConnected = true;
-    connectionStatus.textContent = `Connected: ${idn}`;
+    connectionStatus.textContent = id.model ? `Connected: ${id.manufacturer} ${id.model}` : `Connected: ${id.raw}`;
     connectionStatus.className = "status connected";
-    log(`Connected: ${idn}`, "success");
+    log(`Connected: ${id.raw}`, "success");
+    log(`Endpoint: ${conn.endpoint}`);
+    if (id.serial) {
+      log(`Serial ${id.serial}, firmware ${id.firmware}`);
+    }
+    if (conn.model.known) {
+      const m = conn.model;
+      log(
+        `${m.name}: ${m.min_freq_hz / 1e6}-${m.max_freq_hz / 1e6} MHz, ` +
+          `max ${m.max_power_dbm} dBm, ARB ${m.max_sample_rate_hz / 1e6} MSa/s`,
+      );
+    }
+    if (conn.reference) {
+      const { source, locked } = conn.reference;
+      log(`Reference: ${source}, ${locked ? "locked" : "unlocked"}`);
+    }
+    if (conn.options.length > 0) {
+      log(`Options: ${conn.options.join(", ")}`);
+    }
+    conn.warnings.forEach((w) => log(w, "error"));
   } catch (e) {
     log(`Connection failed: ${e}`, "error");
     connectionStatus.textContent = "Connection failed";
@@ -101,11 +278,14 @@ async function connect() {
 
 async function disconnect() {
   try {
-    await invoke("disconnect_instrument");
+    const warning = await invoke<string | null>("disconnect_instrument");
     isConnected = false;
     connectionStatus.textContent = "Disconnected";
     connectionStatus.className = "status";
     log("VSG disconnected");
+    if (warning) {
+      log(`Output may not have been stopped: ${warning}`, "error");
+    }
   } catch (e) {
     log(`Disconnect error: ${e}`, "error");
   }
@@ -124,11 +304,22 @@ async function connectDut() {
   log(`Connecting to DUT at ${ip}...`);
 
   try {
-    await invoke("connect_dut", { ip });
+    const { endpoint, init_warning } = await invoke<{ endpoint: string; init_warning: string | null }>(
+      "connect_dut",
+      {
+        ip,
+        interface: dutIfaceSelect.value,
+        supports320: dut320Check.checked,
+        keepAlive: dutKeepAliveCheck.checked,
+      },
+    );
     isDutConnected = true;
-    dutStatus.textContent = `Connected: ${ip}:9600`;
+    dutStatus.textContent = `Connected: ${endpoint}`;
     dutStatus.className = "status connected";
-    log(`DUT connected: ${ip}`, "success");
+    log(`DUT connected: ${ip} via ${endpoint}`, "success");
+    if (init_warning) {
+      log(`DUT ATEInit failed: ${init_warning}`, "error");
+    }
   } catch (e) {
     log(`DUT connection failed: ${e}`, "error");
     dutStatus.textContent = "Connection failed";
@@ -138,10 +329,38 @@ async function connectDut() {
   updateUI();
 }
 
+// Ping the connected devices; the backend emits "connection-lost" on failure
+async function checkConnections() {
+  if ((!isConnected && !isDutConnected) || isSweeping) return;
+  try {
+    const status = await invoke<ConnectionStatus>("get_connection_status");
+    connectionStatus.classList.toggle("connected", status.vsg_alive);
+    connectionStatus.classList.toggle("error", status.vsg_connected && !status.vsg_alive);
+    dutStatus.classList.toggle("connected", status.dut_alive);
+    dutStatus.classList.toggle("error", status.dut_connected && !status.dut_alive);
+  } catch {
+    // Busy with another command; check again next time
+  }
+}
+
+// Sweep the local subnet; results arrive as instrument-found events
+async function findInstruments() {
+  findBtn.disabled = true;
+  instrumentList.replaceChildren();
+  log("Searching the local network for instruments...");
+  try {
+    await invoke("discover_instruments");
+  } catch (e) {
+    log(`Instrument search failed: ${e}`, "error");
+    findBtn.disabled = isConnected;
+  }
+}
+
 async function disconnectDut() {
   try {
     await invoke("disconnect_dut");
     isDutConnected = false;
+    isDutLogging = false;
     dutStatus.textContent = "Not connected";
     dutStatus.className = "status";
     log("DUT disconnected");
@@ -152,11 +371,142 @@ async function disconnectDut() {
   updateUI();
 }
 
+async function toggleDutLog() {
+  try {
+    if (isDutLogging) {
+      await invoke("stop_dut_log");
+      isDutLogging = false;
+    } else {
+      await invoke("start_dut_log");
+      isDutLogging = true;
+      log("Following the DUT kernel log");
+    }
+  } catch (e) {
+    log(`DUT log failed: ${e}`, "error");
+  }
+  updateUI();
+}
+
+async function readDutMib() {
+  try {
+    const mib = await invoke<MibResult>("dut_read_mib", {
+      cfMhz: Math.round(parseFloat(cfInput.value)),
+      bwMhz: parseInt(bwInput.value),
+    });
+    log(`DUT MIB: ${formatMib(mib)}`, "success");
+    const counts = (map: Record<string, number>, name: (key: string) => string) =>
+      Object.entries(map)
+        .map(([key, count]) => `${name(key)}=${count}`)
+        .join(", ");
+    if (Object.keys(mib.ok_by_bw).length > 0) {
+      log(`DUT MIB OK by BW: ${counts(mib.ok_by_bw, (bw) => `${bw}M`)}`);
+    }
+    if (mib.ok_by_mcs !== null) {
+      log(`DUT MIB OK by MCS: ${counts(mib.ok_by_mcs, (mcs) => `MCS${mcs}`)}`);
+    }
+  } catch (e) {
+    log(`DUT MIB read failed: ${e}`, "error");
+  }
+}
+
+async function drawPreview() {
+  const ctx = previewCanvas.getContext("2d");
+  if (!ctx) return;
+  previewCanvas.width = previewCanvas.clientWidth;
+  previewCanvas.height = previewCanvas.clientHeight;
+  ctx.clearRect(0, 0, previewCanvas.width, previewCanvas.height);
+  if (!wfmLoaded) return;
+
+  try {
+    const preview = await invoke<WaveformPreview>("get_waveform_preview", {
+      maxPoints: previewCanvas.width,
+    });
+    const h = previewCanvas.height;
+    const peak = Math.max(...preview.max, 1e-9);
+    const dx = previewCanvas.width / preview.max.length;
+    ctx.fillStyle = "#396cd8";
+    preview.max.forEach((hi, k) => {
+      const lo = preview.min[k];
+      const top = h - (hi / peak) * h;
+      const bottom = h - (lo / peak) * h;
+      ctx.fillRect(k * dx, top, Math.max(dx, 1), Math.max(bottom - top, 1));
+    });
+  } catch (e) {
+    log(`Preview failed: ${e}`, "error");
+  }
+}
+
+async function drawSpectrum() {
+  const ctx = spectrumCanvas.getContext("2d");
+  if (!ctx) return;
+  spectrumCanvas.width = spectrumCanvas.clientWidth;
+  spectrumCanvas.height = spectrumCanvas.clientHeight;
+  ctx.clearRect(0, 0, spectrumCanvas.width, spectrumCanvas.height);
+  if (!wfmLoaded) return;
+
+  try {
+    const spec = await invoke<Spectrum>("compute_spectrum", { fftSize: 1024 });
+    // Plot the top 80 dB; x spans -fs/2..fs/2 with fs = 2 × BW
+    const w = spectrumCanvas.width;
+    const h = spectrumCanvas.height;
+    const top = Math.max(...spec.power_db);
+    ctx.strokeStyle = "#396cd8";
+    ctx.beginPath();
+    spec.power_db.forEach((p, k) => {
+      const x = (spec.freq[k] + 0.5) * w;
+      const y = (Math.min(top - p, 80) / 80) * h;
+      if (k === 0) ctx.moveTo(x, y);
+      else ctx.lineTo(x, y);
+    });
+    ctx.stroke();
+    const fsMhz = parseInt(bwInput.value, 10) * 2;
+    ctx.fillStyle = "#666";
+    ctx.font = "10px sans-serif";
+    ctx.fillText(`±${fsMhz / 2} MHz`, 4, 12);
+  } catch (e) {
+    log(`Spectrum failed: ${e}`, "error");
+  }
+}
+
+interface WfmDownloadProgress {
+  slot: string;
+  bytes_sent: number;
+  total: number;
+}
+
+interface WaveformLoadProgress {
+  slot: string;
+  bytes_read: number;
+  total: number;
+}
+
+// Start a background load and resolve once waveform-loaded / waveform-load-failed arrives.
+async function loadWaveformInBackground(args: Record<string, unknown>): Promise<WaveformInfo> {
+  const unlisteners: (() => void)[] = [];
+  try {
+    return await new Promise<WaveformInfo>(async (resolve, reject) => {
+      unlisteners.push(
+        await listen<WaveformLoadProgress>("waveform-load-progress", (event) => {
+          const { bytes_read, total } = event.payload;
+          if (total > 0) {
+            fileNameLabel.textContent = `Loading... ${Math.floor((bytes_read * 100) / total)}%`;
+          }
+        }),
+        await listen<{ info: WaveformInfo }>("waveform-loaded", (event) => resolve(event.payload.info)),
+        await listen<{ error: string }>("waveform-load-failed", (event) => reject(event.payload.error)),
+      );
+      invoke("load_waveform", args).catch(reject);
+    });
+  } finally {
+    unlisteners.forEach((unlisten) => unlisten());
+  }
+}
+
 async function reloadWaveform() {
   if (!currentFilePath) return;
 
   const bwMhz = parseInt(bwInput.value, 10);
-  const frameIntervalUs = parseInt(frameIntervalInput.value, 10);
+  const frameIntervalUs = parseFloat(frameIntervalInput.value);
   if (isNaN(bwMhz) || bwMhz <= 0 || isNaN(frameIntervalUs) || frameIntervalUs < 0) {
     log("Invalid BW or Frame Interval values", "error");
     return;
@@ -166,19 +516,91 @@ async function reloadWaveform() {
   log(`Loading file: ${fileName} (BW=${bwMhz} MHz, FrameInterval=${frameIntervalUs} us)...`);
 
   try {
-    const info = await invoke<WaveformInfo>("load_waveform", {
+    const info = await loadWaveformInBackground({
       filePath: currentFilePath,
       bwMhz,
-      frameIntervalUs,
+      gapUs: frameIntervalUs,
+      options: { path_index: parseInt(pathSelect.value || "0", 10), measure_obw: true },
     });
+    fileNameLabel.textContent = fileName;
     wfmLoaded = true;
+    if (info.bw_mhz !== null && info.bw_mhz !== bwMhz) {
+      bwInput.value = String(info.bw_mhz);
+      log(`BW set to ${info.bw_mhz} MHz from the waveform's metadata sidecar`);
+    }
+    updatePathSelector(info.path_count);
     log(`Loaded: ${info.file_name} (${info.sample_count} IQ samples, ${info.file_size} bytes)`, "success");
+    if (info.duration_us !== null && info.burst_duration_us !== null) {
+      log(
+        `Duration: ${info.duration_us.toFixed(2)} us per pass (burst ${info.burst_duration_us.toFixed(2)} us + ` +
+          `${(info.duration_us - info.burst_duration_us).toFixed(2)} us gap)`,
+      );
+    }
+    const mode = info.quantization === "legacy-compat" ? " (legacy truncation)" : "";
+    const scale = info.scale_factor !== null ? `scale ${info.scale_factor.toFixed(3)}${mode}, ` : "";
+    const lv = info.levels;
+    log(
+      `Levels: ${scale}peak ${lv.peak_dbfs.toFixed(2)} dBFS, RMS ${lv.rms_dbfs.toFixed(2)} dBFS, ` +
+        `PAPR ${lv.papr_db.toFixed(2)} dB (with gap: RMS ${info.padded_levels.rms_dbfs.toFixed(2)} dBFS, ` +
+        `PAPR ${info.padded_levels.papr_db.toFixed(2)} dB)`,
+    );
+    if (info.trimmed_sample_count !== info.original_sample_count) {
+      log(`Trimmed to ${info.trimmed_sample_count} of ${info.original_sample_count} samples`);
+    }
+    if (info.source_sample_rate_hz !== null && info.source_sample_rate_hz !== info.sample_rate_hz) {
+      log(
+        `Capture clock ${(info.source_sample_rate_hz / 1e6).toFixed(3)} MS/s differs from the ARB rate; samples are not resampled`,
+        "error",
+      );
+    }
+    if (info.decimation > 1) {
+      log(`Decimated by ${info.decimation}: ${info.sample_count} output samples`);
+    }
+    if (info.tile_count > 1) {
+      log(`Short burst repeated ${info.tile_count}x to reach the minimum segment length`);
+    }
+    if (info.dc_removed) {
+      const dc = info.dc_removed;
+      log(`Removed DC: I ${dc.i.toExponential(3)}, Q ${dc.q.toExponential(3)} (${dc.dbfs.toFixed(1)} dBFS)`);
+    }
+    if (info.clamped_samples > 0) {
+      const over = info.clamp_overshoot_db !== null ? ` (up to ${info.clamp_overshoot_db.toFixed(2)} dB over)` : "";
+      log(`Warning: ${info.clamped_samples} samples saturated at int16 full scale${over}`, "error");
+    }
+    if (info.clipped_percent > 0) {
+      log(`Clipped ${info.clipped_percent.toFixed(3)}% of samples`);
+    }
+    if (info.activity_warning) {
+      log(`Warning: ${info.activity_warning}`, "error");
+    }
+    if (info.dynamic_range_db !== null) {
+      log(`Dynamic range: ${info.dynamic_range_db.toFixed(1)} dB`);
+    }
+    if (info.obw_mhz !== null) {
+      const bw = info.bw_mhz ?? bwMhz;
+      const level = Math.abs(info.obw_mhz - bw) > 0.2 * bw ? "error" : "info";
+      log(`Occupied bandwidth (99%): ${info.obw_mhz.toFixed(2)} MHz for BW=${bw} MHz`, level);
+    }
   } catch (e) {
+    fileNameLabel.textContent = fileName;
     log(`Failed to load waveform: ${e}`, "error");
     wfmLoaded = false;
   }
 
   updateUI();
+  await drawPreview();
+  await drawSpectrum();
+}
+
+function updatePathSelector(pathCount: number) {
+  pathRow.hidden = pathCount <= 1;
+  if (pathSelect.options.length === pathCount) return;
+  const selected = Math.min(parseInt(pathSelect.value || "0", 10), pathCount - 1);
+  pathSelect.innerHTML = "";
+  for (let p = 0; p < pathCount; p++) {
+    pathSelect.add(new Option(`path${p + 1}`, String(p)));
+  }
+  pathSelect.value = String(selected);
 }
 
 async function browse() {
@@ -186,7 +608,10 @@ async function browse() {
     multiple: false,
     filters: [
       { name: "MATLAB Files", extensions: ["mat"] },
+      { name: "NumPy Arrays", extensions: ["npy"] },
       { name: "Waveform Files", extensions: ["WAVEFORM", "waveform"] },
+      { name: "Raw float32 IQ", extensions: ["bin", "iq"] },
+      { name: "R&S iq-tar", extensions: ["tar"] },
       { name: "All Files", extensions: ["*"] },
     ],
   });
@@ -195,8 +620,9 @@ async function browse() {
 
   currentFilePath = selected as string;
   const fileName = currentFilePath.split(/[/\\]/).pop() || currentFilePath;
-  isMatSource = fileName.toLowerCase().endsWith(".mat");
+  isMatSource = !fileName.toLowerCase().endsWith(".waveform");
   fileNameLabel.textContent = fileName;
+  pathSelect.innerHTML = "";
 
   await reloadWaveform();
 
@@ -214,16 +640,20 @@ async function exportWaveform() {
     defaultPath: defaultName,
     filters: [
       { name: "Waveform Files", extensions: ["WAVEFORM"] },
+      { name: "CSV (int16 I/Q)", extensions: ["csv"] },
+      { name: "MATLAB", extensions: ["mat"] },
     ],
   });
 
   if (!savePath) return;
+  const ext = savePath.split(".").pop()?.toLowerCase();
+  const format = ext === "csv" ? "csv" : ext === "mat" ? "mat" : "raw";
 
   exportBtn.disabled = true;
   log("Exporting waveform...");
 
   try {
-    await invoke("export_waveform", { filePath: savePath });
+    await invoke("export_waveform", { filePath: savePath, format });
     const savedName = savePath.split(/[/\\]/).pop() || savePath;
     log(`Exported: ${savedName}`, "success");
   } catch (e) {
@@ -253,7 +683,14 @@ async function play() {
   log(`Playing waveform (CF=${cfInput.value} MHz, BW=${bwInput.value} MHz, Power=${outputPower} dBm${lossInfo}, ${repeatInfo})...`);
 
   try {
-    await invoke("play_waveform", { cf, bwMhz, amp, repeatCount });
+    const warnings = await invoke<string[]>("play_waveform", {
+      cf,
+      bwMhz,
+      amp,
+      repeatCount,
+      markerToEvent1: markerCheck.checked,
+    });
+    warnings.forEach((w) => log(`Warning: ${w}`, "error"));
     log("Waveform playing", "success");
   } catch (e) {
     log(`Play failed: ${e}`, "error");
@@ -267,6 +704,8 @@ async function stop() {
   log("Stopping waveform...");
 
   try {
+    // Aborts a download still in progress so the stop is not queued behind it
+    await invoke("cancel_download");
     await invoke("stop_waveform");
     log("Waveform stopped", "success");
   } catch (e) {
@@ -276,6 +715,84 @@ async function stop() {
   updateUI();
 }
 
+async function selfTest() {
+  selfTestBtn.disabled = true;
+  log("Running instrument self test (may take a minute)...");
+  try {
+    const result = await invoke<{ passed: boolean; code: number }>("vsg_self_test");
+    if (result.passed) {
+      log("Self test passed", "success");
+    } else {
+      log(`Self test failed with code ${result.code}`, "error");
+    }
+  } catch (e) {
+    log(`Self test failed: ${e}`, "error");
+  }
+  updateUI();
+}
+
+async function resetInstrumentIo() {
+  try {
+    await invoke("reset_instrument_io");
+    log("Instrument I/O cleared", "success");
+  } catch (e) {
+    log(`Clearing instrument I/O failed: ${e}`, "error");
+  }
+}
+
+async function toggleModulation() {
+  try {
+    const enabled = await invoke<boolean>("toggle_modulation");
+    log(enabled ? "Modulation on" : "Modulation off (CW)", "success");
+  } catch (e) {
+    log(`Modulation toggle failed: ${e}`, "error");
+  }
+}
+
+async function sendScpi() {
+  const cmd = scpiInput.value.trim();
+  if (!cmd) return;
+  if (consoleTarget.value === "dut") {
+    await sendDutCommand(cmd);
+    return;
+  }
+  log(`[SCPI] > ${cmd}`);
+  try {
+    if (cmd.includes("?")) {
+      const resp = await invoke<string>("scpi_query", { cmd });
+      log(`[SCPI] < ${resp}`);
+    } else {
+      await invoke("scpi_write", { cmd });
+    }
+    scpiInput.value = "";
+  } catch (e) {
+    log(`[SCPI] ${e}`, "error");
+  }
+}
+
+async function sendDutCommand(cmd: string) {
+  log(`[DUT] > ${cmd}`);
+  try {
+    const output = await invoke<string>("dut_raw_command", { args: cmd.split(/\s+/) });
+    for (const line of output.trimEnd().split("\n")) {
+      if (line) log(`[DUT] < ${line}`);
+    }
+    scpiInput.value = "";
+  } catch (e) {
+    log(`[DUT] ${e}`, "error");
+  }
+}
+
+async function setLogLevel() {
+  const level = ioLogLevelSelect.value;
+  try {
+    await invoke("set_log_level", { level, live: ioLogLiveCheck.checked });
+    log(level === "off" ? "I/O log off" : `I/O log: ${level}`);
+  } catch (e) {
+    log(`I/O log: ${e}`, "error");
+  }
+}
+
 async function startSweep() {
   const cf = parseFloat(cfInput.value) * 1e6;
   const bwMhz = parseFloat(bwInput.value);
@@ -329,12 +846,23 @@ async function stopSweep() {
 
 window.addEventListener("DOMContentLoaded", () => {
   ipInput = document.querySelector("#ip-input")!;
+  findBtn = document.querySelector("#find-btn")!;
+  instrumentList = document.querySelector("#instrument-list")!;
+  refSelect = document.querySelector("#ref-select")!;
+  retrySelect = document.querySelector("#retry-select")!;
   connectBtn = document.querySelector("#connect-btn")!;
   disconnectBtn = document.querySelector("#disconnect-btn")!;
+  selfTestBtn = document.querySelector("#self-test-btn")!;
+  clearIoBtn = document.querySelector("#clear-io-btn")!;
   connectionStatus = document.querySelector("#connection-status")!;
   dutIpInput = document.querySelector("#dut-ip-input")!;
+  dutIfaceSelect = document.querySelector("#dut-iface-select")!;
+  dut320Check = document.querySelector("#dut-320-check")!;
+  dutKeepAliveCheck = document.querySelector("#dut-keepalive-check")!;
   dutConnectBtn = document.querySelector("#dut-connect-btn")!;
   dutDisconnectBtn = document.querySelector("#dut-disconnect-btn")!;
+  dutMibBtn = document.querySelector("#dut-mib-btn")!;
+  dutLogBtn = document.querySelector("#dut-log-btn")!;
   dutStatus = document.querySelector("#dut-status")!;
   fileNameLabel = document.querySelector("#file-name")!;
   browseBtn = document.querySelector("#browse-btn")!;
@@ -342,11 +870,23 @@ window.addEventListener("DOMContentLoaded", () => {
   cfInput = document.querySelector("#cf-input")!;
   bwInput = document.querySelector("#bw-input")!;
   frameIntervalInput = document.querySelector("#frame-interval-input")!;
+  previewCanvas = document.querySelector("#preview-canvas")!;
+  pathRow = document.querySelector("#path-row")!;
+  pathSelect = document.querySelector("#path-select")!;
+  spectrumCanvas = document.querySelector("#spectrum-canvas")!;
   ampInput = document.querySelector("#amp-input")!;
   cableLossInput = document.querySelector("#cable-loss-input")!;
+  powerLimitInput = document.querySelector("#power-limit-input")!;
   playBtn = document.querySelector("#play-btn")!;
   stopBtn = document.querySelector("#stop-btn")!;
+  modBtn = document.querySelector("#mod-btn")!;
+  consoleTarget = document.querySelector("#console-target")!;
+  scpiInput = document.querySelector("#scpi-input")!;
+  scpiSendBtn = document.querySelector("#scpi-send-btn")!;
+  ioLogLevelSelect = document.querySelector("#io-log-level")!;
+  ioLogLiveCheck = document.querySelector("#io-log-live")!;
   repeatCheck = document.querySelector("#repeat-check")!;
+  markerCheck = document.querySelector("#marker-check")!;
   repeatCountInput = document.querySelector("#repeat-count")!;
   logArea = document.querySelector("#log-area")!;
   sweepStartInput = document.querySelector("#sweep-start")!;
@@ -355,6 +895,18 @@ window.addEventListener("DOMContentLoaded", () => {
   sweepBtn = document.querySelector("#sweep-btn")!;
   sweepStopBtn = document.querySelector("#sweep-stop-btn")!;
 
+  const applyPowerLimit = async () => {
+    const dbm = parseFloat(powerLimitInput.value);
+    try {
+      await invoke("set_power_limit", { dbm });
+      log(`[VSG] Power limit: ${dbm} dBm`);
+    } catch (e) {
+      log(`[VSG] ${e}`, "error");
+    }
+  };
+  powerLimitInput.addEventListener("change", applyPowerLimit);
+  applyPowerLimit();
+
   // Tab switching
   const tabBtns = document.querySelectorAll<HTMLButtonElement>(".tab-bar .tab");
   const tabContents = document.querySelectorAll<HTMLElement>(".tab-content");
@@ -368,14 +920,31 @@ window.addEventListener("DOMContentLoaded", () => {
     });
   });
 
+  findBtn.addEventListener("click", findInstruments);
   connectBtn.addEventListener("click", connect);
   disconnectBtn.addEventListener("click", disconnect);
+  selfTestBtn.addEventListener("click", selfTest);
+  clearIoBtn.addEventListener("click", resetInstrumentIo);
   dutConnectBtn.addEventListener("click", connectDut);
   dutDisconnectBtn.addEventListener("click", disconnectDut);
+  dutMibBtn.addEventListener("click", readDutMib);
+  dutLogBtn.addEventListener("click", toggleDutLog);
   browseBtn.addEventListener("click", browse);
   exportBtn.addEventListener("click", exportWaveform);
   playBtn.addEventListener("click", play);
   stopBtn.addEventListener("click", stop);
+  modBtn.addEventListener("click", toggleModulation);
+  scpiSendBtn.addEventListener("click", sendScpi);
+  consoleTarget.addEventListener("change", () => {
+    scpiInput.placeholder =
+      consoleTarget.value === "dut" ? "ATE command, e.g. wlan0 fastconfig -R" : "SCPI command, e.g. *idn? or :OUTP?";
+    updateUI();
+  });
+  ioLogLevelSelect.addEventListener("change", setLogLevel);
+  ioLogLiveCheck.addEventListener("change", setLogLevel);
+  scpiInput.addEventListener("keydown", (e) => {
+    if (e.key === "Enter" && !scpiSendBtn.disabled) sendScpi();
+  });
   sweepBtn.addEventListener("click", startSweep);
   sweepStopBtn.addEventListener("click", stopSweep);
   repeatCheck.addEventListener("change", () => {
@@ -390,6 +959,7 @@ window.addEventListener("DOMContentLoaded", () => {
   };
   bwInput.addEventListener("change", onWaveformParamChange);
   frameIntervalInput.addEventListener("change", onWaveformParamChange);
+  pathSelect.addEventListener("change", onWaveformParamChange);
 
   // Channel help popup
   const channelHelp = document.querySelector("#channel-help")!;
@@ -422,20 +992,122 @@ window.addEventListener("DOMContentLoaded", () => {
 
   // Listen for sweep progress events from backend
   listen<SweepProgress>("sweep-progress", (event) => {
-    const { current_power, step_index, total_steps, rec_rx_count, rx_ok_count } = event.payload;
+    const { current_power, step_index, total_steps, rec_rx_count, dut_retries, mib_cleared, ok_other_bw } =
+      event.payload;
     const cableLoss = parseFloat(cableLossInput.value) || 0;
     const txPower = (current_power + cableLoss).toFixed(1);
     let msg = `[Sweep] Step ${step_index}/${total_steps}: ${current_power} dBm (TxPower ${txPower} dBm)`;
     if (rec_rx_count !== null) {
-      msg += ` | RX=${rec_rx_count}, OK=${rx_ok_count ?? "?"}`;
+      msg += ` | ${formatMib(event.payload)}`;
+    }
+    if (ok_other_bw) {
+      msg += ` (${ok_other_bw} OK at other BW)`;
+    }
+    if (mib_cleared === false) {
+      msg += " (counters read instead of reset)";
+    }
+    if (dut_retries > 0) {
+      msg += ` (DUT retries: ${dut_retries})`;
+    }
+    log(msg, dut_retries > 0 ? "error" : "info");
+  });
+
+  listen<{ burst_index: number; total_bursts: number }>("sweep-warmup", (event) => {
+    const { burst_index, total_bursts } = event.payload;
+    log(`[Sweep] Warm-up burst ${burst_index}/${total_bursts}`);
+  });
+
+  // Log download progress in 10% steps
+  let downloadDecile = -1;
+  listen<WfmDownloadProgress>("wfm-download-progress", (event) => {
+    const { slot, bytes_sent, total } = event.payload;
+    const decile = total > 0 ? Math.floor((bytes_sent * 10) / total) : 10;
+    if (decile !== downloadDecile) {
+      downloadDecile = decile;
+      log(`Downloading '${slot}' to instrument: ${decile * 10}%`);
+    }
+    if (bytes_sent >= total) {
+      downloadDecile = -1;
+    }
+  });
+
+  listen<{ slot: string; downloaded: boolean; skipped_total: number }>("waveform-download", (event) => {
+    const { slot, downloaded, skipped_total } = event.payload;
+    if (!downloaded) {
+      log(`Instrument already has '${slot}', download skipped (${skipped_total} this session)`);
+    }
+  });
+
+  listen<{ step_index: number | null; message: string }>("sweep-error", (event) => {
+    const { step_index, message } = event.payload;
+    const where = step_index === null ? "warm-up" : `step ${step_index}`;
+    log(`[Sweep] Aborted at ${where}, output stopped: ${message}`, "error");
+  });
+
+  listen<{ line: string }>("dut-log", (event) => {
+    log(`[DUT] ${event.payload.line}`);
+  });
+
+  listen<{ error: string | null }>("dut-log-ended", (event) => {
+    const { error } = event.payload;
+    if (error !== null) {
+      log(`DUT log stopped: ${error}`, "error");
+    } else {
+      log("DUT log stopped");
+    }
+    isDutLogging = false;
+    updateUI();
+  });
+
+  listen<{ message: string }>("sweep-warning", (event) => {
+    log(`[Sweep] Warning: ${event.payload.message}`, "error");
+  });
+
+  listen<{ inst_id: string; missing_waveforms: string[] }>("vsg-reconnected", (event) => {
+    const { inst_id, missing_waveforms } = event.payload;
+    log(`[VSG] Connection dropped and restored: ${inst_id}`, "error");
+    if (missing_waveforms.length > 0) {
+      log(`[VSG] Instrument lost ${missing_waveforms.join(", ")}; will download again`, "error");
     }
-    log(msg);
+  });
+
+  const ioLogMarks = { write: ">", read: "<", block: "#", error: "!" };
+  listen<{ t: number; source: string; kind: keyof typeof ioLogMarks; text: string }>("io-log", (event) => {
+    const { t, source, kind, text } = event.payload;
+    log(`[IO ${t.toFixed(3)}] ${source} ${ioLogMarks[kind]} ${text}`, kind === "error" ? "error" : "info");
+  });
+
+  listen<{ cmd: string; attempt: number; max_attempts: number; error: string }>("scpi-retry", (event) => {
+    const { cmd, attempt, max_attempts, error } = event.payload;
+    log(`[VSG] Retry ${attempt}/${max_attempts} of ${cmd}: ${error}`, "error");
+  });
+
+  listen<{ device: string; error: string }>("connection-lost", (event) => {
+    const { device, error } = event.payload;
+    log(`[${device.toUpperCase()}] Not responding: ${error}`, "error");
+  });
+
+  listen<FoundInstrument>("instrument-found", (event) => {
+    const { ip, idn, latency_ms } = event.payload;
+    const option = document.createElement("option");
+    option.value = ip;
+    option.label = idn ?? "unidentified";
+    instrumentList.appendChild(option);
+    log(`Found ${ip} (${latency_ms.toFixed(0)} ms): ${idn ?? "unidentified"}`);
+  });
+
+  listen<{ instruments: FoundInstrument[] }>("discovery-done", (event) => {
+    const count = event.payload.instruments.length;
+    log(count > 0 ? `Search done, ${count} found` : "Search done, no instruments found");
+    findBtn.disabled = isConnected;
   });
 
   listen("sweep-done", () => {
     log("[Sweep] Done", "success");
   });
 
+  setInterval(checkConnections, 5000);
+
   updateUI();
   log("Application ready");
 });